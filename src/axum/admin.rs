@@ -0,0 +1,75 @@
+//! Admin endpoints for on-call engineers
+
+use std::future::Ready;
+
+use axum::Json;
+
+use super::route_table::{RouteInfo, RouteTable};
+use crate::dependencies::DependencyInfo;
+use crate::info::ServiceInfo;
+#[cfg(feature = "logging")]
+use crate::{
+    errors::format_error,
+    problem::{ErrorCode, Problem},
+};
+
+/// Lists the declared dependencies of the service with their status and redacted
+/// configuration, see [`crate::dependencies`].
+///
+/// ```ignore
+/// let router = Router::new().route("/admin/dependencies", get(admin::dependencies));
+/// ```
+pub async fn dependencies() -> Json<Vec<DependencyInfo>> {
+    Json(crate::dependencies::dependencies())
+}
+
+/// Lists the routes of the service with their middlewares, from the
+/// [table](super::route_table::ListedRouter::table) of the router.
+///
+/// ```ignore
+/// let router = ListedRouter::new();
+/// let table = router.table();
+/// let router = router.route("/admin/routes", get(admin::routes(table)));
+/// ```
+pub fn routes(
+    table: RouteTable,
+) -> impl Fn() -> Ready<Json<Vec<RouteInfo>>> + Clone + Send + Sync + 'static {
+    move || std::future::ready(Json(table.routes()))
+}
+
+/// Describes the service: version, enabled features, configuration, key sets, log sinks and
+/// runtime, see [`crate::info`].
+pub async fn info() -> Json<ServiceInfo> {
+    Json(crate::info::info())
+}
+
+/// Log filter of the service, see [`crate::logging::log_filter`]
+///
+/// ```ignore
+/// let router = Router::new().route(
+///     "/admin/log-level",
+///     get(admin::log_level).put(admin::set_log_level),
+/// );
+/// ```
+#[cfg(feature = "logging")]
+pub async fn log_level() -> Result<String, Problem> {
+    crate::logging::log_filter().ok_or_else(no_reloadable_filter)
+}
+
+/// Changes the log filter of the service to the directives of the request body, like
+/// `info,orders_api=debug` (see [`crate::logging::set_log_filter`]), and returns them.
+#[cfg(feature = "logging")]
+pub async fn set_log_level(directives: String) -> Result<String, Problem> {
+    if crate::logging::log_filter().is_none() {
+        return Err(no_reloadable_filter());
+    }
+    let directives = directives.trim();
+    crate::logging::set_log_filter(directives)
+        .map_err(|err| Problem::new(ErrorCode::BadRequest).with_detail(format_error(err)))?;
+    Ok(directives.to_string())
+}
+
+#[cfg(feature = "logging")]
+fn no_reloadable_filter() -> Problem {
+    Problem::new(ErrorCode::NotFound).with_detail("No reloadable log filter installed")
+}
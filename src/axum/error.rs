@@ -1,36 +1,98 @@
-use axum::response::IntoResponse;
+use axum::{
+    response::{IntoResponse, Response},
+    BoxError, Json,
+};
 use futures::Future;
-use http::StatusCode;
+use http::{header::CONTENT_TYPE, HeaderValue, StatusCode};
 use log::error;
 
 use crate::errors::format_error;
+use crate::problem::{CodedError, ErrorCode, Problem, PROBLEM_JSON};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::record_handled_error;
+#[cfg(not(feature = "metrics"))]
+fn record_handled_error(_code: &ErrorCode) {}
 
 /// Reject the request if a NotFound error is returned by the future. Otherwise, log the error
 /// and send  a 500 error.
+///
+/// Errors are responded as `application/problem+json` [`Problem`] bodies, with the code of a
+/// [`CodedError`] when one is returned.
+///
+/// With the `metrics` feature, errors are counted in `handled_errors_total{kind}`.
 pub async fn handle_errors<R: IntoResponse, F: Future<Output = anyhow::Result<R>>>(
     f: F,
-) -> Result<R, (StatusCode, String)> {
-    match f.await {
-        Ok(resp) => Ok(resp),
-        Err(err) => match err.downcast::<NotFound>() {
-            Ok(_not_found) => Err((StatusCode::NOT_FOUND, "404 Not Found".to_string())),
-            Err(err) => match err.downcast::<BadRequest>() {
-                Ok(bad_request) => Err((StatusCode::BAD_REQUEST, bad_request.0)),
-                Err(err) => match err.downcast::<Forbidden>() {
-                    Ok(forbidden) => Err((StatusCode::FORBIDDEN, forbidden.0)),
+) -> Result<R, Problem> {
+    let err = match f.await {
+        Ok(resp) => return Ok(resp),
+        Err(err) => err,
+    };
+    let problem = match err.downcast::<NotFound>() {
+        Ok(_not_found) => Problem::new(ErrorCode::NotFound),
+        Err(err) => match err.downcast::<BadRequest>() {
+            Ok(bad_request) => Problem::new(ErrorCode::BadRequest).with_detail(bad_request.0),
+            Err(err) => match err.downcast::<Forbidden>() {
+                Ok(forbidden) => Problem::new(ErrorCode::Forbidden).with_detail(forbidden.0),
+                Err(err) => match err.downcast::<CodedError>() {
+                    Ok(coded) if coded.code.status() < 500 => {
+                        Problem::new(coded.code).with_detail(coded.detail)
+                    }
+                    Ok(coded) => {
+                        let code = coded.code;
+                        error!("Unable to handle request: {}", format_error(coded));
+                        Problem::new(code)
+                    }
                     Err(err) => {
                         error!("Unable to handle request: {}", format_error(err));
-                        Err((
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            "500 Internal Server Error".to_string(),
-                        ))
+                        Problem::new(ErrorCode::Internal)
                     }
                 },
             },
         },
+    };
+    record_handled_error(&problem.code);
+    Err(problem)
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let headers = self.headers();
+        let mut resp = (status, [(CONTENT_TYPE, PROBLEM_JSON)], Json(self)).into_response();
+        for (name, value) in headers {
+            resp.headers_mut()
+                .insert(name, HeaderValue::from_str(&value).unwrap());
+        }
+        resp
     }
 }
 
+/// Responds to the errors of the tower `Timeout` and `LoadShed` middlewares with `timeout` and
+/// `overloaded` problems, to be used with `HandleErrorLayer`:
+///
+/// ```ignore
+/// let router = Router::new().route("/", get(handler)).layer(
+///     ServiceBuilder::new()
+///         .layer(HandleErrorLayer::new(handle_middleware_error))
+///         .load_shed()
+///         .concurrency_limit(100)
+///         .timeout(Duration::from_secs(10)),
+/// );
+/// ```
+pub async fn handle_middleware_error(err: BoxError) -> Problem {
+    let problem = if err.is::<tower::timeout::error::Elapsed>() {
+        Problem::new(ErrorCode::Timeout)
+    } else if err.is::<tower::load_shed::error::Overloaded>() {
+        Problem::new(ErrorCode::Overloaded)
+    } else {
+        error!("Unable to handle request: {}", err);
+        Problem::new(ErrorCode::Internal)
+    };
+    record_handled_error(&problem.code);
+    problem
+}
+
 /// When returned by a future handled by handle_errors, respond with a 404 not found.
 #[derive(Debug, thiserror::Error)]
 #[error("Not found")]
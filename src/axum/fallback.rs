@@ -0,0 +1,86 @@
+use axum::{
+    extract::Request,
+    middleware::{from_fn, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use http::{header::ALLOW, StatusCode};
+use http_body::Body as _;
+
+use crate::problem::{ErrorCode, Problem};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::record_handled_error;
+#[cfg(not(feature = "metrics"))]
+fn record_handled_error(_code: &ErrorCode) {}
+
+/// Responds `application/problem+json` errors instead of axum's empty defaults: a `not_found`
+/// problem for unknown routes ([`route_not_found`]) and a `method_not_allowed` problem, with
+/// the `Allow` header, for known paths requested with another method
+/// ([`method_not_allowed`]).
+///
+/// With the `metrics` feature, they are counted in `handled_errors_total` with the
+/// `not_found` and `bad_request` kinds.
+///
+/// ```ignore
+/// let app = fallback_handlers(Router::new().route("/users", get(list_users)));
+/// ```
+pub fn fallback_handlers<S: Clone + Send + Sync + 'static>(router: Router<S>) -> Router<S> {
+    router
+        .fallback(route_not_found)
+        .layer(from_fn(method_not_allowed))
+}
+
+/// Fallback handler responding a `not_found` problem
+pub async fn route_not_found() -> Problem {
+    record_handled_error(&ErrorCode::NotFound);
+    Problem::new(ErrorCode::NotFound)
+}
+
+/// Middleware replacing the empty `405 Method Not Allowed` responses of axum by a
+/// `method_not_allowed` problem, keeping the `Allow` header
+pub async fn method_not_allowed(req: Request, next: Next) -> Response {
+    let resp = next.run(req).await;
+    if resp.status() != StatusCode::METHOD_NOT_ALLOWED || resp.body().size_hint().exact() != Some(0)
+    {
+        return resp;
+    }
+    record_handled_error(&ErrorCode::MethodNotAllowed);
+    let mut problem = Problem::new(ErrorCode::MethodNotAllowed).into_response();
+    if let Some(allow) = resp.headers().get(ALLOW) {
+        problem.headers_mut().insert(ALLOW, allow.clone());
+    }
+    problem
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn problem_fallbacks() {
+    use axum::{body::Body, routing::get};
+    use http::header::CONTENT_TYPE;
+    use tower::ServiceExt;
+
+    use crate::problem::PROBLEM_JSON;
+
+    let app = fallback_handlers(Router::new().route("/users", get(|| async { "users" })));
+    let call = |method: &str, uri: &str| {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(req)
+    };
+
+    let resp = call("GET", "/users").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = call("GET", "/unknown").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(resp.headers()[CONTENT_TYPE], PROBLEM_JSON);
+
+    let resp = call("DELETE", "/users").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(resp.headers()[CONTENT_TYPE], PROBLEM_JSON);
+    assert_eq!(resp.headers()[ALLOW], "GET,HEAD");
+}
@@ -0,0 +1,61 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{header::CONTENT_LENGTH, HeaderValue, Method};
+use http_body::Body as _;
+
+/// Answers `HEAD` requests by running them as `GET` requests and stripping the body of the
+/// response. Headers are kept, and `Content-Length` is set to the length of the stripped body
+/// when it is known.
+///
+/// Routes declared with `get` already answer `HEAD` requests: this middleware is useful for
+/// services and nested routers that do not (`route_service`, `nest_service`...). Add it as the
+/// innermost layer: the outer ones, like the metrics and access logs, then see the `HEAD`
+/// method and not the rewritten `GET`.
+pub async fn head_middleware(mut req: Request, next: Next) -> impl IntoResponse {
+    if req.method() != Method::HEAD {
+        return next.run(req).await;
+    }
+    *req.method_mut() = Method::GET;
+    let (mut parts, body) = next.run(req).await.into_parts();
+    if !parts.headers.contains_key(CONTENT_LENGTH) {
+        if let Some(len) = body.size_hint().exact() {
+            parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+        }
+    }
+    Response::from_parts(parts, Body::empty())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn answers_head_with_get() {
+    use std::convert::Infallible;
+
+    use axum::{middleware::from_fn, Router};
+    use http::StatusCode;
+    use tower::{service_fn, ServiceExt};
+
+    let app = Router::new()
+        .route_service(
+            "/file",
+            service_fn(|req: Request| async move {
+                let status = if req.method() == Method::GET {
+                    StatusCode::OK
+                } else {
+                    StatusCode::METHOD_NOT_ALLOWED
+                };
+                Ok::<_, Infallible>((status, "contents").into_response())
+            }),
+        )
+        .layer(from_fn(head_middleware));
+
+    let req = Request::head("/file").body(Body::empty()).unwrap();
+    let resp = app.oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[CONTENT_LENGTH], "8");
+    let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+    assert!(body.is_empty());
+}
@@ -0,0 +1,224 @@
+//! Liveness and readiness endpoints for the Kubernetes probes
+//!
+//! ```ignore
+//! let health = HealthCheckRegistry::new();
+//! health.register("postgres", Duration::from_secs(2), move || {
+//!     let pool = pool.clone();
+//!     async move { pool.get().await?.execute("SELECT 1", &[]).await.map(|_| ()).map_err(Into::into) }
+//! });
+//! let router = Router::new().merge(health.router());
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::{http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+
+use crate::errors::format_error;
+
+type CheckFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+type Check = Arc<dyn Fn() -> CheckFuture + Send + Sync>;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+/// Result of a check
+#[derive(Serialize, Clone, Debug)]
+pub struct CheckReport {
+    pub status: HealthStatus,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Results of all the checks, `down` if any of them failed
+#[derive(Serialize, Clone, Debug)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: BTreeMap<String, CheckReport>,
+}
+
+#[derive(Clone)]
+struct HealthCheck {
+    name: String,
+    timeout: Duration,
+    check: Check,
+}
+
+/// Checks of the readiness of the service, like a database ping or the reachability of a
+/// dependency. They are run concurrently on every readiness probe.
+///
+/// Clones share the same checks, so checks can be registered after the
+/// [router](Self::router) is built.
+#[derive(Clone, Default)]
+pub struct HealthCheckRegistry {
+    inner: Arc<RegistryInner>,
+}
+
+#[derive(Default)]
+struct RegistryInner {
+    checks: Mutex<Vec<HealthCheck>>,
+    shutting_down: AtomicBool,
+}
+
+impl HealthCheckRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails the readiness probe from now on, without running the checks, so the service
+    /// stops receiving new requests
+    pub fn shut_down(&self) {
+        self.inner.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    /// Adds a check, failed when it returns an error or does not complete within `timeout`.
+    /// A check with the same name is replaced.
+    pub fn register<F, Fut>(&self, name: impl Into<String>, timeout: Duration, check: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let name = name.into();
+        let check: Check = Arc::new(move || Box::pin(check()));
+        let mut checks = self.inner.checks.lock().unwrap();
+        checks.retain(|registered| registered.name != name);
+        checks.push(HealthCheck {
+            name,
+            timeout,
+            check,
+        });
+    }
+
+    /// Runs the checks, or reports a `shutdown` check failure once shut down
+    pub async fn check(&self) -> HealthReport {
+        if self.inner.shutting_down.load(Ordering::Relaxed) {
+            let report = CheckReport {
+                status: HealthStatus::Down,
+                duration_ms: 0,
+                error: Some("The service is shutting down".to_string()),
+            };
+            return HealthReport {
+                status: HealthStatus::Down,
+                checks: BTreeMap::from([("shutdown".to_string(), report)]),
+            };
+        }
+        let checks = self.inner.checks.lock().unwrap().clone();
+        let reports = futures::future::join_all(checks.into_iter().map(|check| async move {
+            let start = Instant::now();
+            let result = match tokio::time::timeout(check.timeout, (check.check)()).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow!("Timed out after {:?}", check.timeout)),
+            };
+            let report = CheckReport {
+                status: match result {
+                    Ok(()) => HealthStatus::Up,
+                    Err(_) => HealthStatus::Down,
+                },
+                duration_ms: start.elapsed().as_millis() as u64,
+                error: result.err().map(format_error),
+            };
+            (check.name, report)
+        }))
+        .await;
+        let status = if reports
+            .iter()
+            .all(|(_, report)| report.status == HealthStatus::Up)
+        {
+            HealthStatus::Up
+        } else {
+            HealthStatus::Down
+        };
+        HealthReport {
+            status,
+            checks: reports.into_iter().collect(),
+        }
+    }
+
+    /// Router of the probes, to merge into the router of the service:
+    /// - `/health/live` always responds `200 {"status":"up"}` while the service is running
+    /// - `/health/ready` runs the [registered](Self::register) checks, and responds `200` if
+    ///   all succeeded, `503` otherwise, with the result of each check:
+    ///   `{"status":"down","checks":{"postgres":{"status":"down","duration_ms":2000,"error":"Timed out after 2s"}}}`
+    pub fn router<S: Clone + Send + Sync + 'static>(&self) -> Router<S> {
+        let registry = self.clone();
+        Router::new()
+            .route("/health/live", get(|| async { Json(Alive::UP) }))
+            .route(
+                "/health/ready",
+                get(move || async move {
+                    let report = registry.check().await;
+                    let status = match report.status {
+                        HealthStatus::Up => StatusCode::OK,
+                        HealthStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+                    };
+                    (status, Json(report))
+                }),
+            )
+    }
+}
+
+#[derive(Serialize)]
+struct Alive {
+    status: HealthStatus,
+}
+
+impl Alive {
+    const UP: Alive = Alive {
+        status: HealthStatus::Up,
+    };
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn reports_failed_checks() {
+    use axum::body::Body;
+    use http::Request;
+    use tower::ServiceExt;
+
+    let registry = HealthCheckRegistry::new();
+    let router: Router = registry.router();
+    registry.register("postgres", Duration::from_secs(1), || async { Ok(()) });
+    let call = |path: &str| {
+        let req = Request::get(path).body(Body::empty()).unwrap();
+        router.clone().oneshot(req)
+    };
+    let resp = call("/health/ready").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    registry.register("billing-api", Duration::from_millis(10), || async {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Ok(())
+    });
+    let resp = call("/health/ready").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = axum::body::to_bytes(resp.into_body(), 4096).await.unwrap();
+    let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["status"], "down");
+    assert_eq!(report["checks"]["postgres"]["status"], "up");
+    assert_eq!(
+        report["checks"]["billing-api"]["error"],
+        "Timed out after 10ms"
+    );
+
+    let resp = call("/health/live").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    registry.clone().shut_down();
+    let resp = call("/health/ready").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
@@ -1,60 +1,702 @@
-use std::time::Instant;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
-use axum::response::IntoResponse;
-use axum::{extract::Request, middleware::Next};
-use futures::FutureExt;
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use http::Method;
 use lazy_static::lazy_static;
-use prometheus::{Histogram, IntCounterVec, IntGauge};
+use prometheus::{
+    core::Collector, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry,
+};
+use tower::{Layer, Service};
 
-use crate::metrics::{create_counter_with_labels, create_gauge};
+use super::body::{known_size, ObservedBody};
+use crate::excluded_paths::ExcludedPaths;
+use crate::metrics::{status_class, track_registered, DEFAULT_DURATION_BUCKETS};
+
+/// Label value used in per route mode for requests that did not match any route
+pub const UNMATCHED_ROUTE: &str = "__unmatched__";
+
+type RequestLabel = Arc<dyn Fn(&Request) -> String + Send + Sync>;
 
 lazy_static! {
-    pub static ref REQUEST_DURATION: Histogram = {
-        let ret = prometheus::Histogram::with_opts(
-            prometheus::HistogramOpts::new(
-                "http_request_duration_seconds",
-                "HTTP requests duration",
-            )
-            .buckets(vec![
-                0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 7.5, 10.0,
-                25.0, 50.0, 100.0,
-            ]),
-        )
-        .unwrap();
-        prometheus::register(Box::new(ret.clone())).unwrap();
-
-        ret
-    };
-    pub static ref INFLIGHT_REQUESTS: IntGauge = create_gauge(
-        "inflight_http_request_total",
-        "Number of requests being processed"
-    );
-    pub static ref REQUEST_TOTAL: IntCounterVec = create_counter_with_labels(
-        "http_request_total",
-        "HTTP requests handled",
-        &["method", "status"]
+    static ref DEFAULT_METRICS: Arc<HttpMetrics> = Arc::new(
+        MetricsLayerBuilder::new()
+            .build_metrics()
+            .expect("Cannot register default HTTP metrics")
     );
 }
 
+/// Record HTTP metrics for every request except `/metrics` and `/health`, in the default
+/// registry.
+///
+/// This is equivalent to `MetricsLayerBuilder::new().build()` and cannot be used along
+/// another layer built on the default registry.
 pub async fn metrics_middleware(req: Request, next: Next) -> impl IntoResponse {
-    // do not record metrics on /metrics nor /health endpoint
-    let path = req.uri().path();
-    let record_metrics = path != "/metrics" && path != "/health";
-    let start = Instant::now();
-    let method = req.method().clone();
-    if record_metrics {
-        INFLIGHT_REQUESTS.inc();
-    }
-    next.run(req)
-        .then(|r| async {
-            if record_metrics {
-                REQUEST_DURATION.observe(start.elapsed().as_secs_f64());
-                INFLIGHT_REQUESTS.dec();
-                REQUEST_TOTAL
-                    .with_label_values(&[method.as_str(), r.status().as_str()])
-                    .inc();
+    let (recording, req) = DEFAULT_METRICS.start(req);
+    let resp = next.run(req).await;
+    finish(recording, resp)
+}
+
+/// Builds a [`MetricsLayer`] recording:
+/// - `http_request_duration_seconds` histogram, measured until the response body is sent,
+///   except for [streaming paths](MetricsLayerBuilder::streaming_paths)
+/// - `http_request_ttfb_seconds` histogram, measured when the response headers are produced
+/// - `inflight_http_request_total` gauge, and `inflight_http_request_by_route_total` labelled
+///   by `route` when [enabled](MetricsLayerBuilder::inflight_per_route)
+/// - `http_request_total` counter labelled by `method`, `status` and `status_class`
+/// - `http_request_size_bytes` and `http_response_size_bytes` histograms
+/// - `http_request_apdex_total` counter labelled by `apdex`, when an
+///   [Apdex target](MetricsLayerBuilder::apdex_target) is set
+///
+/// ```ignore
+/// let router = Router::new()
+///     .route("/users/:id", get(user))
+///     .layer(
+///         MetricsLayerBuilder::new()
+///             .per_route(true)
+///             .excluded_paths(ExcludedPaths::default().exact("/ping"))
+///             .build()?,
+///     );
+/// ```
+pub struct MetricsLayerBuilder {
+    registry: Registry,
+    /// The metrics are registered in the default registry, and tracked for the tests
+    default_registry: bool,
+    duration_buckets: Vec<f64>,
+    size_buckets: Vec<f64>,
+    const_labels: HashMap<String, String>,
+    request_labels: Vec<(String, RequestLabel)>,
+    per_route: bool,
+    inflight_per_route: bool,
+    excluded_paths: ExcludedPaths,
+    streaming_paths: ExcludedPaths,
+    apdex_target: Option<Duration>,
+}
+
+impl MetricsLayerBuilder {
+    pub fn new() -> Self {
+        Self {
+            registry: prometheus::default_registry().clone(),
+            default_registry: true,
+            duration_buckets: DEFAULT_DURATION_BUCKETS.to_vec(),
+            // 64B to 16MiB
+            size_buckets: prometheus::exponential_buckets(64.0, 4.0, 10).unwrap(),
+            const_labels: HashMap::new(),
+            request_labels: vec![],
+            per_route: false,
+            inflight_per_route: false,
+            excluded_paths: ExcludedPaths::default(),
+            streaming_paths: ExcludedPaths::none(),
+            apdex_target: None,
+        }
+    }
+
+    /// Registry the metrics are registered in (default: prometheus default registry)
+    pub fn registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self.default_registry = false;
+        self
+    }
+
+    /// Buckets of the request duration histogram, in seconds
+    pub fn duration_buckets(mut self, buckets: Vec<f64>) -> Self {
+        self.duration_buckets = buckets;
+        self
+    }
+
+    /// Buckets of the request and response size histograms, in bytes
+    pub fn size_buckets(mut self, buckets: Vec<f64>) -> Self {
+        self.size_buckets = buckets;
+        self
+    }
+
+    /// Adds a label with a constant value to all metrics
+    pub fn const_label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.const_labels.insert(name.into(), value.into());
+        self
+    }
+
+    /// Adds a label computed from the request to the request counter and duration histogram.
+    ///
+    /// Beware of cardinality: the function should only return a few distinct values.
+    pub fn request_label<F>(mut self, name: impl Into<String>, label: F) -> Self
+    where
+        F: Fn(&Request) -> String + Send + Sync + 'static,
+    {
+        self.request_labels.push((name.into(), Arc::new(label)));
+        self
+    }
+
+    /// Adds a `route` label (the matched route template, eg. `/users/:id`) to the request
+    /// counter and duration histogram.
+    pub fn per_route(mut self, per_route: bool) -> Self {
+        self.per_route = per_route;
+        self
+    }
+
+    /// Also records the inflight requests by matched route, in the
+    /// `inflight_http_request_by_route_total` gauge, to see which endpoints are holding
+    /// connections.
+    pub fn inflight_per_route(mut self, inflight_per_route: bool) -> Self {
+        self.inflight_per_route = inflight_per_route;
+        self
+    }
+
+    /// Paths for which no metrics are recorded (default: `/metrics` and `/health`)
+    pub fn excluded_paths(mut self, excluded_paths: ExcludedPaths) -> Self {
+        self.excluded_paths = excluded_paths;
+        self
+    }
+
+    /// Paths of streaming responses (eg. SSE endpoints), excluded from the
+    /// `http_request_duration_seconds` histogram: only their time to first byte is recorded
+    /// (default: none).
+    pub fn streaming_paths(mut self, streaming_paths: ExcludedPaths) -> Self {
+        self.streaming_paths = streaming_paths;
+        self
+    }
+
+    /// Records the `http_request_apdex_total` counter, labelled by `apdex`:
+    /// - `satisfied`: the request took less than `target`
+    /// - `tolerating`: the request took less than 4 times `target`
+    /// - `frustrated`: the request took longer, or failed with a 5xx status
+    ///
+    /// The Apdex score is `(satisfied + tolerating / 2) / total`.
+    pub fn apdex_target(mut self, target: Duration) -> Self {
+        self.apdex_target = Some(target);
+        self
+    }
+
+    /// Registers the metrics and builds the layer
+    pub fn build(self) -> prometheus::Result<MetricsLayer> {
+        Ok(MetricsLayer {
+            metrics: Arc::new(self.build_metrics()?),
+        })
+    }
+
+    fn build_metrics(self) -> prometheus::Result<HttpMetrics> {
+        let mut labels = vec![];
+        if self.per_route {
+            labels.push("route".to_string());
+        }
+        labels.extend(self.request_labels.iter().map(|(name, _)| name.clone()));
+        let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+        let total_labels: Vec<&str> = ["method", "status", "status_class"]
+            .into_iter()
+            .chain(labels.iter().copied())
+            .collect();
+
+        let duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP requests duration")
+                .const_labels(self.const_labels.clone())
+                .buckets(self.duration_buckets.clone()),
+            &labels,
+        )?;
+        let ttfb = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_ttfb_seconds",
+                "HTTP requests time to first byte",
+            )
+            .const_labels(self.const_labels.clone())
+            .buckets(self.duration_buckets),
+            &labels,
+        )?;
+        let inflight = IntGauge::with_opts(
+            Opts::new(
+                "inflight_http_request_total",
+                "Number of requests being processed",
+            )
+            .const_labels(self.const_labels.clone()),
+        )?;
+        let inflight_by_route = if self.inflight_per_route {
+            Some(IntGaugeVec::new(
+                Opts::new(
+                    "inflight_http_request_by_route_total",
+                    "Number of requests being processed, by route",
+                )
+                .const_labels(self.const_labels.clone()),
+                &["route"],
+            )?)
+        } else {
+            None
+        };
+        let total = IntCounterVec::new(
+            Opts::new("http_request_total", "HTTP requests handled")
+                .const_labels(self.const_labels.clone()),
+            &total_labels,
+        )?;
+        let apdex = match self.apdex_target {
+            Some(target) => {
+                let apdex_labels: Vec<&str> = ["apdex"]
+                    .into_iter()
+                    .chain(labels.iter().copied())
+                    .collect();
+                let counter = IntCounterVec::new(
+                    Opts::new(
+                        "http_request_apdex_total",
+                        "HTTP requests by Apdex satisfaction",
+                    )
+                    .const_labels(self.const_labels.clone()),
+                    &apdex_labels,
+                )?;
+                Some((target, counter))
             }
-            r
+            None => None,
+        };
+        let request_size = Histogram::with_opts(
+            HistogramOpts::new("http_request_size_bytes", "HTTP requests body size")
+                .const_labels(self.const_labels.clone())
+                .buckets(self.size_buckets.clone()),
+        )?;
+        let response_size = Histogram::with_opts(
+            HistogramOpts::new("http_response_size_bytes", "HTTP responses body size")
+                .const_labels(self.const_labels)
+                .buckets(self.size_buckets),
+        )?;
+
+        let (registry, tracked) = (&self.registry, self.default_registry);
+        register_metric(registry, tracked, &duration)?;
+        register_metric(registry, tracked, &ttfb)?;
+        register_metric(registry, tracked, &inflight)?;
+        if let Some(inflight_by_route) = &inflight_by_route {
+            register_metric(registry, tracked, inflight_by_route)?;
+        }
+        register_metric(registry, tracked, &total)?;
+        register_metric(registry, tracked, &request_size)?;
+        register_metric(registry, tracked, &response_size)?;
+        if let Some((_, apdex)) = &apdex {
+            register_metric(registry, tracked, apdex)?;
+        }
+
+        Ok(HttpMetrics {
+            excluded_paths: self.excluded_paths,
+            streaming_paths: self.streaming_paths,
+            per_route: self.per_route,
+            request_labels: self.request_labels,
+            duration,
+            ttfb,
+            inflight,
+            inflight_by_route,
+            total,
+            request_size,
+            response_size,
+            apdex,
         })
-        .await
+    }
+}
+
+/// Registers a metric of the HTTP layer, tracking it if it is in the default registry
+fn register_metric<T: Collector + Clone + 'static>(
+    registry: &Registry,
+    default_registry: bool,
+    metric: &T,
+) -> prometheus::Result<()> {
+    registry.register(Box::new(metric.clone()))?;
+    if default_registry {
+        track_registered(Box::new(metric.clone()));
+    }
+    Ok(())
+}
+
+impl Default for MetricsLayerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct HttpMetrics {
+    excluded_paths: ExcludedPaths,
+    streaming_paths: ExcludedPaths,
+    per_route: bool,
+    request_labels: Vec<(String, RequestLabel)>,
+    duration: HistogramVec,
+    ttfb: HistogramVec,
+    inflight: IntGauge,
+    inflight_by_route: Option<IntGaugeVec>,
+    total: IntCounterVec,
+    request_size: Histogram,
+    response_size: Histogram,
+    apdex: Option<(Duration, IntCounterVec)>,
+}
+
+impl HttpMetrics {
+    /// Starts recording a request, unless its path is excluded
+    fn start(self: &Arc<Self>, req: Request) -> (Option<Recording>, Request) {
+        if self.excluded_paths.is_excluded(req.uri().path()) {
+            return (None, req);
+        }
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|p| p.as_str())
+            .unwrap_or(UNMATCHED_ROUTE);
+        let mut labels = vec![];
+        if self.per_route {
+            labels.push(route.to_string());
+        }
+        labels.extend(self.request_labels.iter().map(|(_, label)| label(&req)));
+
+        self.inflight.inc();
+        let inflight_route = self.inflight_by_route.as_ref().map(|inflight_by_route| {
+            inflight_by_route.with_label_values(&[route]).inc();
+            route.to_string()
+        });
+        let recording = Recording {
+            metrics: self.clone(),
+            start: Instant::now(),
+            method: req.method().clone(),
+            labels,
+            inflight_route,
+            streaming: self.streaming_paths.is_excluded(req.uri().path()),
+        };
+        (Some(recording), self.observe_request_size(req))
+    }
+
+    /// Record the request body size, counting the bytes read by the handler when the size
+    /// is not known upfront.
+    fn observe_request_size(&self, req: Request) -> Request {
+        match known_size(req.headers(), req.body()) {
+            Some(size) => {
+                self.request_size.observe(size as f64);
+                req
+            }
+            None => {
+                let request_size = self.request_size.clone();
+                req.map(|body| {
+                    Body::new(ObservedBody::new(body, move |size| {
+                        request_size.observe(size as f64)
+                    }))
+                })
+            }
+        }
+    }
+
+    /// Record the response body size, counting the bytes sent when the size is not known
+    /// upfront, and the request duration once the body is sent.
+    fn observe_response(
+        &self,
+        resp: Response,
+        start: Instant,
+        duration: Option<Histogram>,
+    ) -> Response {
+        let known_size = known_size(resp.headers(), resp.body());
+        if let Some(size) = known_size {
+            self.response_size.observe(size as f64);
+        }
+        if known_size.is_some() && duration.is_none() {
+            return resp;
+        }
+        let response_size = known_size.is_none().then(|| self.response_size.clone());
+        resp.map(|body| {
+            Body::new(ObservedBody::new(body, move |size| {
+                if let Some(duration) = duration {
+                    duration.observe(start.elapsed().as_secs_f64());
+                }
+                if let Some(response_size) = response_size {
+                    response_size.observe(size as f64);
+                }
+            }))
+        })
+    }
+}
+
+/// Metrics of a request being processed. The inflight gauges are decremented when dropped.
+struct Recording {
+    metrics: Arc<HttpMetrics>,
+    start: Instant,
+    method: Method,
+    labels: Vec<String>,
+    inflight_route: Option<String>,
+    streaming: bool,
+}
+
+impl Recording {
+    fn finish(self, resp: Response) -> Response {
+        let labels: Vec<&str> = self.labels.iter().map(String::as_str).collect();
+        let elapsed = self.start.elapsed();
+        self.metrics
+            .ttfb
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+        let status = resp.status();
+        if let Some((target, apdex)) = &self.metrics.apdex {
+            let satisfaction = if status.is_server_error() || elapsed > *target * 4 {
+                "frustrated"
+            } else if elapsed > *target {
+                "tolerating"
+            } else {
+                "satisfied"
+            };
+            let apdex_labels: Vec<&str> = [satisfaction]
+                .into_iter()
+                .chain(labels.iter().copied())
+                .collect();
+            apdex.with_label_values(&apdex_labels).inc();
+        }
+        let total_labels: Vec<&str> = [
+            self.method.as_str(),
+            status.as_str(),
+            status_class(status.as_u16()),
+        ]
+        .into_iter()
+        .chain(labels.iter().copied())
+        .collect();
+        self.metrics.total.with_label_values(&total_labels).inc();
+        let duration = (!self.streaming).then(|| self.metrics.duration.with_label_values(&labels));
+        self.metrics.observe_response(resp, self.start, duration)
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        self.metrics.inflight.dec();
+        if let (Some(inflight_by_route), Some(route)) =
+            (&self.metrics.inflight_by_route, &self.inflight_route)
+        {
+            inflight_by_route.with_label_values(&[route]).dec();
+        }
+    }
+}
+
+fn finish(recording: Option<Recording>, resp: Response) -> Response {
+    match recording {
+        Some(recording) => recording.finish(resp),
+        None => resp,
+    }
+}
+
+/// Tower layer recording HTTP metrics, built with [`MetricsLayerBuilder`]
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<HttpMetrics>,
+}
+
+impl fmt::Debug for MetricsLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsLayer").finish_non_exhaustive()
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+/// Service produced by [`MetricsLayer`]
+#[derive(Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<HttpMetrics>,
+}
+
+impl<S> Service<Request> for MetricsService<S>
+where
+    S: Service<Request, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let (recording, req) = self.metrics.start(req);
+        let fut = self.inner.call(req);
+        Box::pin(async move { Ok(finish(recording, fut.await?)) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    // the warp metrics have the same names in the default registry
+    #[cfg(not(feature = "warp"))]
+    #[test]
+    fn registers_again_once_reset() {
+        // the only layer of the tests built on the default registry
+        let build = || MetricsLayerBuilder::new().inflight_per_route(true).build();
+        build().unwrap();
+        assert!(build().is_err());
+        crate::metrics::test_support::unregister_registered();
+        build().unwrap();
+        crate::metrics::test_support::unregister_registered();
+    }
+
+    #[tokio::test]
+    async fn records_per_route_in_custom_registry() {
+        let registry = Registry::new();
+        let router = Router::new()
+            .route("/users/:id", get(|| async { "user" }))
+            .layer(
+                MetricsLayerBuilder::new()
+                    .registry(registry.clone())
+                    .per_route(true)
+                    .build()
+                    .unwrap(),
+            );
+        let req = Request::get("/users/42").body(Body::empty()).unwrap();
+        let resp = router.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), 200);
+
+        let total = registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "http_request_total")
+            .unwrap();
+        let labels: Vec<_> = total.get_metric()[0]
+            .get_label()
+            .iter()
+            .map(|l| (l.get_name(), l.get_value()))
+            .collect();
+        assert!(labels.contains(&("route", "/users/:id")));
+        assert!(labels.contains(&("status_class", "2xx")));
+    }
+
+    #[tokio::test]
+    async fn streaming_paths_only_record_ttfb() {
+        let registry = Registry::new();
+        let router = Router::new()
+            .route("/events", get(|| async { "event" }))
+            .layer(
+                MetricsLayerBuilder::new()
+                    .registry(registry.clone())
+                    .streaming_paths(ExcludedPaths::none().exact("/events"))
+                    .build()
+                    .unwrap(),
+            );
+        let req = Request::get("/events").body(Body::empty()).unwrap();
+        drop(router.oneshot(req).await.unwrap());
+
+        let sample_count = |name: &str| {
+            registry
+                .gather()
+                .into_iter()
+                .find(|family| family.get_name() == name)
+                .map(|family| family.get_metric()[0].get_histogram().get_sample_count())
+        };
+        assert_eq!(sample_count("http_request_ttfb_seconds"), Some(1));
+        assert_eq!(sample_count("http_request_duration_seconds"), None);
+    }
+
+    #[tokio::test]
+    async fn records_apdex_satisfaction() {
+        let registry = Registry::new();
+        let slow = |delay| {
+            get(move || async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                "done"
+            })
+        };
+        let router = Router::new()
+            .route("/fast", slow(0))
+            .route("/slow", slow(30))
+            .route("/slowest", slow(100))
+            .route(
+                "/error",
+                get(|| async { http::StatusCode::INTERNAL_SERVER_ERROR }),
+            )
+            .layer(
+                MetricsLayerBuilder::new()
+                    .registry(registry.clone())
+                    .apdex_target(Duration::from_millis(20))
+                    .build()
+                    .unwrap(),
+            );
+        for path in ["/fast", "/fast", "/slow", "/slowest", "/error"] {
+            let req = Request::get(path).body(Body::empty()).unwrap();
+            router.clone().oneshot(req).await.unwrap();
+        }
+
+        let apdex = registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "http_request_apdex_total")
+            .unwrap();
+        let counts: HashMap<&str, f64> = apdex
+            .get_metric()
+            .iter()
+            .map(|metric| {
+                (
+                    metric.get_label()[0].get_value(),
+                    metric.get_counter().get_value(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            counts,
+            HashMap::from([("satisfied", 2.0), ("tolerating", 1.0), ("frustrated", 2.0)])
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_inflight_requests_per_route() {
+        let registry = Registry::new();
+        let router = Router::new()
+            .route(
+                "/users/:id",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    "user"
+                }),
+            )
+            .layer(
+                MetricsLayerBuilder::new()
+                    .registry(registry.clone())
+                    .inflight_per_route(true)
+                    .build()
+                    .unwrap(),
+            );
+        let inflight = || {
+            registry
+                .gather()
+                .into_iter()
+                .find(|family| family.get_name() == "inflight_http_request_by_route_total")
+                .map(|family| {
+                    let metric = &family.get_metric()[0];
+                    (
+                        metric.get_label()[0].get_value().to_string(),
+                        metric.get_gauge().get_value(),
+                    )
+                })
+        };
+
+        let requests: Vec<_> = ["/users/1", "/users/2"]
+            .into_iter()
+            .map(|path| {
+                let req = Request::get(path).body(Body::empty()).unwrap();
+                tokio::spawn(router.clone().oneshot(req))
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(inflight(), Some(("/users/:id".to_string(), 2.0)));
+        for request in requests {
+            request.await.unwrap().unwrap();
+        }
+        assert_eq!(inflight(), Some(("/users/:id".to_string(), 0.0)));
+    }
 }
@@ -1,11 +1,40 @@
 #[cfg(feature = "metrics")]
 pub mod metrics;
 
+#[cfg(any(feature = "metrics", feature = "tracing"))]
+mod body;
+
+mod fallback;
+mod head;
+mod normalize;
 mod options;
+#[cfg(feature = "ids")]
+mod request_ids;
 
+pub use fallback::{fallback_handlers, method_not_allowed, route_not_found};
+pub use head::head_middleware;
+pub use normalize::{NormalizePath, NormalizePathLayer, TrailingSlash};
 pub use options::options_middleware;
+#[cfg(feature = "ids")]
+pub use request_ids::{request_ids, request_ids_middleware, TxId};
 
 pub mod error;
 
+#[cfg(feature = "health")]
+pub mod health;
+
+#[cfg(feature = "shutdown")]
+pub mod shutdown;
+
+#[cfg(feature = "runner")]
+pub mod runner;
+
+pub mod admin;
+
+pub mod route_table;
+
 #[cfg(feature = "tracing")]
 pub mod tracing_access_log;
+
+#[cfg(feature = "webhooks")]
+pub mod webhook;
@@ -0,0 +1,215 @@
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::Request,
+    response::{IntoResponse, Redirect, Response},
+};
+use futures::future::BoxFuture;
+use http::Uri;
+use tower::{Layer, Service};
+
+/// Trailing slash policy of [`NormalizePathLayer`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Paths are kept as is
+    #[default]
+    Keep,
+    /// `/users/` is normalized to `/users`
+    Trim,
+    /// `/users` is normalized to `/users/`
+    Append,
+}
+
+/// Normalizes request paths before routing, so `/users/`, `/Users` and `/users` are served by
+/// the same route and recorded under the same metrics labels.
+///
+/// Only the static segments of the routes, given to
+/// [`lowercase_segments`](Self::lowercase_segments), are lowercased: path parameters like
+/// `/users/AbC42` keep their case.
+///
+/// By default, requests are rewritten; with [`redirect`](Self::redirect), clients are sent a
+/// `308 Permanent Redirect` to the normalized path. The root path `/` is never changed, and
+/// repeated leading slashes are collapsed.
+///
+/// Axum runs router layers after routing, so the layer must wrap the router:
+///
+/// ```ignore
+/// let app = NormalizePathLayer::new()
+///     .trailing_slash(TrailingSlash::Trim)
+///     .lowercase_segments(["users", "orders"])
+///     .layer(router);
+/// axum::serve(listener, ServiceExt::<Request>::into_make_service(app)).await?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct NormalizePathLayer {
+    trailing_slash: TrailingSlash,
+    lowercase_segments: Arc<[String]>,
+    redirect: bool,
+}
+
+impl NormalizePathLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trailing_slash(mut self, trailing_slash: TrailingSlash) -> Self {
+        self.trailing_slash = trailing_slash;
+        self
+    }
+
+    /// Lowercases the path segments equal to one of `segments` regardless of case, typically
+    /// the static segments of the routes (default: none)
+    pub fn lowercase_segments<I>(mut self, segments: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.lowercase_segments = segments
+            .into_iter()
+            .map(|segment| segment.into().to_lowercase())
+            .collect();
+        self
+    }
+
+    /// Redirects to the normalized path instead of rewriting the request (default: false)
+    pub fn redirect(mut self, redirect: bool) -> Self {
+        self.redirect = redirect;
+        self
+    }
+
+    /// Normalized path, if different from `path`. The leading slashes are collapsed, as a
+    /// `//evil.com` redirect location would send clients to another host.
+    fn normalize(&self, path: &str) -> Option<String> {
+        let collapsed = match path.strip_prefix("//") {
+            Some(_) => format!("/{}", path.trim_start_matches('/')),
+            None => path.to_string(),
+        };
+        let mut normalized = if self.lowercase_segments.is_empty() {
+            collapsed
+        } else {
+            collapsed
+                .split('/')
+                .map(|segment| {
+                    match self
+                        .lowercase_segments
+                        .iter()
+                        .find(|known| known.eq_ignore_ascii_case(segment))
+                    {
+                        Some(known) => known.as_str(),
+                        None => segment,
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+        if normalized != "/" {
+            match self.trailing_slash {
+                TrailingSlash::Keep => {}
+                TrailingSlash::Trim => {
+                    let trimmed = normalized.trim_end_matches('/');
+                    normalized = if trimmed.is_empty() {
+                        "/".to_string()
+                    } else {
+                        trimmed.to_string()
+                    };
+                }
+                TrailingSlash::Append => {
+                    if !normalized.ends_with('/') {
+                        normalized.push('/');
+                    }
+                }
+            }
+        }
+        (normalized != path).then_some(normalized)
+    }
+}
+
+impl<S> Layer<S> for NormalizePathLayer {
+    type Service = NormalizePath<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        NormalizePath {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service produced by [`NormalizePathLayer`]
+#[derive(Clone)]
+pub struct NormalizePath<S> {
+    inner: S,
+    layer: NormalizePathLayer,
+}
+
+impl<S> Service<Request> for NormalizePath<S>
+where
+    S: Service<Request, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        if let Some(path) = self.layer.normalize(req.uri().path()) {
+            let path_and_query = match req.uri().query() {
+                Some(query) => format!("{path}?{query}"),
+                None => path,
+            };
+            if self.layer.redirect {
+                let resp = Redirect::permanent(&path_and_query).into_response();
+                return Box::pin(async move { Ok(resp) });
+            }
+            let mut parts = req.uri().clone().into_parts();
+            if let Ok(path_and_query) = path_and_query.parse() {
+                parts.path_and_query = Some(path_and_query);
+                if let Ok(uri) = Uri::from_parts(parts) {
+                    *req.uri_mut() = uri;
+                }
+            }
+        }
+        Box::pin(self.inner.call(req))
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn normalizes_before_routing() {
+    use axum::{body::Body, routing::get, Router};
+    use http::{header::LOCATION, StatusCode};
+    use tower::ServiceExt;
+
+    let router = Router::new()
+        .route("/users", get(|| async { "users" }))
+        .route("/users/:id", get(|| async { "user" }));
+    let layer = NormalizePathLayer::new()
+        .trailing_slash(TrailingSlash::Trim)
+        .lowercase_segments(["users"]);
+
+    let req = Request::get("/Users/?page=2").body(Body::empty()).unwrap();
+    let resp = layer.layer(router.clone()).oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let req = Request::get("/Users/?page=2").body(Body::empty()).unwrap();
+    let layer = layer.redirect(true);
+    let resp = layer.layer(router.clone()).oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(resp.headers()[LOCATION], "/users?page=2");
+
+    let req = Request::get("/USERS/AbC42/").body(Body::empty()).unwrap();
+    let resp = layer.layer(router.clone()).oneshot(req).await.unwrap();
+    assert_eq!(resp.headers()[LOCATION], "/users/AbC42");
+
+    // not a protocol-relative redirect to another host
+    let req = Request::get("//evil.com/").body(Body::empty()).unwrap();
+    let resp = layer.layer(router).oneshot(req).await.unwrap();
+    assert_eq!(resp.headers()[LOCATION], "/evil.com");
+}
@@ -0,0 +1,117 @@
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request},
+    middleware::Next,
+    response::Response,
+};
+use http::{request::Parts, HeaderMap, HeaderName, HeaderValue};
+
+use crate::ids::{RequestIds, REQUEST_ID_HEADER, TRACEPARENT_HEADER};
+
+/// Ids of the request: the ones set by [`request_ids_middleware`], or ids derived from the
+/// incoming headers (see [`RequestIds::from_incoming`])
+pub fn request_ids(req: &Request) -> RequestIds {
+    if let Some(ids) = req.extensions().get::<RequestIds>() {
+        return *ids;
+    }
+    incoming_ids(req.headers())
+}
+
+fn incoming_ids(headers: &HeaderMap) -> RequestIds {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    RequestIds::from_incoming(header(REQUEST_ID_HEADER), header(TRACEPARENT_HEADER))
+}
+
+/// Extracts the transaction id of the request, the `tx` field of the access log, to add it
+/// to error responses or to the calls to other services:
+///
+/// ```ignore
+/// async fn handler(TxId(tx_id): TxId) -> String {
+///     format!("Request {tx_id} queued")
+/// }
+/// ```
+///
+/// Without [`request_ids_middleware`] nor access log, the ids derived from the incoming
+/// headers are set in the request extensions, so all the extractors get the same id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxId(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for TxId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ids = match parts.extensions.get::<RequestIds>() {
+            Some(ids) => *ids,
+            None => {
+                let ids = incoming_ids(&parts.headers);
+                parts.extensions.insert(ids);
+                ids
+            }
+        };
+        Ok(TxId(ids.tx_id()))
+    }
+}
+
+/// Sets the [`RequestIds`] of the request in its extensions, for the handlers, the access log
+/// and the calls to other services, and returns the request id in the `x-request-id` header
+/// of the response.
+///
+/// Add it outside of the access log so both use the same ids.
+pub async fn request_ids_middleware(mut req: Request, next: Next) -> Response {
+    let ids = request_ids(&req);
+    req.extensions_mut().insert(ids);
+    let mut resp = next.run(req).await;
+    if let Ok(value) = HeaderValue::from_str(&ids.request_id()) {
+        resp.headers_mut()
+            .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    resp
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn returns_the_request_id() {
+    use axum::{body::Body, middleware::from_fn, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    let app = Router::new()
+        .route(
+            "/",
+            get(|Extension(ids): Extension<RequestIds>| async move { ids.trace_id() }),
+        )
+        .layer(from_fn(request_ids_middleware));
+
+    let req = Request::get("/")
+        .header(
+            TRACEPARENT_HEADER,
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        )
+        .body(Body::empty())
+        .unwrap();
+    let resp = app.oneshot(req).await.unwrap();
+    let request_id = resp.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+    let id = crate::ids::from_short(request_id).unwrap();
+    assert_eq!(id.simple().to_string(), "0af7651916cd43dd8448eb211c80319c");
+    let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+    assert_eq!(body, "0af7651916cd43dd8448eb211c80319c");
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn extracts_the_tx_id() {
+    use axum::{body::Body, middleware::from_fn, routing::get, Router};
+    use tower::ServiceExt;
+
+    let app = Router::new()
+        .route("/", get(|TxId(tx_id): TxId| async move { tx_id }))
+        .layer(from_fn(request_ids_middleware));
+
+    let req = Request::get("/").body(Body::empty()).unwrap();
+    let resp = app.oneshot(req).await.unwrap();
+    let request_id = resp.headers()[REQUEST_ID_HEADER].clone();
+    let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+    assert_eq!(body, request_id.as_bytes());
+}
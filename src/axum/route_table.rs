@@ -0,0 +1,265 @@
+//! Route table of the service, listed on an admin endpoint
+
+use std::{
+    convert::Infallible,
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    extract::Request,
+    handler::Handler,
+    response::IntoResponse,
+    routing::{self, MethodRouter, Route},
+    Router,
+};
+use http::Method;
+use serde::Serialize;
+use tower::{Layer, Service};
+
+/// A route served by the service
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct RouteInfo {
+    pub method: String,
+    pub path: String,
+    /// Names of the middlewares applied to the route, innermost first
+    pub middlewares: Vec<String>,
+}
+
+/// Routes of a [`ListedRouter`], published when it is [finished](ListedRouter::finish)
+///
+/// Clones share the same routes, so the table can be served before the router is finished.
+#[derive(Clone, Default, Debug)]
+pub struct RouteTable {
+    routes: Arc<RwLock<Vec<RouteInfo>>>,
+}
+
+impl RouteTable {
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.routes.read().unwrap().clone()
+    }
+}
+
+/// Method router keeping track of the methods it answers, see [`get`], [`post`], [`put`],
+/// [`patch`] and [`delete`]
+///
+/// ```ignore
+/// ListedRouter::new().route("/users", get(list_users).post(create_user))
+/// ```
+pub struct ListedMethodRouter<S = ()> {
+    router: MethodRouter<S>,
+    methods: Vec<Method>,
+}
+
+macro_rules! listed_methods {
+    ($($name:ident => $method:ident),*) => {
+        $(
+            #[doc = concat!("Routes `", stringify!($method), "` requests to `handler`")]
+            pub fn $name<H: Handler<T, S>, T: 'static, S: Clone + Send + Sync + 'static>(
+                handler: H,
+            ) -> ListedMethodRouter<S> {
+                ListedMethodRouter {
+                    router: routing::$name(handler),
+                    methods: vec![Method::$method],
+                }
+            }
+
+            impl<S: Clone + Send + Sync + 'static> ListedMethodRouter<S> {
+                #[doc = concat!("Also routes `", stringify!($method), "` requests to `handler`")]
+                pub fn $name<H: Handler<T, S>, T: 'static>(mut self, handler: H) -> Self {
+                    self.router = self.router.$name(handler);
+                    self.methods.push(Method::$method);
+                    self
+                }
+            }
+        )*
+    };
+}
+
+listed_methods!(get => GET, post => POST, put => PUT, patch => PATCH, delete => DELETE);
+
+/// Router keeping track of its routes and middlewares, for the
+/// [`admin::routes`](super::admin::routes) endpoint
+///
+/// ```ignore
+/// let router = ListedRouter::new();
+/// let table = router.table();
+/// let router = router
+///     .route("/users", get(list_users).post(create_user))
+///     .route_layer("auth", from_fn(auth))
+///     .route("/admin/routes", get(admin::routes(table)))
+///     .layer("metrics", metrics_layer)
+///     .finish();
+/// ```
+pub struct ListedRouter<S = ()> {
+    router: Router<S>,
+    routes: Vec<RouteInfo>,
+    table: RouteTable,
+}
+
+impl<S: Clone + Send + Sync + 'static> ListedRouter<S> {
+    pub fn new() -> Self {
+        Self {
+            router: Router::new(),
+            routes: vec![],
+            table: RouteTable::default(),
+        }
+    }
+
+    /// Table of the routes, filled once the router is [finished](Self::finish)
+    pub fn table(&self) -> RouteTable {
+        self.table.clone()
+    }
+
+    /// Adds a route answering the methods of `method_router`
+    pub fn route(mut self, path: &str, method_router: ListedMethodRouter<S>) -> Self {
+        self.router = self.router.route(path, method_router.router);
+        self.routes
+            .extend(method_router.methods.into_iter().map(|method| RouteInfo {
+                method: method.to_string(),
+                path: path.to_string(),
+                middlewares: vec![],
+            }));
+        self
+    }
+
+    pub fn get<H: Handler<T, S>, T: 'static>(self, path: &str, handler: H) -> Self {
+        self.route(path, get(handler))
+    }
+
+    pub fn post<H: Handler<T, S>, T: 'static>(self, path: &str, handler: H) -> Self {
+        self.route(path, post(handler))
+    }
+
+    pub fn put<H: Handler<T, S>, T: 'static>(self, path: &str, handler: H) -> Self {
+        self.route(path, put(handler))
+    }
+
+    pub fn patch<H: Handler<T, S>, T: 'static>(self, path: &str, handler: H) -> Self {
+        self.route(path, patch(handler))
+    }
+
+    pub fn delete<H: Handler<T, S>, T: 'static>(self, path: &str, handler: H) -> Self {
+        self.route(path, delete(handler))
+    }
+
+    /// Nests the routes of `router` under `path`
+    pub fn nest(mut self, path: &str, router: ListedRouter<S>) -> Self {
+        self.router = self.router.nest(path, router.router);
+        let prefix = path.trim_end_matches('/');
+        self.routes
+            .extend(router.routes.into_iter().map(|route| RouteInfo {
+                path: match route.path.as_str() {
+                    // axum serves the nested root on the prefix itself
+                    "/" if !prefix.is_empty() => prefix.to_string(),
+                    _ => format!("{prefix}{}", route.path),
+                },
+                ..route
+            }));
+        self
+    }
+
+    /// Applies `layer` to the routes added so far (see `Router::layer`)
+    pub fn layer<L>(mut self, name: &str, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.router = self.router.layer(layer);
+        self.add_middleware(name);
+        self
+    }
+
+    /// Applies `layer` to the routes added so far, only when they match (see
+    /// `Router::route_layer`)
+    pub fn route_layer<L>(mut self, name: &str, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.router = self.router.route_layer(layer);
+        self.add_middleware(name);
+        self
+    }
+
+    fn add_middleware(&mut self, name: &str) {
+        for route in &mut self.routes {
+            route.middlewares.push(name.to_string());
+        }
+    }
+
+    /// Routes added so far
+    pub fn routes(&self) -> &[RouteInfo] {
+        &self.routes
+    }
+
+    /// Logs the routes, publishes them in the [table](Self::table) and returns the router
+    pub fn finish(self) -> Router<S> {
+        for route in &self.routes {
+            log::info!(
+                "Route {} {} [{}]",
+                route.method,
+                route.path,
+                route.middlewares.join(", ")
+            );
+        }
+        *self.table.routes.write().unwrap() = self.routes;
+        self.router
+    }
+}
+
+impl<S: Clone + Send + Sync + 'static> Default for ListedRouter<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn lists_routes() {
+    use axum::{body::Body, middleware::from_fn};
+    use tower::ServiceExt;
+
+    use super::options_middleware;
+
+    let users =
+        ListedRouter::new().route("/", get(|| async { "users" }).post(|| async { "created" }));
+    let router = ListedRouter::new();
+    let table = router.table();
+    let router = router
+        .get("/health", || async { "ok" })
+        .route_layer("options", from_fn(options_middleware))
+        .nest("/users", users)
+        .route("/admin/routes", get(super::admin::routes(table.clone())))
+        .finish();
+
+    let req = Request::post("/users").body(Body::empty()).unwrap();
+    let resp = router.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let req = Request::get("/admin/routes").body(Body::empty()).unwrap();
+    let resp = router.oneshot(req).await.unwrap();
+    let body = axum::body::to_bytes(resp.into_body(), 4096).await.unwrap();
+    let served: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(served, serde_json::to_value(table.routes()).unwrap());
+
+    let listed: Vec<_> = table
+        .routes()
+        .into_iter()
+        .map(|route| (route.method, route.path, route.middlewares.join(",")))
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            ("GET".into(), "/health".into(), "options".into()),
+            ("GET".into(), "/users".into(), "".into()),
+            ("POST".into(), "/users".into(), "".into()),
+            ("GET".into(), "/admin/routes".into(), "".into()),
+        ]
+    );
+}
@@ -0,0 +1,265 @@
+//! Service serving its router with the middlewares of this crate, until it is shut down
+//!
+//! ```ignore
+//! let guard = LoggingBuilder::new(SERVICE).levels(config.log_levels).install()?;
+//! let router = Router::new()
+//!     .route("/orders", get(list_orders).post(create_order))
+//!     .route_layer(middleware::from_fn(auth));
+//! ServiceRunner::new(config.server, router)
+//!     .logging(guard)
+//!     .run()
+//!     .await
+//! ```
+
+use std::{net::SocketAddr, time::Duration};
+
+use anyhow::Context;
+use axum::{middleware::from_fn, Router};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    fallback_handlers, head_middleware, health::HealthCheckRegistry, options_middleware, shutdown,
+};
+
+/// Server section of the configuration
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ServerParams {
+    /// Address the service listens on (default: `0.0.0.0:8080`)
+    #[serde(default = "default_address")]
+    pub address: String,
+    /// Wait between the shutdown signal and the end of the accepted connections, see
+    /// [`shutdown_signal`](shutdown::shutdown_signal) (default: `5s`)
+    #[serde(default = "default_drain", with = "crate::config::duration")]
+    pub drain: Duration,
+    #[cfg(feature = "tracing")]
+    #[serde(default)]
+    pub access_log: super::tracing_access_log::AccessLogConfig,
+}
+
+fn default_address() -> String {
+    "0.0.0.0:8080".to_string()
+}
+
+fn default_drain() -> Duration {
+    Duration::from_secs(5)
+}
+
+impl Default for ServerParams {
+    fn default() -> Self {
+        Self {
+            address: default_address(),
+            drain: default_drain(),
+            #[cfg(feature = "tracing")]
+            access_log: Default::default(),
+        }
+    }
+}
+
+/// Runs the router of a service, see [`ServiceRunner::router`] and [`ServiceRunner::run`]
+#[must_use = "the runner does nothing until run"]
+pub struct ServiceRunner {
+    params: ServerParams,
+    router: Router,
+    health: HealthCheckRegistry,
+    health_routes: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Option<super::metrics::MetricsLayer>,
+    #[cfg(feature = "metrics")]
+    metrics_route: bool,
+    #[cfg(feature = "logging")]
+    logging: Option<crate::logging::LoggingGuard>,
+}
+
+impl ServiceRunner {
+    /// Runner of `router`, which has the routes of the service with their authentication
+    pub fn new(params: ServerParams, router: Router) -> Self {
+        Self {
+            params,
+            router,
+            health: HealthCheckRegistry::new(),
+            health_routes: true,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            #[cfg(feature = "metrics")]
+            metrics_route: true,
+            #[cfg(feature = "logging")]
+            logging: None,
+        }
+    }
+
+    /// Checks of the readiness probe, none by default
+    pub fn health(mut self, health: HealthCheckRegistry) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Serves the probes at `/health/live` and `/health/ready` (default: true). Disabled when
+    /// the router of the service has these routes, which would conflict.
+    pub fn health_routes(mut self, enabled: bool) -> Self {
+        self.health_routes = enabled;
+        self
+    }
+
+    /// Serves the metrics at `/metrics` (default: true). Disabled when the router of the
+    /// service has this route, which would conflict.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_route(mut self, enabled: bool) -> Self {
+        self.metrics_route = enabled;
+        self
+    }
+
+    /// HTTP metrics layer, eg. with per route metrics, instead of the
+    /// [default one](super::metrics::metrics_middleware)
+    #[cfg(feature = "metrics")]
+    pub fn metrics(mut self, layer: super::metrics::MetricsLayer) -> Self {
+        self.metrics = Some(layer);
+        self
+    }
+
+    /// Guard of the logging, [shut down](crate::logging::LoggingGuard::shutdown) once the
+    /// service stopped so the last logs are written
+    #[cfg(feature = "logging")]
+    pub fn logging(mut self, guard: crate::logging::LoggingGuard) -> Self {
+        self.logging = Some(guard);
+        self
+    }
+
+    /// Router of the service with:
+    /// - `/health/live` and `/health/ready`, see [`HealthCheckRegistry::router`], unless
+    ///   [disabled](Self::health_routes)
+    /// - `/metrics`, with the metrics of the default registry (with the `metrics` feature),
+    ///   unless [disabled](Self::metrics_route)
+    /// - the `not_found` and `method_not_allowed` [problems](fallback_handlers)
+    /// - the answers to `OPTIONS` and `HEAD` requests
+    /// - the [HTTP metrics](Self::metrics) (with the `metrics` feature)
+    /// - the [access log](super::tracing_access_log::access_log_with) and the
+    ///   [request ids](super::request_ids_middleware) (with the `tracing` feature)
+    ///
+    /// Panics if the router of the service has the routes of the probes or the metrics, as
+    /// axum does on conflicting routes.
+    pub fn router(&self) -> Router {
+        let mut router = self.router.clone();
+        if self.health_routes {
+            router = router.merge(self.health.router());
+        }
+        #[cfg(feature = "metrics")]
+        if self.metrics_route {
+            router = router.route(
+                "/metrics",
+                axum::routing::get(|| async { crate::metrics::generate_metrics() }),
+            );
+        }
+        let router = fallback_handlers(router)
+            .layer(from_fn(head_middleware))
+            .layer(from_fn(options_middleware));
+        #[cfg(feature = "metrics")]
+        let router = match &self.metrics {
+            Some(layer) => router.layer(layer.clone()),
+            None => router.layer(from_fn(super::metrics::metrics_middleware)),
+        };
+        #[cfg(feature = "tracing")]
+        let router = router
+            .layer(axum::middleware::from_fn_with_state(
+                std::sync::Arc::new(self.params.access_log.clone()),
+                super::tracing_access_log::access_log_with,
+            ))
+            .layer(from_fn(super::request_ids_middleware));
+        router
+    }
+
+    /// Serves the [router](Self::router) on the configured address until a
+    /// [shutdown signal](shutdown::shutdown_signal), then waits for the accepted requests
+    pub async fn run(self) -> anyhow::Result<()> {
+        let listener = tokio::net::TcpListener::bind(&self.params.address)
+            .await
+            .with_context(|| format!("Cannot listen on {}", self.params.address))?;
+        log::info!("Listening on {}", self.params.address);
+        let app = self
+            .router()
+            .into_make_service_with_connect_info::<SocketAddr>();
+        let served = axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown::shutdown_signal(
+                self.params.drain,
+                self.health.clone(),
+            ))
+            .await
+            .context("Cannot serve the requests");
+        log::info!("Service stopped");
+        #[cfg(feature = "logging")]
+        if let Some(logging) = self.logging {
+            logging.shutdown().await;
+        }
+        served
+    }
+}
+
+/// Runs `router` with the configured `params`, see [`ServiceRunner`]
+pub async fn run_service(params: ServerParams, router: Router) -> anyhow::Result<()> {
+    ServiceRunner::new(params, router).run().await
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn serves_the_probes() {
+    use axum::{body::Body, routing::get};
+    use http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let params: ServerParams = serde_yaml::from_str("drain: 10s").unwrap();
+    assert_eq!(params.drain, Duration::from_secs(10));
+    let runner = ServiceRunner::new(
+        params,
+        Router::new().route("/orders", get(|| async { "[]" })),
+    );
+    // the default registry is shared with the other tests
+    #[cfg(feature = "metrics")]
+    let runner = runner.metrics(
+        super::metrics::MetricsLayerBuilder::new()
+            .registry(prometheus::Registry::new())
+            .build()
+            .unwrap(),
+    );
+    let router = runner.router();
+    let call = |method: &str, path: &str| {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        router.clone().oneshot(req)
+    };
+    assert_eq!(
+        call("GET", "/orders").await.unwrap().status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        call("GET", "/health/live").await.unwrap().status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        call("GET", "/users").await.unwrap().status(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        call("DELETE", "/orders").await.unwrap().status(),
+        StatusCode::METHOD_NOT_ALLOWED
+    );
+
+    // the routes of the service replace the disabled ones
+    let runner = ServiceRunner::new(
+        ServerParams::default(),
+        Router::new().route("/health/live", get(|| async { "alive" })),
+    )
+    .health_routes(false);
+    #[cfg(feature = "metrics")]
+    let runner = runner.metrics_route(false).metrics(
+        super::metrics::MetricsLayerBuilder::new()
+            .registry(prometheus::Registry::new())
+            .build()
+            .unwrap(),
+    );
+    let req = Request::get("/health/live").body(Body::empty()).unwrap();
+    let resp = runner.router().oneshot(req).await.unwrap();
+    let body = axum::body::to_bytes(resp.into_body(), 64).await.unwrap();
+    assert_eq!(&body[..], b"alive");
+}
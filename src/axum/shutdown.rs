@@ -0,0 +1,102 @@
+//! Graceful shutdown on `SIGTERM` and `SIGINT`
+//!
+//! ```ignore
+//! let listener = TcpListener::bind("0.0.0.0:8080").await?;
+//! axum::serve(listener, router.merge(health.router()))
+//!     .with_graceful_shutdown(shutdown::shutdown_signal(Duration::from_secs(10), health))
+//!     .await?;
+//! logging_guard.shutdown().await;
+//! ```
+
+use std::{future::Future, time::Duration};
+
+use super::health::HealthCheckRegistry;
+
+/// Resolves once the service must stop accepting requests, for
+/// `axum::serve(..).with_graceful_shutdown(..)`: on `SIGTERM` or `SIGINT`, the readiness probe
+/// of the [router](HealthCheckRegistry::router) of `health` fails, then the future resolves
+/// after `drain`, leaving Kubernetes the time to stop routing new requests to the service. The
+/// in-flight requests are then completed by axum. A second signal skips the drain.
+pub async fn shutdown_signal(drain: Duration, health: HealthCheckRegistry) {
+    drain_on(next_signal(), next_signal(), drain, || health.shut_down()).await;
+}
+
+/// Waits for `signal`, calls `shut_down`, then waits for `drain` or `interrupt`
+async fn drain_on(
+    signal: impl Future<Output = &'static str>,
+    interrupt: impl Future<Output = &'static str>,
+    drain: Duration,
+    shut_down: impl FnOnce(),
+) {
+    let name = signal.await;
+    log::info!("{name} received, draining the requests for {drain:?} before shutting down");
+    shut_down();
+    tokio::select! {
+        _ = tokio::time::sleep(drain) => log::info!("Shutting down"),
+        name = interrupt => log::info!("{name} received, shutting down without draining"),
+    }
+}
+
+/// Name of the next `SIGTERM` or `SIGINT` received
+async fn next_signal() -> &'static str {
+    let interrupt = async {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => "SIGINT",
+            Err(err) => {
+                log::warn!("Cannot listen to SIGINT: {err}");
+                std::future::pending().await
+            }
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+                "SIGTERM"
+            }
+            Err(err) => {
+                log::warn!("Cannot listen to SIGTERM: {err}");
+                std::future::pending().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending();
+    tokio::select! {
+        name = interrupt => name,
+        name = terminate => name,
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn drains_after_the_signal() {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Instant,
+    };
+
+    let shut_down = AtomicBool::new(false);
+    let start = Instant::now();
+    let drain = Duration::from_millis(50);
+    drain_on(async { "SIGTERM" }, std::future::pending(), drain, || {
+        shut_down.store(true, Ordering::Relaxed)
+    })
+    .await;
+    assert!(shut_down.load(Ordering::Relaxed));
+    assert!(start.elapsed() >= drain);
+
+    let interrupted = tokio::time::timeout(
+        Duration::from_secs(1),
+        drain_on(
+            async { "SIGTERM" },
+            async { "SIGINT" },
+            Duration::from_secs(3600),
+            || (),
+        ),
+    )
+    .await;
+    assert!(interrupted.is_ok());
+}
@@ -1,70 +1,543 @@
-use std::{net::SocketAddr, time::Instant};
+use std::{net::SocketAddr, sync::Arc, time::Instant};
 
 use axum::{
-    extract::{ConnectInfo, Request},
+    body::Body,
+    extract::{ConnectInfo, MatchedPath, Request, State},
     middleware::Next,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
-use data_encoding::BASE64URL_NOPAD;
 use futures::FutureExt;
-use tracing::{error_span, Instrument, Level};
+use http::{header, HeaderMap, HeaderName, HeaderValue, Uri};
+use serde::{Deserialize, Serialize};
+use tracing::{error_span, field::Empty, Instrument, Level, Span};
+
+use super::body::{known_size, ObservedBody};
+use crate::{
+    excluded_paths::ExcludedPaths,
+    ids::REQUEST_ID_HEADER,
+    redact::{is_sensitive_key, redact_url, REDACTED},
+};
+
+/// Headers always redacted from the access log
+const REDACTED_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+/// Configuration of the access log, see [`access_log_with`]. The optional fields of
+/// [`AccessLogRecord`] are only logged when enabled.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessLogConfig {
+    /// Paths of the requests which are not logged, see [`ExcludedPaths`] (default: `/metrics`
+    /// and `/health`)
+    #[serde(default)]
+    pub excluded_paths: ExcludedPaths,
+    /// Logs the excluded requests in Debug instead of ignoring them
+    #[serde(default)]
+    pub log_excluded: bool,
+    /// Logs the query string, in `url.query`, sensitive parameters being redacted
+    #[serde(default)]
+    pub query: bool,
+    /// Logs the `User-Agent` header, in `user_agent.original`
+    #[serde(default)]
+    pub user_agent: bool,
+    /// Logs the `Referer` header, in `http.request.referrer`, its credentials and sensitive
+    /// parameters being redacted
+    #[serde(default)]
+    pub referer: bool,
+    /// Request headers logged in the `http.request.headers` field of the `request` span, like
+    /// `user-agent`
+    #[serde(default)]
+    pub headers: Vec<String>,
+    /// Headers whose values are replaced by `***`, in addition to `Authorization`,
+    /// `Proxy-Authorization`, `Cookie`, `Set-Cookie` and the headers named like secrets
+    /// (`x-api-key`, `x-auth-token`...)
+    #[serde(default)]
+    pub redacted_headers: Vec<String>,
+}
+
+impl AccessLogConfig {
+    /// Whether the values of the header `name` are redacted
+    pub fn is_redacted(&self, name: &str) -> bool {
+        REDACTED_HEADERS
+            .iter()
+            .any(|header| header.eq_ignore_ascii_case(name))
+            || self
+                .redacted_headers
+                .iter()
+                .any(|header| header.eq_ignore_ascii_case(name))
+            || is_sensitive_key(name)
+    }
+
+    /// Logged headers of the request, redacted, like `user-agent: curl/8.5.0; authorization: ***`
+    fn logged_headers(&self, headers: &HeaderMap) -> Option<String> {
+        let logged: Vec<String> = self
+            .headers
+            .iter()
+            .flat_map(|name| {
+                headers.get_all(name.as_str()).iter().map(move |value| {
+                    let value = if self.is_redacted(name) {
+                        REDACTED.into()
+                    } else {
+                        String::from_utf8_lossy(value.as_bytes())
+                    };
+                    format!("{}: {value}", name.to_ascii_lowercase())
+                })
+            })
+            .collect();
+        (!logged.is_empty()).then(|| logged.join("; "))
+    }
+
+    /// Logged query string, with the values of the sensitive parameters redacted
+    fn logged_query(&self, uri: &Uri) -> Option<String> {
+        let query = uri.query().filter(|_| self.query)?;
+        Some(redact_url(&format!("?{query}"))[1..].to_string())
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    let value = headers.get(name)?;
+    Some(String::from_utf8_lossy(value.as_bytes()).into_owned())
+}
 
 /// Logs every request to `access_log` target in Info.
 ///
 /// Also setup a tracing span with:
-/// - `tx_id` an id for the current request
+/// - `tx_id` an id for the current request, the request id of its
+///   [`RequestIds`](crate::ids::RequestIds) (see [`request_ids`](super::request_ids)). The ids
+///   are set in the request extensions, and the request id is returned in the `x-request-id`
+///   header of the response, as done by [`request_ids_middleware`](super::request_ids_middleware)
 /// - `method`
 /// - `path`
+/// - `route` the matched route template, like `/users/:id`, when the middleware is added with
+///   [`Router::layer`](axum::Router::layer)
 /// - `remote_ip` if the service has a ConnectInfo<RemoteAddr> in a request extention
+///
+/// The final event has the size of the response body: from its `Content-Length` or, for the
+/// streamed bodies, counted while sending them, the event being emitted once sent.
+///
+/// Field names are described by [`AccessLogRecord`]. See [`access_log_with`] to log more
+/// fields.
 pub async fn access_log(req: Request, next: Next) -> impl IntoResponse {
-    // do not record metrics on /metrics nor /health endpoint
+    log_request(&AccessLogConfig::default(), req, next).await
+}
+
+/// Same as [`access_log`], with a configuration:
+///
+/// ```ignore
+/// let config = AccessLogConfig {
+///     query: true,
+///     headers: vec!["x-tenant".to_string(), "x-api-key".to_string()],
+///     ..Default::default()
+/// };
+/// let router = router.layer(middleware::from_fn_with_state(Arc::new(config), access_log_with));
+/// ```
+pub async fn access_log_with(
+    State(config): State<Arc<AccessLogConfig>>,
+    req: Request,
+    next: Next,
+) -> impl IntoResponse {
+    log_request(&config, req, next).await
+}
+
+async fn log_request(config: &AccessLogConfig, mut req: Request, next: Next) -> Response {
     let path = req.uri().path().to_string();
-    let log = path != "/metrics" && path != "/health";
+    let excluded = config.excluded_paths.is_excluded(&path);
     let start = Instant::now();
-    let method = req.method().clone();
 
-    let tx_id = BASE64URL_NOPAD.encode(uuid::Uuid::new_v4().as_bytes());
+    #[allow(unused_mut)]
+    let mut ids = super::request_ids(&req);
 
-    let remote_addr = req.extensions().get::<ConnectInfo<SocketAddr>>();
-
-    let span = match remote_addr {
-        Some(ConnectInfo(remote_addr)) => error_span!(
-            "request",
-            tx = tx_id,
-            method = method.to_string(),
-            path = path,
-            remote_ip = format!("{}", remote_addr.ip()),
-        ),
-        None => error_span!(
-            "request",
-            tx = tx_id,
-            method = method.to_string(),
-            path = path,
-        ),
+    let mut record = AccessLogRecord {
+        tx: ids.tx_id(),
+        method: req.method().to_string(),
+        path,
+        route: req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|route| route.as_str().to_string()),
+        remote_ip: req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(remote_addr)| remote_addr.ip().to_string()),
+        query: config.logged_query(req.uri()),
+        user_agent: header_value(req.headers(), header::USER_AGENT).filter(|_| config.user_agent),
+        referer: header_value(req.headers(), header::REFERER)
+            .filter(|_| config.referer)
+            .map(|referer| redact_url(&referer)),
+        headers: config.logged_headers(req.headers()),
+        user_id: None,
+        client_id: None,
+        duration_ms: 0,
+        status_code: 0,
+        response_size: None,
     };
-    if log {
+    let log_excluded = config.log_excluded;
+
+    let span = record.span();
+    #[cfg(feature = "otlp")]
+    if let Some(trace_id) = crate::otlp::set_remote_parent(&span, req.headers()) {
+        ids = ids.with_trace_id(uuid::Uuid::from_u128(trace_id));
+    }
+    req.extensions_mut().insert(ids);
+    let body_span = span.clone();
+    if !excluded {
         let _enter = span.enter();
         tracing::debug!(
             target: "access_log",
-            "{method} {path} received",
+            "{} {} received",
+            record.method,
+            record.path,
         );
     }
 
     next.run(req)
-        .then(|r| async {
-            if log {
-                let elapsed = start.elapsed().as_millis();
-                let status = r.status().as_u16();
-                tracing::event!(
-                    target: "access_log",
-                    Level::INFO,
-                    transaction.duration_ms = elapsed,
-                    http.response.status_code = status,
-                    "{method} {path} {status} {elapsed}ms",
-                );
+        .then(|mut r| async move {
+            if let Ok(value) = HeaderValue::from_str(&ids.request_id()) {
+                r.headers_mut()
+                    .entry(HeaderName::from_static(REQUEST_ID_HEADER))
+                    .or_insert(value);
+            }
+            if !excluded || log_excluded {
+                record.duration_ms = start.elapsed().as_millis() as u64;
+                record.status_code = r.status().as_u16();
+                let (parts, body) = r.into_parts();
+                match known_size(&parts.headers, &body) {
+                    Some(size) => {
+                        record.response_size = Some(size);
+                        emit(&record, excluded);
+                        r = Response::from_parts(parts, body);
+                    }
+                    // streamed body: logged once sent
+                    None => {
+                        let body = ObservedBody::new(body, move |bytes| {
+                            record.response_size = Some(bytes);
+                            body_span.in_scope(|| emit(&record, excluded));
+                        });
+                        r = Response::from_parts(parts, Body::new(body));
+                    }
+                }
             }
             r
         })
         .instrument(span)
         .await
 }
+
+fn emit(record: &AccessLogRecord, excluded: bool) {
+    if excluded {
+        record.emit_debug();
+    } else {
+        record.emit();
+    }
+}
+
+/// Records the authenticated subject of the request, like the `sub` and `client_id` claims of
+/// its token, in the `request` span of the access log.
+///
+/// Call it from the authentication middleware, added inside the access log: it must run in
+/// the `request` span, not in a span of its own.
+pub fn record_subject(user_id: &str, client_id: Option<&str>) {
+    let span = Span::current();
+    span.record("user.id", user_id);
+    if let Some(client_id) = client_id {
+        span.record("client_id", client_id);
+    }
+}
+
+/// Stable schema of the access log.
+///
+/// The serialized field names of this struct are the names of the fields of the `request`
+/// span (`tx`, `method`, `path`, and the optional `route`, `remote_ip`, `url.query`,
+/// `user_agent.original`, `http.request.referrer`, `http.request.headers`, `user.id`,
+/// `client_id`) and of the final
+/// `access_log` event (`transaction.duration_ms`, `http.response.status_code`,
+/// `http.response.body.bytes`). Log extractors rely on them: they must not change
+/// without a major version bump.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AccessLogRecord {
+    pub tx: String,
+    pub method: String,
+    pub path: String,
+    /// Route template matched by axum, like `/users/:id`, missing for the unknown routes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_ip: Option<String>,
+    #[serde(rename = "url.query", default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    #[serde(
+        rename = "user_agent.original",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub user_agent: Option<String>,
+    #[serde(
+        rename = "http.request.referrer",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub referer: Option<String>,
+    /// Logged request headers, see [`AccessLogConfig`]
+    #[serde(
+        rename = "http.request.headers",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub headers: Option<String>,
+    /// Authenticated subject, like the `sub` claim of a token, see [`record_subject`]
+    #[serde(rename = "user.id", default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    /// OAuth client of the authenticated request, see [`record_subject`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(rename = "transaction.duration_ms")]
+    pub duration_ms: u64,
+    #[serde(rename = "http.response.status_code")]
+    pub status_code: u16,
+    /// Size of the response body
+    #[serde(
+        rename = "http.response.body.bytes",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub response_size: Option<u64>,
+}
+
+/// The level of an event must be a constant
+macro_rules! emit_event {
+    ($record:expr, $level:expr) => {
+        tracing::event!(
+            target: "access_log",
+            $level,
+            transaction.duration_ms = $record.duration_ms,
+            http.response.status_code = $record.status_code,
+            http.response.body.bytes = $record.response_size,
+            "{}",
+            $record.message(),
+        )
+    };
+}
+
+impl AccessLogRecord {
+    /// The `request` span, with the request fields
+    pub fn span(&self) -> Span {
+        let span = error_span!(
+            "request",
+            tx = self.tx,
+            method = self.method,
+            path = self.path,
+            route = Empty,
+            remote_ip = Empty,
+            url.query = Empty,
+            user_agent.original = Empty,
+            http.request.referrer = Empty,
+            http.request.headers = Empty,
+            user.id = Empty,
+            client_id = Empty,
+        );
+        let optional = [
+            ("route", &self.route),
+            ("remote_ip", &self.remote_ip),
+            ("url.query", &self.query),
+            ("user_agent.original", &self.user_agent),
+            ("http.request.referrer", &self.referer),
+            ("http.request.headers", &self.headers),
+            ("user.id", &self.user_id),
+            ("client_id", &self.client_id),
+        ];
+        for (field, value) in optional {
+            if let Some(value) = value {
+                span.record(field, value.as_str());
+            }
+        }
+        span
+    }
+
+    /// Message of the final event, eg. `GET /users 200 12ms`
+    pub fn message(&self) -> String {
+        format!(
+            "{} {} {} {}ms",
+            self.method, self.path, self.status_code, self.duration_ms
+        )
+    }
+
+    /// Emits the final `access_log` event, with the response fields. Should be called in the
+    /// `request` span.
+    pub fn emit(&self) {
+        emit_event!(self, Level::INFO);
+    }
+
+    /// Same as [`emit`](Self::emit) in Debug, for the excluded requests
+    pub fn emit_debug(&self) {
+        emit_event!(self, Level::DEBUG);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> AccessLogRecord {
+        AccessLogRecord {
+            tx: "AYzbC2yXcNKhrA5Zr3NHfQ".to_string(),
+            method: "GET".to_string(),
+            path: "/users/42".to_string(),
+            route: None,
+            remote_ip: Some("10.0.0.1".to_string()),
+            query: None,
+            user_agent: None,
+            referer: None,
+            headers: None,
+            user_id: None,
+            client_id: None,
+            duration_ms: 12,
+            status_code: 200,
+            response_size: None,
+        }
+    }
+
+    /// Changing this golden value breaks log extractors
+    const GOLDEN: &str = r#"{"tx":"AYzbC2yXcNKhrA5Zr3NHfQ","method":"GET","path":"/users/42","remote_ip":"10.0.0.1","transaction.duration_ms":12,"http.response.status_code":200}"#;
+
+    #[test]
+    fn serialized_field_names_are_stable() {
+        assert_eq!(serde_json::to_string(&record()).unwrap(), GOLDEN);
+        assert_eq!(
+            serde_json::from_str::<AccessLogRecord>(GOLDEN).unwrap(),
+            record()
+        );
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn emitted_fields_match_the_schema() {
+        use std::collections::BTreeSet;
+
+        let capture = crate::testing::TracingCapture::new();
+        let _guard = capture.set_default();
+        let record = record();
+        record.span().in_scope(|| record.emit());
+
+        let event = capture.events_for_target("access_log").remove(0);
+        let mut emitted: BTreeSet<String> = event.fields.keys().cloned().collect();
+        emitted.extend(event.spans[0].fields.keys().cloned());
+        emitted.remove("message");
+
+        let serde_json::Value::Object(schema) = serde_json::to_value(&record).unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(emitted, schema.keys().cloned().collect());
+        assert_eq!(event.message(), Some("GET /users/42 200 12ms"));
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn counts_streamed_response_bytes() {
+        use axum::{middleware::from_fn, routing::get, Router};
+        use tower::ServiceExt;
+
+        let capture = crate::testing::TracingCapture::new();
+        let _guard = capture.set_default();
+        let chunks = || async {
+            let chunks = [Ok::<_, std::io::Error>("ab"), Ok("cde")];
+            Body::from_stream(futures::stream::iter(chunks))
+        };
+        let app = Router::new()
+            .route("/exports/:id", get(chunks))
+            .layer(from_fn(access_log));
+        let req = Request::builder()
+            .uri("/exports/7")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        // only the `received` event
+        assert_eq!(capture.events_for_target("access_log").len(), 1);
+        axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let events = capture.events_for_target("access_log");
+        assert_eq!(events[1].field("http.response.body.bytes"), Some("5"));
+        assert!(events[1].in_span("request"));
+        assert_eq!(events[1].span_field("route"), Some("/exports/:id"));
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn records_the_subject() {
+        use axum::{middleware::from_fn, routing::get, Router};
+        use tower::ServiceExt;
+
+        let capture = crate::testing::TracingCapture::new();
+        let _guard = capture.set_default();
+        let authenticate = |req: Request, next: Next| async move {
+            record_subject("user-42", Some("mobile-app"));
+            next.run(req).await
+        };
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(from_fn(authenticate))
+            .layer(from_fn(access_log));
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        app.oneshot(req).await.unwrap();
+
+        let event = capture.events_for_target("access_log").pop().unwrap();
+        assert_eq!(event.span_field("user.id"), Some("user-42"));
+        assert_eq!(event.span_field("client_id"), Some("mobile-app"));
+    }
+
+    #[test]
+    fn excludes_path_prefixes() {
+        let config = AccessLogConfig::default();
+        assert!(config.excluded_paths.is_excluded("/health"));
+        assert!(config.excluded_paths.is_excluded("/health/ready"));
+        assert!(!config.excluded_paths.is_excluded("/healthy"));
+        let config: AccessLogConfig = serde_yaml::from_str("excluded_paths: [/ping/]").unwrap();
+        assert!(config.excluded_paths.is_excluded("/ping"));
+        assert!(config.excluded_paths.is_excluded("/ping/db"));
+        assert!(!config.excluded_paths.is_excluded("/pingpong"));
+        assert!(!config.excluded_paths.is_excluded("/metrics"));
+        let config: AccessLogConfig =
+            serde_yaml::from_str("excluded_paths: { exact: [/favicon.ico] }").unwrap();
+        assert!(config.excluded_paths.is_excluded("/favicon.ico"));
+        assert!(config.excluded_paths.is_excluded("/health/live"));
+    }
+
+    #[test]
+    fn redacts_headers() {
+        let config = AccessLogConfig {
+            headers: vec![
+                "User-Agent".to_string(),
+                "Authorization".to_string(),
+                "X-Api-Key".to_string(),
+                "X-Tenant".to_string(),
+            ],
+            redacted_headers: vec!["x-tenant".to_string()],
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("user-agent", HeaderValue::from_static("curl/8.5.0"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer abc"));
+        headers.insert("x-api-key", HeaderValue::from_static("k3y"));
+        headers.insert("x-tenant", HeaderValue::from_static("acme"));
+        assert_eq!(
+            config.logged_headers(&headers).unwrap(),
+            "user-agent: curl/8.5.0; authorization: ***; x-api-key: ***; x-tenant: ***"
+        );
+        assert_eq!(AccessLogConfig::default().logged_headers(&headers), None);
+    }
+
+    #[test]
+    fn redacts_query() {
+        let config = AccessLogConfig {
+            query: true,
+            ..Default::default()
+        };
+        let uri: Uri = "/orders?page=2&token=s3cret".parse().unwrap();
+        assert_eq!(config.logged_query(&uri).unwrap(), "page=2&token=***");
+        assert_eq!(AccessLogConfig::default().logged_query(&uri), None);
+    }
+}
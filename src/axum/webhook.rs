@@ -0,0 +1,373 @@
+//! Signature verification of incoming webhooks
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use hmac::{Hmac, Mac};
+use http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::problem::{ErrorCode, Problem};
+use crate::redact::REDACTED;
+
+#[cfg(feature = "metrics")]
+use crate::metrics::record_webhook_verification_failure;
+#[cfg(not(feature = "metrics"))]
+fn record_webhook_verification_failure(_provider: &str, _reason: &str) {}
+
+const GITHUB_SIGNATURE_HEADER: &str = "x-hub-signature-256";
+const STRIPE_SIGNATURE_HEADER: &str = "stripe-signature";
+
+/// How a provider signs its webhooks
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureScheme {
+    /// `X-Hub-Signature-256: sha256=<hex>`, HMAC-SHA256 of the body
+    Github,
+    /// `Stripe-Signature: t=<timestamp>,v1=<hex>`, HMAC-SHA256 of `<timestamp>.<body>`
+    Stripe,
+}
+
+/// Webhook configuration of a provider. The secret is redacted from the `Debug` output.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct WebhookConfig {
+    pub scheme: SignatureScheme,
+    pub secret: String,
+    /// Maximum age of a signed timestamp, in seconds (default: 300)
+    #[serde(default = "default_tolerance_seconds")]
+    pub tolerance_seconds: u64,
+    /// Larger bodies are rejected (default: 1MiB)
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+}
+
+fn default_tolerance_seconds() -> u64 {
+    300
+}
+
+fn default_max_body_bytes() -> usize {
+    1024 * 1024
+}
+
+impl fmt::Debug for WebhookConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebhookConfig")
+            .field("scheme", &self.scheme)
+            .field("secret", &REDACTED)
+            .field("tolerance_seconds", &self.tolerance_seconds)
+            .field("max_body_bytes", &self.max_body_bytes)
+            .finish()
+    }
+}
+
+/// Webhook configurations by provider name, typically a `webhooks` section of the service
+/// configuration:
+///
+/// ```yaml
+/// webhooks:
+///   github:
+///     scheme: github
+///     secret: ${GITHUB_WEBHOOK_SECRET}
+///   stripe:
+///     scheme: stripe
+///     secret: ${STRIPE_WEBHOOK_SECRET}
+/// ```
+pub type WebhooksConfig = BTreeMap<String, WebhookConfig>;
+
+/// Reason of a webhook rejection
+#[derive(Debug, thiserror::Error, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookError {
+    #[error("missing webhook signature")]
+    MissingSignature,
+    #[error("malformed webhook signature")]
+    MalformedSignature,
+    #[error("invalid webhook signature")]
+    InvalidSignature,
+    #[error("webhook timestamp out of tolerance")]
+    Expired,
+    #[error("webhook body too large")]
+    BodyTooLarge,
+    #[error("webhook body cannot be read")]
+    UnreadableBody,
+}
+
+impl WebhookError {
+    /// `reason` label of `webhook_verification_failures_total`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            WebhookError::MissingSignature => "missing_signature",
+            WebhookError::MalformedSignature => "malformed_signature",
+            WebhookError::InvalidSignature => "invalid_signature",
+            WebhookError::Expired => "expired",
+            WebhookError::BodyTooLarge => "body_too_large",
+            WebhookError::UnreadableBody => "unreadable_body",
+        }
+    }
+}
+
+/// Verifies the signatures of the webhooks of a provider
+///
+/// ```ignore
+/// let github = WebhookVerifier::from_config(&config.webhooks, "github")?;
+/// let app = Router::new()
+///     .route("/webhooks/github", post(on_github_event))
+///     .layer(middleware::from_fn_with_state(github, verify_webhook));
+/// ```
+#[derive(Clone, Debug)]
+pub struct WebhookVerifier {
+    provider: Arc<str>,
+    config: Arc<WebhookConfig>,
+}
+
+impl WebhookVerifier {
+    pub fn new(provider: &str, config: WebhookConfig) -> Self {
+        Self {
+            provider: provider.into(),
+            config: Arc::new(config),
+        }
+    }
+
+    /// Verifier of `provider`, failing if it is not configured
+    pub fn from_config(config: &WebhooksConfig, provider: &str) -> anyhow::Result<Self> {
+        let config = config
+            .get(provider)
+            .with_context(|| format!("No webhook configuration for {provider}"))?;
+        Ok(Self::new(provider, config.clone()))
+    }
+
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    /// Verifies the signature of `body`, given the request `headers`
+    pub fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), WebhookError> {
+        match self.config.scheme {
+            SignatureScheme::Github => {
+                let signature = header(headers, GITHUB_SIGNATURE_HEADER)?
+                    .strip_prefix("sha256=")
+                    .ok_or(WebhookError::MalformedSignature)?;
+                self.check(&[body], signature)
+            }
+            SignatureScheme::Stripe => {
+                let header = header(headers, STRIPE_SIGNATURE_HEADER)?;
+                let mut timestamp = None;
+                let mut signatures = vec![];
+                for (key, value) in header.split(',').filter_map(|kv| kv.split_once('=')) {
+                    match key.trim() {
+                        "t" => timestamp = Some(value),
+                        "v1" => signatures.push(value),
+                        _ => {}
+                    }
+                }
+                let timestamp = timestamp.ok_or(WebhookError::MalformedSignature)?;
+                let signed_at: u64 = timestamp
+                    .parse()
+                    .map_err(|_| WebhookError::MalformedSignature)?;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                if now.abs_diff(signed_at) > self.config.tolerance_seconds {
+                    return Err(WebhookError::Expired);
+                }
+                if signatures.is_empty() {
+                    return Err(WebhookError::MissingSignature);
+                }
+                // several signatures are sent while the secret is rolled
+                let parts = [timestamp.as_bytes(), b".", body];
+                signatures
+                    .into_iter()
+                    .map(|signature| self.check(&parts, signature))
+                    .find(Result::is_ok)
+                    .unwrap_or(Err(WebhookError::InvalidSignature))
+            }
+        }
+    }
+
+    /// Compares, in constant time, the hex `signature` to the HMAC of `parts`
+    fn check(&self, parts: &[&[u8]], signature: &str) -> Result<(), WebhookError> {
+        let signature = hex::decode(signature).map_err(|_| WebhookError::MalformedSignature)?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.config.secret.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        for part in parts {
+            mac.update(part);
+        }
+        mac.verify_slice(&signature)
+            .map_err(|_| WebhookError::InvalidSignature)
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str, WebhookError> {
+    headers
+        .get(name)
+        .ok_or(WebhookError::MissingSignature)?
+        .to_str()
+        .map_err(|_| WebhookError::MalformedSignature)
+}
+
+/// Middleware rejecting the webhooks without a valid signature with a `403` problem, before
+/// the handler deserializes them. The body is buffered to be verified, then passed on to the
+/// handler: a body larger than `max_body_bytes` is rejected with a `413` problem, a body that
+/// cannot be read with a `400` problem.
+///
+/// With the `metrics` feature, rejections are counted in
+/// `webhook_verification_failures_total{provider,reason}`.
+pub async fn verify_webhook(
+    State(verifier): State<WebhookVerifier>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = req.into_parts();
+    let verified = read_body(body, verifier.config.max_body_bytes)
+        .await
+        .and_then(|body| verifier.verify(&parts.headers, &body).map(|_| body));
+    match verified {
+        Ok(body) => next.run(Request::from_parts(parts, Body::from(body))).await,
+        Err(err) => {
+            log::warn!("Webhook from {} rejected: {err}", verifier.provider);
+            record_webhook_verification_failure(&verifier.provider, err.reason());
+            let code = match err {
+                WebhookError::BodyTooLarge => ErrorCode::PayloadTooLarge,
+                WebhookError::UnreadableBody => ErrorCode::BadRequest,
+                _ => ErrorCode::Forbidden,
+            };
+            Problem::new(code)
+                .with_detail(err.to_string())
+                .into_response()
+        }
+    }
+}
+
+/// Buffers a body of at most `limit` bytes
+async fn read_body(body: Body, limit: usize) -> Result<Bytes, WebhookError> {
+    let mut stream = body.into_data_stream();
+    let mut buffer = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|_| WebhookError::UnreadableBody)?;
+        if buffer.len() + chunk.len() > limit {
+            return Err(WebhookError::BodyTooLarge);
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok(buffer.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware::from_fn_with_state, routing::post, Router};
+    use http::StatusCode;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn sign(secret: &str, payload: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(payload);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn app(scheme: SignatureScheme) -> Router {
+        let verifier = WebhookVerifier::new(
+            "provider",
+            WebhookConfig {
+                scheme,
+                secret: "s3cr3t".to_string(),
+                tolerance_seconds: 300,
+                max_body_bytes: 1024,
+            },
+        );
+        Router::new()
+            .route("/hook", post(|body: String| async move { body }))
+            .layer(from_fn_with_state(verifier, verify_webhook))
+    }
+
+    async fn call(app: Router, header: (&str, String)) -> StatusCode {
+        send(app, header, Body::from(r#"{"event":"push"}"#)).await
+    }
+
+    async fn send(app: Router, header: (&str, String), body: Body) -> StatusCode {
+        let req = Request::post("/hook")
+            .header(header.0, header.1)
+            .body(body)
+            .unwrap();
+        app.oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn rejects_unreadable_bodies() {
+        let header = || (GITHUB_SIGNATURE_HEADER, "sha256=00".to_string());
+        let large = Body::from(vec![b'a'; 2048]);
+        assert_eq!(
+            send(app(SignatureScheme::Github), header(), large).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        let broken = Body::from_stream(futures::stream::once(async {
+            Err::<Bytes, _>(std::io::Error::other("reset"))
+        }));
+        assert_eq!(
+            send(app(SignatureScheme::Github), header(), broken).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn verifies_signatures() {
+        let body = br#"{"event":"push"}"#;
+        let github = format!("sha256={}", sign("s3cr3t", body));
+        assert_eq!(
+            call(
+                app(SignatureScheme::Github),
+                (GITHUB_SIGNATURE_HEADER, github)
+            )
+            .await,
+            StatusCode::OK
+        );
+        let forged = format!("sha256={}", sign("guess", body));
+        assert_eq!(
+            call(
+                app(SignatureScheme::Github),
+                (GITHUB_SIGNATURE_HEADER, forged)
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let stripe = |t: u64| {
+            let payload = [t.to_string().as_bytes(), b".", body].concat();
+            format!("t={t},v1={}", sign("s3cr3t", &payload))
+        };
+        assert_eq!(
+            call(
+                app(SignatureScheme::Stripe),
+                (STRIPE_SIGNATURE_HEADER, stripe(now))
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(
+                app(SignatureScheme::Stripe),
+                (STRIPE_SIGNATURE_HEADER, stripe(now - 600))
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Command line overrides of the configuration
+
+use serde::de::DeserializeOwned;
+
+use super::{read_source, value, LoadConfigMode, Source};
+use crate::ServiceDef;
+
+/// Configuration flags, to flatten in the command line arguments of the service:
+///
+/// ```ignore
+/// #[derive(clap::Parser)]
+/// struct Cli {
+///     #[command(flatten)]
+///     config: ConfigArgs,
+/// }
+///
+/// let cli = Cli::parse();
+/// let config: Config = load_config_with_args(LoadConfigMode::FileOnly(None), &cli.config, &SERVICE)?;
+/// ```
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct ConfigArgs {
+    /// Configuration file
+    #[arg(long = "config", value_name = "FILE")]
+    pub config: Option<String>,
+    /// Overrides a configuration value, eg. `--set db.pool_size=20`
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_override)]
+    pub overrides: Vec<(String, String)>,
+}
+
+fn parse_override(raw: &str) -> Result<(String, String), String> {
+    match raw.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got `{raw}`")),
+    }
+}
+
+/// Loads the configuration (see [`load_config`](super::load_config)) from the `--config` file if
+/// given (in the modes reading a file), then applies the `--set` overrides.
+///
+/// Overridden keys are `.` separated paths (`db.pool_size`), values are parsed as YAML scalars.
+/// In the `EnvOnly` and `EnvWithPrefix` modes, `--set db_pool_size=20` overrides the
+/// `DB_POOL_SIZE` variable (without prefix).
+pub fn load_config_with_args<C: DeserializeOwned>(
+    config_mode: LoadConfigMode,
+    args: &ConfigArgs,
+    service_def: &ServiceDef,
+) -> anyhow::Result<C> {
+    let config_mode = match &args.config {
+        Some(file) if config_mode.file().is_some() => config_mode.with_file(Some(file)),
+        _ => config_mode,
+    };
+    let mut source = read_source(config_mode, None, service_def)?;
+    apply_overrides(&mut source, &args.overrides);
+    source.deserialize()
+}
+
+fn apply_overrides(source: &mut Source, overrides: &[(String, String)]) {
+    for (key, raw) in overrides {
+        match source {
+            Source::Env { vars, .. } => {
+                vars.insert(key.to_uppercase(), raw.clone());
+            }
+            Source::Document(doc) => {
+                let path: Vec<String> = key.split('.').map(str::to_string).collect();
+                value::set_path(doc, &path, value::parse_scalar(raw));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn overrides_from_command_line() {
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        config: ConfigArgs,
+    }
+
+    let cli = Cli::parse_from([
+        "service",
+        "--config",
+        "/etc/service/other.yaml",
+        "--set",
+        "db.pool_size=20",
+        "--set",
+        "name=orders",
+    ]);
+    assert_eq!(
+        cli.config.config.as_deref(),
+        Some("/etc/service/other.yaml")
+    );
+
+    let mut source = Source::Document(serde_yaml::from_str("db:\n  pool_size: 4").unwrap());
+    apply_overrides(&mut source, &cli.config.overrides);
+    let Source::Document(doc) = source else {
+        unreachable!()
+    };
+    assert_eq!(doc["db"]["pool_size"], 20);
+    assert_eq!(doc["name"], "orders");
+
+    assert!(Cli::try_parse_from(["service", "--set", "oops"]).is_err());
+}
@@ -0,0 +1,275 @@
+//! Deserialization of configurations reporting all the missing and invalid fields at once.
+//!
+//! Deserializers stop at the first error: it is recorded, the faulty field is replaced by a
+//! placeholder of a type the configuration accepts, and deserialization is run again until
+//! it succeeds or no placeholder fits.
+
+use std::collections::BTreeMap;
+
+use serde::de::DeserializeOwned;
+use serde_path_to_error::Segment;
+use serde_yaml::{Mapping, Value};
+
+use super::{value::parse_scalar, ValidationErrors};
+
+/// Maximum number of errors reported
+const MAX_ERRORS: usize = 64;
+
+/// Placeholders tried in place of a faulty field of a document
+fn placeholders() -> [Value; 7] {
+    [
+        Value::Number(0.into()),
+        Value::String(String::new()),
+        // durations, see `units`
+        Value::String("0s".to_string()),
+        Value::Bool(false),
+        Value::Sequence(vec![]),
+        Value::Mapping(Mapping::new()),
+        Value::Null,
+    ]
+}
+
+/// Placeholders tried in place of a faulty environment variable
+const ENV_PLACEHOLDERS: [&str; 4] = ["0", "false", "0s", "x"];
+
+#[derive(Clone, PartialEq, Eq)]
+enum Key {
+    Field(String),
+    Index(usize),
+}
+
+/// Error of a field of a document
+#[derive(PartialEq, Eq)]
+struct FieldError {
+    field: String,
+    message: String,
+    /// Location of the field, if it can be replaced by a placeholder
+    keys: Option<Vec<Key>>,
+}
+
+fn try_document<C: DeserializeOwned>(doc: &Value) -> Result<C, FieldError> {
+    let text = serde_yaml::to_string(doc).map_err(|err| FieldError {
+        field: String::new(),
+        message: err.to_string(),
+        keys: None,
+    })?;
+    serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(&text)).map_err(
+        |err: serde_path_to_error::Error<serde_yaml::Error>| {
+            let mut message = err.inner().to_string();
+            // locations in the serialized document are meaningless
+            if let Some(at) = message.find(" at line ") {
+                message.truncate(at);
+            }
+            let mut field = err.path().to_string();
+            // serde_yaml prefixes the messages with the path of the field
+            if let Some(stripped) = message.strip_prefix(&format!("{field}: ")) {
+                message = stripped.to_string();
+            }
+            let mut keys: Option<Vec<Key>> = err
+                .path()
+                .iter()
+                .map(|segment| match segment {
+                    Segment::Map { key } => Some(Key::Field(key.clone())),
+                    Segment::Seq { index } => Some(Key::Index(*index)),
+                    Segment::Enum { .. } | Segment::Unknown => None,
+                })
+                .collect();
+            if let Some(missing) = message
+                .strip_prefix("missing field `")
+                .and_then(|rest| rest.strip_suffix('`'))
+            {
+                field = match field.as_str() {
+                    "." => missing.to_string(),
+                    parent => format!("{parent}.{missing}"),
+                };
+                if let Some(keys) = &mut keys {
+                    keys.push(Key::Field(missing.to_string()));
+                }
+                message = "missing field".to_string();
+            }
+            FieldError {
+                field,
+                message,
+                keys,
+            }
+        },
+    )
+}
+
+/// Value at `keys`, missing fields being added to their mapping
+fn value_at<'a>(doc: &'a mut Value, keys: &[Key]) -> Option<&'a mut Value> {
+    let Some((key, rest)) = keys.split_first() else {
+        return Some(doc);
+    };
+    let child = match (key, doc) {
+        (Key::Field(field), Value::Mapping(mapping)) => mapping
+            .entry(Value::String(field.clone()))
+            .or_insert(Value::Null),
+        (Key::Index(index), Value::Sequence(seq)) => seq.get_mut(*index)?,
+        _ => return None,
+    };
+    value_at(child, rest)
+}
+
+/// Document with the string at `keys` parsed as a number or a boolean, if it holds one: the
+/// strings (eg. of the environment overrides) are only parsed for the fields rejecting them
+fn coerced(doc: &Value, keys: &[Key]) -> Option<Value> {
+    let mut doc = doc.clone();
+    let value = value_at(&mut doc, keys)?;
+    let parsed = parse_scalar(value.as_str()?);
+    if parsed.is_string() {
+        return None;
+    }
+    *value = parsed;
+    Some(doc)
+}
+
+/// Deserializes a document, reporting all the missing and invalid fields
+pub(crate) fn from_document<C: DeserializeOwned>(doc: &Value) -> Result<C, ValidationErrors> {
+    let mut doc = doc.clone();
+    let mut errors = ValidationErrors::new();
+    let mut result = try_document(&doc);
+    while let Err(error) = result {
+        if let Some(patched) = error.keys.as_deref().and_then(|keys| coerced(&doc, keys)) {
+            doc = patched;
+            result = try_document(&doc);
+            continue;
+        }
+        let keys = error.keys.clone();
+        errors.add(&error.field, &error.message);
+        let next = keys
+            .filter(|_| errors.errors().len() < MAX_ERRORS)
+            .and_then(|keys| {
+                placeholders().into_iter().find_map(|placeholder| {
+                    let mut patched = doc.clone();
+                    *value_at(&mut patched, &keys)? = placeholder;
+                    let result = try_document::<C>(&patched);
+                    match &result {
+                        Err(next) if next.field == error.field => None,
+                        _ => Some((patched, result)),
+                    }
+                })
+            });
+        match next {
+            Some((patched, next)) => {
+                doc = patched;
+                result = next;
+            }
+            None => return Err(errors),
+        }
+    }
+    match result {
+        Ok(config) if errors.is_empty() => Ok(config),
+        _ => Err(errors),
+    }
+}
+
+/// Error of an environment variable: its name (as seen by `envy`) and message
+fn env_error(err: &envy::Error) -> (Option<String>, String, String) {
+    match err {
+        envy::Error::MissingValue(field) => (
+            Some(field.to_string()),
+            field.to_uppercase(),
+            "missing variable".to_string(),
+        ),
+        envy::Error::Custom(message) => match message.rsplit_once(" provided by ") {
+            Some((reason, var)) => (
+                Some(var.to_lowercase()),
+                var.to_uppercase(),
+                reason.to_string(),
+            ),
+            None => (None, String::new(), message.clone()),
+        },
+    }
+}
+
+/// Deserializes environment variables with `envy`, reporting all the missing and invalid
+/// variables
+pub(crate) fn from_env<C: DeserializeOwned>(
+    vars: &BTreeMap<String, String>,
+) -> Result<C, ValidationErrors> {
+    // envy matches the lowercased names with the fields
+    let mut vars: BTreeMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value.clone()))
+        .collect();
+    let mut errors = ValidationErrors::new();
+    let mut result = envy::from_iter::<_, C>(vars.clone());
+    while let Err(err) = result {
+        let (key, field, message) = env_error(&err);
+        errors.add(&field, message);
+        let next = key
+            .filter(|_| errors.errors().len() < MAX_ERRORS)
+            .and_then(|key| {
+                ENV_PLACEHOLDERS.iter().find_map(|placeholder| {
+                    let mut patched = vars.clone();
+                    patched.insert(key.clone(), placeholder.to_string());
+                    let result = envy::from_iter::<_, C>(patched.clone());
+                    match &result {
+                        Err(next) if env_error(next).1 == field => None,
+                        _ => Some((patched, result)),
+                    }
+                })
+            });
+        match next {
+            Some((patched, next)) => {
+                vars = patched;
+                result = next;
+            }
+            None => return Err(errors),
+        }
+    }
+    match result {
+        Ok(config) if errors.is_empty() => Ok(config),
+        _ => Err(errors),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Database {
+        url: String,
+        pool_size: u32,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Config {
+        port: u16,
+        debug: bool,
+        database: Database,
+    }
+
+    #[test]
+    fn reports_all_document_errors() {
+        let doc: Value = serde_yaml::from_str("port: http\ndatabase: { pool_size: -1 }").unwrap();
+        let errors = from_document::<Config>(&doc).unwrap_err();
+        let fields: Vec<_> = errors.errors().iter().map(|(f, _)| f.as_str()).collect();
+        assert_eq!(
+            fields,
+            ["port", "database.pool_size", "database.url", "debug"]
+        );
+    }
+
+    #[test]
+    fn reports_all_env_errors() {
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct EnvConfig {
+            port: u16,
+            debug: bool,
+            name: String,
+        }
+
+        let vars = BTreeMap::from([("PORT".to_string(), "http".to_string())]);
+        let errors = from_env::<EnvConfig>(&vars).unwrap_err();
+        let fields: Vec<_> = errors.errors().iter().map(|(f, _)| f.as_str()).collect();
+        assert_eq!(fields, ["PORT", "DEBUG", "NAME"]);
+    }
+}
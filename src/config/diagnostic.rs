@@ -0,0 +1,102 @@
+//! Readable reports of configuration errors
+
+use serde::de::DeserializeOwned;
+
+use super::{config_dir, config_path, env, load_config, LoadConfigMode};
+use crate::ServiceDef;
+
+/// Exit code of a service with an invalid configuration (`EX_CONFIG` of `sysexits.h`)
+pub const CONFIG_ERROR_EXIT_CODE: i32 = 78;
+
+/// Loads the configuration (see [`load_config`]), or prints a diagnostic of the error on
+/// stderr and exits with [`CONFIG_ERROR_EXIT_CODE`].
+///
+/// The diagnostic tells where the configuration was read from, and lists the error chain
+/// with one line per cause and per invalid field or variable.
+pub fn load_config_or_exit<C: DeserializeOwned>(
+    config_mode: LoadConfigMode,
+    service_def: &ServiceDef,
+) -> C {
+    match load_config(config_mode, service_def) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", diagnostic(&err, config_mode, service_def));
+            std::process::exit(CONFIG_ERROR_EXIT_CODE);
+        }
+    }
+}
+
+/// Where the configuration of `config_mode` is read from
+fn source(config_mode: LoadConfigMode, service_def: &ServiceDef) -> String {
+    let prefixed = |prefix: Option<&str>| {
+        let prefix = prefix
+            .map(str::to_string)
+            .unwrap_or_else(|| env::default_prefix(service_def.pkg_name));
+        format!("environment variables prefixed with {prefix}")
+    };
+    match config_mode {
+        LoadConfigMode::EnvOnly => "environment variables".to_string(),
+        LoadConfigMode::EnvWithPrefix(prefix) | LoadConfigMode::EnvNested(prefix) => {
+            prefixed(prefix)
+        }
+        LoadConfigMode::FileOnly(file) => {
+            format!("file {}", config_path(file, service_def).display())
+        }
+        LoadConfigMode::FileAndEnvFallback(file) => format!(
+            "file {}, or environment variables",
+            config_path(file, service_def).display()
+        ),
+        LoadConfigMode::FileWithEnvOverrides(file) => format!(
+            "file {}, with overrides by {}",
+            config_path(file, service_def).display(),
+            prefixed(None)
+        ),
+        LoadConfigMode::Directory(dir) => {
+            format!("directory {}", config_dir(dir, service_def).display())
+        }
+    }
+}
+
+/// Multi-line report of a configuration error
+fn diagnostic(
+    err: &anyhow::Error,
+    config_mode: LoadConfigMode,
+    service_def: &ServiceDef,
+) -> String {
+    let mut report = format!(
+        "Cannot load the configuration of {} {}\n  source: {}",
+        service_def.pkg_name,
+        service_def.version,
+        source(config_mode, service_def)
+    );
+    for (i, cause) in err.chain().enumerate() {
+        let label = if i == 0 { "error" } else { "caused by" };
+        let text = cause.to_string().replace('\n', "\n  ");
+        report.push_str(&format!("\n  {label}: {text}"));
+    }
+    report
+}
+
+#[cfg(test)]
+#[test]
+fn reports_fields_and_source() {
+    #[derive(serde::Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Config {
+        port: u16,
+        name: String,
+    }
+
+    let service = ServiceDef::new("orders-api", "1.2.0", "abc123");
+    let mode = LoadConfigMode::EnvWithPrefix(Some("DIAGNOSTIC_TEST_"));
+    let err = load_config::<Config>(mode, &service).unwrap_err();
+    assert_eq!(
+        diagnostic(&err, mode, &service),
+        "Cannot load the configuration of orders-api 1.2.0\n  \
+         source: environment variables prefixed with DIAGNOSTIC_TEST_\n  \
+         error: Cannot read configuration from environment variables\n  \
+         caused by: Invalid configuration:\n    \
+         - PORT: missing variable\n    \
+         - NAME: missing variable"
+    );
+}
@@ -0,0 +1,70 @@
+//! Configuration directories with a file per key, as mounted from Kubernetes ConfigMaps
+
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use serde_yaml::Value;
+
+use super::{
+    resolve_values,
+    value::{parse_scalar, set_path},
+};
+
+/// Reads the configuration document of a directory: each file name is a key, nested with `.`
+/// (`database.host`), and its contents the value.
+///
+/// Contents holding a YAML list or mapping are parsed as such, other contents as scalars (see
+/// [`parse_scalar`]), without their trailing newline. Hidden entries are skipped, such as the
+/// `..data` directory of ConfigMap volumes, as well as subdirectories. Placeholders are
+/// substituted as in configuration files.
+pub(crate) fn read_directory(dir: &Path) -> anyhow::Result<Value> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("Cannot load configuration directory {}", dir.display()))?;
+    let mut doc = Value::Mapping(Default::default());
+    for entry in entries {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        // ConfigMap keys are symbolic links to the files of the `..data` directory
+        if name.starts_with('.') || !path.is_file() {
+            continue;
+        }
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Cannot read configuration file {}", path.display()))?;
+        let value = parse_value(&contents);
+        let key: Vec<String> = name.split('.').map(str::to_string).collect();
+        set_path(&mut doc, &key, value);
+    }
+    resolve_values(&mut doc)?;
+    Ok(doc)
+}
+
+/// Value of a key stored alone (in a file, a KV store...): a YAML list or mapping, or a
+/// scalar, without its trailing newline
+pub(crate) fn parse_value(contents: &str) -> Value {
+    let contents = contents.strip_suffix('\n').unwrap_or(contents);
+    match serde_yaml::from_str::<Value>(contents) {
+        Ok(value @ (Value::Sequence(_) | Value::Mapping(_))) => value,
+        _ => parse_scalar(contents),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn reads_key_per_file() {
+    let dir = std::env::temp_dir().join(format!("config-dir-{}", std::process::id()));
+    fs::create_dir_all(dir.join("..data")).unwrap();
+    fs::write(dir.join("port"), "8080\n").unwrap();
+    fs::write(dir.join("database.host"), "db.local").unwrap();
+    fs::write(dir.join("brokers"), "- kafka-1\n- kafka-2\n").unwrap();
+    fs::write(dir.join("..data/ignored"), "true").unwrap();
+
+    let doc = read_directory(&dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let expected: Value = serde_yaml::from_str(
+        "port: 8080\ndatabase: { host: db.local }\nbrokers: [kafka-1, kafka-2]",
+    )
+    .unwrap();
+    assert_eq!(doc, expected);
+}
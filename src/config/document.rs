@@ -0,0 +1,112 @@
+//! Loaded configuration documents, deserialized by section
+
+use anyhow::{anyhow, Context};
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+
+use super::{collect, read_source, value, LoadConfigMode, Source};
+use crate::ServiceDef;
+
+/// Configuration loaded but not deserialized yet, so each module can pull its own section out
+/// of it:
+///
+/// ```ignore
+/// let config = load_config_document(LoadConfigMode::FileOnly(None), &SERVICE)?;
+/// let gelf: GelfParams = config.section("logging")?;
+/// let app: AppConfig = config.deserialize()?;
+/// ```
+///
+/// Environment variables are read as in [`LoadConfigMode::EnvNested`]: the `LOGGING__LEVEL`
+/// variable is the `level` field of the `logging` section. With [`LoadConfigMode::EnvOnly`],
+/// which reads the whole process environment, numeric keys with gaps (`FOO__1` without
+/// `FOO__0`) are kept as mapping keys instead of failing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigDocument(Value);
+
+impl ConfigDocument {
+    /// Deserializes the whole configuration
+    pub fn deserialize<C: DeserializeOwned>(&self) -> anyhow::Result<C> {
+        value::from_value(&self.0)
+    }
+
+    /// Deserializes the section at `path`, nested with `.` (`logging` or `logging.gelf`).
+    /// Fails if the section is missing.
+    pub fn section<C: DeserializeOwned>(&self, path: &str) -> anyhow::Result<C> {
+        self.optional_section(path)?
+            .ok_or_else(|| anyhow!("Missing configuration section `{path}`"))
+    }
+
+    /// Deserializes the section at `path` (see [`section`](Self::section)), `None` if it is
+    /// missing
+    pub fn optional_section<C: DeserializeOwned>(&self, path: &str) -> anyhow::Result<Option<C>> {
+        let key: Vec<String> = path.split('.').map(str::to_string).collect();
+        match value::get_path(&self.0, &key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(section) => collect::from_document(section)
+                .map(Some)
+                .with_context(|| format!("Cannot parse configuration section `{path}`")),
+        }
+    }
+
+    /// Document as a YAML value
+    pub fn as_value(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl TryFrom<Source> for ConfigDocument {
+    type Error = anyhow::Error;
+
+    fn try_from(source: Source) -> anyhow::Result<Self> {
+        match source {
+            // the whole process environment: unrelated variables must not fail
+            Source::Env { vars, .. } => Ok(ConfigDocument(value::lenient_nested_document(vars))),
+            Source::Document(doc) => Ok(ConfigDocument(doc)),
+        }
+    }
+}
+
+/// Loads the configuration (see [`load_config`](super::load_config)) as a document, to be
+/// deserialized by section
+pub fn load_config_document(
+    config_mode: LoadConfigMode,
+    service_def: &ServiceDef,
+) -> anyhow::Result<ConfigDocument> {
+    read_source(config_mode, None, service_def)?.try_into()
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[test]
+fn extracts_sections() {
+    #[derive(serde::Deserialize, Debug)]
+    struct Gelf {
+        host: String,
+        port: u16,
+    }
+    #[derive(serde::Deserialize)]
+    struct Logging {
+        level: String,
+    }
+
+    let fixture = crate::testing::ConfigFixture::new().file(
+        "config.yaml",
+        "port: 8080\nlogging:\n  level: info\n  gelf: { host: graylog, port: 12201 }",
+    );
+    let path = fixture.path("config.yaml");
+    let service = ServiceDef::new("section-test", "0.0.0", "0000000");
+    let config = load_config_document(LoadConfigMode::FileOnly(path.to_str()), &service).unwrap();
+
+    let gelf: Gelf = config.section("logging.gelf").unwrap();
+    assert_eq!((gelf.host.as_str(), gelf.port), ("graylog", 12201));
+    let logging: Logging = config.section("logging").unwrap();
+    assert_eq!(logging.level, "info");
+    assert!(config
+        .optional_section::<Gelf>("metrics")
+        .unwrap()
+        .is_none());
+    let err = config.section::<Gelf>("logging").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Cannot parse configuration section `logging`"
+    );
+}
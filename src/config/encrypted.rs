@@ -0,0 +1,164 @@
+//! Configuration values encrypted with [age](https://age-encryption.org)
+
+use std::io::{BufReader, Read};
+
+use age::{armor::ArmoredReader, Decryptor, Identity, IdentityFile};
+use anyhow::{anyhow, bail, Context};
+use data_encoding::BASE64;
+use serde_yaml::Value;
+
+/// Environment variable holding the age identities (`AGE-SECRET-KEY-1...`) decrypting the
+/// configuration values
+pub const AGE_KEY_VAR: &str = "CONFIG_AGE_KEY";
+/// Environment variable naming a file of age identities, as written by `age-keygen`
+pub const AGE_KEY_FILE_VAR: &str = "CONFIG_AGE_KEY_FILE";
+
+/// Prefix of the values holding base64 encoded age ciphertexts
+const AGE_PREFIX: &str = "age:";
+const ARMOR_BEGIN: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Decrypts the encrypted string values of `doc`: `age:` followed by a base64 encoded age
+/// ciphertext (`age -r ... | base64 -w0`), or an armored one (`age -a -r ...`) in a YAML
+/// block scalar.
+///
+/// The identities are read from [`AGE_KEY_VAR`], or the file named by [`AGE_KEY_FILE_VAR`],
+/// only if the document holds encrypted values. Decrypted values are kept as strings, parsed
+/// by serde as the fields expect. Fails listing all the values that cannot be decrypted.
+pub(crate) fn decrypt_values(doc: &mut Value) -> anyhow::Result<()> {
+    if !has_encrypted(doc) {
+        return Ok(());
+    }
+    let identities = identities()?;
+    let mut failed = vec![];
+    decrypt_in(doc, &mut vec![], &identities, &mut failed);
+    if !failed.is_empty() {
+        bail!(
+            "Cannot decrypt configuration values:\n  - {}",
+            failed.join("\n  - ")
+        );
+    }
+    Ok(())
+}
+
+fn is_encrypted(text: &str) -> bool {
+    text.starts_with(AGE_PREFIX) || text.trim_start().starts_with(ARMOR_BEGIN)
+}
+
+fn has_encrypted(doc: &Value) -> bool {
+    match doc {
+        Value::String(text) => is_encrypted(text),
+        Value::Sequence(seq) => seq.iter().any(has_encrypted),
+        Value::Mapping(mapping) => mapping.values().any(has_encrypted),
+        Value::Tagged(tagged) => has_encrypted(&tagged.value),
+        _ => false,
+    }
+}
+
+/// Decrypts the values in place, pushing the path and error of the failed ones to `failed`
+fn decrypt_in(
+    doc: &mut Value,
+    path: &mut Vec<String>,
+    identities: &[Box<dyn Identity>],
+    failed: &mut Vec<String>,
+) {
+    match doc {
+        Value::String(text) if is_encrypted(text) => match decrypt(text, identities) {
+            Ok(plaintext) => *text = plaintext,
+            Err(err) => failed.push(format!("{}: {err:#}", path.join("."))),
+        },
+        Value::Sequence(seq) => {
+            for (i, item) in seq.iter_mut().enumerate() {
+                path.push(i.to_string());
+                decrypt_in(item, path, identities, failed);
+                path.pop();
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (key, item) in mapping.iter_mut() {
+                path.push(key.as_str().map_or_else(
+                    || {
+                        serde_yaml::to_string(key)
+                            .unwrap_or_default()
+                            .trim()
+                            .to_string()
+                    },
+                    str::to_string,
+                ));
+                decrypt_in(item, path, identities, failed);
+                path.pop();
+            }
+        }
+        Value::Tagged(tagged) => decrypt_in(&mut tagged.value, path, identities, failed),
+        _ => {}
+    }
+}
+
+fn identities() -> anyhow::Result<Vec<Box<dyn Identity>>> {
+    let file = match (std::env::var(AGE_KEY_VAR), std::env::var(AGE_KEY_FILE_VAR)) {
+        (Ok(key), _) => IdentityFile::from_buffer(key.as_bytes())
+            .with_context(|| format!("Invalid age identity in {AGE_KEY_VAR}"))?,
+        (_, Ok(path)) => IdentityFile::from_file(path.clone())
+            .with_context(|| format!("Cannot read age identity file {path}"))?,
+        _ => bail!(
+            "The configuration holds encrypted values but neither {AGE_KEY_VAR} nor \
+             {AGE_KEY_FILE_VAR} is set"
+        ),
+    };
+    file.into_identities()
+        .map_err(|err| anyhow!("Invalid age identity: {err}"))
+}
+
+fn decrypt(text: &str, identities: &[Box<dyn Identity>]) -> anyhow::Result<String> {
+    let ciphertext = match text.strip_prefix(AGE_PREFIX) {
+        Some(encoded) => BASE64
+            .decode(encoded.trim().as_bytes())
+            .context("invalid base64")?,
+        None => text.trim().as_bytes().to_vec(),
+    };
+    let decryptor = Decryptor::new_buffered(BufReader::new(ArmoredReader::new(&ciphertext[..])))?;
+    let mut plaintext = String::new();
+    decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref()))?
+        .read_to_string(&mut plaintext)?;
+    Ok(plaintext)
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[test]
+fn decrypts_values() {
+    use age::{secrecy::ExposeSecret, x25519};
+
+    let identity = x25519::Identity::generate();
+    let encrypt = |plaintext: &str| {
+        let ciphertext = age::encrypt(&identity.to_public(), plaintext.as_bytes()).unwrap();
+        format!("{AGE_PREFIX}{}", BASE64.encode(&ciphertext))
+    };
+    let armored = age::encrypt_and_armor(&identity.to_public(), b"s3cret").unwrap();
+    let mut doc = Value::Mapping(Default::default());
+    doc["password"] = Value::String(encrypt("hunter2"));
+    doc["port"] = Value::String(encrypt("5432"));
+    doc["tokens"] = Value::Sequence(vec![Value::String(armored)]);
+    doc["user"] = Value::String("app".to_string());
+    let mut ports = serde_yaml::Mapping::new();
+    ports.insert(Value::from(5432), Value::String(encrypt("primary")));
+    doc["ports"] = Value::Mapping(ports);
+
+    let _fixture = crate::testing::ConfigFixture::new()
+        .remove_env(AGE_KEY_FILE_VAR)
+        .env(AGE_KEY_VAR, identity.to_string().expose_secret());
+    decrypt_values(&mut doc).unwrap();
+    let expected: Value = serde_yaml::from_str(
+        "password: hunter2\nport: '5432'\ntokens: [s3cret]\nuser: app\nports: { 5432: primary }",
+    )
+    .unwrap();
+    assert_eq!(doc, expected);
+
+    let other = x25519::Identity::generate();
+    let mut doc = Value::Mapping(Default::default());
+    doc["password"] = Value::String(format!(
+        "{AGE_PREFIX}{}",
+        BASE64.encode(&age::encrypt(&other.to_public(), b"hunter2").unwrap())
+    ));
+    let err = decrypt_values(&mut doc).unwrap_err().to_string();
+    assert!(err.starts_with("Cannot decrypt configuration values:\n  - password: "));
+}
@@ -0,0 +1,123 @@
+//! Environment variables read by the configuration
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+/// Suffix of the variables holding the path of a secret file
+const FILE_SUFFIX: &str = "_FILE";
+
+/// Environment variables of the process, with the secret files (see [`resolve_files`]) of the
+/// service: only the variables starting with `prefix` name secret files, `{prefix}FOO_FILE`
+/// defines `FOO`
+pub(crate) fn vars(prefix: &str) -> anyhow::Result<BTreeMap<String, String>> {
+    with_secret_files(std::env::vars(), prefix)
+}
+
+/// Environment variables starting with `prefix`, without it, with their secret files resolved
+pub(crate) fn prefixed_vars(prefix: &str) -> anyhow::Result<BTreeMap<String, String>> {
+    resolve_files(strip_prefix(std::env::vars().collect(), prefix))
+}
+
+fn with_secret_files<I>(vars: I, prefix: &str) -> anyhow::Result<BTreeMap<String, String>>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut vars: BTreeMap<String, String> = vars.into_iter().collect();
+    let prefixed = strip_prefix(vars.clone(), prefix);
+    let secrets: Vec<(String, String)> = resolve_files(prefixed.clone())?
+        .into_iter()
+        .filter(|(name, _)| prefixed.contains_key(&format!("{name}{FILE_SUFFIX}")))
+        .collect();
+    for (name, value) in secrets {
+        vars.entry(name).or_insert(value);
+    }
+    Ok(vars)
+}
+
+/// Prefix of the variables of a package: `MY_SERVICE_` for `my-service`
+pub(crate) fn default_prefix(pkg_name: &str) -> String {
+    format!("{}_", pkg_name.to_uppercase().replace(['-', '.'], "_"))
+}
+
+/// Variables starting with `prefix`, without it
+pub(crate) fn strip_prefix(
+    vars: BTreeMap<String, String>,
+    prefix: &str,
+) -> BTreeMap<String, String> {
+    vars.into_iter()
+        .filter_map(|(name, value)| Some((name.strip_prefix(prefix)?.to_string(), value)))
+        .filter(|(name, _)| !name.is_empty())
+        .collect()
+}
+
+/// Resolves the Docker/Kubernetes secret files convention: a `FOO_FILE=/run/secrets/foo`
+/// variable defines `FOO` with the contents of the file, without its trailing newline.
+///
+/// A `FOO` variable defined along `FOO_FILE` takes precedence. Fails if a file cannot be read.
+pub(crate) fn resolve_files<I>(vars: I) -> anyhow::Result<BTreeMap<String, String>>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut resolved: BTreeMap<String, String> = vars.into_iter().collect();
+    let files: Vec<(String, String)> = resolved
+        .iter()
+        .filter_map(|(name, path)| {
+            let name = name.strip_suffix(FILE_SUFFIX)?;
+            (!name.is_empty() && !path.is_empty() && !resolved.contains_key(name))
+                .then(|| (name.to_string(), path.clone()))
+        })
+        .collect();
+    for (name, path) in files {
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Cannot read {name}{FILE_SUFFIX} secret file {path}"))?;
+        let value = contents
+            .strip_suffix('\n')
+            .map(|value| value.strip_suffix('\r').unwrap_or(value))
+            .unwrap_or(&contents);
+        resolved.insert(name, value.to_string());
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+#[test]
+fn secret_files() {
+    let path = std::env::temp_dir().join(format!("service-helpers-secret-{}", std::process::id()));
+    std::fs::write(&path, "p4ssw0rd\n").unwrap();
+    let var = |name: &str, value: &str| (name.to_string(), value.to_string());
+
+    let resolved = resolve_files([
+        var("DB_PASSWORD_FILE", path.to_str().unwrap()),
+        var("DB_USER", "app"),
+    ])
+    .unwrap();
+    assert_eq!(resolved["DB_PASSWORD"], "p4ssw0rd");
+    assert_eq!(resolved["DB_USER"], "app");
+
+    let explicit = resolve_files([
+        var("DB_PASSWORD_FILE", path.to_str().unwrap()),
+        var("DB_PASSWORD", "plain"),
+    ])
+    .unwrap();
+    assert_eq!(explicit["DB_PASSWORD"], "plain");
+    assert!(resolve_files([var("DB_PASSWORD_FILE", "/nonexistent")]).is_err());
+
+    let prefix = default_prefix("orders-api");
+    assert_eq!(prefix, "ORDERS_API_");
+    let vars = [
+        var("ORDERS_API_DB_PASSWORD_FILE", path.to_str().unwrap()),
+        var("ORDERS_API_PORT", "8080"),
+        var("PORT", "9090"),
+        // the secret files of other programs are not read
+        var("KUBE_TOKEN_FILE", "/nonexistent"),
+    ];
+    let stripped = resolve_files(strip_prefix(vars.clone().into(), &prefix)).unwrap();
+    assert_eq!(stripped["DB_PASSWORD"], "p4ssw0rd");
+    assert_eq!(stripped["PORT"], "8080");
+    let unprefixed = with_secret_files(vars, &prefix).unwrap();
+    assert_eq!(unprefixed["DB_PASSWORD"], "p4ssw0rd");
+    assert_eq!(unprefixed["PORT"], "9090");
+    assert!(!unprefixed.contains_key("KUBE_TOKEN"));
+    std::fs::remove_file(path).unwrap();
+}
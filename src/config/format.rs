@@ -0,0 +1,65 @@
+use std::{io::Read, path::Path};
+
+use anyhow::Context;
+use serde_yaml::Value;
+
+/// Format of a configuration file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    /// Requires the `toml` feature
+    Toml,
+    /// Requires the `json` feature
+    Json,
+}
+
+impl ConfigFormat {
+    /// Format of a file by its extension: `.toml`, `.json`, YAML otherwise
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+
+    /// Parses a document in this format
+    pub(crate) fn parse(self, reader: impl Read) -> anyhow::Result<Value> {
+        match self {
+            ConfigFormat::Yaml => {
+                Ok(serde_yaml::from_reader(reader).context("Cannot parse YAML configuration")?)
+            }
+            #[cfg(feature = "toml")]
+            ConfigFormat::Toml => {
+                let mut reader = reader;
+                let mut text = String::new();
+                reader.read_to_string(&mut text)?;
+                Ok(toml::from_str(&text).context("Cannot parse TOML configuration")?)
+            }
+            #[cfg(not(feature = "toml"))]
+            ConfigFormat::Toml => anyhow::bail!("TOML configuration requires the `toml` feature"),
+            #[cfg(feature = "json")]
+            ConfigFormat::Json => {
+                Ok(serde_json::from_reader(reader).context("Cannot parse JSON configuration")?)
+            }
+            #[cfg(not(feature = "json"))]
+            ConfigFormat::Json => anyhow::bail!("JSON configuration requires the `json` feature"),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "toml", feature = "json"))]
+#[test]
+fn formats_parse_to_the_same_document() {
+    let yaml = ConfigFormat::Yaml
+        .parse("port: 8080\ndatabase:\n  url: db\n".as_bytes())
+        .unwrap();
+    let toml = ConfigFormat::Toml
+        .parse("port = 8080\n[database]\nurl = \"db\"\n".as_bytes())
+        .unwrap();
+    let json = ConfigFormat::Json
+        .parse(r#"{"port": 8080, "database": {"url": "db"}}"#.as_bytes())
+        .unwrap();
+    assert_eq!(yaml, toml);
+    assert_eq!(yaml, json);
+}
@@ -0,0 +1,137 @@
+//! Configuration files split across several files
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use serde_yaml::{Mapping, Value};
+
+use super::{format::ConfigFormat, value::merge};
+
+/// Key listing the files included by a configuration file
+const INCLUDE_KEY: &str = "include";
+/// YAML tag replaced by the included file
+const INCLUDE_TAG: &str = "include";
+
+/// Resolves the includes of the document read from `path`, recursively:
+/// - the top level `include` key lists files merged under the document, in order: the values
+///   of the including file win
+/// - in YAML files, `!include other.yaml` values are replaced by the included document
+///
+/// Paths are relative to the including file. Included files are parsed according to their
+/// extension, and include cycles are errors.
+pub(crate) fn resolve_includes(doc: &mut Value, path: &Path) -> anyhow::Result<()> {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    resolve(doc, path, &mut vec![canonical])
+}
+
+fn resolve(doc: &mut Value, path: &Path, stack: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    if let Some(includes) = doc
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove(INCLUDE_KEY))
+    {
+        let files = match includes {
+            Value::String(file) => vec![file],
+            Value::Sequence(files) => files
+                .into_iter()
+                .map(|file| match file {
+                    Value::String(file) => Ok(file),
+                    _ => bail!(
+                        "Invalid `include` in {}: expected file names",
+                        path.display()
+                    ),
+                })
+                .collect::<anyhow::Result<_>>()?,
+            _ => bail!(
+                "Invalid `include` in {}: expected file names",
+                path.display()
+            ),
+        };
+        let mut merged = Value::Mapping(Mapping::new());
+        for file in files {
+            merge(&mut merged, load(&dir.join(file), stack)?);
+        }
+        merge(&mut merged, std::mem::take(doc));
+        *doc = merged;
+    }
+    replace_tags(doc, dir, stack)
+}
+
+fn replace_tags(doc: &mut Value, dir: &Path, stack: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    match doc {
+        Value::Tagged(tagged) if tagged.tag == INCLUDE_TAG => {
+            let Value::String(file) = &tagged.value else {
+                bail!("Invalid !include: expected a file name");
+            };
+            *doc = load(&dir.join(file), stack)?;
+        }
+        Value::Tagged(tagged) => replace_tags(&mut tagged.value, dir, stack)?,
+        Value::Sequence(seq) => {
+            for value in seq {
+                replace_tags(value, dir, stack)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (_, value) in mapping.iter_mut() {
+                replace_tags(value, dir, stack)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn load(path: &Path, stack: &mut Vec<PathBuf>) -> anyhow::Result<Value> {
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("Cannot load included configuration file {}", path.display()))?;
+    if stack.contains(&canonical) {
+        let cycle: Vec<_> = stack
+            .iter()
+            .chain([&canonical])
+            .map(|path| path.display().to_string())
+            .collect();
+        bail!("Configuration include cycle: {}", cycle.join(" -> "));
+    }
+    let file = File::open(&canonical)
+        .with_context(|| format!("Cannot load included configuration file {}", path.display()))?;
+    let mut doc = ConfigFormat::from_path(path)
+        .parse(file)
+        .with_context(|| format!("Cannot parse configuration file {}", path.display()))?;
+    stack.push(canonical);
+    resolve(&mut doc, path, stack)?;
+    stack.pop();
+    Ok(doc)
+}
+
+#[cfg(test)]
+#[test]
+fn includes_files() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("config-include-{}", std::process::id()));
+    fs::create_dir_all(dir.join("parts")).unwrap();
+    fs::write(dir.join("parts/base.yaml"), "port: 80\nlog: info\n").unwrap();
+    fs::write(dir.join("parts/kafka.yaml"), "brokers: [kafka-1]\n").unwrap();
+    fs::write(dir.join("parts/loop.yaml"), "include: ../config.yaml\n").unwrap();
+    let config = dir.join("config.yaml");
+    fs::write(
+        &config,
+        "include: [parts/base.yaml]\nport: 8080\nkafka: !include parts/kafka.yaml\n",
+    )
+    .unwrap();
+
+    let mut doc: Value = serde_yaml::from_str(&fs::read_to_string(&config).unwrap()).unwrap();
+    resolve_includes(&mut doc, &config).unwrap();
+    let expected: Value =
+        serde_yaml::from_str("port: 8080\nlog: info\nkafka: { brokers: [kafka-1] }").unwrap();
+    assert_eq!(doc, expected);
+
+    let mut doc: Value = serde_yaml::from_str("include: parts/loop.yaml").unwrap();
+    let err = resolve_includes(&mut doc, &config).unwrap_err();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(err.to_string().contains("include cycle"), "{err}");
+}
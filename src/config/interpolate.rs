@@ -0,0 +1,103 @@
+//! `${VAR}` placeholders in configuration values
+
+use anyhow::bail;
+use serde_yaml::Value;
+
+/// Substitutes the `${VAR}` and `${VAR:-default}` placeholders of the string values of `doc`
+/// with `lookup(VAR)`. `$${` is an escaped `${`.
+///
+/// Substituted values are strings, parsed by serde as the fields expect: `port: ${PORT}` is
+/// read as a number by a `u16` field and `0x1F` is kept as is by a `String` one. Fails
+/// listing all the missing variables without default.
+pub(crate) fn interpolate<F>(doc: &mut Value, lookup: &F) -> anyhow::Result<()>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut missing = vec![];
+    walk(doc, lookup, &mut missing);
+    if !missing.is_empty() {
+        missing.sort();
+        missing.dedup();
+        bail!(
+            "Missing environment variables in configuration: {}",
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn walk<F>(doc: &mut Value, lookup: &F, missing: &mut Vec<String>)
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match doc {
+        Value::String(s) if s.contains('$') => *s = substitute(s, lookup, missing),
+        Value::Sequence(seq) => seq.iter_mut().for_each(|v| walk(v, lookup, missing)),
+        Value::Mapping(mapping) => mapping.values_mut().for_each(|v| walk(v, lookup, missing)),
+        Value::Tagged(tagged) => walk(&mut tagged.value, lookup, missing),
+        _ => {}
+    }
+}
+
+fn substitute<F>(s: &str, lookup: &F, missing: &mut Vec<String>) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start..];
+        if let Some(escaped) = after.strip_prefix("$${") {
+            out.push_str("${");
+            rest = escaped;
+        } else if let Some(placeholder) = after.strip_prefix("${") {
+            let Some(end) = placeholder.find('}') else {
+                out.push_str(after);
+                return out;
+            };
+            let expr = &placeholder[..end];
+            let (name, default) = match expr.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (expr, None),
+            };
+            match (lookup(name), default) {
+                (Some(value), _) => out.push_str(&value),
+                (None, Some(default)) => out.push_str(default),
+                (None, None) => missing.push(name.to_string()),
+            }
+            rest = &placeholder[end + 1..];
+        } else {
+            out.push('$');
+            rest = &after[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+#[test]
+fn placeholders() {
+    let lookup = |name: &str| match name {
+        "HOST" => Some("db".to_string()),
+        "PORT" => Some("5432".to_string()),
+        _ => None,
+    };
+    let mut doc: Value = serde_yaml::from_str(
+        "url: postgres://${HOST}:${PORT}/app\nport: ${PORT}\npool: ${POOL:-4}\nraw: $${HOST} costs $5",
+    )
+    .unwrap();
+    interpolate(&mut doc, &lookup).unwrap();
+    assert_eq!(doc["url"], "postgres://db:5432/app");
+    assert_eq!(doc["port"], "5432");
+    assert_eq!(doc["pool"], "4");
+    assert_eq!(doc["raw"], "${HOST} costs $5");
+
+    let mut doc: Value = serde_yaml::from_str("a: ${MISSING_B}\nb: ${MISSING_A}").unwrap();
+    let err = interpolate(&mut doc, &lookup).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Missing environment variables in configuration: MISSING_A, MISSING_B"
+    );
+}
@@ -0,0 +1,277 @@
+//! Configuration read from a Consul or etcd key-value store
+
+use std::{fmt, time::Duration};
+
+use anyhow::{bail, Context};
+use data_encoding::BASE64;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use serde_yaml::{Mapping, Value};
+
+use super::{directory::parse_value, resolve_values, value};
+use crate::redact::REDACTED;
+
+/// Kind of key-value store
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KvStore {
+    /// Consul KV, through the HTTP API
+    Consul,
+    /// etcd v3, through the JSON gateway
+    Etcd,
+}
+
+/// Key-value store and prefix of the configuration keys. The token is redacted from the
+/// `Debug` output.
+#[derive(Clone)]
+pub struct KvConfig {
+    pub store: KvStore,
+    /// Address of the store, like `http://consul.service:8500`
+    pub addr: String,
+    /// Prefix of the keys, like `config/orders-api/`
+    pub prefix: String,
+    /// ACL token (Consul) or bearer token (etcd), if any
+    pub token: Option<String>,
+    pub timeout: Duration,
+}
+
+impl KvConfig {
+    pub fn consul(addr: impl Into<String>, prefix: impl Into<String>) -> Self {
+        Self::new(KvStore::Consul, addr.into(), prefix.into())
+    }
+
+    pub fn etcd(addr: impl Into<String>, prefix: impl Into<String>) -> Self {
+        Self::new(KvStore::Etcd, addr.into(), prefix.into())
+    }
+
+    fn new(store: KvStore, addr: String, prefix: String) -> Self {
+        Self {
+            store,
+            addr,
+            prefix,
+            token: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl fmt::Debug for KvConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KvConfig")
+            .field("store", &self.store)
+            .field("addr", &self.addr)
+            .field("prefix", &self.prefix)
+            .field("token", &self.token.as_ref().map(|_| REDACTED))
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// Loads the configuration from the keys under the prefix of a key-value store: each key is
+/// a field, nested with `/` (`config/orders-api/database/host` is the `host` field of
+/// `database` with the `config/orders-api/` prefix), and its value is parsed as in
+/// [`LoadConfigMode::Directory`](super::LoadConfigMode::Directory).
+///
+/// Placeholders are substituted as in the configuration files.
+pub async fn load_config_from_kv<C: DeserializeOwned>(kv: &KvConfig) -> anyhow::Result<C> {
+    let client = reqwest::Client::builder().timeout(kv.timeout).build()?;
+    let entries = match kv.store {
+        KvStore::Consul => consul_entries(&client, kv).await,
+        KvStore::Etcd => etcd_entries(&client, kv).await,
+    }
+    .with_context(|| format!("Cannot read configuration from {}", kv.addr))?;
+
+    let mut doc = Value::Mapping(Mapping::new());
+    for (key, contents) in entries {
+        let Some(key) = key.strip_prefix(&kv.prefix) else {
+            continue;
+        };
+        let path: Vec<String> = key
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string)
+            .collect();
+        // folders
+        if path.is_empty() || key.ends_with('/') {
+            continue;
+        }
+        value::set_path(&mut doc, &path, parse_value(&contents));
+    }
+    resolve_values(&mut doc)?;
+    value::from_value(&doc)
+}
+
+fn decode(encoded: &str) -> anyhow::Result<String> {
+    Ok(String::from_utf8(BASE64.decode(encoded.as_bytes())?)?)
+}
+
+async fn consul_entries(
+    client: &reqwest::Client,
+    kv: &KvConfig,
+) -> anyhow::Result<Vec<(String, String)>> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Entry {
+        key: String,
+        value: Option<String>,
+    }
+
+    let url = format!(
+        "{}/v1/kv/{}?recurse=true",
+        kv.addr.trim_end_matches('/'),
+        kv.prefix
+    );
+    let mut req = client.get(url);
+    if let Some(token) = &kv.token {
+        req = req.header("X-Consul-Token", token);
+    }
+    let resp = req.send().await?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        bail!("No key under {}", kv.prefix);
+    }
+    let entries: Vec<Entry> = resp.error_for_status()?.json().await?;
+    entries
+        .into_iter()
+        .map(|entry| Ok((entry.key, decode(&entry.value.unwrap_or_default())?)))
+        .collect()
+}
+
+async fn etcd_entries(
+    client: &reqwest::Client,
+    kv: &KvConfig,
+) -> anyhow::Result<Vec<(String, String)>> {
+    #[derive(Deserialize)]
+    struct Range {
+        #[serde(default)]
+        kvs: Vec<Entry>,
+    }
+    #[derive(Deserialize)]
+    struct Entry {
+        key: String,
+        #[serde(default)]
+        value: String,
+    }
+
+    // the range of the keys starting with the prefix ends with the prefix, last byte + 1
+    let mut range_end = kv.prefix.as_bytes().to_vec();
+    match range_end.last_mut() {
+        Some(last) if *last < 0xff => *last += 1,
+        _ => bail!("Invalid etcd prefix {}", kv.prefix),
+    }
+    let url = format!("{}/v3/kv/range", kv.addr.trim_end_matches('/'));
+    let mut req = client.post(url).json(&json!({
+        "key": BASE64.encode(kv.prefix.as_bytes()),
+        "range_end": BASE64.encode(&range_end),
+    }));
+    if let Some(token) = &kv.token {
+        req = req.bearer_auth(token);
+    }
+    let range: Range = req.send().await?.error_for_status()?.json().await?;
+    range
+        .kvs
+        .into_iter()
+        .map(|entry| Ok((decode(&entry.key)?, decode(&entry.value)?)))
+        .collect()
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[tokio::test]
+async fn reads_consul_keys() {
+    use http::Method;
+
+    use crate::testing::{FakeResponse, FakeUpstream};
+
+    #[derive(Deserialize)]
+    struct Config {
+        port: u16,
+        database: Database,
+    }
+    #[derive(Deserialize)]
+    struct Database {
+        host: String,
+    }
+
+    let consul = FakeUpstream::start().await;
+    let entry = |key: &str, value: Option<&str>| {
+        let value = value.map(|value| BASE64.encode(value.as_bytes()));
+        json!({ "Key": key, "Value": value })
+    };
+    let entries = json!([
+        entry("config/orders-api/", None),
+        entry("config/orders-api/port", Some("8080")),
+        entry("config/orders-api/database/host", Some("db.local")),
+    ]);
+    consul.on(
+        Method::GET,
+        "/v1/kv/config/orders-api/",
+        FakeResponse::json(entries.to_string()),
+    );
+
+    let kv = KvConfig::consul(consul.url(""), "config/orders-api/").token("t0ken");
+    assert!(!format!("{kv:?}").contains("t0ken"));
+    let config: Config = load_config_from_kv(&kv).await.unwrap();
+    assert_eq!(config.port, 8080);
+    assert_eq!(config.database.host, "db.local");
+    assert_eq!(consul.calls()[0].headers["x-consul-token"], "t0ken");
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[tokio::test]
+async fn reads_etcd_keys() {
+    use http::Method;
+
+    use crate::testing::{FakeResponse, FakeUpstream};
+
+    #[derive(Deserialize, Debug)]
+    struct Config {
+        port: u16,
+        database: Database,
+    }
+    #[derive(Deserialize, Debug)]
+    struct Database {
+        host: String,
+    }
+
+    let etcd = FakeUpstream::start().await;
+    let entry = |key: &str, value: &str| json!({ "key": BASE64.encode(key.as_bytes()), "value": BASE64.encode(value.as_bytes()) });
+    let range = json!({
+        "kvs": [
+            entry("config/orders-api/port", "8080"),
+            entry("config/orders-api/database/host", "db.local"),
+        ]
+    });
+    etcd.on(
+        Method::POST,
+        "/v3/kv/range",
+        FakeResponse::json(range.to_string()),
+    );
+
+    let kv = KvConfig::etcd(etcd.url(""), "config/orders-api/").token("t0ken");
+    let config: Config = load_config_from_kv(&kv).await.unwrap();
+    assert_eq!(config.port, 8080);
+    assert_eq!(config.database.host, "db.local");
+    let call = &etcd.calls()[0];
+    assert_eq!(call.headers["authorization"], "Bearer t0ken");
+    let body: serde_json::Value = serde_json::from_slice(&call.body).unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "key": BASE64.encode(b"config/orders-api/"),
+            "range_end": BASE64.encode(b"config/orders-api0"),
+        })
+    );
+
+    // no key under the prefix
+    etcd.on(Method::POST, "/v3/kv/range", FakeResponse::json("{}"));
+    let error = load_config_from_kv::<Config>(&kv).await.unwrap_err();
+    assert!(format!("{error:#}").contains("port: missing field"));
+}
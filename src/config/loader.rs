@@ -0,0 +1,356 @@
+//! Builder of configuration loaders, combining the sources of [`LoadConfigMode`]
+
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+#[cfg(feature = "dotenv")]
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde_yaml::{Mapping, Value};
+
+use super::{
+    config_dir, directory, env, open_config, read_document, validate, value, ConfigDocument,
+    ConfigFormat, LoadConfigMode, Source, ValidateConfig,
+};
+use crate::ServiceDef;
+
+/// Loads the configuration from a combination of sources: a file, a key-per-file directory and
+/// environment variables.
+///
+/// By default, the first available source is used: the file, then the directory, then the
+/// environment. In [layered](Self::layered) mode, all the sources are read and merged in this
+/// order, each one overriding the previous ones.
+///
+/// A missing file or directory is skipped when other sources are configured, and is an error
+/// otherwise. Files support the includes, profiles and placeholders described in
+/// [`LoadConfigMode`].
+///
+/// ```ignore
+/// let config: Config = ConfigLoader::new(&SERVICE)
+///     .default_file()
+///     .directory("/etc/orders-api/overrides")
+///     .env_prefix("APP_")
+///     .layered(true)
+///     .load()?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct ConfigLoader<'a> {
+    service_def: ServiceDef<'a>,
+    /// `Some(None)` for the default file
+    file: Option<Option<String>>,
+    directory: Option<Option<String>>,
+    format: Option<ConfigFormat>,
+    env: bool,
+    env_prefix: Option<String>,
+    env_nested: bool,
+    layered: bool,
+    /// `Some(None)` for the `.env` file of the working directory
+    #[cfg(feature = "dotenv")]
+    dotenv: Option<Option<String>>,
+}
+
+impl<'a> ConfigLoader<'a> {
+    /// Loader without sources
+    pub fn new(service_def: &ServiceDef<'a>) -> Self {
+        Self {
+            service_def: *service_def,
+            file: None,
+            directory: None,
+            format: None,
+            env: false,
+            env_prefix: None,
+            env_nested: false,
+            layered: false,
+            #[cfg(feature = "dotenv")]
+            dotenv: None,
+        }
+    }
+
+    /// Reads the configuration file at `path`
+    pub fn file(mut self, path: impl Into<String>) -> Self {
+        self.file = Some(Some(path.into()));
+        self
+    }
+
+    /// Reads the default configuration file (see [`LoadConfigMode::FileOnly`])
+    pub fn default_file(mut self) -> Self {
+        self.file = Some(None);
+        self
+    }
+
+    /// Parses the file as `format` whatever its extension
+    pub fn format(mut self, format: ConfigFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Reads the key-per-file directory at `path` (see [`LoadConfigMode::Directory`])
+    pub fn directory(mut self, path: impl Into<String>) -> Self {
+        self.directory = Some(Some(path.into()));
+        self
+    }
+
+    /// Reads the default key-per-file directory, "/etc/{pkg_name}/config"
+    pub fn default_directory(mut self) -> Self {
+        self.directory = Some(None);
+        self
+    }
+
+    /// Reads the environment variables
+    pub fn env(mut self) -> Self {
+        self.env = true;
+        self
+    }
+
+    /// Reads the environment variables starting with `prefix`, without it
+    pub fn env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env = true;
+        self.env_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Reads nested fields from the environment variables, with `__` separated names (see
+    /// [`LoadConfigMode::EnvNested`]). Always the case in layered mode.
+    pub fn env_nested(mut self, nested: bool) -> Self {
+        self.env = true;
+        self.env_nested = nested;
+        self
+    }
+
+    /// Merges all the sources instead of using the first available one (default: false). The
+    /// environment variables then override the fields with their nested names (see
+    /// [`LoadConfigMode::FileWithEnvOverrides`]), only the ones starting with the
+    /// [prefix](Self::env_prefix) being read, `MY_SERVICE_` for `my-service` by default.
+    pub fn layered(mut self, layered: bool) -> Self {
+        self.layered = layered;
+        self
+    }
+
+    /// Loads the variables of a dotenv file (`.env` of the working directory if `path` is
+    /// `None`) into the environment before reading the sources, for local development.
+    /// Variables already defined are kept. A missing `.env` file is ignored, a missing file at
+    /// `path` is an error.
+    #[cfg(feature = "dotenv")]
+    pub fn dotenv(mut self, path: Option<&str>) -> Self {
+        self.dotenv = Some(path.map(str::to_string));
+        self
+    }
+
+    pub fn load<C: DeserializeOwned>(&self) -> anyhow::Result<C> {
+        self.read()?.deserialize()
+    }
+
+    /// Loads the configuration as a document, to be deserialized by section
+    pub fn load_document(&self) -> anyhow::Result<ConfigDocument> {
+        self.read()?.try_into()
+    }
+
+    /// Loads the configuration and validates it (see
+    /// [`load_validated_config`](super::load_validated_config))
+    pub fn load_validated<C: DeserializeOwned + ValidateConfig>(&self) -> anyhow::Result<C> {
+        let config = self.load()?;
+        validate::validate(&config)?;
+        Ok(config)
+    }
+
+    fn sources(&self) -> usize {
+        [self.file.is_some(), self.directory.is_some(), self.env]
+            .into_iter()
+            .filter(|source| *source)
+            .count()
+    }
+
+    fn vars(&self) -> anyhow::Result<BTreeMap<String, String>> {
+        match &self.env_prefix {
+            Some(prefix) => env::prefixed_vars(prefix),
+            None => env::vars(&env::default_prefix(self.service_def.pkg_name)),
+        }
+    }
+
+    /// Variables overriding the fields in layered mode, which always have a prefix
+    fn override_vars(&self) -> anyhow::Result<BTreeMap<String, String>> {
+        let prefix = self
+            .env_prefix
+            .clone()
+            .unwrap_or_else(|| env::default_prefix(self.service_def.pkg_name));
+        env::prefixed_vars(&prefix)
+    }
+
+    /// Document of the file, `None` if it does not exist and other sources are configured
+    fn file_document(&self) -> anyhow::Result<Option<Value>> {
+        let Some(file) = &self.file else {
+            return Ok(None);
+        };
+        match open_config(file.as_deref(), self.format, &self.service_def) {
+            Ok(opened) => Ok(Some(read_document(opened)?)),
+            Err(err) if self.sources() > 1 && is_not_found(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Document of the directory, `None` if it is missing and other sources are configured
+    fn directory_document(&self) -> anyhow::Result<Option<Value>> {
+        let Some(dir) = &self.directory else {
+            return Ok(None);
+        };
+        let dir = config_dir(dir.as_deref(), &self.service_def);
+        if !dir.is_dir() && self.sources() > 1 {
+            return Ok(None);
+        }
+        Ok(Some(directory::read_directory(&dir)?))
+    }
+
+    #[cfg(feature = "dotenv")]
+    fn load_dotenv(&self) -> anyhow::Result<()> {
+        match &self.dotenv {
+            None => {}
+            Some(None) => match dotenvy::dotenv() {
+                Err(err) if err.not_found() => {}
+                result => {
+                    result.context("Cannot load .env file")?;
+                }
+            },
+            Some(Some(path)) => {
+                dotenvy::from_path(path)
+                    .with_context(|| format!("Cannot load dotenv file {path}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&self) -> anyhow::Result<Source> {
+        if self.sources() == 0 {
+            bail!("No configuration source");
+        }
+        #[cfg(feature = "dotenv")]
+        self.load_dotenv()?;
+        if self.layered {
+            let mut doc = Value::Mapping(Mapping::new());
+            for source in [self.file_document()?, self.directory_document()?]
+                .into_iter()
+                .flatten()
+            {
+                value::merge(&mut doc, source);
+            }
+            if self.env {
+                value::apply_env_overrides(&mut doc, self.override_vars()?);
+            }
+            return Ok(Source::Document(doc));
+        }
+
+        if let Some(doc) = self.file_document()? {
+            return Ok(Source::Document(doc));
+        }
+        if let Some(doc) = self.directory_document()? {
+            return Ok(Source::Document(doc));
+        }
+        if self.env_nested {
+            return Ok(Source::Document(value::nested_document(self.vars()?)?));
+        }
+        Ok(Source::Env {
+            vars: self.vars()?,
+            fallback: self.file.is_some() || self.directory.is_some(),
+        })
+    }
+}
+
+impl<'a> From<(LoadConfigMode<'a>, &ServiceDef<'a>)> for ConfigLoader<'a> {
+    /// Loader reading the sources of a [`LoadConfigMode`]
+    fn from((config_mode, service_def): (LoadConfigMode<'a>, &ServiceDef<'a>)) -> Self {
+        let loader = ConfigLoader::new(service_def);
+        let with_file = |loader: ConfigLoader<'a>, file: Option<&str>| match file {
+            Some(file) => loader.file(file),
+            None => loader.default_file(),
+        };
+        let prefix = |prefix: Option<&str>| {
+            prefix
+                .map(str::to_string)
+                .unwrap_or_else(|| env::default_prefix(service_def.pkg_name))
+        };
+        match config_mode {
+            LoadConfigMode::EnvOnly => loader.env(),
+            LoadConfigMode::EnvWithPrefix(p) => loader.env_prefix(prefix(p)),
+            LoadConfigMode::EnvNested(p) => loader.env_prefix(prefix(p)).env_nested(true),
+            LoadConfigMode::FileOnly(file) => with_file(loader, file),
+            LoadConfigMode::FileAndEnvFallback(file) => with_file(loader, file).env(),
+            LoadConfigMode::FileWithEnvOverrides(file) => {
+                with_file(loader, file).env().layered(true)
+            }
+            LoadConfigMode::Directory(dir) => match dir {
+                Some(dir) => loader.directory(dir),
+                None => loader.default_directory(),
+            },
+        }
+    }
+}
+
+fn is_not_found(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|err| err.kind() == std::io::ErrorKind::NotFound)
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[test]
+fn layers_sources() {
+    #[derive(serde::Deserialize)]
+    struct Config {
+        port: u16,
+        log: String,
+        workers: u32,
+    }
+
+    let fixture = crate::testing::ConfigFixture::new()
+        .file("config.yaml", "port: 8080\nlog: info\nworkers: 2")
+        .file("overrides/log", "debug")
+        .env("LOADER_TEST_WORKERS", "8");
+    let service = ServiceDef::new("loader-test", "0.0.0", "0000000");
+    let loader = ConfigLoader::new(&service)
+        .file(fixture.path("config.yaml").to_str().unwrap())
+        .directory(fixture.path("overrides").to_str().unwrap())
+        .env_prefix("LOADER_TEST_");
+
+    let config: Config = loader.clone().layered(true).load().unwrap();
+    assert_eq!(
+        (config.port, config.log.as_str(), config.workers),
+        (8080, "debug", 8)
+    );
+    let config: Config = loader.load().unwrap();
+    assert_eq!(
+        (config.port, config.log.as_str(), config.workers),
+        (8080, "info", 2)
+    );
+
+    let missing = ConfigLoader::new(&service).file(fixture.path("missing.yaml").to_str().unwrap());
+    assert!(missing.load::<Config>().is_err());
+    assert!(missing
+        .clone()
+        .directory(fixture.path("overrides").to_str().unwrap())
+        .load_document()
+        .is_ok());
+    // an unreadable file is an error, even with other sources
+    let unreadable = loader.file(fixture.path("config.yaml/nested.yaml").to_str().unwrap());
+    assert!(unreadable.load::<Config>().is_err());
+}
+
+#[cfg(all(test, feature = "testing", feature = "dotenv"))]
+#[test]
+fn loads_dotenv() {
+    #[derive(serde::Deserialize)]
+    struct Config {
+        port: u16,
+        log: String,
+    }
+
+    let fixture = crate::testing::ConfigFixture::new()
+        .file(".env", "DOTENV_TEST_PORT=8080\nDOTENV_TEST_LOG=info\n")
+        .remove_env("DOTENV_TEST_PORT")
+        .env("DOTENV_TEST_LOG", "debug");
+    let service = ServiceDef::new("dotenv-test", "0.0.0", "0000000");
+    let config: Config = ConfigLoader::new(&service)
+        .dotenv(fixture.path(".env").to_str())
+        .env_prefix("DOTENV_TEST_")
+        .load()
+        .unwrap();
+    assert_eq!((config.port, config.log.as_str()), (8080, "debug"));
+    drop(fixture);
+}
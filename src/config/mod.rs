@@ -0,0 +1,499 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+
+use crate::ServiceDef;
+
+#[cfg(feature = "clap")]
+mod args;
+mod collect;
+mod diagnostic;
+mod directory;
+mod document;
+#[cfg(feature = "encrypted-config")]
+mod encrypted;
+mod env;
+mod format;
+mod include;
+mod interpolate;
+#[cfg(feature = "kv-config")]
+mod kv;
+mod loader;
+#[cfg(feature = "remote-config")]
+mod remote;
+mod secret;
+mod units;
+mod validate;
+mod value;
+#[cfg(feature = "vault")]
+mod vault;
+#[cfg(feature = "config-watch")]
+mod watch;
+
+#[cfg(feature = "clap")]
+pub use args::{load_config_with_args, ConfigArgs};
+pub use diagnostic::{load_config_or_exit, CONFIG_ERROR_EXIT_CODE};
+pub use document::{load_config_document, ConfigDocument};
+#[cfg(feature = "encrypted-config")]
+pub use encrypted::{AGE_KEY_FILE_VAR, AGE_KEY_VAR};
+pub use format::ConfigFormat;
+#[cfg(feature = "kv-config")]
+pub use kv::{load_config_from_kv, KvConfig, KvStore};
+pub use loader::ConfigLoader;
+#[cfg(feature = "remote-config")]
+pub use remote::{load_remote_config, load_remote_config_watched, RemoteConfig};
+pub use secret::{log_config_diff, log_effective_config, redacted_config, Secret};
+pub use units::{
+    duration, format_duration, option_duration, option_size, parse_duration, parse_size, size,
+};
+pub use validate::{ValidateConfig, ValidationErrors};
+#[cfg(feature = "vault")]
+pub use vault::{load_config_with_vault, VaultAuth, VaultConfig};
+#[cfg(feature = "config-watch")]
+pub use watch::{load_config_watched, load_config_watched_every};
+
+/// Load configuration mode. See [`ConfigLoader`] for other combinations of sources.
+///
+/// In the modes reading a file, string values may contain `${VAR}` or `${VAR:-default}`
+/// placeholders, substituted with environment variables (`$${` is an escaped `${`). Loading
+/// fails if a variable without default is missing.
+///
+/// Configuration files may be split: the top level `include` key lists files merged under the
+/// including one, and YAML `!include other.yaml` values are replaced by the included file.
+/// Paths are relative to the including file.
+///
+/// With the `encrypted-config` feature, values may be encrypted with age, to keep secrets in
+/// configuration repositories: `password: age:YWdlLWVuY3J5cHRpb24...` (see [`AGE_KEY_VAR`]).
+///
+/// When the `CONFIG_PROFILE` environment variable is set (eg. to `staging`), the profile file
+/// next to the configuration file (`config.staging.yaml`) is merged over it if it exists, so
+/// one image can target several environments.
+#[derive(Clone, Copy, Debug)]
+pub enum LoadConfigMode<'a> {
+    /// configuration is only read from environment variable.
+    ///
+    /// Note that using this mode prevents from using nested structure, lists or maps. (see `envy` crate)
+    ///
+    /// You may want to load dot env files with the `dotenv` crate while using this mode.
+    ///
+    /// Secrets may be read from files: `MY_SERVICE_FOO_FILE=/run/secrets/foo` defines `FOO`
+    /// with the contents of the file (Docker/Kubernetes secrets convention). Only the variables
+    /// with the prefix of the package name name secret files, see EnvWithPrefix.
+    EnvOnly,
+    /// Same as EnvOnly, reading only the variables starting with the prefix, without it:
+    /// `MY_SERVICE_PORT` for the `port` field. If the prefix is not specified, it is derived
+    /// from the package name: `MY_SERVICE_` for `my-service`.
+    EnvWithPrefix(Option<&'static str>),
+    /// Configuration is read from the environment variables starting with the prefix (see
+    /// EnvWithPrefix), nested with `__`: `MY_SERVICE_DATABASE__HOST` is the `database.host`
+    /// field with the `MY_SERVICE_` prefix. Lists are indexed, without gaps:
+    /// `MY_SERVICE_BROKERS__0`, `MY_SERVICE_BROKERS__1`...
+    ///
+    /// Values are parsed as YAML scalars (`8080`, `true`...).
+    EnvNested(Option<&'static str>),
+    /// Configuration is loaded from filesystem. If the path is not specified, the config file
+    /// is the one named by the `CONFIG_PATH` or `{PKG_NAME}_CONFIG` environment variable
+    /// (`ORDERS_API_CONFIG` for `orders-api`), "/etc/{pkg_name}/config.yaml" otherwise
+    FileOnly(Option<&'a str>),
+    /// Configuration is loaded from filesystem, see FileOnly.
+    ///
+    /// If the file does not exists, configuration is loaded from env. (see EnvOnly)
+    FileAndEnvFallback(Option<&'a str>),
+    /// Configuration is loaded from filesystem (see FileOnly), then each field is overridden
+    /// by the environment variable with the same name in upper case prefixed with the one of
+    /// the package (`MY_SERVICE_PORT` for `port` in `my-service`), if any.
+    ///
+    /// Nested fields are overridden with `__` separated names: `database.pool_size` by
+    /// `MY_SERVICE_DATABASE__POOL_SIZE`. Values are kept as strings, and only parsed for the
+    /// numeric and boolean fields.
+    FileWithEnvOverrides(Option<&'a str>),
+    /// Configuration is loaded from a directory with a file per key, as mounted from a
+    /// Kubernetes ConfigMap: the file `port` holds the `port` field, `database.host` the
+    /// `host` field of `database`. If the path is not specified, the directory is
+    /// "/etc/{pkg_name}/config"
+    ///
+    /// Values are parsed as YAML scalars (`8080`, `true`...), or lists and mappings.
+    Directory(Option<&'a str>),
+}
+
+impl<'a> LoadConfigMode<'a> {
+    /// Configuration file of the modes reading one
+    #[cfg_attr(
+        not(any(feature = "config-watch", feature = "clap", feature = "tokio")),
+        allow(dead_code)
+    )]
+    fn file(&self) -> Option<Option<&'a str>> {
+        match *self {
+            LoadConfigMode::EnvOnly
+            | LoadConfigMode::EnvWithPrefix(_)
+            | LoadConfigMode::EnvNested(_) => None,
+            LoadConfigMode::FileOnly(file)
+            | LoadConfigMode::FileAndEnvFallback(file)
+            | LoadConfigMode::FileWithEnvOverrides(file)
+            | LoadConfigMode::Directory(file) => Some(file),
+        }
+    }
+
+    /// Path of the configuration file or directory of the modes reading one
+    #[cfg_attr(not(feature = "config-watch"), allow(dead_code))]
+    fn path(&self, service_def: &ServiceDef) -> Option<PathBuf> {
+        match *self {
+            LoadConfigMode::Directory(dir) => Some(config_dir(dir, service_def)),
+            _ => self.file().map(|file| config_path(file, service_def)),
+        }
+    }
+
+    /// Same mode, reading `file`
+    #[cfg_attr(
+        not(any(feature = "config-watch", feature = "clap", feature = "tokio")),
+        allow(dead_code)
+    )]
+    fn with_file<'b>(&self, file: Option<&'b str>) -> LoadConfigMode<'b> {
+        match self {
+            LoadConfigMode::EnvOnly => LoadConfigMode::EnvOnly,
+            LoadConfigMode::EnvWithPrefix(prefix) => LoadConfigMode::EnvWithPrefix(*prefix),
+            LoadConfigMode::EnvNested(prefix) => LoadConfigMode::EnvNested(*prefix),
+            LoadConfigMode::FileOnly(_) => LoadConfigMode::FileOnly(file),
+            LoadConfigMode::FileAndEnvFallback(_) => LoadConfigMode::FileAndEnvFallback(file),
+            LoadConfigMode::FileWithEnvOverrides(_) => LoadConfigMode::FileWithEnvOverrides(file),
+            LoadConfigMode::Directory(_) => LoadConfigMode::Directory(file),
+        }
+    }
+}
+
+/// Loads the configuration. Files are parsed according to their extension (see
+/// [`ConfigFormat::from_path`]).
+pub fn load_config<C: DeserializeOwned>(
+    config_mode: LoadConfigMode,
+    service_def: &ServiceDef,
+) -> anyhow::Result<C> {
+    load(config_mode, None, service_def)
+}
+
+/// Loads the configuration, files being parsed as `format` whatever their extension
+pub fn load_config_with_format<C: DeserializeOwned>(
+    config_mode: LoadConfigMode,
+    format: ConfigFormat,
+    service_def: &ServiceDef,
+) -> anyhow::Result<C> {
+    load(config_mode, Some(format), service_def)
+}
+
+/// Loads the configuration from a YAML string, as a configuration file without includes nor
+/// profiles. Placeholders are not substituted, so the process environment is not read: for
+/// tests building their configuration in memory.
+pub fn load_config_from_str<C: DeserializeOwned>(yaml: &str) -> anyhow::Result<C> {
+    let doc = ConfigFormat::Yaml
+        .parse(yaml.as_bytes())
+        .context("Cannot parse configuration")?;
+    value::from_value(&doc)
+}
+
+/// Loads the configuration from a map of variables, as [`LoadConfigMode::EnvOnly`] does from
+/// the process environment (`_FILE` secrets excepted): for tests building their configuration
+/// in memory.
+pub fn load_config_from_env_map<C, I, K, V>(vars: I) -> anyhow::Result<C>
+where
+    C: DeserializeOwned,
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+{
+    let vars = vars
+        .into_iter()
+        .map(|(name, value)| (name.into(), value.into()))
+        .collect();
+    Source::Env {
+        vars,
+        fallback: false,
+    }
+    .deserialize()
+}
+
+/// Loads the configuration (see [`load_config`]) without blocking the tokio runtime: files
+/// are read on the blocking thread pool. Use it from `async` code, like the Vault and remote
+/// sources.
+#[cfg(feature = "tokio")]
+pub async fn load_config_async<C>(
+    config_mode: LoadConfigMode<'_>,
+    service_def: &ServiceDef<'_>,
+) -> anyhow::Result<C>
+where
+    C: DeserializeOwned + Send + 'static,
+{
+    let file = config_mode.file().flatten().map(str::to_string);
+    let mode = config_mode.with_file(None);
+    let (pkg_name, version, git_hash) = (
+        service_def.pkg_name.to_string(),
+        service_def.version.to_string(),
+        service_def.git_hash.to_string(),
+    );
+    tokio::task::spawn_blocking(move || {
+        let service_def = ServiceDef::new(&pkg_name, &version, &git_hash);
+        load(mode.with_file(file.as_deref()), None, &service_def)
+    })
+    .await
+    .context("Configuration loading task failed")?
+}
+
+/// Loads the configuration (see [`load_config`]) and validates it, failing with all the
+/// [`ValidationErrors`] at once.
+pub fn load_validated_config<C: DeserializeOwned + ValidateConfig>(
+    config_mode: LoadConfigMode,
+    service_def: &ServiceDef,
+) -> anyhow::Result<C> {
+    let config = load_config(config_mode, service_def)?;
+    validate::validate(&config)?;
+    Ok(config)
+}
+
+fn load<C: DeserializeOwned>(
+    config_mode: LoadConfigMode,
+    format: Option<ConfigFormat>,
+    service_def: &ServiceDef,
+) -> anyhow::Result<C> {
+    read_source(config_mode, format, service_def)?.deserialize()
+}
+
+/// Configuration read, not deserialized yet
+enum Source {
+    /// Environment variables, deserialized with `envy`
+    Env {
+        vars: BTreeMap<String, String>,
+        /// the configuration file was not found
+        fallback: bool,
+    },
+    /// Configuration document read from a file
+    Document(serde_yaml::Value),
+}
+
+impl Source {
+    fn deserialize<C: DeserializeOwned>(self) -> anyhow::Result<C> {
+        match self {
+            Source::Env { vars, fallback } => {
+                let config = collect::from_env(&vars);
+                if fallback {
+                    config.context(
+                        "Cannot read configuration from filesystem nor environment variables",
+                    )
+                } else {
+                    config.context("Cannot read configuration from environment variables")
+                }
+            }
+            Source::Document(doc) => value::from_value(&doc),
+        }
+    }
+}
+
+fn read_source(
+    config_mode: LoadConfigMode,
+    format: Option<ConfigFormat>,
+    service_def: &ServiceDef,
+) -> anyhow::Result<Source> {
+    match config_mode {
+        LoadConfigMode::EnvOnly => Ok(Source::Env {
+            vars: env::vars(&env::default_prefix(service_def.pkg_name))?,
+            fallback: false,
+        }),
+        LoadConfigMode::EnvWithPrefix(prefix) => Ok(Source::Env {
+            vars: prefixed_vars(prefix, service_def)?,
+            fallback: false,
+        }),
+        LoadConfigMode::EnvNested(prefix) => Ok(Source::Document(value::nested_document(
+            prefixed_vars(prefix, service_def)?,
+        )?)),
+        LoadConfigMode::FileOnly(file) => Ok(Source::Document(read_document(open_config(
+            file,
+            format,
+            service_def,
+        )?)?)),
+        LoadConfigMode::FileAndEnvFallback(file) => match open_config(file, format, service_def) {
+            Ok(opened) => Ok(Source::Document(read_document(opened)?)),
+            Err(_) => Ok(Source::Env {
+                vars: env::vars(&env::default_prefix(service_def.pkg_name))?,
+                fallback: true,
+            }),
+        },
+        LoadConfigMode::FileWithEnvOverrides(file) => {
+            let mut doc = read_document(open_config(file, format, service_def)?)?;
+            value::apply_env_overrides(&mut doc, prefixed_vars(None, service_def)?);
+            Ok(Source::Document(doc))
+        }
+        LoadConfigMode::Directory(dir) => Ok(Source::Document(directory::read_directory(
+            &config_dir(dir, service_def),
+        )?)),
+    }
+}
+
+/// Environment variables starting with `prefix` (derived from the package name if not
+/// specified), without it
+fn prefixed_vars(
+    prefix: Option<&str>,
+    service_def: &ServiceDef,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let prefix = prefix
+        .map(str::to_string)
+        .unwrap_or_else(|| env::default_prefix(service_def.pkg_name));
+    env::prefixed_vars(&prefix)
+}
+
+/// Environment variable selecting the profile of the configuration, see [`LoadConfigMode`]
+pub const PROFILE_VAR: &str = "CONFIG_PROFILE";
+
+/// Parses a configuration file, resolves its includes, overlays the profile file and
+/// substitutes its placeholders
+fn read_document(
+    (reader, format, path): (impl Read, ConfigFormat, PathBuf),
+) -> anyhow::Result<serde_yaml::Value> {
+    let mut doc = format
+        .parse(reader)
+        .context("Cannot parse configuration file")?;
+    include::resolve_includes(&mut doc, &path)?;
+    if let Some(profile) = std::env::var(PROFILE_VAR).ok().filter(|p| !p.is_empty()) {
+        let overlay = profile_path(&path, &profile);
+        if overlay.exists() {
+            let file = File::open(&overlay)
+                .with_context(|| format!("Cannot load configuration file {}", overlay.display()))?;
+            let mut overlay_doc =
+                ConfigFormat::from_path(&overlay)
+                    .parse(file)
+                    .with_context(|| {
+                        format!("Cannot parse configuration file {}", overlay.display())
+                    })?;
+            include::resolve_includes(&mut overlay_doc, &overlay)?;
+            value::merge(&mut doc, overlay_doc);
+        }
+    }
+    resolve_values(&mut doc)?;
+    Ok(doc)
+}
+
+/// Substitutes the placeholders of the document, and decrypts its encrypted values with the
+/// `encrypted-config` feature
+fn resolve_values(doc: &mut serde_yaml::Value) -> anyhow::Result<()> {
+    interpolate::interpolate(doc, &|name| std::env::var(name).ok())?;
+    #[cfg(feature = "encrypted-config")]
+    encrypted::decrypt_values(doc)?;
+    Ok(())
+}
+
+/// Opens the configuration file, with its format and path
+fn open_config(
+    file: Option<&str>,
+    format: Option<ConfigFormat>,
+    service_def: &ServiceDef,
+) -> anyhow::Result<(impl Read, ConfigFormat, PathBuf)> {
+    let path = config_path(file, service_def);
+    let reader = File::open(&path)
+        .with_context(|| format!("Cannot load configuration file {}", path.to_string_lossy()))?;
+    let format = format.unwrap_or_else(|| ConfigFormat::from_path(&path));
+    Ok((reader, format, path))
+}
+
+/// Environment variable overriding the default path of the configuration file
+pub const CONFIG_PATH_VAR: &str = "CONFIG_PATH";
+
+/// Path of the configuration file if not specified: the `CONFIG_PATH` or `{PKG_NAME}_CONFIG`
+/// environment variable, "/etc/{pkg_name}/config.yaml" otherwise
+fn config_path(file: Option<&str>, service_def: &ServiceDef) -> PathBuf {
+    if let Some(filename) = file {
+        return filename.into();
+    }
+    let var = |name: &str| std::env::var(name).ok().filter(|path| !path.is_empty());
+    let service_var = format!("{}CONFIG", env::default_prefix(service_def.pkg_name));
+    match var(CONFIG_PATH_VAR).or_else(|| var(&service_var)) {
+        Some(path) => path.into(),
+        None => format!("/etc/{}/config.yaml", service_def.pkg_name).into(),
+    }
+}
+
+/// Overlay of the configuration file for the profile: `config.staging.yaml` for
+/// `config.yaml` and the `staging` profile
+fn profile_path(path: &Path, profile: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}.{profile}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{profile}"),
+    };
+    path.with_file_name(name)
+}
+
+/// Path of the configuration directory, "/etc/{pkg_name}/config" if not specified
+fn config_dir(dir: Option<&str>, service_def: &ServiceDef) -> PathBuf {
+    match dir {
+        Some(dir) => dir.into(),
+        None => format!("/etc/{}/config", service_def.pkg_name).into(),
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[test]
+fn config_path_from_env() {
+    let service = ServiceDef::new("orders-api", "0.0.0", "0000000");
+    let fixture = crate::testing::ConfigFixture::new()
+        .remove_env(CONFIG_PATH_VAR)
+        .env("ORDERS_API_CONFIG", "/tmp/orders.yaml");
+    assert_eq!(config_path(None, &service), Path::new("/tmp/orders.yaml"));
+    let _fixture = fixture.env(CONFIG_PATH_VAR, "/tmp/config.yaml");
+    assert_eq!(config_path(None, &service), Path::new("/tmp/config.yaml"));
+    assert_eq!(config_path(Some("a.yaml"), &service), Path::new("a.yaml"));
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[tokio::test]
+async fn loads_async() {
+    #[derive(serde::Deserialize)]
+    struct Config {
+        port: u16,
+    }
+
+    let fixture = crate::testing::ConfigFixture::new().file("config.yaml", "port: 8080");
+    let path = fixture.path("config.yaml");
+    let service = ServiceDef::new("async-test", "0.0.0", "0000000");
+    let config: Config = load_config_async(LoadConfigMode::FileOnly(path.to_str()), &service)
+        .await
+        .unwrap();
+    assert_eq!(config.port, 8080);
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[test]
+fn overlays_profile() {
+    #[derive(serde::Deserialize)]
+    struct Config {
+        port: u16,
+        log: String,
+    }
+
+    let fixture = crate::testing::ConfigFixture::new()
+        .file("config.yaml", "port: 8080\nlog: info")
+        .file("config.staging.yaml", "log: debug")
+        .env(PROFILE_VAR, "staging");
+    let path = fixture.path("config.yaml");
+    let service = ServiceDef::new("profile-test", "0.0.0", "0000000");
+
+    let config: Config = load_config(LoadConfigMode::FileOnly(path.to_str()), &service).unwrap();
+    assert_eq!((config.port, config.log.as_str()), (8080, "debug"));
+}
+
+#[cfg(test)]
+#[test]
+fn loads_in_memory() {
+    #[derive(serde::Deserialize)]
+    struct Config {
+        port: u16,
+        log: String,
+    }
+
+    let config: Config = load_config_from_str("port: 8080\nlog: ${LOG}").unwrap();
+    assert_eq!((config.port, config.log.as_str()), (8080, "${LOG}"));
+    let config: Config = load_config_from_env_map([("PORT", "9090"), ("LOG", "debug")]).unwrap();
+    assert_eq!((config.port, config.log.as_str()), (9090, "debug"));
+    assert!(load_config_from_env_map::<Config, _, _, _>([("PORT", "9090")]).is_err());
+}
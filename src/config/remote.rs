@@ -0,0 +1,206 @@
+//! Configuration fetched from an HTTP(S) config service
+
+use std::{fmt, time::Duration};
+
+use anyhow::Context;
+use http::{
+    header::{ETAG, IF_NONE_MATCH},
+    HeaderValue, StatusCode,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_yaml::Value;
+use tokio::sync::watch;
+
+use super::{format::ConfigFormat, log_config_diff, resolve_values, value};
+use crate::redact::{redact_url, REDACTED};
+
+/// URL of the configuration and credentials. The token is redacted from the `Debug` output.
+#[derive(Clone)]
+pub struct RemoteConfig {
+    /// URL of the YAML (or JSON) configuration document
+    pub url: String,
+    /// Bearer token sent to the config service, if any
+    pub token: Option<String>,
+    pub timeout: Duration,
+}
+
+impl RemoteConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            token: None,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl fmt::Debug for RemoteConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteConfig")
+            .field("url", &redact_url(&self.url))
+            .field("token", &self.token.as_ref().map(|_| REDACTED))
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// Fetches the configuration document, remembering its `ETag`
+struct Fetcher {
+    client: reqwest::Client,
+    remote: RemoteConfig,
+    etag: Option<HeaderValue>,
+}
+
+impl Fetcher {
+    fn new(remote: RemoteConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder().timeout(remote.timeout).build()?;
+        Ok(Self {
+            client,
+            remote,
+            etag: None,
+        })
+    }
+
+    /// The document, or `None` if it did not change since the last fetch
+    async fn fetch(&mut self) -> anyhow::Result<Option<Value>> {
+        let url = redact_url(&self.remote.url);
+        let mut req = self.client.get(&self.remote.url);
+        if let Some(token) = &self.remote.token {
+            req = req.bearer_auth(token);
+        }
+        if let Some(etag) = &self.etag {
+            req = req.header(IF_NONE_MATCH, etag.clone());
+        }
+        let resp = req
+            .send()
+            .await
+            .with_context(|| format!("Cannot fetch configuration from {url}"))?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let resp = resp
+            .error_for_status()
+            .with_context(|| format!("Cannot fetch configuration from {url}"))?;
+        let etag = resp.headers().get(ETAG).cloned();
+        let body = resp
+            .bytes()
+            .await
+            .with_context(|| format!("Cannot fetch configuration from {url}"))?;
+        let mut doc = ConfigFormat::Yaml
+            .parse(&body[..])
+            .with_context(|| format!("Cannot parse configuration from {url}"))?;
+        resolve_values(&mut doc)?;
+        self.etag = etag;
+        Ok(Some(doc))
+    }
+}
+
+/// Loads the configuration from a config service: the document is fetched with a `GET` on
+/// the URL, and parsed as YAML. Placeholders are substituted as in the configuration files
+/// (see [`LoadConfigMode`](super::LoadConfigMode)).
+pub async fn load_remote_config<C: DeserializeOwned>(remote: &RemoteConfig) -> anyhow::Result<C> {
+    let doc = Fetcher::new(remote.clone())?
+        .fetch()
+        .await?
+        .context("Configuration not modified")?;
+    value::from_value(&doc)
+}
+
+/// Loads the configuration from a config service (see [`load_remote_config`]), then fetches
+/// it again every `interval`. Refreshes are conditional (`If-None-Match` with the `ETag` of
+/// the last document), so unchanged configurations are not downloaded nor parsed again.
+///
+/// A refresh failing is logged and the previous configuration is kept. The changed fields of
+/// a refreshed configuration are logged, redacted (see [`log_config_diff`]). Refreshing stops
+/// when all the receivers are dropped.
+///
+/// Must be called from a tokio runtime.
+pub async fn load_remote_config_watched<C>(
+    remote: RemoteConfig,
+    interval: Duration,
+) -> anyhow::Result<watch::Receiver<C>>
+where
+    C: DeserializeOwned + Serialize + Send + Sync + 'static,
+{
+    let mut fetcher = Fetcher::new(remote)?;
+    let doc = fetcher
+        .fetch()
+        .await?
+        .context("Configuration not modified")?;
+    let (tx, rx) = watch::channel(value::from_value(&doc)?);
+
+    tokio::spawn(async move {
+        let mut poll = tokio::time::interval(interval);
+        poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        poll.tick().await;
+        loop {
+            tokio::select! {
+                _ = tx.closed() => break,
+                _ = poll.tick() => {}
+            }
+            let config = match fetcher.fetch().await {
+                Ok(None) => continue,
+                Ok(Some(doc)) => value::from_value(&doc),
+                Err(err) => Err(err),
+            };
+            match config {
+                Ok(config) => {
+                    log::info!("Remote configuration changed, configuration reloaded");
+                    log_config_diff(&*tx.borrow(), &config);
+                    tx.send_replace(config);
+                }
+                Err(err) => {
+                    log::error!("Cannot reload configuration, keeping the previous one: {err:#}")
+                }
+            }
+        }
+    });
+    Ok(rx)
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[tokio::test]
+async fn refreshes_with_etag() {
+    use http::{header::AUTHORIZATION, Method};
+    use serde::Deserialize;
+
+    use crate::testing::{FakeResponse, FakeUpstream};
+
+    #[derive(Deserialize)]
+    struct Config {
+        port: u16,
+    }
+
+    let service = FakeUpstream::start().await;
+    service.on_sequence(
+        Method::GET,
+        "/config.yaml",
+        vec![
+            FakeResponse::new(StatusCode::OK)
+                .header(ETAG, HeaderValue::from_static("\"v1\""))
+                .body("port: 8080"),
+            FakeResponse::new(StatusCode::NOT_MODIFIED),
+        ],
+    );
+    let remote = RemoteConfig::new(service.url("/config.yaml")).bearer_token("t0ken");
+    assert!(!format!("{remote:?}").contains("t0ken"));
+
+    let mut fetcher = Fetcher::new(remote).unwrap();
+    let config: Config = value::from_value(&fetcher.fetch().await.unwrap().unwrap()).unwrap();
+    assert_eq!(config.port, 8080);
+    assert!(fetcher.fetch().await.unwrap().is_none());
+
+    let calls = service.calls();
+    assert_eq!(calls[0].headers[AUTHORIZATION], "Bearer t0ken");
+    assert_eq!(calls[1].headers[IF_NONE_MATCH], "\"v1\"");
+}
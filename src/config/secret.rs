@@ -0,0 +1,214 @@
+//! Secrets of the configuration, and redacted logging of the configuration
+
+use std::fmt;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_yaml::Value;
+
+use crate::redact::{is_sensitive_key, redact_url, REDACTED};
+
+/// Secret configuration value, redacted from the `Debug` and serialized outputs
+///
+/// ```ignore
+/// #[derive(Deserialize, Serialize)]
+/// struct Config {
+///     database_url: String,
+///     api_secret: Secret<String>,
+/// }
+/// let client = Client::new(config.api_secret.expose());
+/// ```
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// The secret value
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+/// Configuration as a document, with the [`Secret`] fields, the values of sensitive keys
+/// (`password`, `token`...) and the passwords of URLs redacted
+pub fn redacted_config<C: Serialize>(config: &C) -> anyhow::Result<Value> {
+    let mut doc = serde_yaml::to_value(config).context("Cannot serialize configuration")?;
+    redact(&mut doc);
+    Ok(doc)
+}
+
+/// Logs the redacted configuration (see [`redacted_config`]) at info level, to know what a
+/// service actually runs with, and [registers](crate::info::register_config) it
+pub fn log_effective_config<C: Serialize>(config: &C) {
+    match redacted_config(config).and_then(|doc| Ok(serde_yaml::to_string(&doc)?)) {
+        Ok(text) => log::info!("Effective configuration:\n{}", text.trim_end()),
+        Err(err) => log::warn!("Cannot log effective configuration: {err:#}"),
+    }
+    if let Err(err) = crate::info::register_config(config) {
+        log::warn!("Cannot register the configuration: {err:#}");
+    }
+}
+
+/// Logs the fields changed between two configurations at info level, redacted (see
+/// [`redacted_config`]), to audit the changes applied at runtime
+pub fn log_config_diff<C: Serialize>(old: &C, new: &C) {
+    match redacted_config(old).and_then(|old| Ok((old, redacted_config(new)?))) {
+        Ok((old, new)) => {
+            let mut changes = vec![];
+            diff("", &old, &new, &mut changes);
+            if changes.is_empty() {
+                log::info!("Configuration reloaded, no field changed");
+            } else {
+                log::info!("Configuration changed:\n{}", changes.join("\n"));
+            }
+        }
+        Err(err) => log::warn!("Cannot log configuration changes: {err:#}"),
+    }
+}
+
+/// Adds a line per changed field to `changes`, like `database.pool_size: 10 -> 20`
+fn diff(path: &str, old: &Value, new: &Value, changes: &mut Vec<String>) {
+    let field = |key: &Value| {
+        let key = key
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| inline(key));
+        if path.is_empty() {
+            key
+        } else {
+            format!("{path}.{key}")
+        }
+    };
+    match (old, new) {
+        (Value::Mapping(old), Value::Mapping(new)) => {
+            for (key, old_value) in old {
+                match new.get(key) {
+                    Some(new_value) => diff(&field(key), old_value, new_value, changes),
+                    None => changes.push(format!(
+                        "{}: {} -> (removed)",
+                        field(key),
+                        inline(old_value)
+                    )),
+                }
+            }
+            for (key, new_value) in new.iter().filter(|(key, _)| !old.contains_key(*key)) {
+                changes.push(format!("{}: (added) -> {}", field(key), inline(new_value)));
+            }
+        }
+        (old, new) if old != new => {
+            changes.push(format!("{path}: {} -> {}", inline(old), inline(new)))
+        }
+        _ => {}
+    }
+}
+
+/// Value on a single line, in YAML flow style
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Mapping(mapping) => {
+            let entries: Vec<String> = mapping
+                .iter()
+                .map(|(key, value)| format!("{}: {}", inline(key), inline(value)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::Sequence(seq) => {
+            let items: Vec<String> = seq.iter().map(inline).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Tagged(tagged) => inline(&tagged.value),
+        value => serde_yaml::to_string(value)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
+
+fn redact(doc: &mut Value) {
+    match doc {
+        Value::String(s) if s.contains("://") => *s = redact_url(s),
+        Value::Sequence(seq) => seq.iter_mut().for_each(redact),
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                match key.as_str() {
+                    Some(key) if is_sensitive_key(key) && !value.is_null() => {
+                        *value = Value::String(REDACTED.to_string())
+                    }
+                    _ => redact(value),
+                }
+            }
+        }
+        Value::Tagged(tagged) => redact(&mut tagged.value),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn redacts_secrets() {
+    #[derive(Serialize, Deserialize)]
+    struct Database {
+        url: String,
+        password: String,
+    }
+    #[derive(Serialize, Deserialize)]
+    struct Config {
+        port: u16,
+        signing: Secret<String>,
+        database: Database,
+    }
+
+    let config: Config = serde_yaml::from_str(
+        "port: 8080\nsigning: k3y\ndatabase: { url: 'postgres://app:pw@db/app', password: pw }",
+    )
+    .unwrap();
+    assert_eq!(config.signing.expose(), "k3y");
+    assert_eq!(format!("{:?}", config.signing), "***");
+
+    let expected: Value = serde_yaml::from_str(
+        "port: 8080\nsigning: '***'\ndatabase: { url: 'postgres://app:***@db/app', password: '***' }",
+    )
+    .unwrap();
+    assert_eq!(redacted_config(&config).unwrap(), expected);
+
+    let mut changes = vec![];
+    let new: Value = serde_yaml::from_str(
+        "port: 9090\nsigning: '***'\ndatabase: { url: 'postgres://app:***@db/app' }\nbrokers: [a, b]",
+    )
+    .unwrap();
+    diff("", &expected, &new, &mut changes);
+    assert_eq!(
+        changes,
+        [
+            "port: 8080 -> 9090",
+            "database.password: '***' -> (removed)",
+            "brokers: (added) -> [a, b]"
+        ]
+    );
+}
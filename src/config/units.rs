@@ -0,0 +1,292 @@
+//! Human-friendly durations and sizes in configurations, for `#[serde(with = "...")]`:
+//!
+//! ```ignore
+//! #[derive(Deserialize, Serialize)]
+//! struct Config {
+//!     #[serde(with = "service_helpe_rs::config::duration")]
+//!     timeout: Duration,
+//!     #[serde(with = "service_helpe_rs::config::size")]
+//!     max_body: u64,
+//! }
+//! ```
+//!
+//! allows `timeout: 1m30s` and `max_body: 5MiB`.
+
+use std::{fmt, time::Duration};
+
+use serde::de::{self, Visitor};
+
+const DURATION_UNITS: [(&str, u64); 9] = [
+    ("ns", 1),
+    ("us", 1_000),
+    ("µs", 1_000),
+    ("ms", 1_000_000),
+    ("s", 1_000_000_000),
+    ("m", 60_000_000_000),
+    ("min", 60_000_000_000),
+    ("h", 3_600_000_000_000),
+    ("d", 86_400_000_000_000),
+];
+
+const SIZE_UNITS: [(&str, u64); 13] = [
+    ("b", 1),
+    ("k", 1_000),
+    ("kb", 1_000),
+    ("kib", 1 << 10),
+    ("m", 1_000_000),
+    ("mb", 1_000_000),
+    ("mib", 1 << 20),
+    ("g", 1_000_000_000),
+    ("gb", 1_000_000_000),
+    ("gib", 1 << 30),
+    ("t", 1_000_000_000_000),
+    ("tb", 1_000_000_000_000),
+    ("tib", 1 << 40),
+];
+
+/// Parses a duration made of numbers with units (`ns`, `us`, `ms`, `s`, `m`, `h`, `d`), like
+/// `30s`, `1h30m` or `1h 30m`
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration `{text}`, expected a value like `30s` or `1h30m`");
+    let mut rest = text.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    let mut nanos: u128 = 0;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let value: u128 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let (_, factor) = DURATION_UNITS
+            .iter()
+            .find(|(unit, _)| *unit == &rest[..unit_len])
+            .ok_or_else(invalid)?;
+        nanos = value
+            .checked_mul(*factor as u128)
+            .and_then(|unit_nanos| nanos.checked_add(unit_nanos))
+            .ok_or_else(invalid)?;
+        rest = rest[unit_len..].trim_start();
+    }
+    let secs = u64::try_from(nanos / 1_000_000_000).map_err(|_| invalid())?;
+    Ok(Duration::new(secs, (nanos % 1_000_000_000) as u32))
+}
+
+/// Formats a duration as parsed by [`parse_duration`], like `1h30m` or `250ms`
+pub fn format_duration(duration: Duration) -> String {
+    if duration.is_zero() {
+        return "0s".to_string();
+    }
+    if duration.subsec_nanos() != 0 {
+        let nanos = duration.as_nanos();
+        return match nanos % 1_000_000 {
+            0 => format!("{}ms", nanos / 1_000_000),
+            _ => format!("{nanos}ns"),
+        };
+    }
+    let mut secs = duration.as_secs();
+    let mut text = String::new();
+    for (unit, unit_secs) in [("d", 86_400), ("h", 3_600), ("m", 60), ("s", 1)] {
+        if secs >= unit_secs {
+            text.push_str(&format!("{}{unit}", secs / unit_secs));
+            secs %= unit_secs;
+        }
+    }
+    text
+}
+
+/// Parses a size in bytes, with an optional decimal (`kB`, `MB`...) or binary (`KiB`,
+/// `MiB`...) unit, like `512`, `64kB` or `5MiB`. Units are case insensitive.
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid size `{text}`, expected a value like `512kB` or `5MiB`");
+    let trimmed = text.trim();
+    let digits = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let value: u64 = trimmed[..digits].parse().map_err(|_| invalid())?;
+    let unit = trimmed[digits..].trim().to_lowercase();
+    if unit.is_empty() {
+        return Ok(value);
+    }
+    let (_, factor) = SIZE_UNITS
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .ok_or_else(invalid)?;
+    value.checked_mul(*factor).ok_or_else(invalid)
+}
+
+/// `Duration` written like `30s` or `1h30m`, see [`parse_duration`]
+pub mod duration {
+    use super::*;
+
+    pub fn deserialize<'de, D: de::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Duration, D::Error> {
+        deserializer.deserialize_str(DurationVisitor)
+    }
+
+    pub fn serialize<S: serde::Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_duration(*duration))
+    }
+}
+
+/// Optional `Duration` written like `30s` or `1h30m`, see [`parse_duration`]. Use with
+/// `#[serde(default)]` for the field to be optional.
+pub mod option_duration {
+    use super::*;
+
+    pub fn deserialize<'de, D: de::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        deserializer.deserialize_option(OptionVisitor(DurationVisitor))
+    }
+
+    pub fn serialize<S: serde::Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&format_duration(*duration)),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// Size in bytes written like `512kB` or `5MiB`, see [`parse_size`]
+pub mod size {
+    use super::*;
+
+    pub fn deserialize<'de, D: de::Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        deserializer.deserialize_any(SizeVisitor)
+    }
+
+    pub fn serialize<S: serde::Serializer>(size: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(*size)
+    }
+}
+
+/// Optional size in bytes written like `512kB` or `5MiB`, see [`parse_size`]. Use with
+/// `#[serde(default)]` for the field to be optional.
+pub mod option_size {
+    use super::*;
+
+    pub fn deserialize<'de, D: de::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        deserializer.deserialize_option(OptionVisitor(SizeVisitor))
+    }
+
+    pub fn serialize<S: serde::Serializer>(
+        size: &Option<u64>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match size {
+            Some(size) => serializer.serialize_some(size),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+struct DurationVisitor;
+
+impl Visitor<'_> for DurationVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a duration like `30s` or `1h30m`")
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<Duration, E> {
+        parse_duration(text).map_err(E::custom)
+    }
+}
+
+struct SizeVisitor;
+
+impl Visitor<'_> for SizeVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a size like `512kB` or `5MiB`")
+    }
+
+    fn visit_u64<E: de::Error>(self, size: u64) -> Result<u64, E> {
+        Ok(size)
+    }
+
+    fn visit_i64<E: de::Error>(self, size: i64) -> Result<u64, E> {
+        u64::try_from(size).map_err(|_| E::custom(format!("invalid size {size}")))
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<u64, E> {
+        parse_size(text).map_err(E::custom)
+    }
+}
+
+struct OptionVisitor<V>(V);
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for OptionVisitor<V> {
+    type Value = Option<V::Value>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.expecting(f)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self.0).map(Some)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn parses_durations_and_sizes() {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Deserialize, Serialize)]
+    struct Config {
+        #[serde(with = "duration")]
+        timeout: Duration,
+        #[serde(default, with = "option_duration")]
+        idle: Option<Duration>,
+        #[serde(with = "size")]
+        max_body: u64,
+        #[serde(with = "size")]
+        buffer: u64,
+    }
+
+    let config: Config =
+        serde_yaml::from_str("timeout: 1h 30m\nmax_body: 5MiB\nbuffer: 4096").unwrap();
+    assert_eq!(config.timeout, Duration::from_secs(5400));
+    assert_eq!(config.idle, None);
+    assert_eq!(config.max_body, 5 * 1024 * 1024);
+    assert_eq!(config.buffer, 4096);
+    assert_eq!(
+        serde_yaml::to_string(&config).unwrap(),
+        "timeout: 1h30m\nidle: null\nmax_body: 5242880\nbuffer: 4096\n"
+    );
+
+    assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+    assert_eq!(format_duration(Duration::from_millis(250)), "250ms");
+    assert!(parse_duration("30").is_err());
+    assert!(parse_duration("100000000000000000000000000000000000000d").is_err());
+    assert_eq!(parse_size("64kB"), Ok(64_000));
+    assert!(parse_size("5 parsecs").is_err());
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+
+/// Checks run on a configuration once deserialized by
+/// [`load_validated_config`](super::load_validated_config): range checks, cross-field
+/// constraints...
+///
+/// ```ignore
+/// impl ValidateConfig for Config {
+///     fn validate(&self, errors: &mut ValidationErrors) {
+///         errors.check(self.workers > 0, "workers", "must be greater than 0");
+///         errors.check(
+///             self.min_delay_ms <= self.max_delay_ms,
+///             "min_delay_ms",
+///             "must be lower than max_delay_ms",
+///         );
+///     }
+/// }
+/// ```
+pub trait ValidateConfig {
+    /// Adds an error to `errors` for each invalid field
+    fn validate(&self, errors: &mut ValidationErrors);
+}
+
+/// Errors found by [`ValidateConfig::validate`], reported all at once
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ValidationErrors {
+    errors: Vec<(String, String)>,
+}
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an error on `field`
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push((field.into(), message.into()));
+    }
+
+    /// Adds an error on `field` if `valid` is false
+    pub fn check(&mut self, valid: bool, field: impl Into<String>, message: impl Into<String>) {
+        if !valid {
+            self.add(field, message);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Field and message of the errors
+    pub fn errors(&self) -> &[(String, String)] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for (field, message) in &self.errors {
+            write!(f, "\n  - {field}: {message}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+/// Runs the validation of `config`
+pub(crate) fn validate<C: ValidateConfig>(config: &C) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::new();
+    config.validate(&mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn reports_all_errors() {
+    struct Config {
+        workers: u32,
+        min: u32,
+        max: u32,
+    }
+    impl ValidateConfig for Config {
+        fn validate(&self, errors: &mut ValidationErrors) {
+            errors.check(self.workers > 0, "workers", "must be greater than 0");
+            errors.check(self.min <= self.max, "min", "must be lower than max");
+        }
+    }
+
+    let errors = validate(&Config {
+        workers: 0,
+        min: 2,
+        max: 1,
+    })
+    .unwrap_err();
+    assert_eq!(
+        errors.to_string(),
+        "Invalid configuration:\n  - workers: must be greater than 0\n  - min: must be lower than max"
+    );
+    assert!(validate(&Config {
+        workers: 1,
+        min: 1,
+        max: 1
+    })
+    .is_ok());
+}
@@ -0,0 +1,238 @@
+//! Manipulation of configuration documents as YAML values
+
+use anyhow::{bail, Context};
+use serde::de::DeserializeOwned;
+use serde_yaml::{Mapping, Value};
+
+/// Separator of the nested fields in environment variable names, eg. `DATABASE__URL`
+pub(crate) const NESTED_SEPARATOR: &str = "__";
+
+/// Parses a raw override (eg. an environment variable value) as a YAML scalar, so `8080` and
+/// `true` are a number and a boolean. Anything else is kept as a string.
+pub(crate) fn parse_scalar(raw: &str) -> Value {
+    if raw.is_empty() {
+        return Value::String(String::new());
+    }
+    match serde_yaml::from_str::<Value>(raw) {
+        Ok(value @ (Value::Bool(_) | Value::Number(_))) => value,
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+/// Sets the value at `path` in `doc`, creating (or replacing by) mappings on the way
+pub(crate) fn set_path(doc: &mut Value, path: &[String], value: Value) {
+    let Some((key, rest)) = path.split_first() else {
+        *doc = value;
+        return;
+    };
+    if !doc.is_mapping() {
+        *doc = Value::Mapping(Mapping::new());
+    }
+    let mapping = doc.as_mapping_mut().unwrap();
+    let key = Value::String(key.clone());
+    let child = mapping.entry(key).or_insert(Value::Null);
+    set_path(child, rest, value);
+}
+
+/// Path of the field overridden by an environment variable: `DATABASE__POOL_SIZE` is
+/// `database.pool_size`
+pub(crate) fn env_path(name: &str) -> Vec<String> {
+    name.split(NESTED_SEPARATOR)
+        .map(|segment| segment.to_lowercase())
+        .collect()
+}
+
+/// Value at `path` in `doc`, if any
+pub(crate) fn get_path<'a>(doc: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(doc, |doc, key| doc.get(key.as_str()))
+}
+
+/// Applies the environment variables as overrides of the document fields. Variables naming a
+/// mapping or a list of the document (eg. `USER` for a `user` section) are ignored.
+///
+/// The values are set as strings, so `0x1F` or `+1` are kept as is in string fields: they are
+/// only parsed as numbers or booleans for the fields of these types, see [`from_value`].
+pub(crate) fn apply_env_overrides<I>(doc: &mut Value, vars: I)
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    for (name, value) in vars {
+        let path = env_path(&name);
+        if matches!(
+            get_path(doc, &path),
+            Some(Value::Mapping(_) | Value::Sequence(_))
+        ) {
+            continue;
+        }
+        set_path(doc, &path, Value::String(value));
+    }
+}
+
+/// Document built from environment variables, nested with `__` (see [`env_path`]). Mappings
+/// with numeric keys only are lists: `HOSTS__0` and `HOSTS__1` make the `hosts` list. Fails
+/// if an index is missing, like `HOSTS__0` and `HOSTS__2` without `HOSTS__1`.
+pub(crate) fn nested_document<I>(vars: I) -> anyhow::Result<Value>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut doc = env_document(vars);
+    index_sequences(&mut doc, "", true)?;
+    Ok(doc)
+}
+
+/// Document built from the whole process environment, like [`nested_document`] except that
+/// mappings with missing indexes are kept as mappings: unrelated variables like `FOO__1`
+/// without `FOO__0` are plain keys, which only fail to deserialize if they name a list field
+pub(crate) fn lenient_nested_document<I>(vars: I) -> Value
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut doc = env_document(vars);
+    // cannot fail when not strict
+    let _ = index_sequences(&mut doc, "", false);
+    doc
+}
+
+fn env_document<I>(vars: I) -> Value
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut doc = Value::Mapping(Mapping::new());
+    for (name, value) in vars {
+        set_path(&mut doc, &env_path(&name), parse_scalar(&value));
+    }
+    doc
+}
+
+/// Turns the mappings of `doc` with numeric keys only into lists, `path` being the one of `doc`
+fn index_sequences(doc: &mut Value, path: &str, strict: bool) -> anyhow::Result<()> {
+    let Value::Mapping(mapping) = doc else {
+        return Ok(());
+    };
+    for (key, value) in mapping.iter_mut() {
+        let key = key.as_str().unwrap_or_default();
+        match path {
+            "" => index_sequences(value, key, strict)?,
+            _ => index_sequences(value, &format!("{path}.{key}"), strict)?,
+        }
+    }
+    let indexes: Option<Vec<usize>> = mapping
+        .keys()
+        .map(|key| {
+            let key = key.as_str()?;
+            key.parse()
+                .ok()
+                .filter(|index: &usize| index.to_string() == key)
+        })
+        .collect();
+    if let Some(mut indexes) = indexes.filter(|indexes| !indexes.is_empty()) {
+        indexes.sort_unstable();
+        if let Some(missing) = (0..indexes.len()).find(|&i| indexes[i] != i) {
+            if !strict {
+                return Ok(());
+            }
+            bail!("Missing index {missing} of the `{path}` list");
+        }
+        let sequence = indexes
+            .into_iter()
+            .filter_map(|index| mapping.remove(index.to_string()))
+            .collect();
+        *doc = Value::Sequence(sequence);
+    }
+    Ok(())
+}
+
+/// Merges `overlay` into `base`: mappings are merged recursively, other values of `overlay`
+/// replace the ones of `base`
+pub(crate) fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Deserializes a document, reporting all the missing and invalid fields. It goes through
+/// YAML text so scalars coerce like in a file, and the strings of the fields expecting a
+/// number or a boolean are parsed (eg. an overridden `8080` can be read as a number).
+pub(crate) fn from_value<C: DeserializeOwned>(doc: &Value) -> anyhow::Result<C> {
+    super::collect::from_document(doc).context("Cannot parse configuration")
+}
+
+#[cfg(test)]
+#[test]
+fn env_overrides() {
+    #[derive(serde::Deserialize)]
+    struct Database {
+        url: String,
+        pool_size: u32,
+    }
+    #[derive(serde::Deserialize)]
+    struct Config {
+        port: String,
+        debug: bool,
+        api_key: String,
+        database: Database,
+    }
+
+    let mut doc: Value = serde_yaml::from_str(
+        "port: '80'\ndebug: false\napi_key: k\ndatabase: { url: db, pool_size: 4 }",
+    )
+    .unwrap();
+    apply_env_overrides(
+        &mut doc,
+        [
+            ("PORT".to_string(), "8080".to_string()),
+            ("DEBUG".to_string(), "true".to_string()),
+            ("API_KEY".to_string(), "0x1F".to_string()),
+            ("DATABASE__POOL_SIZE".to_string(), "10".to_string()),
+            ("DATABASE".to_string(), "ignored".to_string()),
+        ],
+    );
+    let config: Config = from_value(&doc).unwrap();
+    assert_eq!(config.port, "8080");
+    assert_eq!(config.api_key, "0x1F");
+    assert!(config.debug);
+    assert_eq!(config.database.url, "db");
+    assert_eq!(config.database.pool_size, 10);
+}
+
+#[cfg(test)]
+#[test]
+fn nested_env() {
+    let var = |name: &str, value: &str| (name.to_string(), value.to_string());
+    let doc = nested_document([
+        var("DATABASE__HOST", "db"),
+        var("DATABASE__PORT", "5432"),
+        var("HOSTS__1", "b"),
+        var("HOSTS__0", "a"),
+        var("NAME", "orders"),
+    ])
+    .unwrap();
+    let expected: Value =
+        serde_yaml::from_str("database: { host: db, port: 5432 }\nhosts: [a, b]\nname: orders")
+            .unwrap();
+    assert_eq!(doc, expected);
+
+    let error = nested_document([
+        var("DATABASE__HOSTS__0", "a"),
+        var("DATABASE__HOSTS__2", "c"),
+    ])
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Missing index 1 of the `database.hosts` list"
+    );
+
+    let doc = lenient_nested_document([var("HOSTS__0", "a"), var("GPU__1", "nvidia")]);
+    let expected: Value = serde_yaml::from_str("hosts: [a]\ngpu: { '1': nvidia }").unwrap();
+    assert_eq!(doc, expected);
+}
@@ -0,0 +1,334 @@
+//! Secrets fetched from HashiCorp Vault (KV v2)
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    fmt,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value as Json;
+use serde_yaml::Value;
+
+use super::{read_source, LoadConfigMode, Source};
+use crate::{redact::REDACTED, ServiceDef};
+
+/// Prefix of the configuration values referencing a Vault secret
+const VAULT_PREFIX: &str = "vault:";
+
+const K8S_TOKEN_PATH: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+
+/// How to authenticate to Vault
+#[derive(Clone)]
+pub enum VaultAuth {
+    Token(String),
+    /// Kubernetes auth method, with the service account token of the pod
+    Kubernetes {
+        role: String,
+        /// Mount path of the auth method (default: `kubernetes`)
+        mount: String,
+        /// Path of the service account token
+        jwt_path: String,
+    },
+}
+
+/// Vault server and credentials. Tokens are redacted from the `Debug` output.
+#[derive(Clone)]
+pub struct VaultConfig {
+    /// Address of the server, like `https://vault.internal:8200`
+    pub addr: String,
+    pub auth: VaultAuth,
+    pub timeout: Duration,
+}
+
+impl VaultConfig {
+    /// Reads the Vault configuration from the environment: `VAULT_ADDR`, then `VAULT_TOKEN`
+    /// or `VAULT_K8S_ROLE` (and optionally `VAULT_K8S_MOUNT`) for the Kubernetes auth method.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let addr = var("VAULT_ADDR").context("VAULT_ADDR is not defined")?;
+        let auth = match (var("VAULT_TOKEN"), var("VAULT_K8S_ROLE")) {
+            (Some(token), _) => VaultAuth::Token(token),
+            (None, Some(role)) => VaultAuth::Kubernetes {
+                role,
+                mount: var("VAULT_K8S_MOUNT").unwrap_or_else(|| "kubernetes".to_string()),
+                jwt_path: K8S_TOKEN_PATH.to_string(),
+            },
+            (None, None) => bail!("Neither VAULT_TOKEN nor VAULT_K8S_ROLE is defined"),
+        };
+        Ok(Self {
+            addr,
+            auth,
+            timeout: Duration::from_secs(5),
+        })
+    }
+}
+
+impl fmt::Debug for VaultAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VaultAuth::Token(_) => f.debug_tuple("Token").field(&REDACTED).finish(),
+            VaultAuth::Kubernetes {
+                role,
+                mount,
+                jwt_path,
+            } => f
+                .debug_struct("Kubernetes")
+                .field("role", role)
+                .field("mount", mount)
+                .field("jwt_path", jwt_path)
+                .finish(),
+        }
+    }
+}
+
+impl fmt::Debug for VaultConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VaultConfig")
+            .field("addr", &self.addr)
+            .field("auth", &self.auth)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// Loads the configuration (see [`load_config`](super::load_config)), replacing the values
+/// referencing a Vault secret by the secret.
+///
+/// A reference is a string value `vault:<path>#<key>`, `<path>` being the API path of a KV
+/// v2 secret (with its `data/` segment): `password: vault:secret/data/orders/db#password`.
+/// In the environment modes, environment variables may hold references.
+pub async fn load_config_with_vault<C: DeserializeOwned>(
+    config_mode: LoadConfigMode<'_>,
+    vault: &VaultConfig,
+    service_def: &ServiceDef<'_>,
+) -> anyhow::Result<C> {
+    let mut source = read_source(config_mode, None, service_def)?;
+    resolve(&mut source, vault).await?;
+    source.deserialize()
+}
+
+/// Replaces the Vault references of `source`
+async fn resolve(source: &mut Source, vault: &VaultConfig) -> anyhow::Result<()> {
+    let mut refs = vec![];
+    match source {
+        Source::Env { vars, .. } => {
+            refs.extend(vars.values().map(String::as_str).filter_map(parse_ref))
+        }
+        Source::Document(doc) => collect_refs(doc, &mut refs),
+    }
+    if refs.is_empty() {
+        return Ok(());
+    }
+
+    let client = VaultClient::login(vault).await?;
+    let mut secrets = BTreeMap::new();
+    for (path, _) in refs {
+        if let Entry::Vacant(entry) = secrets.entry(path) {
+            let secret = client.read(entry.key()).await?;
+            entry.insert(secret);
+        }
+    }
+
+    let mut missing = vec![];
+    let mut lookup = |reference: &str| -> Option<String> {
+        let (path, key) = parse_ref(reference)?;
+        match secrets.get(&path).and_then(|secret| secret.get(&key)) {
+            Some(Json::String(value)) => Some(value.clone()),
+            Some(value) => Some(value.to_string()),
+            None => {
+                missing.push(format!("{path}#{key}"));
+                None
+            }
+        }
+    };
+    match source {
+        Source::Env { vars, .. } => {
+            for value in vars.values_mut() {
+                if let Some(secret) = lookup(value) {
+                    *value = secret;
+                }
+            }
+        }
+        Source::Document(doc) => replace_refs(doc, &mut lookup),
+    }
+    if !missing.is_empty() {
+        bail!("Missing Vault secrets: {}", missing.join(", "));
+    }
+    Ok(())
+}
+
+/// Path and key of a `vault:<path>#<key>` reference
+fn parse_ref(value: &str) -> Option<(String, String)> {
+    let (path, key) = value.strip_prefix(VAULT_PREFIX)?.split_once('#')?;
+    Some((path.trim_matches('/').to_string(), key.to_string()))
+}
+
+fn collect_refs(doc: &Value, refs: &mut Vec<(String, String)>) {
+    match doc {
+        Value::String(s) => refs.extend(parse_ref(s)),
+        Value::Sequence(seq) => seq.iter().for_each(|v| collect_refs(v, refs)),
+        Value::Mapping(mapping) => mapping.values().for_each(|v| collect_refs(v, refs)),
+        Value::Tagged(tagged) => collect_refs(&tagged.value, refs),
+        _ => {}
+    }
+}
+
+fn replace_refs<F>(doc: &mut Value, lookup: &mut F)
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    match doc {
+        Value::String(s) => {
+            if let Some(secret) = lookup(s) {
+                *s = secret;
+            }
+        }
+        Value::Sequence(seq) => seq.iter_mut().for_each(|v| replace_refs(v, lookup)),
+        Value::Mapping(mapping) => mapping.values_mut().for_each(|v| replace_refs(v, lookup)),
+        Value::Tagged(tagged) => replace_refs(&mut tagged.value, lookup),
+        _ => {}
+    }
+}
+
+struct VaultClient {
+    client: reqwest::Client,
+    addr: String,
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    auth: LoginAuth,
+}
+
+#[derive(Deserialize)]
+struct LoginAuth {
+    client_token: String,
+}
+
+#[derive(Deserialize)]
+struct SecretResponse {
+    data: SecretData,
+}
+
+#[derive(Deserialize)]
+struct SecretData {
+    data: BTreeMap<String, Json>,
+}
+
+impl VaultClient {
+    async fn login(config: &VaultConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder().timeout(config.timeout).build()?;
+        let addr = config.addr.trim_end_matches('/').to_string();
+        let token = match &config.auth {
+            VaultAuth::Token(token) => token.clone(),
+            VaultAuth::Kubernetes {
+                role,
+                mount,
+                jwt_path,
+            } => {
+                let jwt = std::fs::read_to_string(jwt_path)
+                    .with_context(|| format!("Cannot read service account token {jwt_path}"))?;
+                let resp: LoginResponse = client
+                    .post(format!("{addr}/v1/auth/{mount}/login"))
+                    .json(&serde_json::json!({ "role": role, "jwt": jwt.trim() }))
+                    .send()
+                    .await
+                    .context("Cannot log in to Vault")?
+                    .error_for_status()
+                    .context("Vault login rejected")?
+                    .json()
+                    .await
+                    .context("Cannot parse Vault login response")?;
+                resp.auth.client_token
+            }
+        };
+        Ok(Self {
+            client,
+            addr,
+            token,
+        })
+    }
+
+    /// Fields of the KV v2 secret at `path`
+    async fn read(&self, path: &str) -> anyhow::Result<BTreeMap<String, Json>> {
+        let resp: SecretResponse = self
+            .client
+            .get(format!("{}/v1/{path}", self.addr))
+            .header("x-vault-token", &self.token)
+            .send()
+            .await
+            .with_context(|| format!("Cannot read Vault secret {path}"))?
+            .error_for_status()
+            .with_context(|| format!("Cannot read Vault secret {path}"))?
+            .json()
+            .await
+            .with_context(|| format!("Cannot parse Vault secret {path}"))?;
+        Ok(resp.data.data)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use http::Method;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::testing::{ConfigFixture, FakeResponse, FakeUpstream};
+
+    #[derive(Deserialize, Debug)]
+    struct Config {
+        user: String,
+        password: String,
+        port: u16,
+    }
+
+    const SERVICE: ServiceDef = ServiceDef::new("vaulted", "1.0.0", "");
+
+    #[tokio::test]
+    async fn replaces_vault_references() {
+        let vault = FakeUpstream::start().await;
+        vault.on(
+            Method::GET,
+            "/v1/secret/data/db",
+            FakeResponse::json(r#"{"data":{"data":{"password":"s3cr3t"}}}"#),
+        );
+        let fixture = ConfigFixture::new().file(
+            "config.yaml",
+            "user: app\npassword: vault:secret/data/db#password\nport: 5432",
+        );
+        let path = fixture.path("config.yaml");
+        let config = VaultConfig {
+            addr: vault.url(""),
+            auth: VaultAuth::Token("root".to_string()),
+            timeout: Duration::from_secs(1),
+        };
+
+        let loaded: Config =
+            load_config_with_vault(LoadConfigMode::FileOnly(path.to_str()), &config, &SERVICE)
+                .await
+                .unwrap();
+        assert_eq!(loaded.user, "app");
+        assert_eq!(loaded.password, "s3cr3t");
+        assert_eq!(loaded.port, 5432);
+
+        std::fs::write(
+            &path,
+            "user: vault:secret/data/db#user\npassword: x\nport: 1",
+        )
+        .unwrap();
+        let err = load_config_with_vault::<Config>(
+            LoadConfigMode::FileOnly(path.to_str()),
+            &config,
+            &SERVICE,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing Vault secrets: secret/data/db#user"
+        );
+    }
+}
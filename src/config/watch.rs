@@ -0,0 +1,178 @@
+//! Hot-reloadable configuration
+
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::watch;
+
+use super::{load, log_config_diff, LoadConfigMode};
+use crate::ServiceDef;
+
+/// Interval at which [`load_config_watched`] checks the configuration file
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Loads the configuration (see [`load_config`](super::load_config)), then reloads it when the
+/// configuration file changes or the process receives `SIGHUP`.
+///
+/// The file is polled every 5 seconds, which also catches the symlink swaps of mounted
+/// Kubernetes ConfigMaps. A reload failing (invalid file...) is logged and the previous
+/// configuration is kept. The changed fields of a reloaded configuration are logged,
+/// redacted (see [`log_config_diff`]). Reloading stops when all the receivers are dropped.
+///
+/// Must be called from a tokio runtime.
+///
+/// ```ignore
+/// let mut config = load_config_watched::<Tunables>(LoadConfigMode::FileOnly(None), &SERVICE)?;
+/// tokio::spawn(async move {
+///     while config.changed().await.is_ok() {
+///         let tunables = config.borrow_and_update();
+///         // apply the new log level, rate limits...
+///     }
+/// });
+/// ```
+pub fn load_config_watched<C>(
+    config_mode: LoadConfigMode,
+    service_def: &ServiceDef<'static>,
+) -> anyhow::Result<watch::Receiver<C>>
+where
+    C: DeserializeOwned + Serialize + Send + Sync + 'static,
+{
+    load_config_watched_every(config_mode, DEFAULT_POLL_INTERVAL, service_def)
+}
+
+/// Same as [`load_config_watched`], polling the configuration file every `interval`
+pub fn load_config_watched_every<C>(
+    config_mode: LoadConfigMode,
+    interval: Duration,
+    service_def: &ServiceDef<'static>,
+) -> anyhow::Result<watch::Receiver<C>>
+where
+    C: DeserializeOwned + Serialize + Send + Sync + 'static,
+{
+    let config: C = load(config_mode, None, service_def)?;
+    register_config(&config);
+    let (tx, rx) = watch::channel(config);
+
+    let service_def = *service_def;
+    let path = config_mode.path(&service_def);
+    let mode = config_mode.with_file(None);
+    tokio::spawn(async move {
+        let path_str = path
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned());
+        let mut last_modified = path.as_deref().and_then(modified);
+        let mut poll = tokio::time::interval(interval);
+        poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut hangup = Hangup::new();
+        loop {
+            tokio::select! {
+                _ = tx.closed() => break,
+                _ = hangup.recv() => {
+                    log::info!("SIGHUP received, reloading configuration");
+                }
+                _ = poll.tick() => {
+                    let modified = path.as_deref().and_then(modified);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+                    log::info!("Configuration file changed, reloading configuration");
+                }
+            }
+            match load(mode.with_file(path_str.as_deref()), None, &service_def) {
+                Ok(config) => {
+                    log_config_diff(&*tx.borrow(), &config);
+                    register_config(&config);
+                    tx.send_replace(config);
+                }
+                Err(err) => {
+                    log::error!("Cannot reload configuration, keeping the previous one: {err:#}")
+                }
+            }
+        }
+    });
+    Ok(rx)
+}
+
+fn register_config<C: Serialize>(config: &C) {
+    if let Err(err) = crate::info::register_config(config) {
+        log::warn!("Cannot register the configuration: {err:#}");
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// `SIGHUP` listener, never firing on non-unix platforms
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let signal = signal(SignalKind::hangup())
+                .map_err(|err| log::warn!("Cannot listen to SIGHUP: {err}"))
+                .ok();
+            Self { signal }
+        }
+        #[cfg(not(unix))]
+        Self {}
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+        std::future::pending::<()>().await
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::testing::ConfigFixture;
+
+    #[derive(Deserialize, Serialize)]
+    struct Tunables {
+        rate_limit: u32,
+    }
+
+    const SERVICE: ServiceDef = ServiceDef::new("watched", "1.0.0", "");
+
+    #[tokio::test]
+    async fn reloads_changed_file() {
+        let fixture = ConfigFixture::new().file("config.yaml", "rate_limit: 10");
+        let path = fixture.path("config.yaml");
+        let mut config: watch::Receiver<Tunables> = load_config_watched_every(
+            LoadConfigMode::FileOnly(path.to_str()),
+            Duration::from_millis(20),
+            &SERVICE,
+        )
+        .unwrap();
+        assert_eq!(config.borrow_and_update().rate_limit, 10);
+
+        // invalid files are ignored
+        std::fs::write(&path, "rate_limit: lots").unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!config.has_changed().unwrap());
+
+        std::fs::write(&path, "rate_limit: 20").unwrap();
+        tokio::time::timeout(Duration::from_secs(5), config.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.borrow().rate_limit, 20);
+    }
+}
@@ -0,0 +1,355 @@
+//! Catalog of the downstream dependencies of the service, exposed to on-call engineers by an
+//! admin endpoint.
+//!
+//! Dependencies are declared with [`register_dependency`]. HTTP upstreams called through the
+//! `reqwest` metrics middleware are added automatically, with the status of their last call.
+//!
+//! The behavior of each dependency (base URL, timeout, retries, circuit breaker, auth) is
+//! configured in a standard `[dependencies.<name>]` section, see [`DependenciesConfig`].
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::redact::{redact_url, redact_value, REDACTED};
+
+type StatusCheck = Arc<dyn Fn() -> DependencyStatus + Send + Sync>;
+
+/// Dependencies, by name
+static CATALOG: Mutex<BTreeMap<String, Dependency>> = Mutex::new(BTreeMap::new());
+
+/// Size of the catalog above which the dependencies reported by calls are not added, so a
+/// service calling URLs built from user input does not grow it without bound
+const MAX_REPORTED_DEPENDENCIES: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyStatus {
+    Up,
+    Down,
+    Unknown,
+}
+
+/// A declared dependency. Its target URL and configuration are redacted when declared.
+#[derive(Clone)]
+pub struct Dependency {
+    name: String,
+    kind: String,
+    target: Option<String>,
+    config: BTreeMap<String, String>,
+    status: DependencyStatus,
+    check: Option<StatusCheck>,
+}
+
+impl fmt::Debug for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dependency")
+            .field("name", &self.name)
+            .field("kind", &self.kind)
+            .field("target", &self.target)
+            .field("config", &self.config)
+            .field("status", &self.status)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Dependency {
+    /// A dependency named `name` of kind `kind` (eg. `http`, `postgres`, `kafka`)
+    pub fn new(name: impl Into<String>, kind: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            kind: kind.into(),
+            target: None,
+            config: BTreeMap::new(),
+            status: DependencyStatus::Unknown,
+            check: None,
+        }
+    }
+
+    /// URL (or address) of the dependency, its password and secret query parameters are
+    /// redacted
+    pub fn target(mut self, target: &str) -> Self {
+        self.target = Some(redact_url(target));
+        self
+    }
+
+    /// Adds a configuration entry, redacted when its key names a secret
+    pub fn config(mut self, key: impl Into<String>, value: impl ToString) -> Self {
+        let key = key.into();
+        let value = redact_value(&key, &value.to_string());
+        self.config.insert(key, value);
+        self
+    }
+
+    /// Function returning the current status of the dependency when the catalog is listed
+    pub fn check<F>(mut self, check: F) -> Self
+    where
+        F: Fn() -> DependencyStatus + Send + Sync + 'static,
+    {
+        self.check = Some(Arc::new(check));
+        self
+    }
+}
+
+/// A dependency as listed by the admin endpoint
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DependencyInfo {
+    pub name: String,
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub status: DependencyStatus,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config: BTreeMap<String, String>,
+}
+
+/// Declares a dependency, replacing any dependency with the same name
+pub fn register_dependency(dependency: Dependency) {
+    CATALOG
+        .lock()
+        .unwrap()
+        .insert(dependency.name.clone(), dependency);
+}
+
+/// Reports the status of a dependency observed by a call. Unknown dependencies are declared
+/// with kind `kind`, unless the catalog has 100 dependencies already.
+pub fn report_status(name: &str, kind: &str, status: DependencyStatus) {
+    report_status_in(&mut CATALOG.lock().unwrap(), name, kind, status);
+}
+
+fn report_status_in(
+    catalog: &mut BTreeMap<String, Dependency>,
+    name: &str,
+    kind: &str,
+    status: DependencyStatus,
+) {
+    if let Some(dependency) = catalog.get_mut(name) {
+        dependency.status = status;
+    } else if catalog.len() < MAX_REPORTED_DEPENDENCIES {
+        let mut dependency = Dependency::new(name, kind);
+        dependency.status = status;
+        catalog.insert(name.to_string(), dependency);
+    } else {
+        log::debug!("Dependency catalog full, {name} is not added");
+    }
+}
+
+/// Lists the dependencies with their current status
+pub fn dependencies() -> Vec<DependencyInfo> {
+    let catalog: Vec<Dependency> = CATALOG.lock().unwrap().values().cloned().collect();
+    // checks run outside of the lock as they may report statuses
+    catalog
+        .into_iter()
+        .map(|dependency| DependencyInfo {
+            status: match &dependency.check {
+                Some(check) => check(),
+                None => dependency.status,
+            },
+            name: dependency.name,
+            kind: dependency.kind,
+            target: dependency.target,
+            config: dependency.config,
+        })
+        .collect()
+}
+
+/// `[dependencies]` section of the service configuration, by dependency name:
+///
+/// ```toml
+/// [dependencies.users]
+/// base_url = "http://users:8080"
+/// timeout_ms = 2000
+/// retries = 2
+/// circuit_breaker = { failure_threshold = 5, open_duration_ms = 30000 }
+/// auth = { type = "bearer", token = "..." }
+/// ```
+pub type DependenciesConfig = BTreeMap<String, DependencyConfig>;
+
+/// Configuration of a dependency
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct DependencyConfig {
+    pub base_url: Option<String>,
+    /// Timeout of a whole call, retries and the delays before them included, in milliseconds
+    /// (default: 5000)
+    pub timeout_ms: u64,
+    /// Retries of the failed calls that are safe to retry (default: 0)
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each retry, in milliseconds (default: 100)
+    pub retry_backoff_ms: u64,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    pub auth: Option<DependencyAuth>,
+}
+
+impl Default for DependencyConfig {
+    fn default() -> Self {
+        Self {
+            base_url: None,
+            timeout_ms: 5000,
+            retries: 0,
+            retry_backoff_ms: 100,
+            circuit_breaker: None,
+            auth: None,
+        }
+    }
+}
+
+impl DependencyConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// Delay before the retry `attempt` (starting at 0)
+    pub fn retry_backoff(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.retry_backoff_ms.saturating_mul(1 << attempt.min(16)))
+    }
+
+    /// URL of `path` relative to the base URL
+    pub fn url(&self, path: &str) -> String {
+        match &self.base_url {
+            Some(base_url) => format!(
+                "{}/{}",
+                base_url.trim_end_matches('/'),
+                path.trim_start_matches('/')
+            ),
+            None => path.to_string(),
+        }
+    }
+
+    /// Declares the dependency `name` of kind `kind` in the catalog, with this configuration
+    pub fn register(&self, name: &str, kind: &str) {
+        let mut dependency = Dependency::new(name, kind)
+            .config("timeout_ms", self.timeout_ms)
+            .config("retries", self.retries);
+        if let Some(base_url) = &self.base_url {
+            dependency = dependency.target(base_url);
+        }
+        if let Some(circuit_breaker) = &self.circuit_breaker {
+            dependency = dependency
+                .config(
+                    "circuit_breaker.failure_threshold",
+                    circuit_breaker.failure_threshold,
+                )
+                .config(
+                    "circuit_breaker.open_duration_ms",
+                    circuit_breaker.open_duration_ms,
+                );
+        }
+        if let Some(auth) = &self.auth {
+            dependency = dependency.config("auth", auth.kind());
+        }
+        register_dependency(dependency);
+    }
+}
+
+/// Stops calling a failing dependency: after `failure_threshold` consecutive failures, calls
+/// fail immediately for `open_duration_ms`. A single trial call is then let through: the
+/// breaker closes if it succeeds, and opens again otherwise.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Default: 5
+    pub failure_threshold: u32,
+    /// Default: 30000
+    pub open_duration_ms: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_duration_ms: 30_000,
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    pub fn open_duration(&self) -> Duration {
+        Duration::from_millis(self.open_duration_ms)
+    }
+}
+
+/// Authentication of the calls to a dependency. Secrets are redacted from the `Debug` output.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DependencyAuth {
+    /// `Authorization: Bearer <token>`
+    Bearer { token: String },
+    /// `Authorization: Basic ...`
+    Basic { username: String, password: String },
+    /// A custom header, eg. `X-Api-Key`
+    Header { name: String, value: String },
+}
+
+impl DependencyAuth {
+    fn kind(&self) -> &'static str {
+        match self {
+            DependencyAuth::Bearer { .. } => "bearer",
+            DependencyAuth::Basic { .. } => "basic",
+            DependencyAuth::Header { .. } => "header",
+        }
+    }
+}
+
+impl fmt::Debug for DependencyAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DependencyAuth::Bearer { .. } => {
+                f.debug_struct("Bearer").field("token", &REDACTED).finish()
+            }
+            DependencyAuth::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &REDACTED)
+                .finish(),
+            DependencyAuth::Header { name, .. } => f
+                .debug_struct("Header")
+                .field("name", name)
+                .field("value", &REDACTED)
+                .finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn lists_redacted_dependencies() {
+    register_dependency(
+        Dependency::new("catalog-test-db", "postgres")
+            .target("postgres://app:s3cr3t@db:5432/app")
+            .config("pool_size", 10)
+            .config("password", "s3cr3t")
+            .check(|| DependencyStatus::Up),
+    );
+    report_status("catalog-test-api", "http", DependencyStatus::Down);
+
+    let listed = dependencies();
+    let db = listed.iter().find(|d| d.name == "catalog-test-db").unwrap();
+    assert_eq!(db.status, DependencyStatus::Up);
+    assert_eq!(db.target.as_deref(), Some("postgres://app:***@db:5432/app"));
+    assert_eq!(db.config["password"], "***");
+    assert_eq!(db.config["pool_size"], "10");
+    let api = listed
+        .iter()
+        .find(|d| d.name == "catalog-test-api")
+        .unwrap();
+    assert_eq!(api.status, DependencyStatus::Down);
+
+    let mut catalog = BTreeMap::new();
+    for i in 0..MAX_REPORTED_DEPENDENCIES + 10 {
+        report_status_in(
+            &mut catalog,
+            &format!("host-{i}"),
+            "http",
+            DependencyStatus::Up,
+        );
+    }
+    assert_eq!(catalog.len(), MAX_REPORTED_DEPENDENCIES);
+    report_status_in(&mut catalog, "host-0", "http", DependencyStatus::Down);
+    assert_eq!(catalog["host-0"].status, DependencyStatus::Down);
+}
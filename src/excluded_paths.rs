@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+/// Set of request paths excluded from metrics (or logs), either by exact match or by prefix.
+/// A prefix matches whole segments: `/ping` excludes `/ping` and `/ping/db`, not `/pingpong`.
+///
+/// The default excludes exactly `/metrics` and `/health`, and the probes below `/health/`.
+///
+/// In the configuration, it is either a list of prefixes, replacing the default ones
+/// (`excluded_paths: [/metrics, /health, /internal]`), or the sets of exact paths and of
+/// prefixes, the missing one being the default (`excluded_paths: { exact: [/favicon.ico] }`).
+///
+/// ```
+/// use service_helpe_rs::excluded_paths::ExcludedPaths;
+///
+/// let excluded = ExcludedPaths::default()
+///     .exact("/favicon.ico")
+///     .prefix("/internal/");
+/// assert!(excluded.is_excluded("/internal/probe"));
+/// assert!(!excluded.is_excluded("/users"));
+/// ```
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(from = "ExcludedPathsConfig")]
+pub struct ExcludedPaths {
+    pub exact: Vec<String>,
+    pub prefixes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExcludedPathsConfig {
+    Prefixes(Vec<String>),
+    Sets {
+        #[serde(default = "default_exact")]
+        exact: Vec<String>,
+        #[serde(default = "default_prefixes")]
+        prefixes: Vec<String>,
+    },
+}
+
+fn default_exact() -> Vec<String> {
+    ExcludedPaths::default().exact
+}
+
+fn default_prefixes() -> Vec<String> {
+    ExcludedPaths::default().prefixes
+}
+
+impl From<ExcludedPathsConfig> for ExcludedPaths {
+    fn from(config: ExcludedPathsConfig) -> Self {
+        match config {
+            ExcludedPathsConfig::Prefixes(prefixes) => Self {
+                exact: vec![],
+                prefixes,
+            },
+            ExcludedPathsConfig::Sets { exact, prefixes } => Self { exact, prefixes },
+        }
+    }
+}
+
+impl ExcludedPaths {
+    /// Excludes nothing
+    pub fn none() -> Self {
+        Self {
+            exact: vec![],
+            prefixes: vec![],
+        }
+    }
+
+    /// Adds a path excluded when it matches exactly
+    pub fn exact(mut self, path: impl Into<String>) -> Self {
+        self.exact.push(path.into());
+        self
+    }
+
+    /// Adds a prefix excluding the path and every path below it, a trailing `/` being ignored
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefixes.push(prefix.into());
+        self
+    }
+
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.exact.iter().any(|p| p == path)
+            || self.prefixes.iter().any(|prefix| {
+                let prefix = prefix.trim_end_matches('/');
+                path.strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+    }
+}
+
+impl Default for ExcludedPaths {
+    fn default() -> Self {
+        Self::none()
+            .exact("/metrics")
+            .exact("/health")
+            .prefix("/health/")
+    }
+}
@@ -0,0 +1,267 @@
+//! Metrics of gRPC servers (eg. tonic), with the same conventions as the HTTP ones
+
+use std::{
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use futures::future::BoxFuture;
+use http::{HeaderMap, Request, Response};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use prometheus::{HistogramVec, IntCounterVec};
+use tower::{Layer, Service};
+
+use crate::metrics::{
+    get_or_create_counter_with_labels, get_or_create_histogram_with_labels,
+    DEFAULT_DURATION_BUCKETS,
+};
+
+/// Tower layer recording, in the default registry:
+/// - `grpc_server_handled_total` counter labelled by `service`, `method` and `code`
+/// - `grpc_server_handling_seconds` histogram labelled by `service` and `method`
+///
+/// The code is read from the `grpc-status` header of trailers-only responses, or from the
+/// trailers. Streams dropped before their trailers are reported as `Canceled`.
+///
+/// ```ignore
+/// Server::builder()
+///     .layer(GrpcMetricsLayer::new())
+///     .add_service(GreeterServer::new(greeter))
+///     .serve(addr)
+///     .await?;
+/// ```
+#[derive(Clone)]
+pub struct GrpcMetricsLayer {
+    metrics: Arc<GrpcMetrics>,
+}
+
+struct GrpcMetrics {
+    handled: IntCounterVec,
+    duration: HistogramVec,
+}
+
+impl GrpcMetricsLayer {
+    pub fn new() -> Self {
+        Self {
+            metrics: Arc::new(GrpcMetrics {
+                handled: get_or_create_counter_with_labels(
+                    "grpc_server_handled_total",
+                    "gRPC calls handled",
+                    &["service", "method", "code"],
+                ),
+                duration: get_or_create_histogram_with_labels(
+                    "grpc_server_handling_seconds",
+                    "gRPC calls duration",
+                    &["service", "method"],
+                    DEFAULT_DURATION_BUCKETS.to_vec(),
+                ),
+            }),
+        }
+    }
+}
+
+impl Default for GrpcMetricsLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for GrpcMetricsLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GrpcMetricsLayer").finish_non_exhaustive()
+    }
+}
+
+impl<S> Layer<S> for GrpcMetricsLayer {
+    type Service = GrpcMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcMetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+/// Service produced by [`GrpcMetricsLayer`]
+#[derive(Clone)]
+pub struct GrpcMetricsService<S> {
+    inner: S,
+    metrics: Arc<GrpcMetrics>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for GrpcMetricsService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<GrpcBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // paths are `/package.Service/Method`
+        let (service, method) = req
+            .uri()
+            .path()
+            .trim_start_matches('/')
+            .split_once('/')
+            .unwrap_or(("unknown", "unknown"));
+        let recording = Recording {
+            metrics: self.metrics.clone(),
+            service: service.to_string(),
+            method: method.to_string(),
+            start: Instant::now(),
+        };
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let resp = fut.await?;
+            let recording = match grpc_code(resp.headers()) {
+                // trailers-only response
+                Some(code) => {
+                    recording.finish(code);
+                    None
+                }
+                None => Some(recording),
+            };
+            Ok(resp.map(|body| GrpcBody {
+                inner: Box::pin(body),
+                recording,
+            }))
+        })
+    }
+}
+
+struct Recording {
+    metrics: Arc<GrpcMetrics>,
+    service: String,
+    method: String,
+    start: Instant,
+}
+
+impl Recording {
+    fn finish(self, code: &str) {
+        self.metrics
+            .handled
+            .with_label_values(&[&self.service, &self.method, code])
+            .inc();
+        self.metrics
+            .duration
+            .with_label_values(&[&self.service, &self.method])
+            .observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+/// Response body of [`GrpcMetricsService`], records the call when the trailers are sent
+pub struct GrpcBody<B> {
+    inner: Pin<Box<B>>,
+    recording: Option<Recording>,
+}
+
+impl<B> GrpcBody<B> {
+    fn finish(&mut self, code: &str) {
+        if let Some(recording) = self.recording.take() {
+            recording.finish(code);
+        }
+    }
+}
+
+impl<B: HttpBody> HttpBody for GrpcBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let polled = self.inner.as_mut().poll_frame(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(trailers) = frame.trailers_ref() {
+                    let code = grpc_code(trailers).unwrap_or("Unknown");
+                    self.finish(code);
+                }
+            }
+            Poll::Ready(Some(Err(_))) | Poll::Ready(None) => self.finish("Unknown"),
+            Poll::Pending => {}
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> Drop for GrpcBody<B> {
+    fn drop(&mut self) {
+        self.finish("Canceled");
+    }
+}
+
+/// Name of the code in the `grpc-status` header, if any
+fn grpc_code(headers: &HeaderMap) -> Option<&'static str> {
+    let status = headers.get("grpc-status")?.to_str().ok()?.parse().ok()?;
+    Some(code_name(status))
+}
+
+/// Name of a gRPC status code, as used by the other gRPC metrics libraries
+pub fn code_name(code: u8) -> &'static str {
+    match code {
+        0 => "OK",
+        1 => "Canceled",
+        2 => "Unknown",
+        3 => "InvalidArgument",
+        4 => "DeadlineExceeded",
+        5 => "NotFound",
+        6 => "AlreadyExists",
+        7 => "PermissionDenied",
+        8 => "ResourceExhausted",
+        9 => "FailedPrecondition",
+        10 => "Aborted",
+        11 => "OutOfRange",
+        12 => "Unimplemented",
+        13 => "Internal",
+        14 => "Unavailable",
+        15 => "DataLoss",
+        16 => "Unauthenticated",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn records_trailers_only_status() {
+    use tower::ServiceExt;
+
+    let layer = GrpcMetricsLayer::new();
+    let service = layer.layer(tower::service_fn(|_req: Request<String>| async {
+        Ok::<_, std::convert::Infallible>(
+            Response::builder()
+                .header("grpc-status", "5")
+                .body(String::new())
+                .unwrap(),
+        )
+    }));
+    let req = Request::post("/users.v1.Users/Get")
+        .body(String::new())
+        .unwrap();
+    drop(service.oneshot(req).await.unwrap());
+
+    let handled = layer
+        .metrics
+        .handled
+        .with_label_values(&["users.v1.Users", "Get", "NotFound"]);
+    assert_eq!(handled.get(), 1);
+}
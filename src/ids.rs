@@ -0,0 +1,368 @@
+//! Identifier generation shared by request ids, job ids and entity ids.
+//!
+//! Ids are UUIDs, by default UUIDv7 so they sort by creation time. The short form is the
+//! unpadded base64url encoding of the 16 bytes of the UUID (22 characters), as used for the
+//! `tx_id` of the access log. The [sortable short form](to_sortable_short) is the Crockford
+//! base32 encoding (26 characters), which sorts like the UUIDs.
+//!
+//! The ids of a request are all derived from a single UUID by [`RequestIds`], so the access
+//! log, the response headers, the traces and the calls to other services can be correlated.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use anyhow::{bail, Context};
+use data_encoding::BASE64URL_NOPAD;
+use uuid::Uuid;
+
+/// Kind of UUID produced by an [`IdGenerator`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IdKind {
+    /// Random UUID
+    UuidV4,
+    /// Time ordered UUID (RFC 9562)
+    UuidV7,
+}
+
+static DEFAULT_KIND: AtomicU8 = AtomicU8::new(IdKind::UuidV7 as u8);
+
+/// Changes the kind of ids produced by [`IdGenerator::default`] and the functions of this
+/// module. Should be called once at startup.
+pub fn set_default_kind(kind: IdKind) {
+    DEFAULT_KIND.store(kind as u8, Ordering::Relaxed);
+}
+
+/// Generates ids of a given kind
+#[derive(Clone, Copy, Debug)]
+pub struct IdGenerator {
+    kind: IdKind,
+}
+
+impl IdGenerator {
+    pub const fn new(kind: IdKind) -> Self {
+        Self { kind }
+    }
+
+    pub fn kind(&self) -> IdKind {
+        self.kind
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        match self.kind {
+            IdKind::UuidV4 => Uuid::new_v4(),
+            IdKind::UuidV7 => Uuid::now_v7(),
+        }
+    }
+
+    /// New id in its short (base64url) form
+    pub fn short(&self) -> String {
+        to_short(&self.uuid())
+    }
+}
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        let kind = if DEFAULT_KIND.load(Ordering::Relaxed) == IdKind::UuidV4 as u8 {
+            IdKind::UuidV4
+        } else {
+            IdKind::UuidV7
+        };
+        Self::new(kind)
+    }
+}
+
+/// New id from the default generator
+pub fn new_id() -> Uuid {
+    IdGenerator::default().uuid()
+}
+
+/// New id from the default generator, in its short form
+pub fn new_short_id() -> String {
+    IdGenerator::default().short()
+}
+
+/// Short (base64url) form of an id. It does not sort like the ids, see
+/// [`to_sortable_short`].
+pub fn to_short(id: &Uuid) -> String {
+    BASE64URL_NOPAD.encode(id.as_bytes())
+}
+
+/// Parses the short (base64url) form of an id
+pub fn from_short(short: &str) -> anyhow::Result<Uuid> {
+    let bytes = BASE64URL_NOPAD
+        .decode(short.as_bytes())
+        .with_context(|| format!("Invalid short id {short}"))?;
+    if bytes.len() != 16 {
+        bail!(
+            "Invalid short id {short}: expected 16 bytes, got {}",
+            bytes.len()
+        );
+    }
+    Ok(Uuid::from_slice(&bytes)?)
+}
+
+/// Crockford base32 alphabet, in ASCII order so the sortable short ids sort like the UUIDs
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+/// Length of the sortable short form: 128 bits in 5 bits characters
+const SORTABLE_SHORT_LEN: usize = 26;
+
+/// Sortable short (Crockford base32) form of an id, sorting like the ids themselves: the one
+/// of a UUIDv7 is its ULID
+pub fn to_sortable_short(id: &Uuid) -> String {
+    let value = id.as_u128();
+    (0..SORTABLE_SHORT_LEN)
+        .map(|i| {
+            let shift = 5 * (SORTABLE_SHORT_LEN - 1 - i);
+            CROCKFORD[((value >> shift) & 0x1f) as usize] as char
+        })
+        .collect()
+}
+
+/// Parses the sortable short (Crockford base32) form of an id, case insensitive
+pub fn from_sortable_short(short: &str) -> anyhow::Result<Uuid> {
+    if short.len() != SORTABLE_SHORT_LEN {
+        bail!(
+            "Invalid short id {short}: expected {SORTABLE_SHORT_LEN} characters, got {}",
+            short.len()
+        );
+    }
+    let mut value: u128 = 0;
+    for (i, c) in short.bytes().enumerate() {
+        let digit = match c.to_ascii_uppercase() {
+            b'O' => 0,
+            b'I' | b'L' => 1,
+            c => CROCKFORD
+                .iter()
+                .position(|&d| d == c)
+                .with_context(|| format!("Invalid short id {short}"))? as u128,
+        };
+        // the first character only holds 3 bits
+        if i == 0 && digit > 7 {
+            bail!("Invalid short id {short}: out of range");
+        }
+        value = value << 5 | digit;
+    }
+    Ok(Uuid::from_u128(value))
+}
+
+/// Header carrying the request id, in responses and calls to other services
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// W3C trace context header
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Incoming id the ids of a request are derived from, see [`RequestIds::from_incoming`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PrimaryId {
+    /// Ids are always generated, incoming ids are ignored
+    Generated,
+    /// The request id sent by the caller, when it is a UUID or a short id, else the trace id
+    RequestId,
+    /// The trace id of the `traceparent` sent by the caller, else the request id. A valid
+    /// request id sent along is still kept as the request id.
+    TraceId,
+}
+
+static PRIMARY_ID: AtomicU8 = AtomicU8::new(PrimaryId::TraceId as u8);
+
+/// Changes the incoming id trusted by [`RequestIds::from_incoming`] (default:
+/// [`PrimaryId::TraceId`]). Should be called once at startup.
+pub fn set_primary_id(primary: PrimaryId) {
+    PRIMARY_ID.store(primary as u8, Ordering::Relaxed);
+}
+
+fn primary_id() -> PrimaryId {
+    match PRIMARY_ID.load(Ordering::Relaxed) {
+        p if p == PrimaryId::Generated as u8 => PrimaryId::Generated,
+        p if p == PrimaryId::RequestId as u8 => PrimaryId::RequestId,
+        _ => PrimaryId::TraceId,
+    }
+}
+
+/// Ids of a request, derived from one UUID:
+/// - the `tx_id` of the access log and the `x-request-id` header are its short form, or the
+///   form of the `x-request-id` sent by the caller, so callers find their own id in the logs
+/// - the trace id is its hexadecimal form (a UUID is a valid W3C trace id), unless it is
+///   [replaced](Self::with_trace_id) by the one of the exported OpenTelemetry span
+///
+/// The trace id is also the value to use for metrics exemplars.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestIds {
+    id: Uuid,
+    trace_id: Uuid,
+    span_id: u64,
+    format: IdFormat,
+}
+
+/// Form of the request id
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IdFormat {
+    Short,
+    Hyphenated,
+    Simple,
+}
+
+impl RequestIds {
+    /// Ids of a new request, from the default generator
+    pub fn new() -> Self {
+        Self::from_id(new_id())
+    }
+
+    pub fn from_id(id: Uuid) -> Self {
+        Self {
+            id,
+            trace_id: id,
+            span_id: Uuid::new_v4().as_u64_pair().0,
+            format: IdFormat::Short,
+        }
+    }
+
+    /// Ids of a request from the `x-request-id` and `traceparent` headers of the caller,
+    /// depending on the [primary id](set_primary_id). New ids are generated when both are
+    /// missing or invalid. The request id keeps the form sent by the caller.
+    pub fn from_incoming(request_id: Option<&str>, traceparent: Option<&str>) -> Self {
+        let request_id = request_id.and_then(parse_request_id);
+        let trace_id = traceparent.and_then(parse_traceparent);
+        let (id, format, trace_id) = match (primary_id(), request_id, trace_id) {
+            (PrimaryId::Generated, _, _) | (_, None, None) => return Self::new(),
+            (PrimaryId::TraceId, Some((id, format)), Some(trace_id)) => (id, format, trace_id),
+            (_, Some((id, format)), _) => (id, format, id),
+            (_, None, Some(trace_id)) => (trace_id, IdFormat::Short, trace_id),
+        };
+        Self {
+            format,
+            trace_id,
+            ..Self::from_id(id)
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Same ids with another trace id, eg. the one of the span exported for the request
+    pub fn with_trace_id(self, trace_id: Uuid) -> Self {
+        Self { trace_id, ..self }
+    }
+
+    /// Id of the request in the access log
+    pub fn tx_id(&self) -> String {
+        match self.format {
+            IdFormat::Short => to_short(&self.id),
+            IdFormat::Hyphenated => self.id.hyphenated().to_string(),
+            IdFormat::Simple => self.id.simple().to_string(),
+        }
+    }
+
+    /// Value of the `x-request-id` header
+    pub fn request_id(&self) -> String {
+        self.tx_id()
+    }
+
+    /// W3C trace id, 32 lowercase hexadecimal digits
+    pub fn trace_id(&self) -> String {
+        self.trace_id.simple().to_string()
+    }
+
+    /// Value of the `traceparent` header of the calls made while handling the request
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{:016x}-01", self.trace_id(), self.span_id)
+    }
+}
+
+impl Default for RequestIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_request_id(request_id: &str) -> Option<(Uuid, IdFormat)> {
+    if let Ok(id) = from_short(request_id) {
+        return Some((id, IdFormat::Short));
+    }
+    let format = match request_id.len() {
+        32 => IdFormat::Simple,
+        36 => IdFormat::Hyphenated,
+        _ => return None,
+    };
+    Some((Uuid::try_parse(request_id).ok()?, format))
+}
+
+fn parse_traceparent(traceparent: &str) -> Option<Uuid> {
+    let mut parts = traceparent.split('-');
+    let (_version, trace_id) = (parts.next()?, parts.next()?);
+    if trace_id.len() != 32 {
+        return None;
+    }
+    let id = u128::from_str_radix(trace_id, 16).ok()?;
+    (id != 0).then(|| Uuid::from_u128(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_ids_round_trip_and_sort() {
+        let generator = IdGenerator::new(IdKind::UuidV7);
+        let first = generator.uuid();
+        let short = to_short(&first);
+        assert_eq!(short.len(), 22);
+        assert_eq!(from_short(&short).unwrap(), first);
+        let sortable = to_sortable_short(&first);
+        assert_eq!(sortable.len(), 26);
+        assert_eq!(from_sortable_short(&sortable).unwrap(), first);
+        assert_eq!(
+            from_sortable_short(&sortable.to_lowercase()).unwrap(),
+            first
+        );
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = generator.uuid();
+        assert!(second > first);
+        assert!(to_sortable_short(&second) > sortable);
+
+        let mut ids: Vec<Uuid> = (0..64).map(|_| Uuid::new_v4()).collect();
+        let mut shorts: Vec<String> = ids.iter().map(to_sortable_short).collect();
+        ids.sort();
+        shorts.sort();
+        assert_eq!(
+            shorts,
+            ids.iter().map(to_sortable_short).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            to_sortable_short(&Uuid::max()),
+            "7ZZZZZZZZZZZZZZZZZZZZZZZZZ"
+        );
+        assert!(from_sortable_short("8ZZZZZZZZZZZZZZZZZZZZZZZZZ").is_err());
+    }
+
+    #[test]
+    fn request_ids_share_one_id() {
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let ids = RequestIds::from_incoming(Some("not-an-id"), Some(traceparent));
+        assert_eq!(ids.trace_id(), "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(from_short(&ids.tx_id()).unwrap(), ids.id());
+        assert_eq!(ids.request_id(), ids.tx_id());
+        assert!(ids
+            .traceparent()
+            .starts_with("00-0af7651916cd43dd8448eb211c80319c-"));
+        assert_eq!(
+            parse_request_id(&ids.request_id()),
+            Some((ids.id(), IdFormat::Short))
+        );
+
+        // without traceparent, the request id of the caller is kept as is
+        let request_id = "0af76519-16cd-43dd-8448-eb211c80319c";
+        let ids = RequestIds::from_incoming(Some(request_id), None);
+        assert_eq!(ids.request_id(), request_id);
+        assert_eq!(ids.trace_id(), "0af7651916cd43dd8448eb211c80319c");
+
+        // with both, the request id of the caller is kept along the trace id
+        let request_id = "9c2e7d4a-5b1f-4e8a-a3c6-1d2f3e4a5b6c";
+        let ids = RequestIds::from_incoming(Some(request_id), Some(traceparent));
+        assert_eq!(ids.request_id(), request_id);
+        assert_eq!(ids.tx_id(), request_id);
+        assert_eq!(ids.trace_id(), "0af7651916cd43dd8448eb211c80319c");
+    }
+}
@@ -0,0 +1,274 @@
+//! Self-description of the service, exposed to on-call engineers by the `/admin/info`
+//! endpoint: service, enabled features, configuration, key sets, log sinks and runtime.
+//!
+//! The service is declared with [`register_service`] at startup, the other parts are
+//! registered by the code owning them ([`register_config`], [`register_key_set`],
+//! [`register_sink`]). The configuration is registered by [`log_effective_config`] and
+//! [`load_config_watched`], the sinks by the [logging](crate::logging) outputs. The hash of
+//! the configuration is only computed with the `config-hash` feature.
+//!
+//! [`log_effective_config`]: crate::config::log_effective_config
+//! [`load_config_watched`]: crate::config::load_config_watched
+
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+#[cfg(feature = "config-hash")]
+use sha2::{Digest, Sha256};
+
+use crate::ServiceDef;
+
+static INFO: Mutex<Registered> = Mutex::new(Registered {
+    service: None,
+    config: None,
+    key_sets: BTreeMap::new(),
+    sinks: BTreeMap::new(),
+});
+
+struct Registered {
+    service: Option<(ServiceDescription, Instant)>,
+    /// with the redacted configuration, to detect the changes
+    config: Option<(ConfigInfo, String)>,
+    key_sets: BTreeMap<String, Vec<String>>,
+    sinks: BTreeMap<String, SinkInfo>,
+}
+
+/// Crate features, with whether they are enabled
+const FEATURES: &[(&str, bool)] = &[
+    ("audit", cfg!(feature = "audit")),
+    ("axum", cfg!(feature = "axum")),
+    ("clap", cfg!(feature = "clap")),
+    ("config-hash", cfg!(feature = "config-hash")),
+    ("config-watch", cfg!(feature = "config-watch")),
+    ("deadpool", cfg!(feature = "deadpool")),
+    ("dotenv", cfg!(feature = "dotenv")),
+    ("encrypted-config", cfg!(feature = "encrypted-config")),
+    ("file-log", cfg!(feature = "file-log")),
+    ("grpc", cfg!(feature = "grpc")),
+    ("health", cfg!(feature = "health")),
+    ("ids", cfg!(feature = "ids")),
+    ("json", cfg!(feature = "json")),
+    ("kafka", cfg!(feature = "kafka")),
+    ("kv-config", cfg!(feature = "kv-config")),
+    ("logging", cfg!(feature = "logging")),
+    ("loki", cfg!(feature = "loki")),
+    ("metrics", cfg!(feature = "metrics")),
+    ("otlp", cfg!(feature = "otlp")),
+    ("outbox", cfg!(feature = "outbox")),
+    ("preflight", cfg!(feature = "preflight")),
+    ("remote-config", cfg!(feature = "remote-config")),
+    ("reqwest", cfg!(feature = "reqwest")),
+    ("runner", cfg!(feature = "runner")),
+    ("saga", cfg!(feature = "saga")),
+    ("shutdown", cfg!(feature = "shutdown")),
+    ("syslog", cfg!(feature = "syslog")),
+    ("testing", cfg!(feature = "testing")),
+    ("time", cfg!(feature = "time")),
+    ("tokio", cfg!(feature = "tokio")),
+    ("toml", cfg!(feature = "toml")),
+    ("tracing", cfg!(feature = "tracing")),
+    ("tracing-gelf", cfg!(feature = "tracing-gelf")),
+    ("vault", cfg!(feature = "vault")),
+    ("warp", cfg!(feature = "warp")),
+    ("webhooks", cfg!(feature = "webhooks")),
+];
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ServiceDescription {
+    pub name: String,
+    pub version: String,
+    pub git_hash: String,
+}
+
+/// Loaded configuration
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConfigInfo {
+    /// Number of configurations loaded, incremented on the reloads changing it
+    pub version: u64,
+    /// SHA-256 of the redacted configuration, to compare the replicas of the service (with the
+    /// `config-hash` feature)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Unix timestamp of the last load, in seconds
+    pub loaded_at: u64,
+}
+
+/// Log (or telemetry) sink, eg. `gelf` or `otlp`. Its state is `configured`, then
+/// `connected` or `disconnected` for the sinks reporting their connection.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SinkInfo {
+    pub target: String,
+    pub state: String,
+}
+
+/// Runtime statistics
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct RuntimeInfo {
+    pub pid: u32,
+    pub uptime_seconds: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokio_workers: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokio_alive_tasks: Option<usize>,
+}
+
+/// Document served by the `/admin/info` endpoint
+#[derive(Serialize, Clone, Debug)]
+pub struct ServiceInfo {
+    pub service: Option<ServiceDescription>,
+    pub features: Vec<&'static str>,
+    pub config: Option<ConfigInfo>,
+    /// Fingerprints of the keys of each key set
+    pub key_sets: BTreeMap<String, Vec<String>>,
+    pub sinks: BTreeMap<String, SinkInfo>,
+    pub runtime: RuntimeInfo,
+}
+
+/// Declares the service; the uptime is measured from this call
+pub fn register_service(service: &ServiceDef) {
+    let description = ServiceDescription {
+        name: service.pkg_name().to_string(),
+        version: service.version().to_string(),
+        git_hash: service.git_hash().to_string(),
+    };
+    INFO.lock().unwrap().service = Some((description, Instant::now()));
+}
+
+/// Declares the loaded configuration. Should be called again when it is reloaded, the
+/// version is incremented if it changed.
+pub fn register_config<C: Serialize>(config: &C) -> anyhow::Result<()> {
+    let text = serde_yaml::to_string(&crate::config::redacted_config(config)?)?;
+    let loaded_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut info = INFO.lock().unwrap();
+    let version = match &info.config {
+        Some((config, registered)) if *registered == text => config.version,
+        Some((config, _)) => config.version + 1,
+        None => 1,
+    };
+    let config = ConfigInfo {
+        version,
+        hash: config_hash(&text),
+        loaded_at,
+    };
+    info.config = Some((config, text));
+    Ok(())
+}
+
+/// Hex SHA-256 of the redacted configuration text
+#[cfg(feature = "config-hash")]
+fn config_hash(text: &str) -> Option<String> {
+    let digest = Sha256::digest(text.as_bytes());
+    Some(digest.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+#[cfg(not(feature = "config-hash"))]
+fn config_hash(_text: &str) -> Option<String> {
+    None
+}
+
+/// Declares the fingerprints (or key ids) of the keys of a key set, eg. the signing keys of
+/// the tokens. Keys must never be registered themselves.
+pub fn register_key_set(name: impl Into<String>, fingerprints: Vec<String>) {
+    INFO.lock()
+        .unwrap()
+        .key_sets
+        .insert(name.into(), fingerprints);
+}
+
+/// Declares a sink and its state (eg. `configured`, `connected`, `disconnected`). The target
+/// must not hold credentials.
+pub fn register_sink(name: impl Into<String>, target: impl Into<String>, state: impl Into<String>) {
+    INFO.lock().unwrap().sinks.insert(
+        name.into(),
+        SinkInfo {
+            target: target.into(),
+            state: state.into(),
+        },
+    );
+}
+
+/// Updates the state of a declared sink, eg. `connected` or `disconnected`
+pub fn set_sink_state(name: &str, state: &str) {
+    if let Some(sink) = INFO.lock().unwrap().sinks.get_mut(name) {
+        sink.state = state.to_string();
+    }
+}
+
+/// Current description of the service
+pub fn info() -> ServiceInfo {
+    let info = INFO.lock().unwrap();
+    let (service, started) = match &info.service {
+        Some((service, started)) => (Some(service.clone()), Some(*started)),
+        None => (None, None),
+    };
+    let (tokio_workers, tokio_alive_tasks) = tokio_stats();
+
+    ServiceInfo {
+        service,
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect(),
+        config: info.config.as_ref().map(|(config, _)| config.clone()),
+        key_sets: info.key_sets.clone(),
+        sinks: info.sinks.clone(),
+        runtime: RuntimeInfo {
+            pid: std::process::id(),
+            uptime_seconds: started.map_or(0, |started| started.elapsed().as_secs()),
+            tokio_workers,
+            tokio_alive_tasks,
+        },
+    }
+}
+
+/// Workers and alive tasks of the current tokio runtime, if any
+#[cfg(feature = "tokio")]
+fn tokio_stats() -> (Option<usize>, Option<usize>) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => {
+            let metrics = handle.metrics();
+            (Some(metrics.num_workers()), Some(metrics.num_alive_tasks()))
+        }
+        Err(_) => (None, None),
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+fn tokio_stats() -> (Option<usize>, Option<usize>) {
+    (None, None)
+}
+
+#[cfg(test)]
+#[test]
+fn describes_the_service() {
+    register_service(&ServiceDef::new("orders-api", "1.2.0", "abc123"));
+    register_config(&BTreeMap::from([("port", 8080)])).unwrap();
+    register_sink("otlp", "http://collector:4318", "configured");
+    set_sink_state("otlp", "disconnected");
+
+    let info = info();
+    assert_eq!(info.service.unwrap().name, "orders-api");
+    assert_eq!(info.sinks["otlp"].state, "disconnected");
+    let config = info.config.unwrap();
+    assert_eq!(
+        config.hash.is_some(),
+        cfg!(feature = "config-hash"),
+        "{config:?}"
+    );
+    #[cfg(feature = "config-hash")]
+    assert_eq!(
+        config_hash("port: 8080\n").unwrap(),
+        // sha256sum of "port: 8080\n"
+        "04eeaa6d3c2a66678af8514f5c8777a8889296f351c790bd3fa21ed2f9dd482e"
+    );
+    assert_eq!(info.features.contains(&"axum"), cfg!(feature = "axum"));
+}
@@ -0,0 +1,142 @@
+//! Generates Kubernetes snippets matching the configuration of the service
+
+use serde::{Deserialize, Serialize};
+
+/// Endpoints and timings of the service probes, typically part of the service configuration.
+///
+/// The defaults match the `/health/live` and `/health/ready` endpoints of the `health`
+/// feature, on port 8080.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ProbesConfig {
+    /// Port the service listens on
+    pub port: u16,
+    /// Name of the container port
+    pub port_name: String,
+    /// Port the metrics are exposed on, if not served on `port`
+    pub metrics_port: Option<u16>,
+    pub liveness_path: String,
+    pub readiness_path: String,
+    /// Path of the startup probe, the liveness path when not set
+    pub startup_path: Option<String>,
+    pub period_seconds: u32,
+    pub timeout_seconds: u32,
+    pub failure_threshold: u32,
+    /// Maximum time the service may take to start before being restarted
+    pub startup_timeout_seconds: u32,
+}
+
+impl Default for ProbesConfig {
+    fn default() -> Self {
+        Self {
+            port: 8080,
+            port_name: "http".to_string(),
+            metrics_port: None,
+            liveness_path: "/health/live".to_string(),
+            readiness_path: "/health/ready".to_string(),
+            startup_path: None,
+            period_seconds: 10,
+            timeout_seconds: 1,
+            failure_threshold: 3,
+            startup_timeout_seconds: 60,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ContainerSnippet<'a> {
+    ports: Vec<ContainerPort<'a>>,
+    liveness_probe: Probe<'a>,
+    readiness_probe: Probe<'a>,
+    startup_probe: Probe<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ContainerPort<'a> {
+    name: &'a str,
+    container_port: u16,
+    protocol: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Probe<'a> {
+    http_get: HttpGet<'a>,
+    period_seconds: u32,
+    timeout_seconds: u32,
+    failure_threshold: u32,
+}
+
+#[derive(Serialize)]
+struct HttpGet<'a> {
+    path: &'a str,
+    port: &'a str,
+}
+
+/// Emits the `ports`, `livenessProbe`, `readinessProbe` and `startupProbe` of a container spec,
+/// in YAML, to be pasted (or templated) in the Helm chart of the service.
+///
+/// The startup probe allows `startup_timeout_seconds` for the service to start, checked every
+/// `period_seconds`.
+pub fn probes_yaml(config: &ProbesConfig) -> String {
+    let probe = |path| Probe {
+        http_get: HttpGet {
+            path,
+            port: &config.port_name,
+        },
+        period_seconds: config.period_seconds,
+        timeout_seconds: config.timeout_seconds,
+        failure_threshold: config.failure_threshold,
+    };
+
+    let mut ports = vec![ContainerPort {
+        name: &config.port_name,
+        container_port: config.port,
+        protocol: "TCP",
+    }];
+    if let Some(metrics_port) = config.metrics_port {
+        ports.push(ContainerPort {
+            name: "metrics",
+            container_port: metrics_port,
+            protocol: "TCP",
+        });
+    }
+
+    let snippet = ContainerSnippet {
+        ports,
+        liveness_probe: probe(&config.liveness_path),
+        readiness_probe: probe(&config.readiness_path),
+        startup_probe: Probe {
+            failure_threshold: config
+                .startup_timeout_seconds
+                .div_ceil(config.period_seconds.max(1))
+                .max(1),
+            ..probe(
+                config
+                    .startup_path
+                    .as_deref()
+                    .unwrap_or(&config.liveness_path),
+            )
+        },
+    };
+    serde_yaml::to_string(&snippet).unwrap()
+}
+
+#[cfg(test)]
+#[test]
+fn probes_match_the_config() {
+    let yaml = probes_yaml(&ProbesConfig {
+        readiness_path: "/ready".to_string(),
+        period_seconds: 5,
+        ..Default::default()
+    });
+    let snippet: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(snippet["ports"][0]["containerPort"], 8080);
+    assert_eq!(snippet["readinessProbe"]["httpGet"]["path"], "/ready");
+    assert_eq!(snippet["livenessProbe"]["httpGet"]["port"], "http");
+    assert_eq!(snippet["livenessProbe"]["httpGet"]["path"], "/health/live");
+    assert_eq!(snippet["startupProbe"]["httpGet"]["path"], "/health/live");
+    assert_eq!(snippet["startupProbe"]["failureThreshold"], 12);
+}
@@ -0,0 +1,282 @@
+//! Metrics of Kafka consumers (requires a [`rdkafka`] consumer).
+//!
+//! Exports:
+//! - `kafka_consumer_lag` gauge labelled by `group`, `topic` and `partition`, updated by a
+//!   background task while the consumer is used
+//! - `kafka_messages_consumed_total` counter labelled by `topic`
+//! - `kafka_message_processing_duration_seconds` histogram labelled by `topic` and `outcome`
+//!
+//! ```ignore
+//! let consumer: Arc<StreamConsumer> = Arc::new(config.create()?);
+//! launch_lag_collector("indexer", consumer.clone(), Duration::from_secs(15));
+//! let metrics = KafkaConsumerMetrics::new();
+//! loop {
+//!     let message = consumer.recv().await?;
+//!     let timer = metrics.start(message.topic());
+//!     let result = process(&message).await;
+//!     timer.finish(result.is_ok());
+//! }
+//! ```
+
+use std::{collections::HashSet, sync::Arc, time::Duration, time::Instant};
+
+use prometheus::{HistogramVec, IntCounterVec, IntGaugeVec};
+use rdkafka::{
+    consumer::{Consumer, ConsumerContext},
+    Offset,
+};
+
+use crate::metrics::{
+    get_or_create_counter_with_labels, get_or_create_gauge_with_labels,
+    get_or_create_histogram_with_labels, DEFAULT_DURATION_BUCKETS,
+};
+
+/// Throughput and processing duration metrics
+#[derive(Clone)]
+pub struct KafkaConsumerMetrics {
+    consumed: IntCounterVec,
+    processing: HistogramVec,
+}
+
+impl KafkaConsumerMetrics {
+    pub fn new() -> Self {
+        Self {
+            consumed: get_or_create_counter_with_labels(
+                "kafka_messages_consumed_total",
+                "Kafka messages consumed",
+                &["topic"],
+            ),
+            processing: get_or_create_histogram_with_labels(
+                "kafka_message_processing_duration_seconds",
+                "Kafka messages processing duration",
+                &["topic", "outcome"],
+                DEFAULT_DURATION_BUCKETS.to_vec(),
+            ),
+        }
+    }
+
+    /// Counts a consumed message and starts measuring its processing
+    pub fn start(&self, topic: &str) -> ProcessingTimer {
+        self.consumed.with_label_values(&[topic]).inc();
+        ProcessingTimer {
+            metrics: self.clone(),
+            topic: topic.to_string(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for KafkaConsumerMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Measures the processing of one message, see [`KafkaConsumerMetrics::start`]
+pub struct ProcessingTimer {
+    metrics: KafkaConsumerMetrics,
+    topic: String,
+    start: Instant,
+}
+
+impl ProcessingTimer {
+    /// Records the processing duration with a `success` or `failure` outcome
+    pub fn finish(self, success: bool) {
+        let outcome = if success { "success" } else { "failure" };
+        self.metrics
+            .processing
+            .with_label_values(&[&self.topic, outcome])
+            .observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+/// Launch a task computing the lag of every partition assigned to the consumer (high
+/// watermark minus current position) at the specified interval, until the other references to
+/// the consumer are dropped or the returned task is aborted. The gauges of the revoked
+/// partitions are removed. It requires a running tokio runtime!
+pub fn launch_lag_collector<C, X>(
+    group: &str,
+    consumer: Arc<C>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()>
+where
+    C: Consumer<X> + Send + Sync + 'static,
+    X: ConsumerContext + 'static,
+{
+    let lag = get_or_create_gauge_with_labels(
+        "kafka_consumer_lag",
+        "Messages not yet consumed by partition",
+        &["group", "topic", "partition"],
+    );
+    let group = group.to_string();
+    let consumer = Arc::downgrade(&consumer);
+    tokio::task::spawn(async move {
+        let mut reported = HashSet::new();
+        loop {
+            let Some(consumer) = consumer.upgrade() else {
+                break;
+            };
+            let lags = match tokio::task::spawn_blocking(move || compute_lags(&*consumer, interval))
+                .await
+            {
+                Ok(lags) => lags,
+                Err(err) => {
+                    log::warn!("Cannot compute kafka consumer lag of {group}: {err}");
+                    None
+                }
+            };
+            if let Some(lags) = lags {
+                update_lags(&lag, &group, &mut reported, lags);
+            }
+            tokio::time::sleep(interval).await;
+        }
+        for (topic, partition) in reported {
+            let _ = lag.remove_label_values(&[&group, &topic, &partition.to_string()]);
+        }
+    })
+}
+
+/// Lag of an assigned partition, `None` if unknown
+struct PartitionLag {
+    topic: String,
+    partition: i32,
+    lag: Option<i64>,
+}
+
+/// Sets the gauges of the assigned partitions, and removes the ones of the partitions
+/// `reported` before and revoked since
+fn update_lags(
+    gauge: &IntGaugeVec,
+    group: &str,
+    reported: &mut HashSet<(String, i32)>,
+    lags: Vec<PartitionLag>,
+) {
+    let mut assigned = HashSet::new();
+    for PartitionLag {
+        topic,
+        partition,
+        lag,
+    } in lags
+    {
+        if let Some(lag) = lag {
+            gauge
+                .with_label_values(&[group, &topic, &partition.to_string()])
+                .set(lag);
+        }
+        assigned.insert((topic, partition));
+    }
+    for (topic, partition) in reported.difference(&assigned) {
+        let _ = gauge.remove_label_values(&[group, topic, &partition.to_string()]);
+    }
+    *reported = assigned;
+}
+
+/// Lag of assigned partitions, `None` if the assignment is unknown. Fetching watermarks is
+/// blocking.
+fn compute_lags<C, X>(consumer: &C, timeout: Duration) -> Option<Vec<PartitionLag>>
+where
+    C: Consumer<X>,
+    X: ConsumerContext,
+{
+    let positions = match consumer.position() {
+        Ok(positions) => positions,
+        Err(err) => {
+            log::warn!("Cannot get kafka consumer positions: {err}");
+            return None;
+        }
+    };
+    let lags = positions
+        .elements()
+        .into_iter()
+        .map(|elem| {
+            let lag = match elem.offset() {
+                Offset::Offset(position) => {
+                    match consumer.fetch_watermarks(elem.topic(), elem.partition(), timeout) {
+                        Ok((_low, high)) => Some((high - position).max(0)),
+                        Err(err) => {
+                            log::warn!(
+                                "Cannot fetch watermarks of {}/{}: {err}",
+                                elem.topic(),
+                                elem.partition()
+                            );
+                            None
+                        }
+                    }
+                }
+                // nothing consumed yet on this partition
+                _ => None,
+            };
+            PartitionLag {
+                topic: elem.topic().to_string(),
+                partition: elem.partition(),
+                lag,
+            }
+        })
+        .collect();
+    Some(lags)
+}
+
+#[cfg(test)]
+#[test]
+fn removes_revoked_partitions() {
+    use prometheus::core::Collector;
+
+    let gauge = get_or_create_gauge_with_labels(
+        "test_kafka_consumer_lag",
+        "Test",
+        &["group", "topic", "partition"],
+    );
+    let lag = |topic: &str, partition, lag| PartitionLag {
+        topic: topic.to_string(),
+        partition,
+        lag,
+    };
+    let mut reported = HashSet::new();
+    update_lags(
+        &gauge,
+        "indexer",
+        &mut reported,
+        vec![lag("orders", 0, Some(12)), lag("orders", 1, Some(3))],
+    );
+    assert_eq!(
+        gauge.with_label_values(&["indexer", "orders", "0"]).get(),
+        12
+    );
+    assert_eq!(gauge.collect()[0].get_metric().len(), 2);
+
+    // partition 1 revoked, the lag of partition 0 is unknown
+    update_lags(
+        &gauge,
+        "indexer",
+        &mut reported,
+        vec![lag("orders", 0, None)],
+    );
+    assert_eq!(gauge.collect()[0].get_metric().len(), 1);
+    assert_eq!(
+        gauge.with_label_values(&["indexer", "orders", "0"]).get(),
+        12
+    );
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn stops_with_the_consumer() {
+    use rdkafka::{consumer::BaseConsumer, ClientConfig};
+
+    let consumer: Arc<BaseConsumer> = Arc::new(
+        ClientConfig::new()
+            .set("bootstrap.servers", "localhost:1")
+            .set("group.id", "lag-test")
+            .set("log_level", "0")
+            .create()
+            .unwrap(),
+    );
+    let collector = launch_lag_collector("lag-test", consumer.clone(), Duration::from_millis(10));
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    assert!(!collector.is_finished());
+    drop(consumer);
+    tokio::time::timeout(Duration::from_secs(1), collector)
+        .await
+        .unwrap()
+        .unwrap();
+}
@@ -1,23 +1,78 @@
 #[cfg(feature = "tracing-gelf")]
 pub mod tracing_gelf;
 
+#[cfg(feature = "logging")]
+pub mod logging;
+
+#[cfg(feature = "otlp")]
+pub mod otlp;
+
 #[cfg(feature = "metrics")]
 pub mod metrics;
 
 #[cfg(feature = "metrics")]
 pub use prometheus;
 
+#[cfg(feature = "deadpool")]
+pub mod pool_metrics;
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+
 #[cfg(feature = "warp")]
 pub mod warp;
 
 #[cfg(feature = "axum")]
 pub mod axum;
 
+#[cfg(feature = "reqwest")]
+pub mod reqwest;
+
+#[cfg(feature = "grpc")]
+pub mod grpc;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "preflight")]
+pub mod preflight;
+
+#[cfg(feature = "outbox")]
+pub mod outbox;
+
+#[cfg(feature = "saga")]
+pub mod saga;
+
+#[cfg(feature = "state-store")]
+pub mod state;
+
+#[cfg(feature = "time")]
+pub mod time;
+
+#[cfg(feature = "ids")]
+pub mod ids;
+
+#[cfg(all(feature = "tokio", feature = "tracing"))]
+pub mod task;
+
 pub mod errors;
 
+pub mod problem;
+
+pub mod excluded_paths;
+
+pub mod redact;
+
+pub mod dependencies;
+
+pub mod info;
+
+pub mod k8s;
+
 pub mod config;
 
 /// Struct used to describe the service (typically used in logging services)
+#[derive(Clone, Copy, Debug)]
 pub struct ServiceDef<'a> {
     version: &'a str,
     git_hash: &'a str,
@@ -32,4 +87,19 @@ impl<'a> ServiceDef<'a> {
             pkg_name,
         }
     }
+
+    /// Name of the package, typically `env!("CARGO_PKG_NAME")`
+    pub const fn pkg_name(&self) -> &'a str {
+        self.pkg_name
+    }
+
+    /// Version of the package, typically `env!("CARGO_PKG_VERSION")`
+    pub const fn version(&self) -> &'a str {
+        self.version
+    }
+
+    /// Git hash the service was built from
+    pub const fn git_hash(&self) -> &'a str {
+        self.git_hash
+    }
 }
@@ -0,0 +1,349 @@
+//! Logs written on stdout and to the outputs composed with [`LoggingBuilder`]
+
+use std::{
+    backtrace::Backtrace,
+    collections::BTreeMap,
+    io::IsTerminal,
+    sync::{Mutex, Once},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::{
+    filter::Directive, fmt::MakeWriter, registry::LookupSpan, reload, EnvFilter, Layer,
+};
+
+#[cfg(feature = "audit")]
+mod audit;
+mod builder;
+#[cfg(feature = "file-log")]
+mod file;
+#[cfg(any(feature = "loki", feature = "audit"))]
+mod json;
+#[cfg(feature = "loki")]
+mod loki;
+mod sampling;
+#[cfg(feature = "syslog")]
+mod syslog;
+#[cfg(feature = "audit")]
+pub use audit::{audit_layer, AuditLayer, AuditLog, AuditParams, AUDIT_TARGET};
+pub use builder::{LoggingBuilder, LoggingGuard};
+#[cfg(feature = "file-log")]
+pub use file::{file_layer, init_file, FileLogParams, FileRotation, RollingFile};
+#[cfg(feature = "loki")]
+pub use loki::{init_loki, loki_layer, LokiGuard, LokiLayer, LokiParams};
+pub use sampling::{SamplingLayer, SamplingParams};
+#[cfg(feature = "syslog")]
+pub use syslog::{init_syslog, syslog_layer, SyslogLayer, SyslogParams};
+#[doc(hidden)]
+pub use tracing;
+
+/// Environment variable selecting the [`LogFormat`] when it is not configured: `pretty`,
+/// `json` or `auto`
+pub const LOG_FORMAT_VAR: &str = "LOG_FORMAT";
+
+/// Format of the logs written on stdout
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines, colored on terminals
+    #[default]
+    Pretty,
+    /// One JSON object per event, with its fields and the ones of its current span, for
+    /// stdout shipped to Loki or ELK
+    Json,
+    /// Pretty on terminals, JSON otherwise
+    Auto,
+}
+
+impl LogFormat {
+    /// `configured` if any, else the format of the `LOG_FORMAT` environment variable, else
+    /// [`LogFormat::Pretty`]
+    pub fn resolve(configured: Option<LogFormat>) -> LogFormat {
+        configured
+            .or_else(|| {
+                let var = std::env::var(LOG_FORMAT_VAR).ok()?;
+                match serde_yaml::from_str(&var.to_lowercase()) {
+                    Ok(format) => Some(format),
+                    Err(_) => {
+                        eprintln!("Ignoring invalid {LOG_FORMAT_VAR} `{var}`");
+                        None
+                    }
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether events are written as JSON, [`LogFormat::Auto`] checking if stdout is a
+    /// terminal
+    pub fn is_json(self) -> bool {
+        match self {
+            LogFormat::Pretty => false,
+            LogFormat::Json => true,
+            LogFormat::Auto => !std::io::stdout().is_terminal(),
+        }
+    }
+}
+
+/// Layer writing the events on stdout in `format`
+pub fn stdout_layer<S>(format: LogFormat) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    stdout_layer_with(format, std::io::stdout)
+}
+
+/// Same as [`stdout_layer`], with a writer to stdout like the non-blocking one of
+/// [`LoggingBuilder`]
+pub fn stdout_layer_with<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    if format.is_json() {
+        layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed()
+    } else {
+        layer
+            // only enable colored output on real terminals
+            .with_ansi(std::io::stdout().is_terminal())
+            .boxed()
+    }
+}
+
+/// Level of the events kept by a filter
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+/// Levels by target, like `{default: info, access_log: info, sqlx: warn}`, `default` being
+/// the level of the other targets. They are merged with `RUST_LOG`, which takes precedence.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct LogLevels {
+    pub targets: BTreeMap<String, LogLevel>,
+}
+
+impl LogLevels {
+    /// Directives of the levels with the syntax of `RUST_LOG`, like `info,sqlx=warn`
+    pub fn directives(&self) -> String {
+        let default = self
+            .targets
+            .get("default")
+            .map(|level| level.as_str().to_string());
+        let targets = self
+            .targets
+            .iter()
+            .filter(|(target, _)| *target != "default")
+            .map(|(target, level)| format!("{target}={}", level.as_str()));
+        default
+            .into_iter()
+            .chain(targets)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+type Reload = Box<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync>;
+
+/// Reload handle of the filter of the installed subscriber, with its current directives
+static FILTER: Mutex<Option<(Reload, String)>> = Mutex::new(None);
+
+/// Filter of the `RUST_LOG` environment variable which can be changed at runtime with
+/// [`set_log_filter`]. The subscribers of this crate are installed with it.
+pub fn reloadable_env_filter<S>() -> reload::Layer<EnvFilter, S>
+where
+    S: tracing::Subscriber + 'static,
+{
+    reloadable_filter(&LogLevels::default())
+}
+
+/// Same as [`reloadable_env_filter`], with the configured `levels` overridden by `RUST_LOG`
+pub fn reloadable_filter<S>(levels: &LogLevels) -> reload::Layer<EnvFilter, S>
+where
+    S: tracing::Subscriber + 'static,
+{
+    let directives = merge_directives(
+        &levels.directives(),
+        &std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_default(),
+    );
+    let filter = with_audit(lossy_filter(&directives));
+    let (layer, handle) = reload::Layer::new(filter);
+    let reload: Reload = Box::new(move |filter| handle.reload(filter));
+    *FILTER.lock().unwrap() = Some((reload, directives));
+    layer
+}
+
+/// Filter of `directives`, ignoring the invalid ones with a warning on stderr, as the logs
+/// are not set up yet
+fn lossy_filter(directives: &str) -> EnvFilter {
+    for directive in directives.split(',').filter(|d| !d.is_empty()) {
+        if let Err(err) = directive.parse::<Directive>() {
+            eprintln!("Ignoring invalid log directive `{directive}`: {err}");
+        }
+    }
+    EnvFilter::builder().parse_lossy(directives)
+}
+
+/// Enables the [audit events](crate::audit), whatever the directives
+fn with_audit(filter: EnvFilter) -> EnvFilter {
+    #[cfg(feature = "audit")]
+    let filter = filter.add_directive(
+        format!("{AUDIT_TARGET}=info")
+            .parse()
+            .expect("Invalid audit directive"),
+    );
+    filter
+}
+
+/// `configured` directives followed by the `env` ones, which win for the same targets
+fn merge_directives(configured: &str, env: &str) -> String {
+    [configured, env]
+        .into_iter()
+        .filter(|directives| !directives.is_empty())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Directives of the filter installed with [`reloadable_env_filter`], like `info,sqlx=warn`
+pub fn log_filter() -> Option<String> {
+    let filter = FILTER.lock().unwrap();
+    filter.as_ref().map(|(_, directives)| directives.clone())
+}
+
+/// Replaces the filter installed with [`reloadable_env_filter`] by `directives`, with the
+/// syntax of `RUST_LOG`, eg. to enable debug logs during an incident without restarting.
+pub fn set_log_filter(directives: &str) -> anyhow::Result<()> {
+    let filter = EnvFilter::builder()
+        .parse(directives)
+        .with_context(|| format!("Invalid log filter `{directives}`"))?;
+    let filter = with_audit(filter);
+    let mut current = FILTER.lock().unwrap();
+    let Some((reload, current)) = current.as_mut() else {
+        bail!("No reloadable log filter installed");
+    };
+    reload(filter).context("Cannot change the log filter")?;
+    tracing::warn!("Log filter changed from `{current}` to `{directives}`");
+    *current = directives.to_string();
+    Ok(())
+}
+
+/// Logs the panics as Error events of the `panic` target, with their location and backtrace,
+/// so they reach the log collectors instead of only stderr. The subscribers of this crate
+/// install it.
+///
+/// The previous hook (printing on stderr by default) is still called when no subscriber is
+/// installed.
+pub fn install_panic_hook() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let subscribed = tracing::dispatcher::get_default(|dispatch| {
+                !dispatch.is::<tracing::subscriber::NoSubscriber>()
+            });
+            if !subscribed {
+                return previous(info);
+            }
+            let payload = info.payload();
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("Box<dyn Any>");
+            let location = info.location().map(ToString::to_string);
+            let thread = std::thread::current();
+            let thread = thread.name().unwrap_or("<unnamed>");
+            tracing::event!(
+                target: "panic",
+                tracing::Level::ERROR,
+                panic.location = location,
+                panic.thread = thread,
+                panic.backtrace = %Backtrace::force_capture(),
+                "Thread `{thread}` panicked: {message}",
+            );
+        }));
+    });
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[test]
+fn resolves_format() {
+    let fixture = crate::testing::ConfigFixture::new().remove_env(LOG_FORMAT_VAR);
+    assert_eq!(LogFormat::resolve(None), LogFormat::Pretty);
+    let _fixture = fixture.env(LOG_FORMAT_VAR, "JSON");
+    assert_eq!(LogFormat::resolve(None), LogFormat::Json);
+    assert_eq!(LogFormat::resolve(Some(LogFormat::Auto)), LogFormat::Auto);
+    assert!(LogFormat::Json.is_json());
+}
+
+#[cfg(test)]
+#[test]
+fn merges_levels() {
+    let levels: LogLevels =
+        serde_yaml::from_str("{access_log: info, sqlx: warn, default: info}").unwrap();
+    assert_eq!(levels.directives(), "info,access_log=info,sqlx=warn");
+    assert_eq!(
+        merge_directives(&levels.directives(), "sqlx=debug"),
+        "info,access_log=info,sqlx=warn,sqlx=debug"
+    );
+    let filter = lossy_filter("info,sqlx=warn,sqlx=debug,sqlx[=warn");
+    assert_eq!(filter.to_string(), "sqlx=debug,info");
+}
+
+#[cfg(test)]
+#[test]
+fn reloads_filter() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let subscriber = tracing_subscriber::registry().with(reloadable_env_filter());
+    let _guard = tracing::subscriber::set_default(subscriber);
+    set_log_filter("info,service_helpe_rs=debug").unwrap();
+    assert!(tracing::enabled!(target: "service_helpe_rs", tracing::Level::DEBUG));
+    assert!(!tracing::enabled!(target: "other", tracing::Level::DEBUG));
+    assert_eq!(log_filter().unwrap(), "info,service_helpe_rs=debug");
+    assert!(set_log_filter("info,=[").is_err());
+}
+
+#[cfg(all(test, feature = "testing", feature = "tracing"))]
+#[test]
+fn logs_panics() {
+    install_panic_hook();
+    let capture = crate::testing::TracingCapture::new();
+    let guard = capture.set_default();
+    let _ = std::panic::catch_unwind(|| panic!("boom"));
+    drop(guard);
+
+    let events = capture.events_for_target("panic");
+    assert_eq!(events.len(), 1);
+    assert!(events[0].message().unwrap().ends_with("panicked: boom"));
+    assert!(events[0]
+        .field("panic.location")
+        .unwrap()
+        .contains("logging.rs"));
+}
@@ -0,0 +1,243 @@
+//! Audit events, for the security-relevant actions (logins, permission changes...)
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::mpsc,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::sync::oneshot;
+use tracing::{span, Event, Subscriber};
+use tracing_subscriber::{layer::Context as LayerContext, registry::LookupSpan, Layer};
+
+use super::json;
+
+/// Target of the events of [`audit!`](crate::audit)
+pub const AUDIT_TARGET: &str = "audit";
+
+/// Emits an audit event: an Info event of the `audit` target, with the syntax of
+/// `tracing::info!`. The audit events are never filtered out nor sampled, and are also
+/// written to the audit file if configured (see [`AuditParams`](crate::logging::AuditParams)),
+/// in the background: use [`AuditLog::record`](crate::logging::AuditLog::record) to wait for
+/// the event to be written.
+///
+/// ```ignore
+/// audit!(action = "role.granted", actor = %admin_id, user = %user_id, "Role admin granted");
+/// ```
+#[macro_export]
+macro_rules! audit {
+    ($($arg:tt)+) => {
+        $crate::logging::tracing::event!(
+            target: "audit",
+            $crate::logging::tracing::Level::INFO,
+            $($arg)+
+        )
+    };
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct AuditParams {
+    /// File the audit events are appended to as JSON lines, like
+    /// `/var/log/orders-api/audit.log`, its directory being created if missing
+    pub path: String,
+    /// Syncs the file to the disk after each event, so they survive a crash of the host
+    /// (default: true)
+    #[serde(default = "default_sync")]
+    pub sync: bool,
+}
+
+fn default_sync() -> bool {
+    true
+}
+
+/// Layer appending the audit events to a file, from a dedicated thread so the writes and syncs
+/// do not block the async workers. The `audit!` events are written in the background, write
+/// failures being printed on stderr: use [`AuditLog::record`] to know whether an event was
+/// written.
+pub struct AuditLayer {
+    log: AuditLog,
+}
+
+/// Handle of the audit file, see [`AuditLayer::log`]
+#[derive(Clone)]
+pub struct AuditLog {
+    sender: mpsc::Sender<AuditLine>,
+}
+
+struct AuditLine {
+    line: String,
+    written: Option<oneshot::Sender<std::io::Result<()>>>,
+}
+
+/// Layer writing the audit events with the fields of their spans, like
+/// `{"timestamp":"2024-05-12T08:30:00Z","tx":"...","action":"login","message":"..."}`
+pub fn audit_layer(params: &AuditParams) -> anyhow::Result<AuditLayer> {
+    let path = Path::new(&params.path);
+    if let Some(directory) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(directory).with_context(|| {
+            format!("Cannot create audit log directory {}", directory.display())
+        })?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Cannot open audit log {}", params.path))?;
+    let (sender, receiver) = mpsc::channel();
+    let sync = params.sync;
+    std::thread::Builder::new()
+        .name("audit-writer".to_string())
+        .spawn(move || write_lines(file, sync, receiver))
+        .context("Cannot start the audit log writer")?;
+    Ok(AuditLayer {
+        log: AuditLog { sender },
+    })
+}
+
+/// Writes the lines until all the senders are dropped
+fn write_lines(mut file: File, sync: bool, receiver: mpsc::Receiver<AuditLine>) {
+    for AuditLine { line, written } in receiver {
+        let result =
+            file.write_all(line.as_bytes())
+                .and_then(|()| if sync { file.sync_data() } else { Ok(()) });
+        match (written, result) {
+            (Some(written), result) => {
+                let _ = written.send(result);
+            }
+            (None, Err(err)) => eprintln!("Cannot write audit event {line}: {err}"),
+            (None, Ok(())) => {}
+        }
+    }
+}
+
+impl AuditLayer {
+    /// Handle to write audit events and know whether they were written
+    pub fn log(&self) -> AuditLog {
+        self.log.clone()
+    }
+}
+
+impl AuditLog {
+    /// Appends `event`, a JSON object, to the audit file with a `timestamp` field. Returns once
+    /// it is written, and synced to the disk if configured, failing otherwise: the action
+    /// audited can then be refused.
+    ///
+    /// ```ignore
+    /// audit_log.record(&json!({"action": "role.granted", "actor": admin_id, "user": user_id})).await?;
+    /// ```
+    pub async fn record(&self, event: &impl Serialize) -> anyhow::Result<()> {
+        let Value::Object(mut fields) = serde_json::to_value(event)? else {
+            anyhow::bail!("Audit events must be JSON objects");
+        };
+        fields.insert("timestamp".to_string(), timestamp());
+        self.write(format!("{}\n", Value::Object(fields))).await
+    }
+
+    /// Waits for the events already emitted to be written
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        self.write(String::new()).await
+    }
+
+    async fn write(&self, line: String) -> anyhow::Result<()> {
+        let (written, result) = oneshot::channel();
+        self.sender
+            .send(AuditLine {
+                line,
+                written: Some(written),
+            })
+            .map_err(|_| anyhow::anyhow!("The audit log writer is stopped"))?;
+        result
+            .await
+            .context("The audit log writer is stopped")?
+            .context("Cannot write audit event")
+    }
+}
+
+fn timestamp() -> Value {
+    crate::time::format_rfc3339(std::time::SystemTime::now()).into()
+}
+
+impl<S> Layer<S> for AuditLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: LayerContext<'_, S>) {
+        json::on_new_span(attrs, id, ctx);
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: LayerContext<'_, S>) {
+        json::on_record(id, values, ctx);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: LayerContext<'_, S>) {
+        if event.metadata().target() != AUDIT_TARGET {
+            return;
+        }
+        let mut fields = Map::new();
+        fields.insert("timestamp".to_string(), timestamp());
+        json::event_fields(event, &ctx, &mut fields);
+        let line = format!("{}\n", Value::Object(fields));
+        let sent = self.log.sender.send(AuditLine {
+            line,
+            written: None,
+        });
+        if let Err(mpsc::SendError(AuditLine { line, .. })) = sent {
+            eprintln!("Cannot write audit event {line}: the writer is stopped");
+        }
+    }
+}
+
+/// Audit layer of [`LoggingBuilder`](super::LoggingBuilder), registered as a sink
+pub(super) fn audit_output(params: &AuditParams) -> anyhow::Result<AuditLayer> {
+    println!("Configuring audit log {}", params.path);
+    let layer = audit_layer(params)?;
+    crate::info::register_sink("audit", params.path.clone(), "configured");
+    Ok(layer)
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn writes_unfiltered_audit_events() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let path = std::env::temp_dir().join(format!("audit-{}.log", std::process::id()));
+    let params = AuditParams {
+        path: path.to_string_lossy().into_owned(),
+        sync: true,
+    };
+    let layer = audit_layer(&params).unwrap();
+    let log = layer.log();
+    let subscriber = tracing_subscriber::registry()
+        .with(super::with_audit(tracing_subscriber::EnvFilter::new(
+            "warn",
+        )))
+        .with(layer);
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::error_span!("request", tx = "AYzbC2yXcNKhrA5Zr3NHfQ");
+        span.in_scope(|| {
+            crate::audit!(action = "login", user = "user-42", "User logged in");
+            tracing::warn!("not audited");
+        });
+    });
+    log.record(&serde_json::json!({"action": "logout", "user": "user-42"}))
+        .await
+        .unwrap();
+    assert!(log.record(&"not an object").await.is_err());
+
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let lines: Vec<Value> = written
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["tx"], "AYzbC2yXcNKhrA5Zr3NHfQ");
+    assert_eq!(lines[0]["action"], "login");
+    assert_eq!(lines[0]["message"], "User logged in");
+    assert_eq!(lines[1]["action"], "logout");
+    assert!(lines[1]["timestamp"].is_string());
+}
@@ -0,0 +1,461 @@
+//! Composition of the logging outputs in a single subscriber
+
+use std::{
+    io::{self, Stdout, Write},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Context;
+use tracing_appender::non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard};
+use tracing_subscriber::{
+    fmt::MakeWriter,
+    layer::{Layered, SubscriberExt},
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+use super::{
+    install_panic_hook, reloadable_filter, stdout_layer_with, LogFormat, LogLevels, SamplingLayer,
+    SamplingParams,
+};
+use crate::ServiceDef;
+
+/// Subscriber the outputs are added to, after the filter and the sampling
+type Filtered = Layered<SamplingLayer, Layered<reload::Layer<EnvFilter, Registry>, Registry>>;
+
+/// Subscriber with all the outputs
+type Subscriber = Layered<Vec<Box<dyn Layer<Filtered> + Send + Sync>>, Filtered>;
+
+/// Logging setup: the logs are written on stdout and sent to the configured outputs, the
+/// levels and sampling applying to all of them.
+///
+/// ```ignore
+/// let _guard = LoggingBuilder::new(service)
+///     .format(LogFormat::Json)
+///     .levels(config.log_levels)
+///     .otlp(config.otlp)
+///     .install()?;
+/// ```
+#[must_use = "the builder does nothing until installed"]
+pub struct LoggingBuilder<'a> {
+    #[cfg_attr(
+        not(any(
+            feature = "tracing-gelf",
+            feature = "otlp",
+            feature = "syslog",
+            feature = "file-log",
+            feature = "loki",
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) service: ServiceDef<'a>,
+    pub(crate) format: Option<LogFormat>,
+    pub(crate) levels: LogLevels,
+    pub(crate) sampling: SamplingParams,
+    #[cfg(feature = "tracing-gelf")]
+    pub(crate) gelf: Option<crate::tracing_gelf::GelfParams>,
+    #[cfg(feature = "otlp")]
+    pub(crate) otlp: Option<crate::otlp::OtlpParams>,
+    #[cfg(feature = "syslog")]
+    pub(crate) syslog: Option<super::SyslogParams>,
+    #[cfg(feature = "file-log")]
+    pub(crate) file: Option<super::FileLogParams>,
+    #[cfg(feature = "loki")]
+    pub(crate) loki: Option<super::LokiParams>,
+    #[cfg(feature = "audit")]
+    pub(crate) audit: Option<super::AuditParams>,
+}
+
+/// Keeps the stdout writer and the exporters running until the service exits, flushing them
+/// when dropped, see [`LoggingBuilder::install`]
+#[must_use = "dropping the guard stops the logging"]
+pub struct LoggingGuard {
+    _stdout: Option<StdoutGuard>,
+    #[cfg(feature = "tracing-gelf")]
+    gelf: Option<crate::tracing_gelf::GelfGuard>,
+    #[cfg(feature = "otlp")]
+    otlp: Option<crate::otlp::OtlpGuard>,
+    #[cfg(feature = "loki")]
+    loki: Option<super::LokiGuard>,
+    #[cfg(feature = "audit")]
+    audit: Option<super::AuditLog>,
+}
+
+impl LoggingGuard {
+    /// Stops the outputs once their queued events are written, waiting at most 5 seconds for
+    /// the ones sent to Graylog and to Loki, and flushes the OTLP exporters and the audit
+    /// file. Call it at the end of the graceful shutdown, eg. when
+    /// `axum::serve(..).with_graceful_shutdown(..)` returns: the events still queued when the
+    /// process exits are lost.
+    #[allow(unused_mut)]
+    pub async fn shutdown(mut self) {
+        #[cfg(feature = "tracing-gelf")]
+        if let Some(gelf) = self.gelf.take() {
+            gelf.shutdown().await;
+        }
+        #[cfg(feature = "otlp")]
+        drop(self.otlp.take());
+        #[cfg(feature = "loki")]
+        if let Some(loki) = self.loki.take() {
+            loki.shutdown().await;
+        }
+        #[cfg(feature = "audit")]
+        if let Some(audit) = self.audit.take() {
+            if let Err(err) = audit.flush().await {
+                eprintln!("Cannot write the last audit events: {err:#}");
+            }
+        }
+    }
+
+    /// Handle of the audit file, when [configured](LoggingBuilder::audit)
+    #[cfg(feature = "audit")]
+    pub fn audit_log(&self) -> Option<super::AuditLog> {
+        self.audit.clone()
+    }
+}
+
+impl<'a> LoggingBuilder<'a> {
+    /// Logs on stdout only, in the format of [`LogFormat::resolve`], with the levels of
+    /// `RUST_LOG`
+    pub fn new(service: ServiceDef<'a>) -> Self {
+        Self {
+            service,
+            format: None,
+            levels: LogLevels::default(),
+            sampling: SamplingParams::default(),
+            #[cfg(feature = "tracing-gelf")]
+            gelf: None,
+            #[cfg(feature = "otlp")]
+            otlp: None,
+            #[cfg(feature = "syslog")]
+            syslog: None,
+            #[cfg(feature = "file-log")]
+            file: None,
+            #[cfg(feature = "loki")]
+            loki: None,
+            #[cfg(feature = "audit")]
+            audit: None,
+        }
+    }
+
+    /// Format of the logs on stdout
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Levels by target, merged with `RUST_LOG`
+    pub fn levels(mut self, levels: LogLevels) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    /// Sampling rates of the high-volume targets
+    pub fn sampling(mut self, sampling: SamplingParams) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Sends the logs to Graylog. Only the GELF output of the params is used.
+    #[cfg(feature = "tracing-gelf")]
+    pub fn gelf(mut self, gelf: crate::tracing_gelf::GelfParams) -> Self {
+        self.gelf = Some(gelf);
+        self
+    }
+
+    /// Exports the logs, and the traces if enabled, to an OTLP collector. Only the OTLP
+    /// output of the params is used.
+    #[cfg(feature = "otlp")]
+    pub fn otlp(mut self, otlp: crate::otlp::OtlpParams) -> Self {
+        self.otlp = Some(otlp);
+        self
+    }
+
+    #[cfg(feature = "syslog")]
+    pub fn syslog(mut self, syslog: super::SyslogParams) -> Self {
+        self.syslog = Some(syslog);
+        self
+    }
+
+    #[cfg(feature = "file-log")]
+    pub fn file(mut self, file: super::FileLogParams) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Pushes the logs to Loki, from a tokio runtime
+    #[cfg(feature = "loki")]
+    pub fn loki(mut self, loki: super::LokiParams) -> Self {
+        self.loki = Some(loki);
+        self
+    }
+
+    /// Also appends the [audit events](crate::audit) to a dedicated file
+    #[cfg(feature = "audit")]
+    pub fn audit(mut self, audit: super::AuditParams) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Installs the subscriber globally, with the [panic hook](super::install_panic_hook).
+    /// Fails if the levels are invalid, an output cannot be set up or a subscriber is already
+    /// installed. The invalid directives of `RUST_LOG` are only ignored, with a warning.
+    ///
+    /// The logs are written on stdout by a dedicated thread, so the requests do not wait for
+    /// it, until the returned guard is dropped: keep it alive until the service exits. The
+    /// events are only waited for when the thread lags behind by more than 128k lines. Once the
+    /// guard is dropped, the logs are written on stdout directly, so dropping it by mistake
+    /// (`install()?;`) loses none of them.
+    pub fn install(self) -> anyhow::Result<LoggingGuard> {
+        let directives = self.levels.directives();
+        EnvFilter::builder()
+            .parse(&directives)
+            .with_context(|| format!("Invalid log levels `{directives}`"))?;
+        let (subscriber, guard) = self.subscriber(reloadable_filter(&self.levels))?;
+        subscriber.try_init()?;
+        install_panic_hook();
+        Ok(guard)
+    }
+
+    /// Subscriber writing to the outputs, with the levels of `filter`
+    fn subscriber(
+        &self,
+        filter: reload::Layer<EnvFilter, Registry>,
+    ) -> anyhow::Result<(Subscriber, LoggingGuard)> {
+        let (stdout, stdout_guard) = stdout_writer(io::stdout(), io::stdout);
+        #[allow(unused_mut)]
+        let mut guard = LoggingGuard {
+            _stdout: Some(stdout_guard),
+            #[cfg(feature = "tracing-gelf")]
+            gelf: None,
+            #[cfg(feature = "otlp")]
+            otlp: None,
+            #[cfg(feature = "loki")]
+            loki: None,
+            #[cfg(feature = "audit")]
+            audit: None,
+        };
+        #[allow(unused_mut)]
+        let mut outputs: Vec<Box<dyn Layer<Filtered> + Send + Sync>> =
+            vec![stdout_layer_with(LogFormat::resolve(self.format), stdout)];
+        #[cfg(feature = "tracing-gelf")]
+        if let Some(gelf) = &self.gelf {
+            let (layer, gelf_guard) = crate::tracing_gelf::gelf_layer(gelf, &self.service)?;
+            outputs.push(layer.boxed());
+            guard.gelf = Some(gelf_guard);
+        }
+        #[cfg(feature = "otlp")]
+        if let Some(otlp) = &self.otlp {
+            let (layers, otlp_guard) = crate::otlp::otlp_layers(otlp, &self.service)?;
+            outputs.extend(layers);
+            guard.otlp = Some(otlp_guard);
+        }
+        #[cfg(feature = "syslog")]
+        if let Some(syslog) = &self.syslog {
+            outputs.push(super::syslog::syslog_output(syslog, &self.service)?.boxed());
+        }
+        #[cfg(feature = "file-log")]
+        if let Some(file) = &self.file {
+            outputs.push(super::file::file_output(file, &self.service)?);
+        }
+        #[cfg(feature = "loki")]
+        if let Some(loki) = &self.loki {
+            let (layer, loki_guard) = super::loki::loki_output(loki, &self.service)?;
+            outputs.push(layer.boxed());
+            guard.loki = Some(loki_guard);
+        }
+        #[cfg(feature = "audit")]
+        if let Some(audit) = &self.audit {
+            let layer = super::audit::audit_output(audit)?;
+            guard.audit = Some(layer.log());
+            outputs.push(layer.boxed());
+        }
+        let subscriber = tracing_subscriber::registry()
+            .with(filter)
+            .with(SamplingLayer::new(&self.sampling))
+            .with(outputs);
+        Ok((subscriber, guard))
+    }
+}
+
+/// Non-blocking writer of the lines on `writer` by the `stdout-logger` thread, writing on
+/// `stdout()` directly once the guard is dropped
+fn stdout_writer<W: Write + Send + 'static, S>(
+    writer: W,
+    stdout: fn() -> S,
+) -> (StdoutWriter<S>, StdoutGuard) {
+    let (lines, worker) = NonBlockingBuilder::default()
+        .lossy(false)
+        .thread_name("stdout-logger")
+        .finish(writer);
+    let stopped = Arc::new(RwLock::new(false));
+    let writer = StdoutWriter {
+        lines,
+        stopped: stopped.clone(),
+        stdout,
+    };
+    (
+        writer,
+        StdoutGuard {
+            stopped,
+            _worker: worker,
+        },
+    )
+}
+
+/// Non-blocking stdout writer, see [`stdout_writer`]
+struct StdoutWriter<S = Stdout> {
+    lines: NonBlocking,
+    /// Read locked while a line is queued, so that no line is queued after the thread is told
+    /// to stop: it would be lost
+    stopped: Arc<RwLock<bool>>,
+    stdout: fn() -> S,
+}
+
+impl<S> Clone for StdoutWriter<S> {
+    fn clone(&self) -> Self {
+        Self {
+            lines: self.lines.clone(),
+            stopped: self.stopped.clone(),
+            stdout: self.stdout,
+        }
+    }
+}
+
+impl<S: Write> Write for StdoutWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let stopped = self.stopped.read().unwrap();
+        if *stopped {
+            return (self.stdout)().write(buf);
+        }
+        self.lines.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (self.stdout)().flush()
+    }
+}
+
+impl<'a, S: Write> MakeWriter<'a> for StdoutWriter<S> {
+    type Writer = StdoutWriter<S>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Stops the `stdout-logger` thread once the queued lines are written, the next lines being
+/// written on stdout directly
+struct StdoutGuard {
+    stopped: Arc<RwLock<bool>>,
+    _worker: WorkerGuard,
+}
+
+impl Drop for StdoutGuard {
+    fn drop(&mut self) {
+        // before the worker guard is dropped, which stops the thread
+        *self.stopped.write().unwrap() = true;
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn writes_stdout_after_the_guard_is_dropped() {
+    static CAPTURED: std::sync::Mutex<Vec<u8>> = std::sync::Mutex::new(Vec::new());
+    struct Captured;
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            CAPTURED.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let (mut writer, guard) = stdout_writer(Captured, || Captured);
+    let concurrent = {
+        let mut writer = writer.clone();
+        std::thread::spawn(move || {
+            for _ in 0..1000 {
+                writer.write_all(b"concurrent\n").unwrap();
+            }
+        })
+    };
+    writer.write_all(b"queued\n").unwrap();
+    drop(guard);
+    writer.write_all(b"written on stdout\n").unwrap();
+    concurrent.join().unwrap();
+
+    let captured = String::from_utf8(CAPTURED.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = captured.lines().collect();
+    assert_eq!(lines.len(), 1002);
+    let position = |line| lines.iter().position(|l| *l == line).unwrap();
+    assert!(position("queued") < position("written on stdout"));
+}
+
+#[cfg(test)]
+#[test]
+fn filters_the_outputs() {
+    use tracing::Level;
+
+    let builder = LoggingBuilder::new(ServiceDef::new("orders-api", "1.2.0", "abc123"))
+        .format(LogFormat::Json)
+        .levels(serde_yaml::from_str("{default: warn, orders_api: debug}").unwrap())
+        .sampling(serde_yaml::from_str("{orders_api: 2}").unwrap());
+    let subscriber = |builder: &LoggingBuilder| {
+        let filter = EnvFilter::new(builder.levels.directives());
+        builder.subscriber(reload::Layer::new(filter).0).unwrap()
+    };
+    let invalid = LoggingBuilder::new(ServiceDef::new("orders-api", "1.2.0", "abc123"))
+        .levels(serde_yaml::from_str("{default: info, \"sqlx[\": warn}").unwrap());
+    let error = invalid.install().err().unwrap();
+    assert_eq!(error.to_string(), "Invalid log levels `info,sqlx[=warn`");
+
+    let (stdout_only, _guard) = subscriber(&builder);
+    tracing::subscriber::with_default(stdout_only, || {
+        assert!(tracing::enabled!(target: "orders_api", Level::DEBUG));
+        assert!(!tracing::enabled!(target: "hyper", Level::INFO));
+        assert!(tracing::enabled!(target: "hyper", Level::WARN));
+    });
+
+    #[cfg(feature = "file-log")]
+    {
+        let directory =
+            std::env::temp_dir().join(format!("logging-builder-{}", std::process::id()));
+        let file = super::FileLogParams {
+            directory: directory.to_string_lossy().into_owned(),
+            file_name: None,
+            rotation: super::FileRotation::Never,
+            max_size: None,
+            max_files: 1,
+            log_format: Some(LogFormat::Json),
+        };
+        let (with_file, guard) = subscriber(&builder.file(file));
+        tracing::subscriber::with_default(with_file, || {
+            tracing::debug!(target: "orders_api", "order created");
+            tracing::debug!(target: "orders_api", "order updated");
+            tracing::info!(target: "hyper", "connection closed");
+            tracing::warn!(target: "sqlx", "slow query");
+        });
+        drop(guard);
+        let logs = std::fs::read_to_string(directory.join("orders-api.log")).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let messages: Vec<String> = logs
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|event| event["message"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(messages, ["order created", "slow query"]);
+
+        // an output which cannot be set up fails the whole setup
+        let unwritable = super::FileLogParams {
+            directory: "/dev/null/logs".to_string(),
+            ..serde_yaml::from_str("directory: ''").unwrap()
+        };
+        let filter = reload::Layer::new(EnvFilter::new("info")).0;
+        let builder = LoggingBuilder::new(ServiceDef::new("orders-api", "1.2.0", "abc123"));
+        assert!(builder.file(unwritable).subscriber(filter).is_err());
+    }
+}
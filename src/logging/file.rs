@@ -0,0 +1,247 @@
+//! Logs written to rotated files, for the deployments without log shipper
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime};
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+use super::{LogFormat, LoggingBuilder, LoggingGuard};
+use crate::ServiceDef;
+
+/// Rotation of the log file over time
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileRotation {
+    /// A new file each day (UTC)
+    #[default]
+    Daily,
+    /// Only rotated on size, see [`FileLogParams::max_size`]
+    Never,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FileLogParams {
+    /// Directory of the log files, created if missing
+    pub directory: String,
+    /// Name of the current log file (default: `{pkg_name}.log`). Rotated files are named after
+    /// it with their date: `orders-api.log.2024-05-12`, `orders-api.log.2024-05-12.1`...
+    #[serde(default)]
+    pub file_name: Option<String>,
+    #[serde(default)]
+    pub rotation: FileRotation,
+    /// Size of the log file triggering its rotation, like `100MiB` (default: unlimited)
+    #[serde(default, with = "crate::config::option_size")]
+    pub max_size: Option<u64>,
+    /// Number of rotated files kept, the oldest ones being deleted (default: 7)
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    /// Format of the logs in the file (default: pretty, without colors)
+    #[serde(default)]
+    pub log_format: Option<LogFormat>,
+}
+
+fn default_max_files() -> usize {
+    7
+}
+
+/// Log file rotated on date and size, see [`FileLogParams`]
+pub struct RollingFile {
+    directory: PathBuf,
+    file_name: String,
+    rotation: FileRotation,
+    max_size: Option<u64>,
+    max_files: usize,
+    file: File,
+    size: u64,
+    date: Date,
+}
+
+impl RollingFile {
+    pub fn open(params: &FileLogParams, service: &ServiceDef) -> anyhow::Result<Self> {
+        let directory = PathBuf::from(&params.directory);
+        fs::create_dir_all(&directory)
+            .with_context(|| format!("Cannot create log directory {}", directory.display()))?;
+        let file_name = params
+            .file_name
+            .clone()
+            .unwrap_or_else(|| format!("{}.log", service.pkg_name));
+        let path = directory.join(&file_name);
+        let file =
+            open(&path).with_context(|| format!("Cannot open log file {}", path.display()))?;
+        let metadata = file.metadata()?;
+        let date = metadata
+            .modified()
+            .map(|modified| OffsetDateTime::from(modified).date())
+            .unwrap_or_else(|_| today());
+        Ok(Self {
+            directory,
+            file_name,
+            rotation: params.rotation,
+            max_size: params.max_size,
+            max_files: params.max_files,
+            file,
+            size: metadata.len(),
+            date,
+        })
+    }
+
+    fn needs_rotation(&self, today: Date, len: usize) -> bool {
+        let new_day = self.rotation == FileRotation::Daily && today != self.date;
+        let full = self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + len as u64 > max);
+        new_day || full
+    }
+
+    /// Renames the current file after its date, opens a new one and deletes the oldest files
+    fn rotate(&mut self, today: Date) -> io::Result<()> {
+        self.file.flush()?;
+        let base = format!("{}.{}", self.file_name, self.date);
+        let mut rotated = self.directory.join(&base);
+        let mut n = 0;
+        while rotated.exists() {
+            n += 1;
+            rotated = self.directory.join(format!("{base}.{n}"));
+        }
+        let path = self.directory.join(&self.file_name);
+        fs::rename(&path, rotated)?;
+        self.file = open(&path)?;
+        self.size = 0;
+        self.date = today;
+        self.delete_oldest()
+    }
+
+    fn delete_oldest(&self) -> io::Result<()> {
+        let prefix = format!("{}.", self.file_name);
+        let mut rotated = vec![];
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                rotated.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.max_files);
+        for (_, path) in &rotated[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn open(path: &std::path::Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn today() -> Date {
+    OffsetDateTime::now_utc().date()
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let today = today();
+        if self.needs_rotation(today, buf.len()) {
+            self.rotate(today)?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Layer writing the events to a [`RollingFile`]
+pub fn file_layer<S>(
+    params: &FileLogParams,
+    service: &ServiceDef,
+) -> anyhow::Result<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    let writer = Mutex::new(RollingFile::open(params, service)?);
+    let layer = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_writer(writer);
+    Ok(if params.log_format.unwrap_or_default().is_json() {
+        layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed()
+    } else {
+        layer.boxed()
+    })
+}
+
+/// File layer of [`LoggingBuilder`], registered as a sink
+pub(super) fn file_output<S>(
+    params: &FileLogParams,
+    service: &ServiceDef,
+) -> anyhow::Result<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    println!("Configuring file logger {}", params.directory);
+    let layer = file_layer(params, service)?;
+    crate::info::register_sink("file", params.directory.clone(), "configured");
+    Ok(layer)
+}
+
+/// Installs a subscriber logging on stdout (see [`LogFormat::resolve`]) and to rotated files
+pub fn init_file(
+    params: &FileLogParams,
+    service: &ServiceDef,
+    format: Option<LogFormat>,
+) -> anyhow::Result<LoggingGuard> {
+    let mut builder = LoggingBuilder::new(*service).file(params.clone());
+    builder.format = format;
+    builder.install()
+}
+
+#[cfg(test)]
+#[test]
+fn rotates_on_size() {
+    let directory = std::env::temp_dir().join(format!("rolling-file-{}", std::process::id()));
+    let params = FileLogParams {
+        directory: directory.to_string_lossy().into_owned(),
+        file_name: None,
+        rotation: FileRotation::Never,
+        max_size: Some(10),
+        max_files: 2,
+        log_format: None,
+    };
+    let service = ServiceDef::new("orders-api", "1.2.0", "abc123");
+    let mut file = RollingFile::open(&params, &service).unwrap();
+    for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+        file.write_all(line.as_bytes()).unwrap();
+    }
+
+    let mut names: Vec<String> = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    let current = fs::read_to_string(directory.join("orders-api.log")).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+    let date = today();
+    assert_eq!(
+        names,
+        [
+            "orders-api.log".to_string(),
+            format!("orders-api.log.{date}.1"),
+            format!("orders-api.log.{date}.2"),
+        ]
+    );
+    assert_eq!(current, "fourth\n");
+}
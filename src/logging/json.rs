@@ -0,0 +1,87 @@
+//! Events as JSON objects, with the fields of their spans, for the Loki and audit outputs
+
+use std::fmt;
+
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan};
+
+/// Fields of a span, recorded in its extensions by the first layer needing them
+struct SpanFields(Map<String, Value>);
+
+/// Records the fields of a new span, to call from `Layer::on_new_span`
+pub(super) fn on_new_span<S>(attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>)
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let Some(span) = ctx.span(id) else {
+        return;
+    };
+    let mut extensions = span.extensions_mut();
+    if extensions.get_mut::<SpanFields>().is_none() {
+        let mut fields = Map::new();
+        attrs.record(&mut JsonFields(&mut fields));
+        extensions.insert(SpanFields(fields));
+    }
+}
+
+/// Records the fields recorded later in a span, to call from `Layer::on_record`
+pub(super) fn on_record<S>(id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>)
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if let Some(span) = ctx.span(id) {
+        if let Some(SpanFields(fields)) = span.extensions_mut().get_mut::<SpanFields>() {
+            values.record(&mut JsonFields(fields));
+        }
+    }
+}
+
+/// Fields of the event and of its spans, the event ones last, added to `fields`
+pub(super) fn event_fields<S>(
+    event: &Event<'_>,
+    ctx: &Context<'_, S>,
+    fields: &mut Map<String, Value>,
+) where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    for span in ctx
+        .event_scope(event)
+        .into_iter()
+        .flat_map(|scope| scope.from_root())
+    {
+        if let Some(SpanFields(span_fields)) = span.extensions().get::<SpanFields>() {
+            fields.extend(span_fields.clone());
+        }
+    }
+    event.record(&mut JsonFields(fields));
+}
+
+/// Records the fields in a JSON object
+struct JsonFields<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonFields<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+}
@@ -0,0 +1,331 @@
+//! Events pushed to Grafana Loki, for the clusters without Graylog nor promtail
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::{timeout_at, Instant},
+};
+use tracing::{span, Event, Subscriber};
+use tracing_subscriber::{layer::Context as LayerContext, registry::LookupSpan, Layer};
+
+use super::{json, LogFormat, LoggingBuilder, LoggingGuard};
+use crate::{redact::redact_url, ServiceDef};
+
+/// Targets never pushed, as pushing their events would produce new ones
+const PUSH_TARGETS: [&str; 3] = ["reqwest", "hyper", "h2"];
+/// Events waiting to be pushed, the next ones being dropped
+const QUEUE_SIZE: usize = 10_000;
+/// Longest wait of an event before being pushed
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Longest wait for the queued events to be pushed on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LokiParams {
+    /// Base URL of Loki, like `http://loki:3100`
+    pub url: String,
+    pub env: String,
+    /// Tenant of the streams, sent in the `X-Scope-OrgID` header, for multi-tenant Loki
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Additional labels of the streams, like `cluster: eu-west-1`. The `service`, `version`,
+    /// `env` and `level` labels cannot be overridden.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Number of events pushed at once (default: 500)
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+
+fn default_batch_size() -> usize {
+    500
+}
+
+/// Line of an event, pushed in the stream of its level
+struct Entry {
+    level: &'static str,
+    timestamp: String,
+    line: String,
+}
+
+/// Layer pushing the events to Loki, see [`loki_layer`]
+pub struct LokiLayer {
+    sender: mpsc::Sender<Entry>,
+}
+
+/// Stops the pushes to Loki once the queued events are pushed, see [`LokiGuard::shutdown`].
+/// When dropped, the events keep being pushed.
+pub struct LokiGuard {
+    shutdown: oneshot::Sender<()>,
+    pusher: JoinHandle<()>,
+}
+
+impl LokiGuard {
+    /// Pushes the queued events, waiting at most 5 seconds, and stops the pushes
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, self.pusher)
+            .await
+            .is_err()
+        {
+            eprintln!("Cannot push the last logs to Loki in {SHUTDOWN_TIMEOUT:?}");
+        }
+    }
+}
+
+/// Layer pushing the events to Loki as JSON lines with the fields of the event and of its
+/// spans, like `{"level":"INFO","target":"access_log","tx":"...","message":"GET / 200 3ms"}`.
+/// The streams are labeled with the package name (`service`), version, `env` and `level`.
+///
+/// The events are pushed in batches by a background task, at least every second, until the
+/// guard is [shut down](LokiGuard::shutdown). They are dropped if Loki lags behind by more
+/// than 10k events. Must be called from a tokio runtime.
+pub fn loki_layer(
+    params: &LokiParams,
+    service: &ServiceDef,
+) -> anyhow::Result<(LokiLayer, LokiGuard)> {
+    let runtime = tokio::runtime::Handle::try_current()
+        .context("The Loki output must be set up in a tokio runtime")?;
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let mut labels = params.labels.clone();
+    labels.insert("service".to_string(), service.pkg_name.to_string());
+    labels.insert(
+        "version".to_string(),
+        format!("{}-{}", service.version, service.git_hash),
+    );
+    labels.insert("env".to_string(), params.env.clone());
+    let pusher = Pusher {
+        client,
+        url: format!("{}/loki/api/v1/push", params.url.trim_end_matches('/')),
+        tenant: params.tenant.clone(),
+        labels,
+    };
+    let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+    let (shutdown, shutdown_receiver) = oneshot::channel();
+    let pusher = runtime.spawn(pusher.run(receiver, params.batch_size.max(1), shutdown_receiver));
+    Ok((LokiLayer { sender }, LokiGuard { shutdown, pusher }))
+}
+
+struct Pusher {
+    client: reqwest::Client,
+    url: String,
+    tenant: Option<String>,
+    labels: BTreeMap<String, String>,
+}
+
+impl Pusher {
+    /// Pushes the batches until all the layers are dropped or `shutdown` is received, then
+    /// pushes the queued events
+    async fn run(
+        self,
+        mut receiver: mpsc::Receiver<Entry>,
+        batch_size: usize,
+        mut shutdown: oneshot::Receiver<()>,
+    ) {
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut deadline = Instant::now() + FLUSH_INTERVAL;
+        // until shut down, or the guard is dropped
+        let mut running = true;
+        loop {
+            let received = tokio::select! {
+                received = timeout_at(deadline, receiver.recv()) => received,
+                stop = &mut shutdown, if running => {
+                    running = false;
+                    if stop.is_ok() {
+                        // the queued events are received, then None
+                        receiver.close();
+                    }
+                    continue;
+                }
+            };
+            let closed = match received {
+                Ok(Some(entry)) => {
+                    if batch.is_empty() {
+                        deadline = Instant::now() + FLUSH_INTERVAL;
+                    }
+                    batch.push(entry);
+                    if batch.len() < batch_size {
+                        continue;
+                    }
+                    false
+                }
+                Ok(None) => true,
+                Err(_) => false,
+            };
+            if !batch.is_empty() {
+                // not logged with tracing, it would be pushed too
+                match self.push(std::mem::take(&mut batch)).await {
+                    Ok(()) => crate::info::set_sink_state("loki", "connected"),
+                    Err(err) => {
+                        crate::info::set_sink_state("loki", "disconnected");
+                        eprintln!("Cannot push logs to Loki: {err:#}");
+                    }
+                }
+            }
+            if closed {
+                break;
+            }
+            deadline = Instant::now() + FLUSH_INTERVAL;
+        }
+    }
+
+    async fn push(&self, batch: Vec<Entry>) -> anyhow::Result<()> {
+        let mut streams: BTreeMap<&str, Vec<[String; 2]>> = BTreeMap::new();
+        for entry in batch {
+            streams
+                .entry(entry.level)
+                .or_default()
+                .push([entry.timestamp, entry.line]);
+        }
+        let streams: Vec<Value> = streams
+            .into_iter()
+            .map(|(level, values)| {
+                let mut labels = self.labels.clone();
+                labels.insert("level".to_string(), level.to_string());
+                json!({ "stream": labels, "values": values })
+            })
+            .collect();
+        let mut req = self
+            .client
+            .post(&self.url)
+            .json(&json!({ "streams": streams }));
+        if let Some(tenant) = &self.tenant {
+            req = req.header("X-Scope-OrgID", tenant);
+        }
+        req.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+impl<S> Layer<S> for LokiLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: LayerContext<'_, S>) {
+        json::on_new_span(attrs, id, ctx);
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: LayerContext<'_, S>) {
+        json::on_record(id, values, ctx);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: LayerContext<'_, S>) {
+        let meta = event.metadata();
+        if PUSH_TARGETS
+            .iter()
+            .any(|target| meta.target().starts_with(target))
+        {
+            return;
+        }
+        let mut fields = Map::new();
+        fields.insert("level".to_string(), meta.level().as_str().into());
+        fields.insert("target".to_string(), meta.target().into());
+        json::event_fields(event, &ctx, &mut fields);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let _ = self.sender.try_send(Entry {
+            level: level_label(meta.level()),
+            timestamp: timestamp.to_string(),
+            line: Value::Object(fields).to_string(),
+        });
+    }
+}
+
+fn level_label(level: &tracing::Level) -> &'static str {
+    match *level {
+        tracing::Level::ERROR => "error",
+        tracing::Level::WARN => "warn",
+        tracing::Level::INFO => "info",
+        tracing::Level::DEBUG => "debug",
+        tracing::Level::TRACE => "trace",
+    }
+}
+
+/// Loki layer of [`LoggingBuilder`], registered as a sink
+pub(super) fn loki_output(
+    params: &LokiParams,
+    service: &ServiceDef,
+) -> anyhow::Result<(LokiLayer, LokiGuard)> {
+    let url = redact_url(&params.url);
+    println!("Configuring Loki logger env:{}, url:{url}", params.env);
+    let output = loki_layer(params, service)?;
+    crate::info::register_sink("loki", url, "configured");
+    Ok(output)
+}
+
+/// Installs a subscriber logging on stdout (see [`LogFormat::resolve`]) and pushing to Loki.
+/// Must be called from a tokio runtime.
+pub fn init_loki(
+    params: &LokiParams,
+    service: &ServiceDef,
+    format: Option<LogFormat>,
+) -> anyhow::Result<LoggingGuard> {
+    let mut builder = LoggingBuilder::new(*service).loki(params.clone());
+    builder.format = format;
+    builder.install()
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[tokio::test]
+async fn pushes_streams() {
+    use http::Method;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::testing::{FakeResponse, FakeUpstream};
+
+    let loki = FakeUpstream::start().await;
+    loki.on(
+        Method::POST,
+        "/loki/api/v1/push",
+        FakeResponse::new(http::StatusCode::NO_CONTENT),
+    );
+    let params = LokiParams {
+        url: loki.url(""),
+        env: "staging".to_string(),
+        tenant: Some("team-a".to_string()),
+        labels: BTreeMap::new(),
+        batch_size: 500,
+    };
+    let service = ServiceDef::new("orders-api", "1.2.0", "abc123");
+    let (layer, guard) = loki_layer(&params, &service).unwrap();
+    let subscriber = tracing_subscriber::registry().with(layer);
+    let _subscriber = tracing::subscriber::set_default(subscriber);
+    let span = tracing::info_span!("request", tx = "AYzbC2yXcNKhrA5Zr3NHfQ");
+    span.in_scope(|| tracing::warn!(retries = 3, "upstream slow"));
+
+    // the partial batch is pushed on shutdown, without waiting for the flush interval
+    let started = Instant::now();
+    guard.shutdown().await;
+    assert!(started.elapsed() < FLUSH_INTERVAL);
+    let call = loki.calls().remove(0);
+    assert_eq!(call.headers["x-scope-orgid"], "team-a");
+    let body: Value = serde_json::from_slice(&call.body).unwrap();
+    let stream = &body["streams"][0];
+    assert_eq!(
+        stream["stream"],
+        json!({"env": "staging", "level": "warn", "service": "orders-api", "version": "1.2.0-abc123"})
+    );
+    let line: Value = serde_json::from_str(stream["values"][0][1].as_str().unwrap()).unwrap();
+    assert_eq!(
+        line,
+        json!({
+            "level": "WARN",
+            "target": "service_helpe_rs::logging::loki",
+            "tx": "AYzbC2yXcNKhrA5Zr3NHfQ",
+            "retries": 3,
+            "message": "upstream slow",
+        })
+    );
+}
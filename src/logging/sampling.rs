@@ -0,0 +1,88 @@
+//! Sampling of the events of high-volume targets
+
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// Sampling rates of the events by target, like `access_log: 100` to keep 1 event in 100
+/// of the `access_log` target and its sub-targets. Warnings, errors and audit events are
+/// always kept.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct SamplingParams {
+    pub targets: BTreeMap<String, u64>,
+}
+
+/// Layer dropping the sampled out events for all the outputs, see [`SamplingParams`]
+pub struct SamplingLayer {
+    /// Rules by decreasing target length, so the most specific one applies
+    rules: Vec<(String, u64, AtomicU64)>,
+}
+
+impl SamplingLayer {
+    pub fn new(params: &SamplingParams) -> Self {
+        let mut rules: Vec<_> = params
+            .targets
+            .iter()
+            .map(|(target, rate)| (target.clone(), (*rate).max(1), AtomicU64::new(0)))
+            .collect();
+        rules.sort_by_key(|(target, _, _)| std::cmp::Reverse(target.len()));
+        Self { rules }
+    }
+}
+
+fn matches(target: &str, prefix: &str) -> bool {
+    target
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+impl<S: Subscriber> Layer<S> for SamplingLayer {
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        let meta = event.metadata();
+        if *meta.level() <= Level::WARN {
+            return true;
+        }
+        #[cfg(feature = "audit")]
+        if meta.target() == super::AUDIT_TARGET {
+            return true;
+        }
+        match self
+            .rules
+            .iter()
+            .find(|(prefix, _, _)| matches(meta.target(), prefix))
+        {
+            Some((_, rate, count)) => count.fetch_add(1, Ordering::Relaxed) % rate == 0,
+            None => true,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing", feature = "tracing"))]
+#[test]
+fn keeps_one_in_n() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let capture = crate::testing::TracingCapture::new();
+    let params = SamplingParams {
+        targets: BTreeMap::from([("access_log".to_string(), 10)]),
+    };
+    let subscriber = tracing_subscriber::registry()
+        .with(SamplingLayer::new(&params))
+        .with(capture.layer());
+    tracing::subscriber::with_default(subscriber, || {
+        for _ in 0..100 {
+            tracing::info!(target: "access_log", "GET /");
+            tracing::info!(target: "access_logger", "kept");
+        }
+        tracing::warn!(target: "access_log", "GET / slow");
+    });
+
+    assert_eq!(capture.events_for_target("access_log").len(), 11);
+    assert_eq!(capture.events_for_target("access_logger").len(), 100);
+}
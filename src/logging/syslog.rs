@@ -0,0 +1,216 @@
+//! Events sent to syslog, formatted as RFC 5424 messages
+
+use std::{
+    fmt::{self, Write},
+    net::UdpSocket,
+    os::unix::net::UnixDatagram,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context as LayerContext, Layer};
+
+use super::{LogFormat, LoggingBuilder, LoggingGuard};
+use crate::ServiceDef;
+
+/// Socket of the local syslog daemon
+const DEV_LOG: &str = "/dev/log";
+/// `user` facility
+const DEFAULT_FACILITY: u8 = 1;
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct SyslogParams {
+    /// UDP address of the syslog server, like `syslog.local:514`. Events are sent to the local
+    /// daemon through `/dev/log` if not set.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Facility code, between 0 and 23 (default: 1, `user`). `local0` to `local7` are 16 to 23.
+    #[serde(default)]
+    pub facility: Option<u8>,
+}
+
+enum Transport {
+    Udp(UdpSocket),
+    Unix(UnixDatagram),
+}
+
+/// Layer sending the events to syslog, see [`syslog_layer`]
+pub struct SyslogLayer {
+    transport: Transport,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+    /// Whether the last message was sent, reported as the state of the sink
+    sent: AtomicBool,
+}
+
+/// Layer sending the events to syslog as RFC 5424 messages, with the package name as
+/// application name. The message is the event message followed by its fields, like
+/// `orders_api::db: query failed error="timeout"`.
+///
+/// Events are dropped if the syslog server or daemon is unreachable.
+pub fn syslog_layer(params: &SyslogParams, service: &ServiceDef) -> anyhow::Result<SyslogLayer> {
+    let transport = match &params.address {
+        Some(address) => {
+            let socket = UdpSocket::bind("0.0.0.0:0").context("Cannot bind syslog socket")?;
+            socket
+                .connect(address)
+                .with_context(|| format!("Cannot resolve syslog address {address}"))?;
+            Transport::Udp(socket)
+        }
+        None => {
+            let socket = UnixDatagram::unbound().context("Cannot create syslog socket")?;
+            socket
+                .connect(DEV_LOG)
+                .with_context(|| format!("Cannot connect to {DEV_LOG}"))?;
+            Transport::Unix(socket)
+        }
+    };
+    let facility = params.facility.unwrap_or(DEFAULT_FACILITY);
+    if facility > 23 {
+        anyhow::bail!("Invalid syslog facility {facility}");
+    }
+    Ok(SyslogLayer {
+        transport,
+        facility,
+        hostname: hostname(),
+        app_name: service.pkg_name.to_string(),
+        sent: AtomicBool::new(true),
+    })
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+impl SyslogLayer {
+    /// RFC 5424 message of an event, without structured data
+    fn format(&self, event: &Event) -> String {
+        let meta = event.metadata();
+        let mut fields = FieldsVisitor::default();
+        event.record(&mut fields);
+        format!(
+            "<{}>1 {} {} {} {} - - {}: {}{}",
+            self.facility * 8 + severity(meta.level()),
+            crate::time::format_rfc3339(std::time::SystemTime::now()),
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            meta.target(),
+            fields.message,
+            fields.fields,
+        )
+    }
+}
+
+impl<S: Subscriber> Layer<S> for SyslogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+        let message = self.format(event);
+        let sent = match &self.transport {
+            Transport::Udp(socket) => socket.send(message.as_bytes()),
+            Transport::Unix(socket) => socket.send(message.as_bytes()),
+        }
+        .is_ok();
+        if self.sent.swap(sent, Ordering::Relaxed) != sent {
+            let state = if sent { "connected" } else { "disconnected" };
+            crate::info::set_sink_state("syslog", state);
+        }
+    }
+}
+
+#[derive(Default)]
+struct FieldsVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for FieldsVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+}
+
+/// Syslog layer of [`LoggingBuilder`](super::LoggingBuilder), registered as a sink
+pub(super) fn syslog_output(
+    params: &SyslogParams,
+    service: &ServiceDef,
+) -> anyhow::Result<SyslogLayer> {
+    let target = params.address.as_deref().unwrap_or(DEV_LOG);
+    println!("Configuring syslog logger {target}");
+    let layer = syslog_layer(params, service)?;
+    crate::info::register_sink("syslog", target.to_string(), "connected");
+    Ok(layer)
+}
+
+/// Installs a subscriber logging on stdout (see [`LogFormat::resolve`]) and to syslog, for
+/// the deployments without GELF nor OTLP collector
+pub fn init_syslog(
+    params: &SyslogParams,
+    service: &ServiceDef,
+    format: Option<LogFormat>,
+) -> anyhow::Result<LoggingGuard> {
+    let mut builder = LoggingBuilder::new(*service).syslog(params.clone());
+    builder.format = format;
+    builder.install()
+}
+
+#[cfg(test)]
+#[test]
+fn sends_rfc5424_messages() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let params = SyslogParams {
+        address: Some(server.local_addr().unwrap().to_string()),
+        facility: Some(16),
+    };
+    let service = ServiceDef::new("orders-api", "1.2.0", "abc123");
+    let layer = syslog_layer(&params, &service).unwrap();
+    let subscriber = tracing_subscriber::registry().with(layer);
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::warn!(target: "orders_api::db", error = "timeout", "query failed");
+    });
+
+    let mut buf = [0; 1024];
+    let len = server.recv(&mut buf).unwrap();
+    let message = std::str::from_utf8(&buf[..len]).unwrap();
+    assert!(message.starts_with("<132>1 "), "{message}");
+    let pid = std::process::id();
+    assert!(
+        message.ends_with(&format!(
+            " orders-api {pid} - - orders_api::db: query failed error=\"timeout\""
+        )),
+        "{message}"
+    );
+}
@@ -1,17 +1,103 @@
-use prometheus::{Encoder, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, TextEncoder};
+//! Helper methods used to creates metrics
+
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, TextEncoder,
+};
+use std::any::Any;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Mutex, RwLock};
+#[cfg(feature = "tokio")]
 use std::time::Duration;
 
-/// Helper methods used to creates metrics
-///
+mod export;
+pub mod job;
+pub use export::{
+    export_alert_rules, export_grafana_dashboard, ERROR_RATE_THRESHOLD,
+    LATENCY_P99_THRESHOLD_SECONDS,
+};
+
+/// Default buckets of the `http_request_duration_seconds` histograms, in seconds
+pub const DEFAULT_DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 7.5, 10.0, 25.0, 50.0,
+    100.0,
+];
+
+/// Metrics created by the helpers of this module, by name
+static CREATED: Mutex<BTreeMap<String, CreatedMetric>> = Mutex::new(BTreeMap::new());
+
+/// Metrics registered in the default registry by other helpers of this crate, like the HTTP
+/// metrics layer
+static REGISTERED: Mutex<Vec<Box<dyn Collector>>> = Mutex::new(Vec::new());
+
+struct CreatedMetric {
+    labels: Vec<String>,
+    collector: Box<dyn Any + Send + Sync>,
+    registered: Box<dyn Collector>,
+}
+
+/// Registers the metric and keeps track of it
+fn register<T: Collector + Clone + 'static>(name: &str, labels: &[&str], metric: T) -> T {
+    register_in(&mut CREATED.lock().unwrap(), name, labels, metric)
+}
+
+fn register_in<T: Collector + Clone + 'static>(
+    created: &mut BTreeMap<String, CreatedMetric>,
+    name: &str,
+    labels: &[&str],
+    metric: T,
+) -> T {
+    prometheus::register(Box::new(metric.clone())).unwrap();
+    created.insert(
+        name.to_string(),
+        CreatedMetric {
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            collector: Box::new(metric.clone()),
+            registered: Box::new(metric.clone()),
+        },
+    );
+    metric
+}
+
+/// Keeps track of a metric registered in the default registry by a helper of this crate, see
+/// [`test_support::unregister_created_metrics`]
+#[cfg_attr(not(feature = "axum"), allow(dead_code))]
+pub(crate) fn track_registered(metric: Box<dyn Collector>) {
+    REGISTERED.lock().unwrap().push(metric);
+}
+
+/// Returns the metric previously created by a helper of this module, or creates and
+/// registers it. The lock is held until it is registered, so concurrent callers get the
+/// same metric.
+fn get_or_register<T: Collector + Clone + 'static>(
+    name: &str,
+    labels: &[&str],
+    create: impl FnOnce() -> T,
+) -> T {
+    let mut created = CREATED.lock().unwrap();
+    if let Some(existing) = created.get(name) {
+        if existing.labels != labels {
+            panic!(
+                "Metric {name} already exists with labels {:?}, not {:?}",
+                existing.labels, labels
+            );
+        }
+        return existing
+            .collector
+            .downcast_ref::<T>()
+            .unwrap_or_else(|| panic!("Metric {name} already exists with another type"))
+            .clone();
+    }
+    register_in(&mut created, name, labels, create())
+}
 
 /// Unconditionnaly creates a counter and register it.
 ///
 /// It will panic if the counter is already registered
 ///
 pub fn create_counter(name: &str, help: &str) -> IntCounter {
-    let counter = IntCounter::new(name, help).unwrap();
-    prometheus::register(Box::new(counter.clone())).unwrap();
-    counter
+    register(name, &[], IntCounter::new(name, help).unwrap())
 }
 
 /// Unconditionnaly creates a counter and register it.
@@ -19,9 +105,11 @@ pub fn create_counter(name: &str, help: &str) -> IntCounter {
 /// It will panic if the counter is already registered
 ///
 pub fn create_counter_with_labels(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
-    let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
-    prometheus::register(Box::new(counter.clone())).unwrap();
-    counter
+    register(
+        name,
+        labels,
+        IntCounterVec::new(Opts::new(name, help), labels).unwrap(),
+    )
 }
 
 /// Unconditionnaly creates a gauge and register it.
@@ -29,9 +117,7 @@ pub fn create_counter_with_labels(name: &str, help: &str, labels: &[&str]) -> In
 /// It will panic if the gauge is already registered
 ///
 pub fn create_gauge(name: &str, help: &str) -> IntGauge {
-    let gauge = IntGauge::new(name, help).unwrap();
-    prometheus::register(Box::new(gauge.clone())).unwrap();
-    gauge
+    register(name, &[], IntGauge::new(name, help).unwrap())
 }
 
 /// Unconditionnaly creates a gauge and register it.
@@ -39,9 +125,107 @@ pub fn create_gauge(name: &str, help: &str) -> IntGauge {
 /// It will panic if the gauge is already registered
 ///
 pub fn create_gauge_with_labels(name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
-    let gauge = IntGaugeVec::new(Opts::new(name, help), labels).unwrap();
-    prometheus::register(Box::new(gauge.clone())).unwrap();
-    gauge
+    register(
+        name,
+        labels,
+        IntGaugeVec::new(Opts::new(name, help), labels).unwrap(),
+    )
+}
+
+/// Unconditionnaly creates an histogram and register it.
+///
+/// It will panic if the histogram is already registered
+///
+pub fn create_histogram(name: &str, help: &str, buckets: Vec<f64>) -> Histogram {
+    register(
+        name,
+        &[],
+        Histogram::with_opts(HistogramOpts::new(name, help).buckets(buckets)).unwrap(),
+    )
+}
+
+/// Unconditionnaly creates an histogram and register it.
+///
+/// It will panic if the histogram is already registered
+///
+pub fn create_histogram_with_labels(
+    name: &str,
+    help: &str,
+    labels: &[&str],
+    buckets: Vec<f64>,
+) -> HistogramVec {
+    register(
+        name,
+        labels,
+        HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets), labels).unwrap(),
+    )
+}
+
+/// Returns the counter if it was already created by a helper of this module, otherwise
+/// creates and registers it.
+///
+/// It will panic if the metric exists with another type or other labels
+///
+pub fn get_or_create_counter(name: &str, help: &str) -> IntCounter {
+    get_or_register(name, &[], || IntCounter::new(name, help).unwrap())
+}
+
+/// Returns the counter if it was already created by a helper of this module, otherwise
+/// creates and registers it.
+///
+/// It will panic if the metric exists with another type or other labels
+///
+pub fn get_or_create_counter_with_labels(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    get_or_register(name, labels, || {
+        IntCounterVec::new(Opts::new(name, help), labels).unwrap()
+    })
+}
+
+/// Returns the gauge if it was already created by a helper of this module, otherwise
+/// creates and registers it.
+///
+/// It will panic if the metric exists with another type or other labels
+///
+pub fn get_or_create_gauge(name: &str, help: &str) -> IntGauge {
+    get_or_register(name, &[], || IntGauge::new(name, help).unwrap())
+}
+
+/// Returns the gauge if it was already created by a helper of this module, otherwise
+/// creates and registers it.
+///
+/// It will panic if the metric exists with another type or other labels
+///
+pub fn get_or_create_gauge_with_labels(name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    get_or_register(name, labels, || {
+        IntGaugeVec::new(Opts::new(name, help), labels).unwrap()
+    })
+}
+
+/// Returns the histogram if it was already created by a helper of this module, otherwise
+/// creates and registers it. Buckets are ignored when the histogram already exists.
+///
+/// It will panic if the metric exists with another type or other labels
+///
+pub fn get_or_create_histogram(name: &str, help: &str, buckets: Vec<f64>) -> Histogram {
+    get_or_register(name, &[], || {
+        Histogram::with_opts(HistogramOpts::new(name, help).buckets(buckets)).unwrap()
+    })
+}
+
+/// Returns the histogram if it was already created by a helper of this module, otherwise
+/// creates and registers it. Buckets are ignored when the histogram already exists.
+///
+/// It will panic if the metric exists with another type or other labels
+///
+pub fn get_or_create_histogram_with_labels(
+    name: &str,
+    help: &str,
+    labels: &[&str],
+    buckets: Vec<f64>,
+) -> HistogramVec {
+    get_or_register(name, labels, || {
+        HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets), labels).unwrap()
+    })
 }
 
 /// Generate the content of /metrics prometheus metrics gathering endpoint.
@@ -55,14 +239,150 @@ pub fn generate_metrics() -> String {
     String::from_utf8(buffer).unwrap()
 }
 
+/// Class of an HTTP status code (`1xx`, `2xx`, `3xx`, `4xx` or `5xx`), used as a
+/// low cardinality `status_class` label next to the exact status.
+pub fn status_class(status: u16) -> &'static str {
+    match status {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+/// Counts an error handled by the `handle_errors` helpers of the web frameworks integrations,
+/// in `handled_errors_total{kind}`. The kind is derived from the status of the problem, so
+/// custom error codes do not add labels: `not_found`, `forbidden` (`401` and `403`),
+/// `bad_request` (other `4xx`) or `internal`.
+#[cfg(any(feature = "axum", feature = "warp"))]
+pub(crate) fn record_handled_error(code: &crate::problem::ErrorCode) {
+    get_or_create_counter_with_labels(
+        "handled_errors_total",
+        "Errors returned by request handlers, by kind",
+        &["kind"],
+    )
+    .with_label_values(&[handled_error_kind(code)])
+    .inc();
+}
+
+#[cfg(any(feature = "axum", feature = "warp"))]
+fn handled_error_kind(code: &crate::problem::ErrorCode) -> &'static str {
+    match code.status() {
+        404 => "not_found",
+        401 | 403 => "forbidden",
+        400..=499 => "bad_request",
+        _ => "internal",
+    }
+}
+
+/// Counts a webhook rejected by the signature verification, in
+/// `webhook_verification_failures_total{provider,reason}`.
+#[cfg(feature = "webhooks")]
+pub(crate) fn record_webhook_verification_failure(provider: &str, reason: &str) {
+    get_or_create_counter_with_labels(
+        "webhook_verification_failures_total",
+        "Webhooks rejected by the signature verification, by provider and reason",
+        &["provider", "reason"],
+    )
+    .with_label_values(&[provider, reason])
+    .inc();
+}
+
+/// Label value reported by [`CardinalityLimiter`] once the limit is reached
+pub const OTHER_LABEL_VALUE: &str = "__other__";
+
+/// Bounds the number of distinct values of a label (typically a path), to protect
+/// prometheus from label explosions caused by scanners.
+///
+/// The first `max` distinct values are reported as is, new ones are reported as
+/// [`OTHER_LABEL_VALUE`].
+pub struct CardinalityLimiter {
+    max: usize,
+    seen: RwLock<HashSet<String>>,
+}
+
+impl CardinalityLimiter {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            seen: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// Value to report for `value`
+    pub fn label<'a>(&self, value: &'a str) -> &'a str {
+        if self.seen.read().unwrap().contains(value) {
+            return value;
+        }
+        let mut seen = self.seen.write().unwrap();
+        if seen.contains(value) {
+            value
+        } else if seen.len() < self.max {
+            seen.insert(value.to_string());
+            value
+        } else {
+            OTHER_LABEL_VALUE
+        }
+    }
+}
+
+/// Helpers for unit tests, to avoid panics caused by duplicate metrics registration.
+///
+/// Since metrics helpers register in the prometheus default registry, tests creating the same
+/// metrics (eg. building a router twice) should either use an isolated registry when the
+/// API allows it, or reset the metrics created by this crate:
+///
+/// ```ignore
+/// #[test]
+/// fn my_test() {
+///     service_helpe_rs::metrics::test_support::unregister_created_metrics();
+///     let filter = requests_metrics(true);
+///     // ...
+/// }
+/// ```
+///
+/// Note that tests run in parallel in the same process: tests relying on the default registry
+/// may need to be serialized.
+pub mod test_support {
+    use prometheus::Registry;
+
+    use super::{CREATED, REGISTERED};
+
+    /// A new registry, independent from the default one
+    pub fn isolated_registry() -> Registry {
+        Registry::new()
+    }
+
+    /// Unregisters from the default registry every metric created by the helpers of this
+    /// crate, including the HTTP metrics layers built on it, so they can be created again.
+    ///
+    /// The metrics of the default [`metrics_middleware`](crate::axum::metrics::metrics_middleware)
+    /// are registered once per process: they are no longer exported.
+    pub fn unregister_created_metrics() {
+        let mut created = CREATED.lock().unwrap();
+        for (_, metric) in std::mem::take(&mut *created) {
+            // the metric may have been unregistered by other means
+            let _ = prometheus::unregister(metric.registered);
+        }
+        unregister_registered();
+    }
+
+    /// Unregisters the metrics tracked by [`track_registered`](super::track_registered)
+    pub(crate) fn unregister_registered() {
+        for metric in std::mem::take(&mut *REGISTERED.lock().unwrap()) {
+            let _ = prometheus::unregister(metric);
+        }
+    }
+}
+
 /// Launch async process collector at specified interval. It requires a running tokio runtime!
-#[cfg(all(feature = "tokio"))]
+#[cfg(feature = "tokio")]
 pub fn launch_async_process_collector(interval: Duration) {
     tokio::task::spawn(collect(interval));
 }
 #[cfg(all(target_os = "linux", feature = "tokio"))]
 async fn collect(interval: Duration) {
-    use prometheus::core::Collector;
     let process_collector = prometheus::process_collector::ProcessCollector::for_self();
     loop {
         log::debug!("Collecting process info");
@@ -78,3 +398,53 @@ async fn collect(interval: Duration) {
         tokio::time::sleep(interval).await;
     }
 }
+
+#[cfg(all(test, any(feature = "axum", feature = "warp")))]
+#[test]
+fn handled_error_kinds() {
+    use crate::problem::ErrorCode;
+
+    assert_eq!(handled_error_kind(&ErrorCode::NotFound), "not_found");
+    assert_eq!(
+        handled_error_kind(&ErrorCode::MethodNotAllowed),
+        "bad_request"
+    );
+    assert_eq!(handled_error_kind(&ErrorCode::Forbidden), "forbidden");
+    assert_eq!(handled_error_kind(&ErrorCode::Overloaded), "internal");
+    assert_eq!(
+        handled_error_kind(&ErrorCode::Custom("unknown")),
+        "internal"
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn cardinality_limiter() {
+    let limiter = CardinalityLimiter::new(2);
+    assert_eq!(limiter.label("/a"), "/a");
+    assert_eq!(limiter.label("/b"), "/b");
+    assert_eq!(limiter.label("/c"), OTHER_LABEL_VALUE);
+    assert_eq!(limiter.label("/a"), "/a");
+}
+
+#[cfg(test)]
+#[test]
+fn get_or_create_returns_existing_metric() {
+    let first = get_or_create_counter_with_labels("test_get_or_create_total", "Test", &["a"]);
+    let second = get_or_create_counter_with_labels("test_get_or_create_total", "Test", &["a"]);
+    first.with_label_values(&["x"]).inc();
+    assert_eq!(second.with_label_values(&["x"]).get(), 1);
+
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            std::thread::spawn(|| get_or_create_counter("test_get_or_create_concurrent", "Test"))
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap().inc();
+    }
+    assert_eq!(
+        get_or_create_counter("test_get_or_create_concurrent", "Test").get(),
+        8
+    );
+}
@@ -0,0 +1,254 @@
+//! Baseline Grafana dashboard and Prometheus alert rules for the metrics emitted by this crate
+
+use std::collections::HashSet;
+
+use serde_json::{json, Value};
+
+/// Ratio of 5xx responses above which the `HighErrorRate` alert fires
+pub const ERROR_RATE_THRESHOLD: f64 = 0.05;
+/// p99 request duration, in seconds, above which the `HighLatency` alert fires
+pub const LATENCY_P99_THRESHOLD_SECONDS: f64 = 1.0;
+
+/// Generates a Grafana dashboard (JSON model) for the service `service`, selected with the
+/// `job` label.
+///
+/// HTTP server panels (request rate, error rate, latency, inflight requests, handled errors) are
+/// always present. Pool, Kafka and HTTP client panels are added when the corresponding metrics
+/// are registered in the default registry.
+pub fn export_grafana_dashboard(service: &str) -> String {
+    let registered = registered_metrics();
+    let sel = selector(service);
+
+    let mut panels = vec![
+        panel(
+            "Requests per second",
+            "reqps",
+            &[(
+                format!("sum by (status_class) (rate(http_request_total{{{sel}}}[5m]))"),
+                "{{status_class}}",
+            )],
+        ),
+        panel(
+            "5xx error rate",
+            "percentunit",
+            &[(error_rate_expr(&sel), "5xx")],
+        ),
+        panel(
+            "Request duration",
+            "s",
+            &[
+                (quantile_expr(0.5, &sel), "p50"),
+                (quantile_expr(0.9, &sel), "p90"),
+                (quantile_expr(0.99, &sel), "p99"),
+            ],
+        ),
+        panel(
+            "Inflight requests",
+            "short",
+            &[(
+                format!("sum(inflight_http_request_total{{{sel}}})"),
+                "inflight",
+            )],
+        ),
+        panel(
+            "Handled errors",
+            "short",
+            &[(
+                format!("sum by (kind) (rate(handled_errors_total{{{sel}}}[5m]))"),
+                "{{kind}}",
+            )],
+        ),
+    ];
+    if registered.contains("pool_connections") {
+        panels.push(panel(
+            "Pool connections",
+            "short",
+            &[
+                (
+                    format!("sum by (pool) (pool_connections{{{sel}}})"),
+                    "{{pool}} open",
+                ),
+                (
+                    format!("sum by (pool) (pool_waiting_requests{{{sel}}})"),
+                    "{{pool}} waiting",
+                ),
+            ],
+        ));
+    }
+    if registered.contains("kafka_consumer_lag") {
+        panels.push(panel(
+            "Kafka consumer lag",
+            "short",
+            &[(
+                format!("sum by (topic) (kafka_consumer_lag{{{sel}}})"),
+                "{{topic}}",
+            )],
+        ));
+    }
+    if registered.contains("http_client_request_duration_seconds") {
+        panels.push(panel(
+            "Outgoing requests p99",
+            "s",
+            &[(
+                format!(
+                    "histogram_quantile(0.99, sum by (le, host) (rate(http_client_request_duration_seconds_bucket{{{sel}}}[5m])))"
+                ),
+                "{{host}}",
+            )],
+        ));
+    }
+
+    for (i, panel) in panels.iter_mut().enumerate() {
+        panel["id"] = json!(i + 1);
+        panel["gridPos"] = json!({ "h": 8, "w": 12, "x": (i % 2) * 12, "y": (i / 2) * 8 });
+    }
+
+    let dashboard = json!({
+        "title": service,
+        "uid": service,
+        "tags": ["service-helpe-rs"],
+        "timezone": "browser",
+        "schemaVersion": 39,
+        "time": { "from": "now-6h", "to": "now" },
+        "templating": {
+            "list": [{
+                "name": "datasource",
+                "type": "datasource",
+                "query": "prometheus",
+            }]
+        },
+        "panels": panels,
+    });
+    serde_json::to_string_pretty(&dashboard).unwrap()
+}
+
+/// Generates Prometheus alert rules (YAML rule file) for the service `service`, selected with
+/// the `job` label: error rate, latency and saturation.
+pub fn export_alert_rules(service: &str) -> String {
+    let registered = registered_metrics();
+    let sel = selector(service);
+
+    let mut rules = vec![
+        rule(
+            service,
+            "HighErrorRate",
+            format!("{} > {ERROR_RATE_THRESHOLD}", error_rate_expr(&sel)),
+            "critical",
+            "More than 5% of the requests fail with a 5xx status",
+        ),
+        rule(
+            service,
+            "HighLatency",
+            format!(
+                "{} > {LATENCY_P99_THRESHOLD_SECONDS}",
+                quantile_expr(0.99, &sel)
+            ),
+            "warning",
+            "p99 of the request duration is above 1s",
+        ),
+    ];
+    if registered.contains("pool_waiting_requests") {
+        rules.push(rule(
+            service,
+            "PoolSaturated",
+            format!("max by (pool) (pool_waiting_requests{{{sel}}}) > 0"),
+            "warning",
+            "Requests are waiting for a connection of the pool",
+        ));
+    }
+    if registered.contains("kafka_consumer_lag") {
+        rules.push(rule(
+            service,
+            "KafkaConsumerLagGrowing",
+            format!("sum by (topic) (deriv(kafka_consumer_lag{{{sel}}}[10m])) > 0"),
+            "warning",
+            "The consumer lag keeps growing",
+        ));
+    }
+
+    let groups = json!({
+        "groups": [{
+            "name": service,
+            "rules": rules,
+        }]
+    });
+    serde_yaml::to_string(&groups).unwrap()
+}
+
+fn registered_metrics() -> HashSet<String> {
+    prometheus::gather()
+        .into_iter()
+        .map(|family| family.get_name().to_string())
+        .collect()
+}
+
+fn selector(service: &str) -> String {
+    format!("job=\"{service}\"")
+}
+
+fn error_rate_expr(sel: &str) -> String {
+    format!(
+        "sum(rate(http_request_total{{{sel},status_class=\"5xx\"}}[5m])) / sum(rate(http_request_total{{{sel}}}[5m]))"
+    )
+}
+
+fn quantile_expr(quantile: f64, sel: &str) -> String {
+    format!(
+        "histogram_quantile({quantile}, sum by (le) (rate(http_request_duration_seconds_bucket{{{sel}}}[5m])))"
+    )
+}
+
+fn panel(title: &str, unit: &str, targets: &[(String, &str)]) -> Value {
+    let targets: Vec<Value> = targets
+        .iter()
+        .enumerate()
+        .map(|(i, (expr, legend))| {
+            json!({
+                "expr": expr,
+                "legendFormat": legend,
+                "refId": ((b'A' + i as u8) as char).to_string(),
+            })
+        })
+        .collect();
+    json!({
+        "type": "timeseries",
+        "title": title,
+        "datasource": { "type": "prometheus", "uid": "${datasource}" },
+        "fieldConfig": { "defaults": { "unit": unit } },
+        "targets": targets,
+    })
+}
+
+fn rule(service: &str, alert: &str, expr: String, severity: &str, summary: &str) -> Value {
+    json!({
+        "alert": alert,
+        "expr": expr,
+        "for": "5m",
+        "labels": { "severity": severity, "service": service },
+        "annotations": { "summary": summary },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_are_parameterized_by_service() {
+        let dashboard: Value = serde_json::from_str(&export_grafana_dashboard("users")).unwrap();
+        assert_eq!(dashboard["title"], "users");
+        assert!(dashboard["panels"][0]["targets"][0]["expr"]
+            .as_str()
+            .unwrap()
+            .contains("job=\"users\""));
+
+        let rules: serde_yaml::Value = serde_yaml::from_str(&export_alert_rules("users")).unwrap();
+        let alerts: Vec<&str> = rules["groups"][0]["rules"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|r| r["alert"].as_str().unwrap())
+            .collect();
+        assert!(alerts.starts_with(&["HighErrorRate", "HighLatency"]));
+    }
+}
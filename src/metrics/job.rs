@@ -0,0 +1,166 @@
+//! Metrics of cron-style background jobs
+//!
+//! - `job_runs_total` counter labelled by `job` and `outcome`
+//! - `job_duration_seconds` histogram labelled by `job`
+//! - `job_last_success_timestamp_seconds` gauge labelled by `job`, to alert on jobs that
+//!   stopped succeeding
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{
+    get_or_create_counter_with_labels, get_or_create_gauge_with_labels,
+    get_or_create_histogram_with_labels, DEFAULT_DURATION_BUCKETS,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobOutcome {
+    Success,
+    Failure,
+    /// The job had nothing to do, or did not run (eg. another instance holds the lock)
+    Skipped,
+}
+
+impl JobOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobOutcome::Success => "success",
+            JobOutcome::Failure => "failure",
+            JobOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+/// Records a run of the job `name`
+pub fn record_job_run(name: &str, duration: Duration, outcome: JobOutcome) {
+    get_or_create_counter_with_labels("job_runs_total", "Background job runs", &["job", "outcome"])
+        .with_label_values(&[name, outcome.as_str()])
+        .inc();
+    get_or_create_histogram_with_labels(
+        "job_duration_seconds",
+        "Background job runs duration",
+        &["job"],
+        DEFAULT_DURATION_BUCKETS.to_vec(),
+    )
+    .with_label_values(&[name])
+    .observe(duration.as_secs_f64());
+    if outcome == JobOutcome::Success {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        get_or_create_gauge_with_labels(
+            "job_last_success_timestamp_seconds",
+            "Time of the last successful run of background jobs",
+            &["job"],
+        )
+        .with_label_values(&[name])
+        .set(now.as_secs() as i64);
+    }
+}
+
+/// Measures a run of a job, recorded when finished. A timer dropped without being finished
+/// (eg. the job panicked) records a failure.
+///
+/// ```ignore
+/// let timer = JobTimer::start("purge_sessions");
+/// match purge_sessions().await {
+///     Ok(_) => timer.success(),
+///     Err(_) => timer.failure(),
+/// }
+/// ```
+pub struct JobTimer {
+    name: String,
+    start: Instant,
+    finished: bool,
+}
+
+impl JobTimer {
+    pub fn start(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            start: Instant::now(),
+            finished: false,
+        }
+    }
+
+    pub fn finish(mut self, outcome: JobOutcome) {
+        self.record(outcome);
+    }
+
+    pub fn success(self) {
+        self.finish(JobOutcome::Success)
+    }
+
+    pub fn failure(self) {
+        self.finish(JobOutcome::Failure)
+    }
+
+    pub fn skipped(self) {
+        self.finish(JobOutcome::Skipped)
+    }
+
+    /// Records `Success` if `result` is `Ok`, `Failure` otherwise, and returns `result`
+    pub fn finish_with<T, E>(self, result: Result<T, E>) -> Result<T, E> {
+        match &result {
+            Ok(_) => self.success(),
+            Err(_) => self.failure(),
+        }
+        result
+    }
+
+    fn record(&mut self, outcome: JobOutcome) {
+        if !self.finished {
+            self.finished = true;
+            record_job_run(&self.name, self.start.elapsed(), outcome);
+        }
+    }
+}
+
+impl Drop for JobTimer {
+    fn drop(&mut self) {
+        self.record(JobOutcome::Failure);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn records_job_outcomes() {
+    let runs = |outcome: JobOutcome| {
+        get_or_create_counter_with_labels(
+            "job_runs_total",
+            "Background job runs",
+            &["job", "outcome"],
+        )
+        .with_label_values(&["test_purge", outcome.as_str()])
+        .get()
+    };
+    let last_success = || {
+        get_or_create_gauge_with_labels(
+            "job_last_success_timestamp_seconds",
+            "Time of the last successful run of background jobs",
+            &["job"],
+        )
+        .with_label_values(&["test_purge"])
+        .get()
+    };
+
+    JobTimer::start("test_purge").skipped();
+    assert_eq!(runs(JobOutcome::Skipped), 1);
+    assert_eq!(last_success(), 0);
+
+    let result: Result<(), &str> = JobTimer::start("test_purge").finish_with(Ok(()));
+    assert!(result.is_ok());
+    assert_eq!(runs(JobOutcome::Success), 1);
+    assert!(last_success() > 0);
+
+    // dropped without being finished, eg. on panic
+    drop(JobTimer::start("test_purge"));
+    assert_eq!(runs(JobOutcome::Failure), 1);
+    let duration = get_or_create_histogram_with_labels(
+        "job_duration_seconds",
+        "Background job runs duration",
+        &["job"],
+        DEFAULT_DURATION_BUCKETS.to_vec(),
+    )
+    .with_label_values(&["test_purge"]);
+    assert_eq!(duration.get_sample_count(), 3);
+}
@@ -0,0 +1,321 @@
+//! OpenTelemetry logs and traces export, for the clusters shipping logs to an OTel Collector
+//! instead of Graylog, and traces to Tempo or Jaeger
+
+use anyhow::Context;
+use http::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    trace::{TraceContextExt, TraceId, TracerProvider},
+    KeyValue,
+};
+use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
+use opentelemetry_otlp::{LogExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    logs::SdkLoggerProvider,
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracerProvider},
+    Resource,
+};
+use serde::{Deserialize, Serialize};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{filter::filter_fn, Layer};
+
+use crate::{
+    logging::{LogFormat, LogLevels, LoggingBuilder, LoggingGuard, SamplingParams},
+    ServiceDef,
+};
+
+/// Targets never exported, as exporting their events would produce new ones
+const EXPORTER_TARGETS: [&str; 4] = ["opentelemetry", "reqwest", "hyper", "h2"];
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct OtlpParams {
+    /// OTLP/HTTP endpoint of the collector, eg. `http://otel-collector:4318`
+    pub endpoint: String,
+    pub env: String,
+    /// Exports the spans too (default: false)
+    #[serde(default)]
+    pub traces: bool,
+    /// Ratio of the traces started by the service which are exported, between 0 and 1
+    /// (default: 1). Traces started upstream follow the sampling decision of the caller.
+    #[serde(default)]
+    pub trace_sample_ratio: Option<f64>,
+    /// Format of the logs on stdout, see [`LogFormat::resolve`]
+    #[serde(default)]
+    pub log_format: Option<LogFormat>,
+    /// Levels by target, merged with `RUST_LOG`
+    #[serde(default)]
+    pub log_levels: LogLevels,
+    /// Sampling rates of the high-volume targets, for all the outputs
+    #[serde(default)]
+    pub sampling: SamplingParams,
+    /// Also sends the logs to syslog
+    #[cfg(feature = "syslog")]
+    #[serde(default)]
+    pub syslog: Option<crate::logging::SyslogParams>,
+    /// Also writes the logs to rotated files
+    #[cfg(feature = "file-log")]
+    #[serde(default)]
+    pub file: Option<crate::logging::FileLogParams>,
+}
+
+/// Resource attributes describing the service, shared by all the exported signals
+pub fn resource(service: &ServiceDef, env: &str) -> Resource {
+    Resource::builder()
+        .with_service_name(service.pkg_name.to_string())
+        .with_attributes([
+            KeyValue::new(
+                "service.version",
+                format!("{}-{}", service.version, service.git_hash),
+            ),
+            KeyValue::new("deployment.environment.name", env.to_string()),
+        ])
+        .build()
+}
+
+/// Flushes and shuts the OpenTelemetry providers down when dropped: keep it alive until the
+/// service exits.
+#[must_use = "dropping the guard stops the export"]
+pub struct OtlpGuard {
+    logs: Option<SdkLoggerProvider>,
+    traces: Option<SdkTracerProvider>,
+}
+
+impl OtlpGuard {
+    /// Guard of the providers of both guards
+    pub fn merge(mut self, mut other: OtlpGuard) -> OtlpGuard {
+        OtlpGuard {
+            logs: self.logs.take().or(other.logs.take()),
+            traces: self.traces.take().or(other.traces.take()),
+        }
+    }
+}
+
+impl Drop for OtlpGuard {
+    fn drop(&mut self) {
+        if let Some(Err(err)) = self.logs.as_ref().map(SdkLoggerProvider::shutdown) {
+            eprintln!("Cannot shut the OTLP logs exporter down: {err}");
+        }
+        if let Some(Err(err)) = self.traces.as_ref().map(SdkTracerProvider::shutdown) {
+            eprintln!("Cannot shut the OTLP traces exporter down: {err}");
+        }
+    }
+}
+
+fn exported(meta: &tracing::Metadata) -> bool {
+    !EXPORTER_TARGETS
+        .iter()
+        .any(|target| meta.target().starts_with(target))
+}
+
+/// Layer exporting the tracing events (and the `log` records) as OTLP logs
+pub fn logs_layer<S>(
+    params: &OtlpParams,
+    service: &ServiceDef,
+) -> anyhow::Result<(impl Layer<S>, OtlpGuard)>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    let endpoint = format!("{}/v1/logs", params.endpoint.trim_end_matches('/'));
+    let exporter = LogExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .context("Cannot build OTLP logs exporter")?;
+    let provider = SdkLoggerProvider::builder()
+        .with_resource(resource(service, &params.env))
+        .with_batch_exporter(exporter)
+        .build();
+    let layer = OpenTelemetryTracingBridge::new(&provider).with_filter(filter_fn(exported));
+    let guard = OtlpGuard {
+        logs: Some(provider),
+        traces: None,
+    };
+    Ok((layer, guard))
+}
+
+/// Layer exporting the spans as OTLP traces, to add next to the stdout or GELF layers. The
+/// trace ids are the ones of the `traceparent` header when the spans are created with a
+/// remote parent.
+pub fn traces_layer<S>(
+    params: &OtlpParams,
+    service: &ServiceDef,
+) -> anyhow::Result<(impl Layer<S>, OtlpGuard)>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    let endpoint = format!("{}/v1/traces", params.endpoint.trim_end_matches('/'));
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .context("Cannot build OTLP traces exporter")?;
+    let ratio = params.trace_sample_ratio.unwrap_or(1.0).clamp(0.0, 1.0);
+    let provider = SdkTracerProvider::builder()
+        .with_resource(resource(service, &params.env))
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            ratio,
+        ))))
+        .with_batch_exporter(exporter)
+        .build();
+    let tracer = provider.tracer(service.pkg_name.to_string());
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(filter_fn(exported));
+    let guard = OtlpGuard {
+        logs: None,
+        traces: Some(provider),
+    };
+    Ok((layer, guard))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Makes `span` the child of the span of the caller, from the `traceparent` header of the
+/// request, with the [propagator](otlp_layers) installed with the traces. Returns the trace
+/// id of the exported span, to use it as the one of the
+/// [`RequestIds`](crate::ids::RequestIds), or `None` when the traces are not exported.
+///
+/// Must be called before the span is entered.
+pub fn set_remote_parent(span: &tracing::Span, headers: &HeaderMap) -> Option<u128> {
+    let parent =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    if parent.span().span_context().is_valid() {
+        // fails when the traces are not exported
+        let _ = span.set_parent(parent);
+    }
+    let trace_id = span.context().span().span_context().trace_id();
+    (trace_id != TraceId::INVALID).then(|| u128::from_be_bytes(trace_id.to_bytes()))
+}
+
+/// Sets the `traceparent` header of a call from the current span, when the traces are
+/// exported. Returns whether the header was set.
+pub fn inject_current_context(headers: &mut HeaderMap) -> bool {
+    let context = tracing::Span::current().context();
+    if !context.span().span_context().is_valid() {
+        return false;
+    }
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+    true
+}
+
+/// Layers of [`LoggingBuilder`] exporting the logs, and the traces if enabled, registered as
+/// a sink. The W3C trace context propagator is installed globally with the traces.
+#[allow(clippy::type_complexity)]
+pub(crate) fn otlp_layers<S>(
+    otlp: &OtlpParams,
+    service: &ServiceDef,
+) -> anyhow::Result<(Vec<Box<dyn Layer<S> + Send + Sync>>, OtlpGuard)>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a> + Send + Sync,
+{
+    println!(
+        "Configuring OTLP logger env:{}, endpoint:{}, traces:{}",
+        otlp.env, otlp.endpoint, otlp.traces
+    );
+    let (logs, mut guard) = logs_layer(otlp, service)?;
+    let mut layers = vec![logs.boxed()];
+    if otlp.traces {
+        let (traces, traces_guard) = traces_layer(otlp, service)?;
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        layers.push(traces.boxed());
+        guard = guard.merge(traces_guard);
+    }
+    crate::info::register_sink(
+        "otlp",
+        crate::redact::redact_url(&otlp.endpoint),
+        "configured",
+    );
+    Ok((layers, guard))
+}
+
+/// Installs a subscriber logging on stdout and exporting to the OTLP collector, the logs and
+/// the traces if enabled, see [`LoggingBuilder`].
+pub fn init(otlp: OtlpParams, service: ServiceDef) -> anyhow::Result<LoggingGuard> {
+    let mut builder = LoggingBuilder::new(service)
+        .levels(otlp.log_levels.clone())
+        .sampling(otlp.sampling.clone());
+    builder.format = otlp.log_format;
+    #[cfg(feature = "syslog")]
+    {
+        builder.syslog = otlp.syslog.clone();
+    }
+    #[cfg(feature = "file-log")]
+    {
+        builder.file = otlp.file.clone();
+    }
+    builder.otlp(otlp).install()
+}
+
+#[cfg(test)]
+#[test]
+fn service_resource() {
+    use opentelemetry::Key;
+
+    let service = ServiceDef::new("orders-api", "1.2.0", "abc123");
+    let resource = resource(&service, "staging");
+    let attr = |key: &'static str| resource.get(&Key::new(key)).map(|v| v.to_string());
+    assert_eq!(attr("service.name").as_deref(), Some("orders-api"));
+    assert_eq!(attr("service.version").as_deref(), Some("1.2.0-abc123"));
+    assert_eq!(
+        attr("deployment.environment.name").as_deref(),
+        Some("staging")
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn propagates_the_trace_context() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let provider = SdkTracerProvider::builder().build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    tracing::subscriber::with_default(subscriber, || {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"),
+        );
+        let span = tracing::info_span!("request");
+        assert_eq!(
+            set_remote_parent(&span, &headers),
+            Some(0x0af7651916cd43dd8448eb211c80319c)
+        );
+
+        let mut call = HeaderMap::new();
+        assert!(span.in_scope(|| inject_current_context(&mut call)));
+        let traceparent = call["traceparent"].to_str().unwrap();
+        assert!(traceparent.starts_with("00-0af7651916cd43dd8448eb211c80319c-"));
+        // the parent of the called service is the span of the request
+        assert!(!traceparent.contains("b7ad6b7169203331"));
+        assert!(traceparent.ends_with("-01"));
+    });
+}
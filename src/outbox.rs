@@ -0,0 +1,622 @@
+//! Transactional outbox on PostgreSQL (requires a [`sqlx`] pool).
+//!
+//! Domain events are stored in the `outbox` table within the transaction of the business
+//! writes, then a background relay publishes them (to Kafka, webhooks...) in order and marks
+//! them as published. Delivery is at least once: the record id is sent along the event so
+//! consumers can deduplicate.
+//!
+//! An event failing [`max_attempts`](OutboxRelay::max_attempts) times is parked: it is not
+//! retried anymore, so it does not block the next ones, until [`requeue_parked`] is called.
+//!
+//! Exports:
+//! - `outbox_events_published_total`, `outbox_publish_failures_total` and
+//!   `outbox_events_parked_total` counters labelled by `topic`
+//! - `outbox_pending_events` gauge and `outbox_lag_seconds` gauge (age of the oldest pending
+//!   event), updated by the relay
+//!
+//! ```ignore
+//! create_outbox_table(&pool).await?;
+//!
+//! let mut tx = pool.begin().await?;
+//! sqlx::query("INSERT INTO orders ...").execute(&mut *tx).await?;
+//! enqueue(&mut *tx, &OutboxEvent::json("orders", &OrderPlaced { id })?.key(id.to_string())).await?;
+//! tx.commit().await?;
+//!
+//! OutboxRelay::new(pool.clone(), producer).spawn();
+//! ```
+
+use std::{collections::BTreeMap, time::Duration};
+
+use prometheus::{IntCounterVec, IntGauge};
+use serde::Serialize;
+use sqlx::{postgres::PgRow, types::Json, PgConnection, PgExecutor, PgPool, Row};
+
+use crate::metrics::{get_or_create_counter_with_labels, get_or_create_gauge};
+
+/// Header carrying the outbox record id, to deduplicate events on the consumer side
+pub const OUTBOX_ID_HEADER: &str = "outbox-id";
+/// Header carrying the topic of the events sent by [`WebhookPublisher`]
+pub const OUTBOX_TOPIC_HEADER: &str = "outbox-topic";
+/// Header carrying the key of the events sent by [`WebhookPublisher`], if any
+pub const OUTBOX_KEY_HEADER: &str = "outbox-key";
+
+/// Key of the advisory lock held by the relay publishing the events
+const RELAY_LOCK: i64 = 0x6f7574626f78;
+
+/// Creates the `outbox` table, if it does not exist
+pub async fn create_outbox_table(pool: &PgPool) -> sqlx::Result<()> {
+    sqlx::raw_sql(
+        r#"CREATE TABLE IF NOT EXISTS outbox (
+            id BIGSERIAL PRIMARY KEY,
+            topic TEXT NOT NULL,
+            key TEXT,
+            payload BYTEA NOT NULL,
+            headers JSONB NOT NULL DEFAULT '{}',
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            published_at TIMESTAMPTZ,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT,
+            parked_at TIMESTAMPTZ
+        );
+        CREATE INDEX IF NOT EXISTS outbox_unpublished ON outbox (id)
+            WHERE published_at IS NULL AND parked_at IS NULL;"#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Event to store in the outbox
+#[derive(Debug, Clone)]
+pub struct OutboxEvent {
+    pub topic: String,
+    pub key: Option<String>,
+    pub payload: Vec<u8>,
+    pub headers: BTreeMap<String, String>,
+}
+
+impl OutboxEvent {
+    pub fn new(topic: impl Into<String>, payload: impl Into<Vec<u8>>) -> Self {
+        Self {
+            topic: topic.into(),
+            key: None,
+            payload: payload.into(),
+            headers: BTreeMap::new(),
+        }
+    }
+
+    /// Event with a JSON payload
+    pub fn json<T: Serialize>(topic: impl Into<String>, payload: &T) -> serde_json::Result<Self> {
+        Ok(Self::new(topic, serde_json::to_vec(payload)?)
+            .header("content-type", "application/json"))
+    }
+
+    /// Key of the event (kafka partitioning key...)
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// Stores `event` in the outbox, typically within the transaction of the business writes
+/// (`enqueue(&mut *tx, &event)`). Returns the id of the record.
+pub async fn enqueue<'c, E: PgExecutor<'c>>(executor: E, event: &OutboxEvent) -> sqlx::Result<i64> {
+    sqlx::query_scalar(
+        "INSERT INTO outbox (topic, key, payload, headers) VALUES ($1, $2, $3, $4) RETURNING id",
+    )
+    .bind(&event.topic)
+    .bind(&event.key)
+    .bind(&event.payload)
+    .bind(Json(&event.headers))
+    .fetch_one(executor)
+    .await
+}
+
+/// Makes the parked events pending again, all of them or only `ids`. Returns the number of
+/// requeued events.
+pub async fn requeue_parked(pool: &PgPool, ids: Option<&[i64]>) -> sqlx::Result<u64> {
+    let result = sqlx::query(
+        "UPDATE outbox SET parked_at = NULL, attempts = 0 \
+         WHERE parked_at IS NOT NULL AND ($1::BIGINT[] IS NULL OR id = ANY($1))",
+    )
+    .bind(ids)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Event read from the outbox by the relay
+#[derive(Debug, Clone)]
+pub struct OutboxRecord {
+    /// Id of the record, increasing in insertion order
+    pub id: i64,
+    /// Number of previous publication attempts
+    pub attempts: i32,
+    pub event: OutboxEvent,
+}
+
+impl OutboxRecord {
+    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        let Json(headers) = row.try_get("headers")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            attempts: row.try_get("attempts")?,
+            event: OutboxEvent {
+                topic: row.try_get("topic")?,
+                key: row.try_get("key")?,
+                payload: row.try_get("payload")?,
+                headers,
+            },
+        })
+    }
+}
+
+/// Destination of the events relayed from the outbox
+#[async_trait::async_trait]
+pub trait OutboxPublisher: Send + Sync {
+    /// Publishes one event. Events are published in order: the relay retries a failed event
+    /// before publishing the next ones, until it is parked.
+    async fn publish(&self, record: &OutboxRecord) -> anyhow::Result<()>;
+}
+
+/// Publishes the events to the kafka topic of the same name, with the record id in the
+/// [`OUTBOX_ID_HEADER`] header
+#[cfg(feature = "kafka")]
+#[async_trait::async_trait]
+impl OutboxPublisher for rdkafka::producer::FutureProducer {
+    async fn publish(&self, record: &OutboxRecord) -> anyhow::Result<()> {
+        use rdkafka::message::{Header, OwnedHeaders};
+        use rdkafka::producer::FutureRecord;
+
+        let id = record.id.to_string();
+        let mut headers = OwnedHeaders::new().insert(Header {
+            key: OUTBOX_ID_HEADER,
+            value: Some(&id),
+        });
+        for (key, value) in &record.event.headers {
+            headers = headers.insert(Header {
+                key,
+                value: Some(value),
+            });
+        }
+        let mut message = FutureRecord::to(&record.event.topic)
+            .payload(&record.event.payload)
+            .headers(headers);
+        if let Some(key) = &record.event.key {
+            message = message.key(key);
+        }
+        self.send(message, Duration::from_secs(10))
+            .await
+            .map_err(|(err, _)| anyhow::anyhow!("Cannot publish to kafka: {err}"))?;
+        Ok(())
+    }
+}
+
+/// Posts the event payloads to a webhook, with the event headers and the [`OUTBOX_ID_HEADER`],
+/// [`OUTBOX_TOPIC_HEADER`] and [`OUTBOX_KEY_HEADER`] ones. Responses other than `2xx` are
+/// failures.
+#[cfg(feature = "reqwest")]
+pub struct WebhookPublisher {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[cfg(feature = "reqwest")]
+impl WebhookPublisher {
+    pub fn new(client: reqwest::Client, url: impl Into<String>) -> Self {
+        Self {
+            client,
+            url: url.into(),
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+#[async_trait::async_trait]
+impl OutboxPublisher for WebhookPublisher {
+    async fn publish(&self, record: &OutboxRecord) -> anyhow::Result<()> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(OUTBOX_ID_HEADER, record.id.to_string())
+            .header(OUTBOX_TOPIC_HEADER, &record.event.topic)
+            .body(record.event.payload.clone());
+        if let Some(key) = &record.event.key {
+            request = request.header(OUTBOX_KEY_HEADER, key);
+        }
+        for (name, value) in &record.event.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            anyhow::bail!("Webhook {} responded {}", self.url, response.status());
+        }
+        Ok(())
+    }
+}
+
+/// Relays the pending events of the outbox to an [`OutboxPublisher`]
+///
+/// Several relays may run concurrently (one per instance): only one of them publishes at a
+/// time, holding a PostgreSQL advisory lock, so that the events are published in order. The
+/// events are published outside of any transaction, each one being marked as published once
+/// acknowledged.
+pub struct OutboxRelay<P> {
+    pool: PgPool,
+    publisher: P,
+    batch_size: i64,
+    max_attempts: i32,
+    poll_interval: Duration,
+    retention: Option<Duration>,
+}
+
+impl<P: OutboxPublisher + 'static> OutboxRelay<P> {
+    pub fn new(pool: PgPool, publisher: P) -> Self {
+        Self {
+            pool,
+            publisher,
+            batch_size: 100,
+            max_attempts: 10,
+            poll_interval: Duration::from_secs(1),
+            retention: None,
+        }
+    }
+
+    /// Maximum number of events published per batch, the relay lock being held for the whole
+    /// batch (default: 100)
+    pub fn batch_size(mut self, batch_size: i64) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Number of failed publications after which an event is parked (default: 10), see
+    /// [`requeue_parked`]
+    pub fn max_attempts(mut self, max_attempts: i32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Interval at which the outbox is checked once empty (default: 1s)
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Deletes the events published for longer than `retention` (default: kept forever)
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Runs the relay in a background task. It requires a running tokio runtime!
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        tokio::task::spawn(self.run())
+    }
+
+    /// Relays events forever
+    pub async fn run(self) {
+        let metrics = RelayMetrics::new();
+        loop {
+            let relayed = match self.relay_batch(&metrics).await {
+                Ok(relayed) => relayed,
+                Err(err) => {
+                    log::error!("Cannot relay outbox events: {err:#}");
+                    0
+                }
+            };
+            if let Err(err) = self.update_gauges(&metrics).await {
+                log::warn!("Cannot compute outbox lag: {err}");
+            }
+            if relayed < self.batch_size as usize {
+                if let Some(retention) = self.retention {
+                    if let Err(err) = self.purge(retention).await {
+                        log::warn!("Cannot purge published outbox events: {err}");
+                    }
+                }
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        }
+    }
+
+    /// Publishes one batch of pending events if no other relay is, stopping at the first
+    /// failure to keep them in order. Returns the number of published events.
+    async fn relay_batch(&self, metrics: &RelayMetrics) -> anyhow::Result<usize> {
+        let mut conn = self.pool.acquire().await?;
+        let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+            .bind(RELAY_LOCK)
+            .fetch_one(&mut *conn)
+            .await?;
+        if !locked {
+            return Ok(0);
+        }
+        let published = self.publish_pending(&mut conn, metrics).await;
+        let unlocked = sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(RELAY_LOCK)
+            .execute(&mut *conn)
+            .await;
+        if let Err(err) = unlocked {
+            log::warn!("Cannot release the outbox relay lock: {err}");
+            // closing the connection releases the lock
+            drop(conn.detach());
+        }
+        published
+    }
+
+    async fn publish_pending(
+        &self,
+        conn: &mut PgConnection,
+        metrics: &RelayMetrics,
+    ) -> anyhow::Result<usize> {
+        let records = sqlx::query(
+            "SELECT id, topic, key, payload, headers, attempts FROM outbox \
+             WHERE published_at IS NULL AND parked_at IS NULL ORDER BY id LIMIT $1",
+        )
+        .bind(self.batch_size)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut published = 0;
+        for row in &records {
+            let record = OutboxRecord::from_row(row)?;
+            match self.publisher.publish(&record).await {
+                Ok(()) => {
+                    sqlx::query(
+                        "UPDATE outbox SET published_at = now(), attempts = attempts + 1 \
+                         WHERE id = $1",
+                    )
+                    .bind(record.id)
+                    .execute(&mut *conn)
+                    .await?;
+                    metrics
+                        .published
+                        .with_label_values(&[&record.event.topic])
+                        .inc();
+                    published += 1;
+                }
+                Err(err) => {
+                    let parked = record.attempts + 1 >= self.max_attempts;
+                    if parked {
+                        log::error!(
+                            "Parking outbox event {} to {} after {} attempts: {err:#}",
+                            record.id,
+                            record.event.topic,
+                            record.attempts + 1
+                        );
+                    } else {
+                        log::warn!(
+                            "Cannot publish outbox event {} to {}: {err:#}",
+                            record.id,
+                            record.event.topic
+                        );
+                    }
+                    sqlx::query(
+                        "UPDATE outbox SET attempts = attempts + 1, last_error = $2, \
+                         parked_at = CASE WHEN $3 THEN now() END WHERE id = $1",
+                    )
+                    .bind(record.id)
+                    .bind(format!("{err:#}"))
+                    .bind(parked)
+                    .execute(&mut *conn)
+                    .await?;
+                    metrics
+                        .failures
+                        .with_label_values(&[&record.event.topic])
+                        .inc();
+                    if parked {
+                        metrics
+                            .parked
+                            .with_label_values(&[&record.event.topic])
+                            .inc();
+                        // the next events can be published
+                        continue;
+                    }
+                    break;
+                }
+            }
+        }
+        Ok(published)
+    }
+
+    async fn update_gauges(&self, metrics: &RelayMetrics) -> sqlx::Result<()> {
+        let (pending, lag): (i64, i64) = sqlx::query_as(
+            "SELECT count(*), \
+             COALESCE(EXTRACT(EPOCH FROM now() - min(created_at)), 0)::BIGINT \
+             FROM outbox WHERE published_at IS NULL AND parked_at IS NULL",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        metrics.pending.set(pending);
+        metrics.lag.set(lag);
+        Ok(())
+    }
+
+    async fn purge(&self, retention: Duration) -> sqlx::Result<()> {
+        sqlx::query("DELETE FROM outbox WHERE published_at < now() - make_interval(secs => $1)")
+            .bind(retention.as_secs_f64())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+struct RelayMetrics {
+    published: IntCounterVec,
+    failures: IntCounterVec,
+    parked: IntCounterVec,
+    pending: IntGauge,
+    lag: IntGauge,
+}
+
+impl RelayMetrics {
+    fn new() -> Self {
+        Self {
+            published: get_or_create_counter_with_labels(
+                "outbox_events_published_total",
+                "Outbox events published",
+                &["topic"],
+            ),
+            failures: get_or_create_counter_with_labels(
+                "outbox_publish_failures_total",
+                "Failed publications of outbox events",
+                &["topic"],
+            ),
+            parked: get_or_create_counter_with_labels(
+                "outbox_events_parked_total",
+                "Outbox events parked after too many failed publications",
+                &["topic"],
+            ),
+            pending: get_or_create_gauge(
+                "outbox_pending_events",
+                "Outbox events not published yet",
+            ),
+            lag: get_or_create_gauge(
+                "outbox_lag_seconds",
+                "Age of the oldest outbox event not published yet",
+            ),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing", feature = "reqwest"))]
+#[tokio::test]
+async fn publishes_to_webhooks() {
+    use crate::testing::{FakeResponse, FakeUpstream};
+    use http::{Method, StatusCode};
+
+    #[derive(Serialize)]
+    struct OrderPlaced {
+        id: u32,
+    }
+
+    let upstream = FakeUpstream::start().await;
+    upstream.on_sequence(
+        Method::POST,
+        "/events",
+        vec![
+            FakeResponse::new(StatusCode::OK),
+            FakeResponse::new(StatusCode::SERVICE_UNAVAILABLE),
+        ],
+    );
+    let publisher = WebhookPublisher::new(reqwest::Client::new(), upstream.url("/events"));
+    let record = OutboxRecord {
+        id: 42,
+        attempts: 0,
+        event: OutboxEvent::json("orders", &OrderPlaced { id: 7 })
+            .unwrap()
+            .key("7"),
+    };
+    publisher.publish(&record).await.unwrap();
+    let err = publisher.publish(&record).await.unwrap_err();
+    assert!(err
+        .to_string()
+        .ends_with("responded 503 Service Unavailable"));
+
+    let call = &upstream.calls()[0];
+    assert_eq!(call.headers[OUTBOX_ID_HEADER], "42");
+    assert_eq!(call.headers[OUTBOX_TOPIC_HEADER], "orders");
+    assert_eq!(call.headers[OUTBOX_KEY_HEADER], "7");
+    assert_eq!(call.headers["content-type"], "application/json");
+    assert_eq!(&call.body[..], br#"{"id":7}"#);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+    use super::*;
+
+    /// Publisher recording the published ids, failing the events of the `failing` topic
+    #[derive(Default, Clone)]
+    struct Recorder {
+        published: Arc<Mutex<Vec<i64>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl OutboxPublisher for Recorder {
+        async fn publish(&self, record: &OutboxRecord) -> anyhow::Result<()> {
+            if record.event.topic == "failing" {
+                anyhow::bail!("broker unavailable");
+            }
+            self.published.lock().unwrap().push(record.id);
+            Ok(())
+        }
+    }
+
+    /// Pool on an empty `schema` of the `DATABASE_URL` database
+    async fn pool(schema: &str) -> PgPool {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
+        let admin = PgPool::connect(&url).await.unwrap();
+        sqlx::raw_sql(&format!(
+            "DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema};"
+        ))
+        .execute(&admin)
+        .await
+        .unwrap();
+        let options: PgConnectOptions = url.parse().unwrap();
+        PgPoolOptions::new()
+            .connect_with(options.options([("search_path", schema)]))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL database in DATABASE_URL"]
+    async fn relays_under_the_lock_and_parks_failing_events() {
+        let pool = pool("outbox_relay_test").await;
+        create_outbox_table(&pool).await.unwrap();
+        let recorder = Recorder::default();
+        let relay = OutboxRelay::new(pool.clone(), recorder.clone()).max_attempts(2);
+        let metrics = RelayMetrics::new();
+
+        let failing = enqueue(&pool, &OutboxEvent::new("failing", "a"))
+            .await
+            .unwrap();
+        let next = enqueue(&pool, &OutboxEvent::new("orders", "b"))
+            .await
+            .unwrap();
+
+        // another relay holds the lock
+        let mut other = pool.acquire().await.unwrap();
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(RELAY_LOCK)
+            .execute(&mut *other)
+            .await
+            .unwrap();
+        assert_eq!(relay.relay_batch(&metrics).await.unwrap(), 0);
+        sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(RELAY_LOCK)
+            .execute(&mut *other)
+            .await
+            .unwrap();
+
+        // the failing event blocks the next one until it is parked
+        assert_eq!(relay.relay_batch(&metrics).await.unwrap(), 0);
+        assert_eq!(relay.relay_batch(&metrics).await.unwrap(), 1);
+        assert_eq!(*recorder.published.lock().unwrap(), [next]);
+        let (attempts, parked, error): (i32, bool, String) = sqlx::query_as(
+            "SELECT attempts, parked_at IS NOT NULL, last_error FROM outbox WHERE id = $1",
+        )
+        .bind(failing)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!((attempts, parked), (2, true));
+        assert_eq!(error, "broker unavailable");
+        assert_eq!(relay.relay_batch(&metrics).await.unwrap(), 0);
+
+        assert_eq!(requeue_parked(&pool, Some(&[failing])).await.unwrap(), 1);
+        let attempts: i32 = sqlx::query_scalar("SELECT attempts FROM outbox WHERE id = $1")
+            .bind(failing)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(attempts, 0);
+    }
+}
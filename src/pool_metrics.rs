@@ -0,0 +1,183 @@
+//! Metrics of [`deadpool`] connection pools.
+//!
+//! Exports, labelled by pool name:
+//! - `pool_connections`, `pool_idle_connections`, `pool_max_connections` and
+//!   `pool_waiting_requests` gauges, updated by a background task
+//! - `pool_acquire_duration_seconds` histogram, the time spent waiting for a connection
+//! - `pool_acquire_errors_total` counter, labelled by `kind` (`timeout`, `backend`, `closed`,
+//!   `no_runtime` or `post_create_hook`)
+//!
+//! ```ignore
+//! let metrics = launch_pool_metrics_collector("main_db", pool.clone(), Duration::from_secs(5));
+//! // acquire connections through the metrics to record wait time and errors
+//! let conn = metrics.get(&pool).await?;
+//! ```
+
+use std::time::{Duration, Instant};
+
+use deadpool::{
+    managed::{Manager, Object, Pool, PoolError},
+    Status,
+};
+use prometheus::{Histogram, IntCounterVec, IntGauge};
+
+use crate::metrics::{
+    get_or_create_counter_with_labels, get_or_create_gauge_with_labels,
+    get_or_create_histogram_with_labels,
+};
+
+/// Metrics of one pool
+#[derive(Clone)]
+pub struct PoolMetrics {
+    size: IntGauge,
+    idle: IntGauge,
+    max_size: IntGauge,
+    waiting: IntGauge,
+    acquire_duration: Histogram,
+    acquire_errors: IntCounterVec,
+    name: String,
+}
+
+impl PoolMetrics {
+    pub fn new(pool_name: &str) -> Self {
+        let gauge = |name: &str, help: &str| {
+            get_or_create_gauge_with_labels(name, help, &["pool"]).with_label_values(&[pool_name])
+        };
+        Self {
+            size: gauge("pool_connections", "Number of connections of the pool"),
+            idle: gauge(
+                "pool_idle_connections",
+                "Number of idle connections of the pool",
+            ),
+            max_size: gauge("pool_max_connections", "Maximum size of the pool"),
+            waiting: gauge(
+                "pool_waiting_requests",
+                "Number of requests waiting for a connection",
+            ),
+            acquire_duration: get_or_create_histogram_with_labels(
+                "pool_acquire_duration_seconds",
+                "Time spent waiting for a connection",
+                &["pool"],
+                vec![
+                    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+                    10.0, 30.0,
+                ],
+            )
+            .with_label_values(&[pool_name]),
+            acquire_errors: get_or_create_counter_with_labels(
+                "pool_acquire_errors_total",
+                "Errors while acquiring a connection",
+                &["pool", "kind"],
+            ),
+            name: pool_name.to_string(),
+        }
+    }
+
+    /// Updates the gauges from a pool status
+    pub fn observe_status(&self, status: Status) {
+        self.size.set(status.size as i64);
+        self.idle.set(status.available as i64);
+        self.max_size.set(status.max_size as i64);
+        self.waiting.set(status.waiting as i64);
+    }
+
+    /// Gets a connection from the pool, recording wait time and errors
+    pub async fn get<M, W>(&self, pool: &Pool<M, W>) -> Result<W, PoolError<M::Error>>
+    where
+        M: Manager,
+        W: From<Object<M>>,
+    {
+        let start = Instant::now();
+        let result = pool.get().await;
+        self.acquire_duration.observe(start.elapsed().as_secs_f64());
+        if let Err(err) = &result {
+            let kind = match err {
+                PoolError::Timeout(_) => "timeout",
+                PoolError::Backend(_) => "backend",
+                PoolError::Closed => "closed",
+                PoolError::NoRuntimeSpecified => "no_runtime",
+                PoolError::PostCreateHook(_) => "post_create_hook",
+            };
+            self.acquire_errors
+                .with_label_values(&[&self.name, kind])
+                .inc();
+        }
+        result
+    }
+}
+
+/// Launch a task updating the pool gauges at the specified interval, until the pool is
+/// closed. It requires a running tokio runtime!
+pub fn launch_pool_metrics_collector<M, W>(
+    pool_name: &str,
+    pool: Pool<M, W>,
+    interval: Duration,
+) -> PoolMetrics
+where
+    M: Manager + 'static,
+    W: From<Object<M>> + 'static,
+{
+    let metrics = PoolMetrics::new(pool_name);
+    let collector = metrics.clone();
+    tokio::task::spawn(async move {
+        while !pool.is_closed() {
+            collector.observe_status(pool.status());
+            tokio::time::sleep(interval).await;
+        }
+    });
+    metrics
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn records_pool_usage() {
+    use deadpool::managed::{Metrics, RecycleResult};
+
+    /// Manager failing to connect once `fail` is set
+    struct Connector {
+        fail: std::sync::atomic::AtomicBool,
+    }
+
+    impl Manager for Connector {
+        type Type = ();
+        type Error = &'static str;
+
+        async fn create(&self) -> Result<(), &'static str> {
+            if self.fail.load(std::sync::atomic::Ordering::Relaxed) {
+                return Err("connection refused");
+            }
+            Ok(())
+        }
+
+        async fn recycle(&self, _: &mut (), _: &Metrics) -> RecycleResult<&'static str> {
+            Ok(())
+        }
+    }
+
+    let manager = Connector { fail: false.into() };
+    let pool: Pool<Connector> = Pool::builder(manager).max_size(2).build().unwrap();
+    let metrics =
+        launch_pool_metrics_collector("test_pool", pool.clone(), Duration::from_millis(5));
+    let conn = metrics.get(&pool).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(metrics.size.get(), 1);
+    assert_eq!(metrics.idle.get(), 0);
+    assert_eq!(metrics.max_size.get(), 2);
+    assert_eq!(metrics.acquire_duration.get_sample_count(), 1);
+
+    pool.manager()
+        .fail
+        .store(true, std::sync::atomic::Ordering::Relaxed);
+    assert!(metrics.get(&pool).await.is_err());
+    let errors = |kind| {
+        metrics
+            .acquire_errors
+            .with_label_values(&["test_pool", kind])
+            .get()
+    };
+    assert_eq!(errors("backend"), 1);
+    drop(conn);
+    pool.close();
+    assert!(metrics.get(&pool).await.is_err());
+    assert_eq!(errors("closed"), 1);
+}
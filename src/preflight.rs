@@ -0,0 +1,284 @@
+//! Preflight checks run by CD before switching traffic to a new version: the service loads its
+//! configuration, checks its dependencies are reachable, prints a report and exits.
+//!
+//! ```ignore
+//! if preflight::is_requested() {
+//!     Preflight::new()
+//!         .check_sync("config", move || load_config::<Config>(mode, &SERVICE).map(|_| "loaded".into()))
+//!         .dependencies(&config.dependencies)
+//!         .run()
+//!         .await
+//!         .exit();
+//! }
+//! ```
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::{dependencies::DependenciesConfig, errors::format_error};
+
+/// Command line flag requesting the preflight mode
+pub const PREFLIGHT_FLAG: &str = "--preflight";
+
+type CheckFuture = Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send>>;
+
+/// Whether the service was started with `--preflight` or with `PREFLIGHT=1`
+pub fn is_requested() -> bool {
+    std::env::args().any(|arg| arg == PREFLIGHT_FLAG)
+        || std::env::var("PREFLIGHT").is_ok_and(|v| v == "1" || v == "true")
+}
+
+/// Checks to run, built with the methods below and run in order
+pub struct Preflight {
+    checks: Vec<(String, CheckFuture)>,
+    timeout: Duration,
+}
+
+impl Preflight {
+    pub fn new() -> Self {
+        Self {
+            checks: vec![],
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Timeout of each check (default: 5s)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds a check. The returned string details the success.
+    pub fn check<F>(mut self, name: impl Into<String>, check: F) -> Self
+    where
+        F: Future<Output = anyhow::Result<String>> + Send + 'static,
+    {
+        self.checks.push((name.into(), Box::pin(check)));
+        self
+    }
+
+    /// Adds a synchronous check, eg. loading the configuration. It is run on a blocking
+    /// thread, which is left running when it times out.
+    pub fn check_sync<F>(self, name: impl Into<String>, check: F) -> Self
+    where
+        F: FnOnce() -> anyhow::Result<String> + Send + 'static,
+    {
+        self.check(name, async move {
+            tokio::task::spawn_blocking(check)
+                .await
+                .context("The check panicked")?
+        })
+    }
+
+    /// Adds a check resolving `host:port` and connecting to it
+    pub fn tcp(self, name: impl Into<String>, addr: impl Into<String>) -> Self {
+        let addr = addr.into();
+        self.check(name, async move { connect(&addr).await })
+    }
+
+    /// Adds a TCP check for every dependency with a base URL. With the `reqwest` feature,
+    /// HTTP dependencies with `auth` are also called to check their credentials are accepted.
+    pub fn dependencies(mut self, dependencies: &DependenciesConfig) -> Self {
+        for (name, config) in dependencies {
+            let Some(base_url) = &config.base_url else {
+                continue;
+            };
+            match host_port(base_url) {
+                Some(addr) => self = self.tcp(format!("{name} (tcp)"), addr),
+                None => {
+                    let error = anyhow!("Cannot read host and port of {base_url}");
+                    self = self.check(format!("{name} (tcp)"), async move { Err(error) });
+                }
+            }
+            #[cfg(feature = "reqwest")]
+            if config.auth.is_some() && base_url.starts_with("http") {
+                let name = name.clone();
+                let config = config.clone();
+                self = self.check(format!("{name} (auth)"), async move {
+                    check_auth(&name, &config).await
+                });
+            }
+        }
+        self
+    }
+
+    /// Runs the checks
+    pub async fn run(self) -> PreflightReport {
+        let mut results = vec![];
+        for (name, check) in self.checks {
+            let start = Instant::now();
+            let result = match tokio::time::timeout(self.timeout, check).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow!("Timed out after {:?}", self.timeout)),
+            };
+            results.push(CheckResult {
+                name,
+                duration: start.elapsed(),
+                outcome: result.map_err(format_error),
+            });
+        }
+        PreflightReport { results }
+    }
+}
+
+impl Default for Preflight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn connect(addr: &str) -> anyhow::Result<String> {
+    let resolved: Vec<_> = tokio::net::lookup_host(addr)
+        .await
+        .with_context(|| format!("Cannot resolve {addr}"))?
+        .collect();
+    let first = resolved
+        .first()
+        .ok_or_else(|| anyhow!("{addr} resolves to no address"))?;
+    tokio::net::TcpStream::connect(first)
+        .await
+        .with_context(|| format!("Cannot connect to {addr} ({first})"))?;
+    Ok(format!("connected to {first}"))
+}
+
+#[cfg(feature = "reqwest")]
+async fn check_auth(
+    name: &str,
+    config: &crate::dependencies::DependencyConfig,
+) -> anyhow::Result<String> {
+    let client = crate::reqwest::dependency_client(name, config)?;
+    let status = client
+        .get(config.url("/"))
+        .send()
+        .await
+        .map_err(|err| anyhow!(err))?
+        .status();
+    if status == 401 || status == 403 {
+        Err(anyhow!("Credentials rejected ({status})"))
+    } else {
+        Ok(format!("credentials accepted ({status})"))
+    }
+}
+
+/// `host:port` of an URL, the port defaulting to the one of the scheme
+fn host_port(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    let authority = rest.split(['/', '?']).next()?;
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    if host_port.is_empty() {
+        return None;
+    }
+    if host_port
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        return Some(host_port.to_string());
+    }
+    let port = match scheme {
+        "http" | "ws" => 80,
+        "https" | "wss" => 443,
+        "postgres" | "postgresql" => 5432,
+        "mysql" => 3306,
+        "redis" | "rediss" => 6379,
+        "amqp" => 5672,
+        "kafka" => 9092,
+        _ => return None,
+    };
+    Some(format!("{host_port}:{port}"))
+}
+
+/// Result of a check
+#[derive(Debug)]
+pub struct CheckResult {
+    pub name: String,
+    pub duration: Duration,
+    /// Details of the success or the error
+    pub outcome: Result<String, String>,
+}
+
+/// Results of the preflight checks
+#[derive(Debug)]
+pub struct PreflightReport {
+    pub results: Vec<CheckResult>,
+}
+
+impl PreflightReport {
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|r| r.outcome.is_ok())
+    }
+
+    /// Prints the report and exits with status 0 if all checks succeeded, 1 otherwise
+    pub fn exit(&self) -> ! {
+        println!("{self}");
+        std::process::exit(if self.is_ok() { 0 } else { 1 })
+    }
+}
+
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            match &result.outcome {
+                Ok(detail) => writeln!(
+                    f,
+                    "[ OK ] {} ({:?}): {detail}",
+                    result.name, result.duration
+                )?,
+                Err(error) => {
+                    writeln!(f, "[FAIL] {} ({:?}): {error}", result.name, result.duration)?
+                }
+            }
+        }
+        let failed = self.results.iter().filter(|r| r.outcome.is_err()).count();
+        write!(f, "{} checks, {} failed", self.results.len(), failed)
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn reports_each_check() {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let loaded = Arc::new(AtomicBool::new(false));
+    let preflight = Preflight::new()
+        .timeout(Duration::from_millis(100))
+        .check_sync("config", {
+            let loaded = loaded.clone();
+            move || {
+                loaded.store(true, Ordering::Relaxed);
+                Ok("loaded".to_string())
+            }
+        })
+        .check_sync("slow", || {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok("done".to_string())
+        })
+        .tcp("listening", addr)
+        .tcp("closed", "127.0.0.1:1");
+    // the checks are only run by `run`
+    assert!(!loaded.load(Ordering::Relaxed));
+    let report = preflight.run().await;
+    assert!(loaded.load(Ordering::Relaxed));
+    assert!(!report.is_ok());
+    let outcomes: Vec<bool> = report.results.iter().map(|r| r.outcome.is_ok()).collect();
+    assert_eq!(outcomes, [true, false, true, false]);
+    assert_eq!(
+        report.results[1].outcome.as_ref().unwrap_err(),
+        "Timed out after 100ms"
+    );
+    assert_eq!(
+        host_port("postgres://app:pwd@db/app").as_deref(),
+        Some("db:5432")
+    );
+}
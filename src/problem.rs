@@ -0,0 +1,248 @@
+//! Problem details ([RFC 7807](https://www.rfc-editor.org/rfc/rfc7807)) bodies of the standard
+//! error responses.
+//!
+//! Error responses are `application/problem+json` documents with the `type`, `title`,
+//! `status`, `detail` (optional) and `code` fields. `code` is a machine readable [`ErrorCode`]
+//! clients can branch on: codes are part of the public API and are never renamed.
+//!
+//! Responses also carry an `X-Retryable: true|false` header telling clients whether the request
+//! can be retried whatever its method, and a `Retry-After` header when the service is
+//! overloaded.
+
+use std::{collections::BTreeMap, fmt, sync::RwLock};
+
+use serde::{Serialize, Serializer};
+
+/// Content type of problem bodies
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Header telling whether a failed request can be safely retried
+pub const RETRYABLE_HEADER: &str = "x-retryable";
+
+/// Application defined codes: code -> (status, title)
+static REGISTRY: RwLock<BTreeMap<&'static str, (u16, &'static str)>> = RwLock::new(BTreeMap::new());
+
+/// Stable machine readable code of an error response.
+///
+/// Application specific codes are registered with [`register_error_code`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    NotFound,
+    BadRequest,
+    Forbidden,
+    MethodNotAllowed,
+    Internal,
+    /// The request took too long and was aborted, it may have been partially processed
+    Timeout,
+    /// The request was rejected without being processed because the service is overloaded
+    Overloaded,
+    /// The request body exceeds the size accepted by the service
+    PayloadTooLarge,
+    /// Code registered with [`register_error_code`]
+    Custom(&'static str),
+}
+
+const STANDARD_CODES: &[ErrorCode] = &[
+    ErrorCode::NotFound,
+    ErrorCode::BadRequest,
+    ErrorCode::Forbidden,
+    ErrorCode::MethodNotAllowed,
+    ErrorCode::Internal,
+    ErrorCode::Timeout,
+    ErrorCode::Overloaded,
+    ErrorCode::PayloadTooLarge,
+];
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::MethodNotAllowed => "method_not_allowed",
+            ErrorCode::Internal => "internal",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::Overloaded => "overloaded",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::Custom(code) => code,
+        }
+    }
+
+    /// HTTP status of the responses with this code
+    pub fn status(&self) -> u16 {
+        match self {
+            ErrorCode::NotFound => 404,
+            ErrorCode::BadRequest => 400,
+            ErrorCode::Forbidden => 403,
+            ErrorCode::MethodNotAllowed => 405,
+            ErrorCode::Internal => 500,
+            ErrorCode::Timeout => 504,
+            ErrorCode::Overloaded => 503,
+            ErrorCode::PayloadTooLarge => 413,
+            ErrorCode::Custom(code) => registered(code).map(|(status, _)| status).unwrap_or(500),
+        }
+    }
+
+    /// Short human readable summary of the error
+    pub fn title(&self) -> &'static str {
+        match self {
+            ErrorCode::NotFound => "Not Found",
+            ErrorCode::BadRequest => "Bad Request",
+            ErrorCode::Forbidden => "Forbidden",
+            ErrorCode::MethodNotAllowed => "Method Not Allowed",
+            ErrorCode::Internal => "Internal Server Error",
+            ErrorCode::Timeout => "Gateway Timeout",
+            ErrorCode::Overloaded => "Service Unavailable",
+            ErrorCode::PayloadTooLarge => "Payload Too Large",
+            ErrorCode::Custom(code) => registered(code)
+                .map(|(_, title)| title)
+                .unwrap_or("Internal Server Error"),
+        }
+    }
+
+    /// Whether a request failing with this code can be retried whatever its method, ie. it was
+    /// not processed at all. Application defined codes are retryable when their status is
+    /// `429` or `503`.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ErrorCode::Overloaded => true,
+            ErrorCode::Custom(_) => matches!(self.status(), 429 | 503),
+            _ => false,
+        }
+    }
+
+    /// Parses a standard or registered code, eg. from the `code` field of a received problem
+    pub fn parse(code: &str) -> Option<ErrorCode> {
+        STANDARD_CODES
+            .iter()
+            .find(|c| c.as_str() == code)
+            .copied()
+            .or_else(|| {
+                REGISTRY
+                    .read()
+                    .unwrap()
+                    .get_key_value(code)
+                    .map(|(code, _)| ErrorCode::Custom(code))
+            })
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+fn registered(code: &str) -> Option<(u16, &'static str)> {
+    REGISTRY.read().unwrap().get(code).copied()
+}
+
+/// Registers an application defined code, responded with `status` and `title`.
+///
+/// Registering the same code twice is allowed as long as status and title are the same.
+///
+/// # Panics
+///
+/// If `code` is a standard code or is already registered with another status or title.
+pub fn register_error_code(code: &'static str, status: u16, title: &'static str) -> ErrorCode {
+    if STANDARD_CODES.iter().any(|c| c.as_str() == code) {
+        panic!("{code} is a standard error code");
+    }
+    let mut registry = REGISTRY.write().unwrap();
+    match registry.get(code) {
+        Some(registered) if *registered != (status, title) => {
+            panic!("Error code {code} already registered with {registered:?}")
+        }
+        _ => {
+            registry.insert(code, (status, title));
+        }
+    }
+    ErrorCode::Custom(code)
+}
+
+/// Problem body of an error response
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub code: ErrorCode,
+    /// Value of the `Retry-After` header, in seconds
+    #[serde(skip)]
+    pub retry_after: Option<u64>,
+}
+
+impl Problem {
+    pub fn new(code: ErrorCode) -> Self {
+        Self {
+            type_: "about:blank".to_string(),
+            title: code.title().to_string(),
+            status: code.status(),
+            detail: None,
+            code,
+            retry_after: (code == ErrorCode::Overloaded).then_some(1),
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    /// Headers to send along the body: `X-Retryable` and `Retry-After`
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![(RETRYABLE_HEADER, self.code.is_retryable().to_string())];
+        if let Some(retry_after) = self.retry_after {
+            headers.push(("retry-after", retry_after.to_string()));
+        }
+        headers
+    }
+}
+
+/// When returned by a future handled by `handle_errors`, respond with a problem with this code
+/// and detail.
+#[derive(Debug, thiserror::Error)]
+#[error("{code}: {detail}")]
+pub struct CodedError {
+    pub code: ErrorCode,
+    pub detail: String,
+}
+
+impl CodedError {
+    pub fn new(code: ErrorCode, detail: impl Into<String>) -> Self {
+        Self {
+            code,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn custom_codes() {
+    let suspended = register_error_code("user_suspended", 403, "User Suspended");
+    assert_eq!(ErrorCode::parse("user_suspended"), Some(suspended));
+    assert_eq!(ErrorCode::parse("not_found"), Some(ErrorCode::NotFound));
+    assert_eq!(ErrorCode::parse("unknown"), None);
+
+    let problem = Problem::new(suspended).with_detail("Suspended since 2024-01-01");
+    assert_eq!(
+        serde_json::to_string(&problem).unwrap(),
+        r#"{"type":"about:blank","title":"User Suspended","status":403,"detail":"Suspended since 2024-01-01","code":"user_suspended"}"#
+    );
+}
@@ -0,0 +1,133 @@
+//! Redaction of secrets from values exposed in logs or admin endpoints
+
+/// Replacement of redacted values
+pub const REDACTED: &str = "***";
+
+/// Parts of a key identifying a secret value wherever they appear, like `PGPASSWORD`
+const SENSITIVE_FRAGMENTS: &[&str] = &[
+    "password",
+    "passwd",
+    "passphrase",
+    "secret",
+    "token",
+    "apikey",
+    "credential",
+    "authorization",
+];
+
+/// Words of a key identifying a secret value, also matched in the plural. They are too short
+/// to be matched inside other words.
+const SENSITIVE_WORDS: &[&str] = &["key", "auth"];
+
+/// Whether a configuration key (or header, query parameter...) names a secret: it contains a
+/// sensitive fragment (`db_password`, `PGPASSWORD`, `accessToken`), or one of its words,
+/// separated by punctuation or case, is a sensitive word (`X-Api-Key`). `monkey` or `author`
+/// are not sensitive.
+pub fn is_sensitive_key(key: &str) -> bool {
+    let lowercase = key.to_ascii_lowercase();
+    SENSITIVE_FRAGMENTS
+        .iter()
+        .any(|fragment| lowercase.contains(fragment))
+        || words(key).any(|word| {
+            let singular = word.strip_suffix('s').unwrap_or(&word);
+            SENSITIVE_WORDS.contains(&word.as_str()) || SENSITIVE_WORDS.contains(&singular)
+        })
+}
+
+/// Lowercase words of a key, split on non alphanumeric characters and before uppercase letters
+/// following a lowercase one
+fn words(key: &str) -> impl Iterator<Item = String> + '_ {
+    let mut words = vec![String::new()];
+    let mut previous_lowercase = false;
+    for c in key.chars() {
+        if !c.is_ascii_alphanumeric() {
+            words.push(String::new());
+        } else if c.is_ascii_uppercase() && previous_lowercase {
+            words.push(c.to_ascii_lowercase().to_string());
+        } else {
+            words.last_mut().unwrap().push(c.to_ascii_lowercase());
+        }
+        previous_lowercase = c.is_ascii_lowercase() || c.is_ascii_digit();
+    }
+    words.into_iter().filter(|word| !word.is_empty())
+}
+
+/// Redacts `value` when `key` names a secret
+pub fn redact_value(key: &str, value: &str) -> String {
+    if is_sensitive_key(key) {
+        REDACTED.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Redacts the password of the user info and the sensitive query parameters of an URL,
+/// eg. `postgres://user:***@db:5432/app` or `https://api/path?token=***`.
+pub fn redact_url(url: &str) -> String {
+    let (url, query) = match url.split_once('?') {
+        Some((url, query)) => (url, Some(query)),
+        None => (url, None),
+    };
+    let mut redacted = match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let authority_end = rest.find('/').unwrap_or(rest.len());
+            let (authority, path) = rest.split_at(authority_end);
+            match authority.rsplit_once('@') {
+                Some((user_info, host)) => {
+                    let user = user_info.split_once(':').map(|(user, _)| user);
+                    match user {
+                        Some(user) => format!("{scheme}://{user}:{REDACTED}@{host}{path}"),
+                        None => format!("{scheme}://{user_info}@{host}{path}"),
+                    }
+                }
+                None => format!("{scheme}://{rest}"),
+            }
+        }
+        None => url.to_string(),
+    };
+    if let Some(query) = query {
+        let query: Vec<String> = query
+            .split('&')
+            .map(|param| match param.split_once('=') {
+                Some((name, _)) if is_sensitive_key(name) => format!("{name}={REDACTED}"),
+                _ => param.to_string(),
+            })
+            .collect();
+        redacted.push('?');
+        redacted.push_str(&query.join("&"));
+    }
+    redacted
+}
+
+#[cfg(test)]
+#[test]
+fn redacts_urls() {
+    assert_eq!(
+        redact_url("postgres://app:s3cr3t@db:5432/app?sslmode=require"),
+        "postgres://app:***@db:5432/app?sslmode=require"
+    );
+    assert_eq!(
+        redact_url("https://api.example.com/v1?api_key=abc&page=2"),
+        "https://api.example.com/v1?api_key=***&page=2"
+    );
+    assert_eq!(redact_url("kafka:9092"), "kafka:9092");
+
+    for key in [
+        "db_password",
+        "X-Api-Key",
+        "accessToken",
+        "apikey",
+        "credentials",
+        "Auth",
+        "PGPASSWORD",
+        "dbpassword",
+        "clientsecret",
+        "accesstoken",
+        "apitoken",
+    ] {
+        assert!(is_sensitive_key(key), "{key}");
+    }
+    for key in ["monkey", "author", "keyspace", "hotkeys_enabled"] {
+        assert!(!is_sensitive_key(key), "{key}");
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use anyhow::anyhow;
+use http::{header::AUTHORIZATION, Extensions, HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Request, Response};
+use reqwest_middleware::{ClientWithMiddleware, Error, Middleware, Next, Result};
+
+use super::retry::{retry_after, should_retry, should_retry_error};
+use crate::dependencies::{report_status, DependencyAuth, DependencyConfig, DependencyStatus};
+use crate::time::{Clock, SystemClock};
+
+/// Builds the client of the dependency `name` from its configuration, and declares it in the
+/// [dependencies catalog](crate::dependencies):
+/// - calls time out after `timeout_ms`, retries included
+/// - calls are authenticated with `auth`
+/// - failed calls are retried `retries` times when safe (see [`should_retry`]), unless the
+///   delay before the retry, from `Retry-After` or the backoff, exceeds the timeout
+/// - calls fail immediately when the circuit breaker is open
+/// - calls are recorded by the
+///   [`ClientMetricsMiddleware`](super::metrics::ClientMetricsMiddleware)
+/// - with the `ids` feature, the [`RequestIds`](crate::ids::RequestIds) set in the extensions
+///   of the calls are propagated by [`PropagateRequestIds`](super::PropagateRequestIds)
+///
+/// ```ignore
+/// let users = dependency_client("users", &config.dependencies["users"])?;
+/// users.get(config.dependencies["users"].url("/users/42")).send().await?;
+/// ```
+pub fn dependency_client(
+    name: &str,
+    config: &DependencyConfig,
+) -> anyhow::Result<ClientWithMiddleware> {
+    config.register(name, "http");
+    let client = reqwest::Client::builder()
+        .timeout(config.timeout())
+        .default_headers(auth_headers(config.auth.as_ref())?)
+        .build()?;
+    let builder = reqwest_middleware::ClientBuilder::new(client);
+    let builder = builder.with(super::metrics::ClientMetricsMiddleware::new());
+    #[cfg(feature = "ids")]
+    let builder = builder.with(super::PropagateRequestIds);
+    Ok(builder
+        .with(DependencyMiddleware::new(name, config.clone()))
+        .build())
+}
+
+fn auth_headers(auth: Option<&DependencyAuth>) -> anyhow::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let (name, mut value) = match auth {
+        None => return Ok(headers),
+        Some(DependencyAuth::Bearer { token }) => (
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}"))?,
+        ),
+        Some(DependencyAuth::Basic { username, password }) => {
+            let credentials =
+                data_encoding::BASE64.encode(format!("{username}:{password}").as_bytes());
+            (
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Basic {credentials}"))?,
+            )
+        }
+        Some(DependencyAuth::Header { name, value }) => (
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        ),
+    };
+    value.set_sensitive(true);
+    headers.insert(name, value);
+    Ok(headers)
+}
+
+/// Retries and circuit breaker of the calls to a dependency, configured by a
+/// [`DependencyConfig`]. The status of the dependency is reported in the catalog.
+#[derive(Clone)]
+pub struct DependencyMiddleware {
+    name: String,
+    config: DependencyConfig,
+    breaker: Arc<Mutex<Breaker>>,
+    clock: Arc<dyn Clock>,
+}
+
+/// State of the circuit breaker
+enum Breaker {
+    /// Calls are let through
+    Closed { consecutive_failures: u32 },
+    /// Calls fail immediately until `until`, then a trial call is let through
+    Open { until: Instant },
+    /// The trial call is in flight, the other calls fail immediately
+    HalfOpen,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Breaker::Closed {
+            consecutive_failures: 0,
+        }
+    }
+}
+
+/// Trial call of the half-open breaker: another call is let through if it is cancelled
+/// before its outcome is recorded
+struct Trial(Arc<Mutex<Breaker>>, Arc<dyn Clock>);
+
+impl Drop for Trial {
+    fn drop(&mut self) {
+        let mut breaker = self.0.lock().unwrap();
+        if matches!(*breaker, Breaker::HalfOpen) {
+            *breaker = Breaker::Open {
+                until: self.1.monotonic(),
+            };
+        }
+    }
+}
+
+impl DependencyMiddleware {
+    pub fn new(name: impl Into<String>, config: DependencyConfig) -> Self {
+        Self {
+            name: name.into(),
+            config,
+            breaker: Default::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Clock of the circuit breaker (default: [`SystemClock`])
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Whether a call can be made, the first one after the open duration being the trial
+    fn acquire(&self) -> std::result::Result<Option<Trial>, Error> {
+        let mut breaker = self.breaker.lock().unwrap();
+        match *breaker {
+            Breaker::Closed { .. } => Ok(None),
+            Breaker::Open { until } if until <= self.clock.monotonic() => {
+                log::info!("Circuit breaker of {} half-open, trying a call", self.name);
+                *breaker = Breaker::HalfOpen;
+                Ok(Some(Trial(self.breaker.clone(), self.clock.clone())))
+            }
+            Breaker::Open { .. } | Breaker::HalfOpen => Err(Error::Middleware(anyhow!(
+                "Circuit breaker of {} open",
+                self.name
+            ))),
+        }
+    }
+
+    fn record(&self, failed: bool) {
+        report_status(
+            &self.name,
+            "http",
+            if failed {
+                DependencyStatus::Down
+            } else {
+                DependencyStatus::Up
+            },
+        );
+        let Some(circuit_breaker) = &self.config.circuit_breaker else {
+            return;
+        };
+        let mut breaker = self.breaker.lock().unwrap();
+        *breaker = match *breaker {
+            _ if !failed => {
+                if !matches!(*breaker, Breaker::Closed { .. }) {
+                    log::info!("Circuit breaker of {} closed", self.name);
+                }
+                Breaker::default()
+            }
+            Breaker::Closed {
+                consecutive_failures,
+            } if consecutive_failures + 1 < circuit_breaker.failure_threshold => Breaker::Closed {
+                consecutive_failures: consecutive_failures + 1,
+            },
+            // a call made before the breaker opened
+            Breaker::Open { until } => Breaker::Open { until },
+            Breaker::Closed { .. } | Breaker::HalfOpen => {
+                log::warn!("Circuit breaker of {} open", self.name);
+                Breaker::Open {
+                    until: self.clock.monotonic() + circuit_breaker.open_duration(),
+                }
+            }
+        };
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for DependencyMiddleware {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let method = req.method().clone();
+        // the timeout covers the whole call, retries and their delays included
+        let deadline = Instant::now() + self.config.timeout();
+        let mut attempt = 0;
+        loop {
+            let trial = self.acquire()?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let timeout = req.timeout_mut();
+            *timeout = Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)));
+            // requests with a streaming body cannot be retried
+            let retry = if attempt < self.config.retries {
+                req.try_clone()
+            } else {
+                None
+            };
+            let result = next.clone().run(req, extensions).await;
+            self.record(match &result {
+                Ok(resp) => resp.status().is_server_error(),
+                Err(_) => true,
+            });
+            drop(trial);
+
+            let Some(retry) = retry else {
+                return result;
+            };
+            let delay = match &result {
+                Ok(resp) if should_retry(&method, resp) => {
+                    retry_after(resp).unwrap_or_else(|| self.config.retry_backoff(attempt))
+                }
+                Err(Error::Reqwest(err)) if should_retry_error(&method, err) => {
+                    self.config.retry_backoff(attempt)
+                }
+                _ => return result,
+            };
+            // not retried if the retry could not be sent within the timeout, eg. when the
+            // dependency asks to retry in an hour
+            if Instant::now() + delay >= deadline {
+                return result;
+            }
+            tokio::time::sleep(delay).await;
+            req = retry;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::time::Duration;
+
+    use http::{Method, StatusCode};
+
+    use super::*;
+    use crate::dependencies::CircuitBreakerConfig;
+    use crate::testing::{FakeResponse, FakeUpstream};
+    use crate::time::MockClock;
+
+    #[tokio::test]
+    async fn retries_then_opens_circuit_breaker() {
+        let upstream = FakeUpstream::start().await;
+        upstream.on_sequence(
+            Method::GET,
+            "/users",
+            vec![
+                FakeResponse::new(StatusCode::SERVICE_UNAVAILABLE),
+                FakeResponse::new(StatusCode::OK),
+            ],
+        );
+        upstream.on(
+            Method::POST,
+            "/users",
+            FakeResponse::new(StatusCode::BAD_GATEWAY),
+        );
+        let config = DependencyConfig {
+            base_url: Some(upstream.url("/")),
+            retries: 1,
+            retry_backoff_ms: 1,
+            circuit_breaker: Some(CircuitBreakerConfig {
+                failure_threshold: 2,
+                open_duration_ms: 60_000,
+            }),
+            ..Default::default()
+        };
+        let client = dependency_client("dependency-test", &config).unwrap();
+
+        let resp = client.get(config.url("/users")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(upstream.calls_to(&Method::GET, "/users"), 2);
+
+        // not retried: POST is not idempotent
+        let resp = client.post(config.url("/users")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(upstream.calls_to(&Method::POST, "/users"), 1);
+
+        // second consecutive failure opens the circuit breaker
+        client.post(config.url("/users")).send().await.unwrap();
+        client.get(config.url("/users")).send().await.unwrap_err();
+        assert_eq!(upstream.calls_to(&Method::GET, "/users"), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_within_the_timeout() {
+        let upstream = FakeUpstream::start().await;
+        upstream.on(
+            Method::GET,
+            "/later",
+            FakeResponse::new(StatusCode::SERVICE_UNAVAILABLE).header(
+                http::header::RETRY_AFTER,
+                http::HeaderValue::from_static("3600"),
+            ),
+        );
+        upstream.on(
+            Method::GET,
+            "/slow",
+            FakeResponse::new(StatusCode::SERVICE_UNAVAILABLE).delay(Duration::from_millis(300)),
+        );
+        let config = DependencyConfig {
+            base_url: Some(upstream.url("/")),
+            timeout_ms: 500,
+            retries: 3,
+            retry_backoff_ms: 1,
+            ..Default::default()
+        };
+        let client = dependency_client("retry-timeout-test", &config).unwrap();
+
+        // not waiting for an hour
+        let start = Instant::now();
+        let resp = client.get(config.url("/later")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(upstream.calls_to(&Method::GET, "/later"), 1);
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        // the retry times out with the remaining time of the call
+        let start = Instant::now();
+        let error = client.get(config.url("/slow")).send().await.unwrap_err();
+        assert!(error.is_timeout(), "{error:?}");
+        assert_eq!(upstream.calls_to(&Method::GET, "/slow"), 2);
+        assert!(start.elapsed() < Duration::from_millis(700));
+    }
+
+    #[tokio::test]
+    async fn tries_a_single_call_once_half_open() {
+        let upstream = FakeUpstream::start().await;
+        upstream.on_sequence(
+            Method::GET,
+            "/orders",
+            vec![
+                FakeResponse::new(StatusCode::SERVICE_UNAVAILABLE),
+                FakeResponse::new(StatusCode::SERVICE_UNAVAILABLE)
+                    .delay(Duration::from_millis(200)),
+                FakeResponse::new(StatusCode::OK),
+            ],
+        );
+        let config = DependencyConfig {
+            base_url: Some(upstream.url("/")),
+            circuit_breaker: Some(CircuitBreakerConfig {
+                failure_threshold: 1,
+                open_duration_ms: 100,
+            }),
+            ..Default::default()
+        };
+        let clock = Arc::new(MockClock::default());
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(DependencyMiddleware::new("half-open-test", config.clone()).clock(clock.clone()))
+            .build();
+        let call = || client.get(config.url("/orders")).send();
+
+        assert_eq!(call().await.unwrap().status(), 503);
+        call().await.unwrap_err();
+        clock.advance(Duration::from_millis(100));
+
+        // the other calls fail while the trial is in flight, and after it failed
+        let (trial, concurrent) = tokio::join!(call(), async {
+            tokio::task::yield_now().await;
+            call().await
+        });
+        assert_eq!(trial.unwrap().status(), 503);
+        concurrent.unwrap_err();
+        call().await.unwrap_err();
+        assert_eq!(upstream.calls_to(&Method::GET, "/orders"), 2);
+
+        // the breaker closes once a trial succeeds
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(call().await.unwrap().status(), 200);
+        assert_eq!(call().await.unwrap().status(), 200);
+        assert_eq!(upstream.calls_to(&Method::GET, "/orders"), 4);
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::Instant;
+
+use http::Extensions;
+use prometheus::{HistogramVec, IntCounterVec};
+use reqwest::{Request, Response};
+use reqwest_middleware::{Error, Middleware, Next, Result};
+
+use crate::dependencies::{report_status, DependencyStatus};
+use crate::metrics::{
+    get_or_create_counter_with_labels, get_or_create_histogram_with_labels,
+    DEFAULT_DURATION_BUCKETS,
+};
+
+/// Records metrics of outgoing HTTP calls, and reports the status of the called hosts in the
+/// [dependencies catalog](crate::dependencies):
+/// - `http_client_request_duration_seconds` histogram labelled by `host`, `method` and `status`
+/// - `http_client_request_errors_total` counter labelled by `host`, `method` and `kind`
+///   (`timeout`, `connect`, `request`, `body` or `middleware`) for calls that got no response
+///
+/// ```ignore
+/// let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+///     .with(ClientMetricsMiddleware::new())
+///     .build();
+/// ```
+#[derive(Clone)]
+pub struct ClientMetricsMiddleware {
+    duration: HistogramVec,
+    errors: IntCounterVec,
+}
+
+impl ClientMetricsMiddleware {
+    pub fn new() -> Self {
+        Self {
+            duration: get_or_create_histogram_with_labels(
+                "http_client_request_duration_seconds",
+                "Outgoing HTTP requests duration",
+                &["host", "method", "status"],
+                DEFAULT_DURATION_BUCKETS.to_vec(),
+            ),
+            errors: get_or_create_counter_with_labels(
+                "http_client_request_errors_total",
+                "Outgoing HTTP requests that got no response",
+                &["host", "method", "kind"],
+            ),
+        }
+    }
+}
+
+impl Default for ClientMetricsMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for ClientMetricsMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let host = req.url().host_str().unwrap_or_default().to_string();
+        let method = req.method().clone();
+        let start = Instant::now();
+        let result = next.run(req, extensions).await;
+        let status = match &result {
+            Ok(resp) => {
+                self.duration
+                    .with_label_values(&[&host, method.as_str(), resp.status().as_str()])
+                    .observe(start.elapsed().as_secs_f64());
+                if resp.status().is_server_error() {
+                    DependencyStatus::Down
+                } else {
+                    DependencyStatus::Up
+                }
+            }
+            Err(err) => {
+                self.errors
+                    .with_label_values(&[&host, method.as_str(), error_kind(err)])
+                    .inc();
+                DependencyStatus::Down
+            }
+        };
+        report_status(&host, "http", status);
+        result
+    }
+}
+
+fn error_kind(err: &Error) -> &'static str {
+    match err {
+        Error::Reqwest(err) if err.is_timeout() => "timeout",
+        Error::Reqwest(err) if err.is_connect() => "connect",
+        Error::Reqwest(err) if err.is_body() || err.is_decode() => "body",
+        Error::Reqwest(_) => "request",
+        Error::Middleware(_) => "middleware",
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use http::{Method, StatusCode};
+
+    use super::*;
+    use crate::testing::{metrics_snapshot, FakeResponse, FakeUpstream};
+
+    #[tokio::test]
+    async fn records_status_and_errors() {
+        let upstream = FakeUpstream::start().await;
+        upstream.on(
+            Method::GET,
+            "/ok",
+            FakeResponse::new(StatusCode::NO_CONTENT),
+        );
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(ClientMetricsMiddleware::new())
+            .build();
+
+        client.get(upstream.url("/ok")).send().await.unwrap();
+        client.get("http://127.0.0.1:1/").send().await.unwrap_err();
+
+        let snapshot = metrics_snapshot(prometheus::default_registry());
+        crate::assert_counter_eq!(
+            snapshot,
+            "http_client_request_errors_total",
+            { "host" => "127.0.0.1", "kind" => "connect" },
+            1
+        );
+        assert_eq!(
+            snapshot.sum(
+                "http_client_request_duration_seconds_count",
+                &[("status", "204")]
+            ),
+            Some(1.0)
+        );
+    }
+}
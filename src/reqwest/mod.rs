@@ -0,0 +1,14 @@
+pub mod metrics;
+
+pub mod retry;
+
+mod dependency;
+pub use dependency::{dependency_client, DependencyMiddleware};
+
+#[cfg(feature = "ids")]
+mod request_ids;
+#[cfg(feature = "ids")]
+pub use request_ids::PropagateRequestIds;
+
+mod token_exchange;
+pub use token_exchange::{ExchangedToken, TokenExchangeConfig, TokenExchanger};
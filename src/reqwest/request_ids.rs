@@ -0,0 +1,50 @@
+use http::{Extensions, HeaderName, HeaderValue};
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next, Result};
+
+use crate::ids::{RequestIds, REQUEST_ID_HEADER, TRACEPARENT_HEADER};
+
+/// Propagates the [`RequestIds`] of the request being handled to the called services, in the
+/// `x-request-id` and `traceparent` headers. Headers already set on the call are kept.
+///
+/// When the traces are exported (with the `otlp` feature), the `traceparent` header is the
+/// one of the current span, see [`inject_current_context`](crate::otlp::inject_current_context).
+///
+/// The ids are taken from the extensions of the call:
+///
+/// ```ignore
+/// async fn handler(Extension(ids): Extension<RequestIds>) -> ... {
+///     client.get(url).with_extension(ids).send().await?;
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PropagateRequestIds;
+
+#[async_trait::async_trait]
+impl Middleware for PropagateRequestIds {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        #[cfg(feature = "otlp")]
+        if !req.headers().contains_key(TRACEPARENT_HEADER) {
+            crate::otlp::inject_current_context(req.headers_mut());
+        }
+        if let Some(ids) = extensions.get::<RequestIds>() {
+            let headers = req.headers_mut();
+            for (name, value) in [
+                (REQUEST_ID_HEADER, ids.request_id()),
+                (TRACEPARENT_HEADER, ids.traceparent()),
+            ] {
+                if let Ok(value) = HeaderValue::from_str(&value) {
+                    headers
+                        .entry(HeaderName::from_static(name))
+                        .or_insert(value);
+                }
+            }
+        }
+        next.run(req, extensions).await
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use http::Method;
+use reqwest::{header::RETRY_AFTER, Response};
+
+use crate::problem::RETRYABLE_HEADER;
+
+/// Whether a request with this method can be sent twice with the same effect
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// Whether the request that got this error response can be retried safely.
+///
+/// The `X-Retryable` header of the services built with this crate is trusted when present.
+/// Otherwise `429` and `503` responses are retryable, `502` and `504` only for idempotent
+/// methods.
+pub fn should_retry(method: &Method, response: &Response) -> bool {
+    if let Some(retryable) = response.headers().get(RETRYABLE_HEADER) {
+        if retryable == "true" {
+            return true;
+        }
+        if retryable == "false" && !is_idempotent(method) {
+            return false;
+        }
+    }
+    match response.status().as_u16() {
+        429 | 503 => true,
+        502 | 504 => is_idempotent(method),
+        _ => false,
+    }
+}
+
+/// Whether the request that failed without response can be retried safely: the connection
+/// could not be established, or it timed out and the method is idempotent.
+pub fn should_retry_error(method: &Method, error: &reqwest::Error) -> bool {
+    error.is_connect() || (error.is_timeout() && is_idempotent(method))
+}
+
+/// Delay requested by the `Retry-After` header, in seconds
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+#[test]
+fn retry_classification() {
+    let response = |status: u16, retryable: Option<&str>| {
+        let mut builder = http::Response::builder().status(status);
+        if let Some(retryable) = retryable {
+            builder = builder
+                .header(RETRYABLE_HEADER, retryable)
+                .header(RETRY_AFTER, "2");
+        }
+        Response::from(builder.body("").unwrap())
+    };
+
+    assert!(should_retry(&Method::POST, &response(503, Some("true"))));
+    assert!(!should_retry(&Method::POST, &response(504, Some("false"))));
+    assert!(should_retry(&Method::GET, &response(504, Some("false"))));
+    assert!(!should_retry(&Method::GET, &response(500, None)));
+    assert!(should_retry(&Method::POST, &response(429, None)));
+    assert_eq!(
+        retry_after(&response(503, Some("true"))),
+        Some(Duration::from_secs(2))
+    );
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::redact::REDACTED;
+use crate::time::{Clock, SystemClock};
+
+const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+/// Exchanged tokens are dropped from the cache this long before they expire
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+/// Expired entries are purged when the cache reaches this size
+const MAX_CACHED: usize = 10_000;
+
+/// Configuration of the token exchange with the IdP. The secret is redacted from the `Debug`
+/// output.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct TokenExchangeConfig {
+    /// Token endpoint of the IdP
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Default audience of the delegated tokens (the downstream API)
+    #[serde(default)]
+    pub audience: Option<String>,
+    /// Default scope of the delegated tokens
+    #[serde(default)]
+    pub scope: Option<String>,
+    /// Timeout of the calls to the IdP, in milliseconds (default: 5000)
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+impl fmt::Debug for TokenExchangeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenExchangeConfig")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &REDACTED)
+            .field("audience", &self.audience)
+            .field("scope", &self.scope)
+            .field("timeout_ms", &self.timeout_ms)
+            .finish()
+    }
+}
+
+/// Token received from the IdP. The token is redacted from the `Debug` output.
+#[derive(Deserialize, Clone)]
+pub struct ExchangedToken {
+    pub access_token: String,
+    #[serde(default)]
+    pub issued_token_type: Option<String>,
+    #[serde(default)]
+    pub token_type: Option<String>,
+    /// Lifetime of the token, in seconds
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+impl fmt::Debug for ExchangedToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExchangedToken")
+            .field("access_token", &REDACTED)
+            .field("issued_token_type", &self.issued_token_type)
+            .field("token_type", &self.token_type)
+            .field("expires_in", &self.expires_in)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ExchangeRequest<'a> {
+    grant_type: &'static str,
+    subject_token: &'a str,
+    subject_token_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audience: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<&'a str>,
+}
+
+type CacheKey = (String, Option<String>, Option<String>);
+
+/// Performs OAuth2 token exchanges ([RFC 8693](https://www.rfc-editor.org/rfc/rfc8693)):
+/// the access token of an end user (subject token) is exchanged for a delegated token to call
+/// a downstream API on their behalf.
+///
+/// Exchanged tokens are cached by subject token, audience and scope until shortly before they
+/// expire. Tokens without `expires_in` are not cached.
+///
+/// ```ignore
+/// let exchanger = TokenExchanger::new(config.token_exchange.clone())?;
+/// let token = exchanger.exchange(user_token).await?;
+/// client.get(url).bearer_auth(token.access_token).send().await?;
+/// ```
+pub struct TokenExchanger {
+    client: reqwest::Client,
+    config: TokenExchangeConfig,
+    cache: Mutex<HashMap<CacheKey, (ExchangedToken, Instant)>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl TokenExchanger {
+    pub fn new(config: TokenExchangeConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+        Ok(Self {
+            client,
+            config,
+            cache: Mutex::new(HashMap::new()),
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Clock of the token cache (default: [`SystemClock`])
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Exchanges `subject_token` for a token with the configured audience and scope
+    pub async fn exchange(&self, subject_token: &str) -> anyhow::Result<ExchangedToken> {
+        self.exchange_for(
+            subject_token,
+            self.config.audience.as_deref(),
+            self.config.scope.as_deref(),
+        )
+        .await
+    }
+
+    /// Exchanges `subject_token` for a token with the given audience and scope
+    pub async fn exchange_for(
+        &self,
+        subject_token: &str,
+        audience: Option<&str>,
+        scope: Option<&str>,
+    ) -> anyhow::Result<ExchangedToken> {
+        let key = (
+            subject_token.to_string(),
+            audience.map(str::to_string),
+            scope.map(str::to_string),
+        );
+        if let Some((token, expires_at)) = self.cache.lock().unwrap().get(&key) {
+            if *expires_at > self.clock.monotonic() {
+                return Ok(token.clone());
+            }
+        }
+
+        let resp = self
+            .client
+            .post(&self.config.token_url)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&ExchangeRequest {
+                grant_type: GRANT_TYPE,
+                subject_token,
+                subject_token_type: ACCESS_TOKEN_TYPE,
+                audience,
+                scope,
+            })
+            .send()
+            .await
+            .context("Cannot call the token endpoint")?;
+        let status = resp.status();
+        let body = resp
+            .bytes()
+            .await
+            .context("Cannot read the token endpoint response")?;
+        if !status.is_success() {
+            match serde_json::from_slice::<ErrorResponse>(&body) {
+                Ok(err) => bail!(
+                    "Token exchange rejected ({status}): {} {}",
+                    err.error,
+                    err.error_description.unwrap_or_default()
+                ),
+                Err(_) => bail!("Token exchange failed ({status})"),
+            }
+        }
+        let token: ExchangedToken =
+            serde_json::from_slice(&body).context("Cannot parse the exchanged token")?;
+
+        if let Some(expires_in) = token.expires_in {
+            let lifetime = Duration::from_secs(expires_in).saturating_sub(EXPIRY_MARGIN);
+            let mut cache = self.cache.lock().unwrap();
+            if cache.len() >= MAX_CACHED {
+                let now = self.clock.monotonic();
+                cache.retain(|_, (_, expires_at)| *expires_at > now);
+                if cache.len() >= MAX_CACHED {
+                    cache.clear();
+                }
+            }
+            cache.insert(key, (token.clone(), self.clock.monotonic() + lifetime));
+        }
+        Ok(token)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use http::{Method, StatusCode};
+
+    use super::*;
+    use crate::testing::{FakeResponse, FakeUpstream};
+    use crate::time::MockClock;
+
+    #[tokio::test]
+    async fn caches_exchanged_tokens() {
+        let idp = FakeUpstream::start().await;
+        idp.on(
+            Method::POST,
+            "/token",
+            FakeResponse::json(r#"{"access_token":"delegated","expires_in":300}"#),
+        );
+        idp.on(
+            Method::POST,
+            "/rejected",
+            FakeResponse::new(StatusCode::BAD_REQUEST).body(r#"{"error":"invalid_grant"}"#),
+        );
+        let config = TokenExchangeConfig {
+            token_url: idp.url("/token"),
+            client_id: "gateway".to_string(),
+            client_secret: "secret".to_string(),
+            audience: Some("users-api".to_string()),
+            scope: None,
+            timeout_ms: 1000,
+        };
+        let clock = Arc::new(MockClock::default());
+        let exchanger = TokenExchanger::new(config.clone())
+            .unwrap()
+            .clock(clock.clone());
+
+        for _ in 0..2 {
+            let token = exchanger.exchange("user-token").await.unwrap();
+            assert_eq!(token.access_token, "delegated");
+        }
+        assert_eq!(idp.calls_to(&Method::POST, "/token"), 1);
+
+        // exchanged again shortly before the expiry
+        clock.advance(Duration::from_secs(269));
+        exchanger.exchange("user-token").await.unwrap();
+        assert_eq!(idp.calls_to(&Method::POST, "/token"), 1);
+        clock.advance(Duration::from_secs(1));
+        exchanger.exchange("user-token").await.unwrap();
+        assert_eq!(idp.calls_to(&Method::POST, "/token"), 2);
+
+        let rejecting = TokenExchanger::new(TokenExchangeConfig {
+            token_url: idp.url("/rejected"),
+            ..config
+        })
+        .unwrap();
+        let err = rejecting.exchange("user-token").await.unwrap_err();
+        assert!(err.to_string().contains("invalid_grant"));
+    }
+}
@@ -0,0 +1,561 @@
+//! Multi-step operations with compensation (sagas).
+//!
+//! Steps run in order; when one fails or times out, the completed steps are compensated in
+//! reverse order. Progress and context are saved in a [`SagaStore`] after each step, so a saga
+//! interrupted by a restart can be resumed, provided the store is durable: the default
+//! [`MemorySagaStore`] is not, services use a [`StateSagaStore`] over a SQLite
+//! [state store](crate::state) (`state-store` feature) or implement [`SagaStore`] on their
+//! database.
+//!
+//! Exports:
+//! - `saga_runs_total` counter labelled by `saga` and `outcome` (`completed`, `compensated` or
+//!   `failed`, the latter meaning a compensation failed)
+//! - `saga_step_duration_seconds` histogram labelled by `saga`, `step`, `phase` (`execute` or
+//!   `compensate`) and `outcome`
+//!
+//! ```ignore
+//! let saga = Saga::new("place_order")
+//!     .step(ReserveStock)
+//!     .step(ChargePayment)
+//!     .step(ConfirmOrder)
+//!     .step_timeout(Duration::from_secs(10))
+//!     .store(store.clone());
+//! let ctx = saga.run(&order_id, OrderContext::new(order)).await?;
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::metrics::{
+    get_or_create_counter_with_labels, get_or_create_histogram_with_labels,
+    DEFAULT_DURATION_BUCKETS,
+};
+
+/// One step of a saga, working on the saga context `C`
+#[async_trait::async_trait]
+pub trait SagaStep<C: Send>: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn execute(&self, ctx: &mut C) -> anyhow::Result<()>;
+
+    /// Undoes [`execute`](Self::execute), once a later step failed. Must be idempotent: it is
+    /// retried when a compensating saga is resumed.
+    async fn compensate(&self, _ctx: &mut C) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Timeout of the step, overriding the saga one
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SagaStatus {
+    Running,
+    Compensating,
+    Completed,
+    Compensated,
+    /// A compensation failed, the saga needs a manual intervention
+    Failed,
+}
+
+/// Persisted progress of a saga
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SagaState {
+    pub id: String,
+    pub saga: String,
+    pub status: SagaStatus,
+    /// Number of steps executed and not compensated
+    pub completed_steps: usize,
+    pub context: serde_json::Value,
+    /// Step that failed, and its error
+    pub failed_step: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Storage of the saga states
+#[async_trait::async_trait]
+pub trait SagaStore: Send + Sync {
+    async fn save(&self, state: &SagaState) -> anyhow::Result<()>;
+    async fn load(&self, saga: &str, id: &str) -> anyhow::Result<Option<SagaState>>;
+
+    /// Saves the state of a new saga, returning false if a saga with the same id exists. The
+    /// default implementation is not atomic: stores should insert the state only if absent
+    /// (eg. `INSERT ... ON CONFLICT DO NOTHING`).
+    async fn create(&self, state: &SagaState) -> anyhow::Result<bool> {
+        if self.load(&state.saga, &state.id).await?.is_some() {
+            return Ok(false);
+        }
+        self.save(state).await?;
+        Ok(true)
+    }
+}
+
+/// In memory [`SagaStore`], for tests and sagas that need not survive restarts: the sagas
+/// interrupted by a restart are lost
+#[derive(Default)]
+pub struct MemorySagaStore {
+    states: Mutex<HashMap<(String, String), SagaState>>,
+}
+
+impl MemorySagaStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl SagaStore for MemorySagaStore {
+    async fn save(&self, state: &SagaState) -> anyhow::Result<()> {
+        self.states
+            .lock()
+            .unwrap()
+            .insert((state.saga.clone(), state.id.clone()), state.clone());
+        Ok(())
+    }
+
+    async fn load(&self, saga: &str, id: &str) -> anyhow::Result<Option<SagaState>> {
+        let key = (saga.to_string(), id.to_string());
+        Ok(self.states.lock().unwrap().get(&key).cloned())
+    }
+
+    async fn create(&self, state: &SagaState) -> anyhow::Result<bool> {
+        let mut states = self.states.lock().unwrap();
+        let key = (state.saga.clone(), state.id.clone());
+        if states.contains_key(&key) {
+            return Ok(false);
+        }
+        states.insert(key, state.clone());
+        Ok(true)
+    }
+}
+
+/// [`SagaStore`] over a [`StateStore`](crate::state::StateStore), in its `saga:<saga>`
+/// namespaces
+#[cfg(feature = "state-store")]
+pub struct StateSagaStore {
+    store: Arc<dyn crate::state::StateStore>,
+}
+
+#[cfg(feature = "state-store")]
+impl StateSagaStore {
+    pub fn new(store: Arc<dyn crate::state::StateStore>) -> Self {
+        Self { store }
+    }
+}
+
+#[cfg(feature = "state-store")]
+#[async_trait::async_trait]
+impl SagaStore for StateSagaStore {
+    async fn save(&self, state: &SagaState) -> anyhow::Result<()> {
+        let namespace = format!("saga:{}", state.saga);
+        let value = serde_json::to_vec(state)?;
+        self.store.put(&namespace, &state.id, &value, None).await
+    }
+
+    async fn load(&self, saga: &str, id: &str) -> anyhow::Result<Option<SagaState>> {
+        let value = self.store.get(&format!("saga:{saga}"), id).await?;
+        Ok(value
+            .map(|value| serde_json::from_slice(&value))
+            .transpose()?)
+    }
+
+    async fn create(&self, state: &SagaState) -> anyhow::Result<bool> {
+        let namespace = format!("saga:{}", state.saga);
+        let value = serde_json::to_vec(state)?;
+        self.store.insert(&namespace, &state.id, &value, None).await
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SagaError {
+    /// A step failed, the previous steps were compensated
+    #[error("saga step {step} failed, saga compensated: {source:#}")]
+    Compensated { step: String, source: anyhow::Error },
+    /// A compensation failed, the saga is left partially applied
+    #[error("compensation of saga step {step} failed: {source:#}")]
+    CompensationFailed { step: String, source: anyhow::Error },
+    #[error("saga {0} not found")]
+    NotFound(String),
+    /// A saga with the same id was already run, see [`Saga::resume`]
+    #[error("saga {0} already exists")]
+    AlreadyExists(String),
+    #[error("saga store error: {0:#}")]
+    Store(anyhow::Error),
+}
+
+/// A multi-step operation, see the [module documentation](self)
+pub struct Saga<C> {
+    name: String,
+    steps: Vec<Box<dyn SagaStep<C>>>,
+    step_timeout: Option<Duration>,
+    store: Arc<dyn SagaStore>,
+}
+
+impl<C> Saga<C>
+where
+    C: Serialize + DeserializeOwned + Send,
+{
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            steps: vec![],
+            step_timeout: None,
+            store: Arc::new(MemorySagaStore::new()),
+        }
+    }
+
+    pub fn step(mut self, step: impl SagaStep<C> + 'static) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    /// Timeout of the steps (default: none)
+    pub fn step_timeout(mut self, timeout: Duration) -> Self {
+        self.step_timeout = Some(timeout);
+        self
+    }
+
+    /// Store of the saga progress (default: [`MemorySagaStore`])
+    pub fn store(mut self, store: Arc<dyn SagaStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Runs the saga `id` from its first step. Fails if the saga `id` was already run.
+    pub async fn run(&self, id: &str, ctx: C) -> Result<C, SagaError> {
+        let state = SagaState {
+            id: id.to_string(),
+            saga: self.name.clone(),
+            status: SagaStatus::Running,
+            completed_steps: 0,
+            context: serde_json::to_value(&ctx).map_err(|err| SagaError::Store(err.into()))?,
+            failed_step: None,
+            error: None,
+        };
+        if !self.store.create(&state).await.map_err(SagaError::Store)? {
+            return Err(SagaError::AlreadyExists(id.to_string()));
+        }
+        self.drive(state, ctx).await
+    }
+
+    /// Resumes the saga `id` where it stopped, from the store. The compensation of a failed
+    /// saga is retried, a completed saga returns its context.
+    pub async fn resume(&self, id: &str) -> Result<C, SagaError> {
+        let mut state = self
+            .store
+            .load(&self.name, id)
+            .await
+            .map_err(SagaError::Store)?
+            .ok_or_else(|| SagaError::NotFound(id.to_string()))?;
+        if state.status == SagaStatus::Failed {
+            state.status = SagaStatus::Compensating;
+        }
+        let ctx = serde_json::from_value(state.context.clone())
+            .map_err(|err| SagaError::Store(err.into()))?;
+        match state.status {
+            SagaStatus::Completed => Ok(ctx),
+            SagaStatus::Compensated => Err(self.compensated_error(&state)),
+            _ => self.drive(state, ctx).await,
+        }
+    }
+
+    async fn drive(&self, mut state: SagaState, mut ctx: C) -> Result<C, SagaError> {
+        while state.status == SagaStatus::Running && state.completed_steps < self.steps.len() {
+            let step = &self.steps[state.completed_steps];
+            match self
+                .timed(step.as_ref(), "execute", step.execute(&mut ctx))
+                .await
+            {
+                Ok(()) => state.completed_steps += 1,
+                Err(err) => {
+                    log::warn!(
+                        "Saga {} {}: step {} failed, compensating: {err:#}",
+                        self.name,
+                        state.id,
+                        step.name()
+                    );
+                    state.status = SagaStatus::Compensating;
+                    state.failed_step = Some(step.name().to_string());
+                    state.error = Some(format!("{err:#}"));
+                }
+            }
+            // the saga could not be resumed from the store: the steps are compensated
+            if let Err(err) = self.save(&mut state, &ctx).await {
+                log::warn!(
+                    "Saga {} {}: cannot save step {}, compensating: {err:#}",
+                    self.name,
+                    state.id,
+                    step.name()
+                );
+                state.status = SagaStatus::Compensating;
+                state.failed_step = Some(step.name().to_string());
+                state.error = Some(format!("{err:#}"));
+            }
+        }
+        if state.status == SagaStatus::Running {
+            state.status = SagaStatus::Completed;
+            self.save(&mut state, &ctx).await?;
+        }
+
+        while state.status == SagaStatus::Compensating && state.completed_steps > 0 {
+            let step = &self.steps[state.completed_steps - 1];
+            match self
+                .timed(step.as_ref(), "compensate", step.compensate(&mut ctx))
+                .await
+            {
+                Ok(()) => state.completed_steps -= 1,
+                Err(err) => {
+                    log::error!(
+                        "Saga {} {}: compensation of step {} failed: {err:#}",
+                        self.name,
+                        state.id,
+                        step.name()
+                    );
+                    state.status = SagaStatus::Failed;
+                    self.save_compensation(&mut state, &ctx).await;
+                    self.record_outcome("failed");
+                    return Err(SagaError::CompensationFailed {
+                        step: step.name().to_string(),
+                        source: err,
+                    });
+                }
+            }
+            self.save_compensation(&mut state, &ctx).await;
+        }
+
+        match state.status {
+            SagaStatus::Completed => {
+                self.record_outcome("completed");
+                Ok(ctx)
+            }
+            SagaStatus::Compensating => {
+                state.status = SagaStatus::Compensated;
+                self.save_compensation(&mut state, &ctx).await;
+                self.record_outcome("compensated");
+                Err(self.compensated_error(&state))
+            }
+            SagaStatus::Compensated => Err(self.compensated_error(&state)),
+            SagaStatus::Running | SagaStatus::Failed => {
+                unreachable!("running and failed sagas are handled above")
+            }
+        }
+    }
+
+    /// Runs a step phase with its timeout, recording its duration
+    async fn timed<F>(&self, step: &dyn SagaStep<C>, phase: &str, fut: F) -> anyhow::Result<()>
+    where
+        F: std::future::Future<Output = anyhow::Result<()>>,
+    {
+        let start = Instant::now();
+        let result = match step.timeout().or(self.step_timeout) {
+            Some(timeout) => match tokio::time::timeout(timeout, fut).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::anyhow!("timed out after {timeout:?}")),
+            },
+            None => fut.await,
+        };
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        get_or_create_histogram_with_labels(
+            "saga_step_duration_seconds",
+            "Saga steps duration",
+            &["saga", "step", "phase", "outcome"],
+            DEFAULT_DURATION_BUCKETS.to_vec(),
+        )
+        .with_label_values(&[&self.name, step.name(), phase, outcome])
+        .observe(start.elapsed().as_secs_f64());
+        result
+    }
+
+    async fn save(&self, state: &mut SagaState, ctx: &C) -> Result<(), SagaError> {
+        state.context = serde_json::to_value(ctx).map_err(|err| SagaError::Store(err.into()))?;
+        self.store.save(state).await.map_err(SagaError::Store)
+    }
+
+    /// Saves the progress of the compensation, which goes on if the store fails
+    async fn save_compensation(&self, state: &mut SagaState, ctx: &C) {
+        if let Err(err) = self.save(state, ctx).await {
+            log::error!(
+                "Saga {} {}: cannot save the compensation progress: {err:#}",
+                self.name,
+                state.id
+            );
+        }
+    }
+
+    fn record_outcome(&self, outcome: &str) {
+        get_or_create_counter_with_labels("saga_runs_total", "Saga runs", &["saga", "outcome"])
+            .with_label_values(&[&self.name, outcome])
+            .inc();
+    }
+
+    fn compensated_error(&self, state: &SagaState) -> SagaError {
+        SagaError::Compensated {
+            step: state.failed_step.clone().unwrap_or_default(),
+            source: anyhow::anyhow!(state.error.clone().unwrap_or_default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Default, Debug)]
+    struct Ctx {
+        log: Vec<String>,
+    }
+
+    struct Step(&'static str, bool);
+
+    #[async_trait::async_trait]
+    impl SagaStep<Ctx> for Step {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        async fn execute(&self, ctx: &mut Ctx) -> anyhow::Result<()> {
+            if !self.1 {
+                anyhow::bail!("unavailable");
+            }
+            ctx.log.push(format!("do {}", self.0));
+            Ok(())
+        }
+
+        async fn compensate(&self, ctx: &mut Ctx) -> anyhow::Result<()> {
+            ctx.log.push(format!("undo {}", self.0));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn compensates_completed_steps() {
+        let store = Arc::new(MemorySagaStore::new());
+        let saga = Saga::new("order")
+            .step(Step("stock", true))
+            .step(Step("payment", true))
+            .step(Step("shipping", false))
+            .store(store.clone());
+
+        let err = saga.run("42", Ctx::default()).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "saga step shipping failed, saga compensated: unavailable"
+        );
+        let state = store.load("order", "42").await.unwrap().unwrap();
+        assert_eq!(state.status, SagaStatus::Compensated);
+        assert_eq!(state.completed_steps, 0);
+        assert_eq!(
+            state.context["log"],
+            serde_json::json!(["do stock", "do payment", "undo payment", "undo stock"])
+        );
+
+        let saga = Saga::new("order").step(Step("stock", true));
+        let ctx = saga.run("43", Ctx::default()).await.unwrap();
+        assert_eq!(ctx.log, ["do stock"]);
+    }
+
+    #[tokio::test]
+    async fn runs_and_resumes_once() {
+        let completed = || {
+            get_or_create_counter_with_labels("saga_runs_total", "Saga runs", &["saga", "outcome"])
+                .with_label_values(&["refund", "completed"])
+                .get()
+        };
+        let saga = Saga::new("refund").step(Step("payment", true));
+        saga.run("42", Ctx::default()).await.unwrap();
+        assert_eq!(completed(), 1);
+
+        let err = saga.run("42", Ctx::default()).await.unwrap_err();
+        assert!(matches!(err, SagaError::AlreadyExists(_)));
+        let ctx = saga.resume("42").await.unwrap();
+        assert_eq!(ctx.log, ["do payment"]);
+        assert_eq!(completed(), 1);
+    }
+
+    /// Memory store failing its `fail_on`-th save
+    struct FlakyStore {
+        inner: MemorySagaStore,
+        saves: Mutex<usize>,
+        fail_on: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl SagaStore for FlakyStore {
+        async fn save(&self, state: &SagaState) -> anyhow::Result<()> {
+            let saves = {
+                let mut saves = self.saves.lock().unwrap();
+                *saves += 1;
+                *saves
+            };
+            if saves == self.fail_on {
+                anyhow::bail!("connection reset");
+            }
+            self.inner.save(state).await
+        }
+
+        async fn load(&self, saga: &str, id: &str) -> anyhow::Result<Option<SagaState>> {
+            self.inner.load(saga, id).await
+        }
+    }
+
+    #[tokio::test]
+    async fn compensates_when_the_store_fails() {
+        let store = Arc::new(FlakyStore {
+            inner: MemorySagaStore::new(),
+            saves: Mutex::new(0),
+            fail_on: 3,
+        });
+        let saga = Saga::new("shipment")
+            .step(Step("stock", true))
+            .step(Step("payment", true))
+            .step(Step("shipping", true))
+            .store(store.clone());
+
+        let err = saga.run("42", Ctx::default()).await.unwrap_err();
+        assert!(matches!(err, SagaError::Compensated { .. }));
+        let state = store.load("shipment", "42").await.unwrap().unwrap();
+        assert_eq!(state.status, SagaStatus::Compensated);
+        assert_eq!(state.failed_step.as_deref(), Some("payment"));
+        assert_eq!(
+            state.context["log"],
+            serde_json::json!(["do stock", "do payment", "undo payment", "undo stock"])
+        );
+    }
+
+    #[cfg(feature = "state-store")]
+    #[tokio::test]
+    async fn resumes_from_the_state_store() {
+        use crate::state::StateStoreConfig;
+
+        let path = std::env::temp_dir().join(format!("saga-{}.db", std::process::id()));
+        let config = StateStoreConfig::Sqlite {
+            path: path.to_string_lossy().into_owned(),
+        };
+        let store = Arc::new(StateSagaStore::new(config.open().await.unwrap()));
+        let saga = Saga::new("invoice")
+            .step(Step("pdf", true))
+            .step(Step("mail", true))
+            .store(store);
+        saga.run("42", Ctx::default()).await.unwrap();
+
+        // after a restart
+        let store = Arc::new(StateSagaStore::new(config.open().await.unwrap()));
+        let state = store.load("invoice", "42").await.unwrap().unwrap();
+        assert_eq!(state.status, SagaStatus::Completed);
+        let saga = Saga::new("invoice").store(store);
+        let err = saga.run("42", Ctx::default()).await.unwrap_err();
+        assert!(matches!(err, SagaError::AlreadyExists(_)));
+        assert_eq!(saga.resume("42").await.unwrap().log, ["do pdf", "do mail"]);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.to_string_lossy()));
+        }
+    }
+}
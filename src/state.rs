@@ -0,0 +1,406 @@
+//! Persistent state for the single-replica services which cannot run Redis: jobs, idempotency
+//! keys, rate-limit buckets, [sagas](crate::saga)...
+//!
+//! The store is selected in the configuration, in memory (the default) or in a SQLite file:
+//!
+//! ```yaml
+//! state_store:
+//!   type: sqlite
+//!   path: /var/lib/orders-api/state.db
+//! ```
+//!
+//! ```ignore
+//! let store = config.state_store.open().await?;
+//! if !store.insert("idempotency", &key, b"", Some(Duration::from_secs(86_400))).await? {
+//!     return Err(Problem::new(ErrorCode::Conflict));
+//! }
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode},
+    Row, SqlitePool,
+};
+
+/// Key-value store of the service state. The keys are grouped by namespace (eg. `jobs`), and
+/// the values expire after their TTL, if any.
+#[async_trait::async_trait]
+pub trait StateStore: Send + Sync {
+    /// Value of `key`, unless absent or expired
+    async fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    async fn put(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<()>;
+
+    /// Sets the value of `key` only if it is absent or expired, returning whether it was set,
+    /// eg. to claim an idempotency key once
+    async fn insert(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<bool>;
+
+    /// Replaces the value of `key` only if it is still `current` (absent or expired for
+    /// `None`), returning whether it was replaced, eg. to update a rate-limit bucket without
+    /// losing the concurrent updates
+    async fn compare_and_swap(
+        &self,
+        namespace: &str,
+        key: &str,
+        current: Option<&[u8]>,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<bool>;
+
+    async fn delete(&self, namespace: &str, key: &str) -> anyhow::Result<()>;
+
+    /// Keys and values of a namespace, ordered by key, eg. the pending jobs
+    async fn list(&self, namespace: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>>;
+}
+
+/// Store of the state, see the [module documentation](self)
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StateStoreConfig {
+    /// In memory: the state is lost on restart
+    #[default]
+    Memory,
+    /// In a SQLite file, created if missing
+    Sqlite { path: String },
+}
+
+impl StateStoreConfig {
+    pub async fn open(&self) -> anyhow::Result<Arc<dyn StateStore>> {
+        Ok(match self {
+            StateStoreConfig::Memory => Arc::new(MemoryStateStore::new()),
+            StateStoreConfig::Sqlite { path } => Arc::new(SqliteStateStore::open(path).await?),
+        })
+    }
+}
+
+/// Expiry time of a value stored now with `ttl`, in milliseconds since the epoch
+fn expires_at(ttl: Option<Duration>) -> Option<i64> {
+    ttl.map(|ttl| now_millis().saturating_add(ttl.as_millis() as i64))
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+type Entry = (Vec<u8>, Option<i64>);
+
+/// In memory [`StateStore`], for tests and the state that need not survive restarts
+#[derive(Default)]
+pub struct MemoryStateStore {
+    entries: Mutex<HashMap<(String, String), Entry>>,
+}
+
+impl MemoryStateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Live value of `key`, removing it if expired
+    fn live<'a>(
+        entries: &'a mut HashMap<(String, String), Entry>,
+        namespace: &str,
+        key: &str,
+    ) -> Option<&'a mut Entry> {
+        let key = (namespace.to_string(), key.to_string());
+        if let Some((_, Some(expires_at))) = entries.get(&key) {
+            if *expires_at <= now_millis() {
+                entries.remove(&key);
+            }
+        }
+        entries.get_mut(&key)
+    }
+}
+
+#[async_trait::async_trait]
+impl StateStore for MemoryStateStore {
+    async fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut entries = self.entries.lock().unwrap();
+        Ok(Self::live(&mut entries, namespace, key).map(|(value, _)| value.clone()))
+    }
+
+    async fn put(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<()> {
+        self.entries.lock().unwrap().insert(
+            (namespace.to_string(), key.to_string()),
+            (value.to_vec(), expires_at(ttl)),
+        );
+        Ok(())
+    }
+
+    async fn insert(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<bool> {
+        self.compare_and_swap(namespace, key, None, value, ttl)
+            .await
+    }
+
+    async fn compare_and_swap(
+        &self,
+        namespace: &str,
+        key: &str,
+        current: Option<&[u8]>,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<bool> {
+        let mut entries = self.entries.lock().unwrap();
+        let live = Self::live(&mut entries, namespace, key).map(|(value, _)| value.as_slice());
+        if live != current {
+            return Ok(false);
+        }
+        entries.insert(
+            (namespace.to_string(), key.to_string()),
+            (value.to_vec(), expires_at(ttl)),
+        );
+        Ok(true)
+    }
+
+    async fn delete(&self, namespace: &str, key: &str) -> anyhow::Result<()> {
+        let key = (namespace.to_string(), key.to_string());
+        self.entries.lock().unwrap().remove(&key);
+        Ok(())
+    }
+
+    async fn list(&self, namespace: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let now = now_millis();
+        let entries = self.entries.lock().unwrap();
+        let mut listed: Vec<_> = entries
+            .iter()
+            .filter(|((ns, _), (_, expires_at))| {
+                ns == namespace && expires_at.is_none_or(|expires_at| expires_at > now)
+            })
+            .map(|((_, key), (value, _))| (key.clone(), value.clone()))
+            .collect();
+        listed.sort();
+        Ok(listed)
+    }
+}
+
+/// [`StateStore`] in a SQLite file, in the `state` table
+#[derive(Clone)]
+pub struct SqliteStateStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStateStore {
+    /// Opens the SQLite file at `path`, creating it and the `state` table if missing
+    pub async fn open(path: &str) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        let pool = SqlitePool::connect_with(options)
+            .await
+            .with_context(|| format!("Cannot open state store {path}"))?;
+        sqlx::raw_sql(
+            r#"CREATE TABLE IF NOT EXISTS state (
+                namespace TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value BLOB NOT NULL,
+                expires_at INTEGER,
+                PRIMARY KEY (namespace, key)
+            );"#,
+        )
+        .execute(&pool)
+        .await
+        .with_context(|| format!("Cannot create the state table in {path}"))?;
+        Ok(Self { pool })
+    }
+
+    /// Deletes the expired values, which are otherwise only replaced
+    pub async fn purge_expired(&self) -> anyhow::Result<u64> {
+        let result = sqlx::query("DELETE FROM state WHERE expires_at <= ?")
+            .bind(now_millis())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait::async_trait]
+impl StateStore for SqliteStateStore {
+    async fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(sqlx::query_scalar(
+            "SELECT value FROM state WHERE namespace = ? AND key = ? \
+             AND (expires_at IS NULL OR expires_at > ?)",
+        )
+        .bind(namespace)
+        .bind(key)
+        .bind(now_millis())
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn put(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO state (namespace, key, value, expires_at) VALUES (?, ?, ?, ?) \
+             ON CONFLICT (namespace, key) DO UPDATE \
+             SET value = excluded.value, expires_at = excluded.expires_at",
+        )
+        .bind(namespace)
+        .bind(key)
+        .bind(value)
+        .bind(expires_at(ttl))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<bool> {
+        // an expired value is replaced
+        let result = sqlx::query(
+            "INSERT INTO state (namespace, key, value, expires_at) VALUES (?, ?, ?, ?) \
+             ON CONFLICT (namespace, key) DO UPDATE \
+             SET value = excluded.value, expires_at = excluded.expires_at \
+             WHERE state.expires_at <= ?",
+        )
+        .bind(namespace)
+        .bind(key)
+        .bind(value)
+        .bind(expires_at(ttl))
+        .bind(now_millis())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn compare_and_swap(
+        &self,
+        namespace: &str,
+        key: &str,
+        current: Option<&[u8]>,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<bool> {
+        let Some(current) = current else {
+            return self.insert(namespace, key, value, ttl).await;
+        };
+        let result = sqlx::query(
+            "UPDATE state SET value = ?, expires_at = ? \
+             WHERE namespace = ? AND key = ? AND value = ? \
+             AND (expires_at IS NULL OR expires_at > ?)",
+        )
+        .bind(value)
+        .bind(expires_at(ttl))
+        .bind(namespace)
+        .bind(key)
+        .bind(current)
+        .bind(now_millis())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn delete(&self, namespace: &str, key: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM state WHERE namespace = ? AND key = ?")
+            .bind(namespace)
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn list(&self, namespace: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let rows = sqlx::query(
+            "SELECT key, value FROM state WHERE namespace = ? \
+             AND (expires_at IS NULL OR expires_at > ?) ORDER BY key",
+        )
+        .bind(namespace)
+        .bind(now_millis())
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get("key")?, row.try_get("value")?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn stores_the_state() {
+    let path = std::env::temp_dir().join(format!("state-store-{}.db", std::process::id()));
+    let sqlite: StateStoreConfig = serde_yaml::from_str(&format!(
+        "{{type: sqlite, path: {}}}",
+        path.to_string_lossy()
+    ))
+    .unwrap();
+    for config in [StateStoreConfig::Memory, sqlite.clone()] {
+        let store = config.open().await.unwrap();
+        assert!(store.insert("idempotency", "k1", b"", None).await.unwrap());
+        assert!(!store.insert("idempotency", "k1", b"", None).await.unwrap());
+
+        let short = Some(Duration::from_millis(20));
+        assert!(store.insert("idempotency", "k2", b"", short).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(store.get("idempotency", "k2").await.unwrap(), None);
+        assert!(store.insert("idempotency", "k2", b"", None).await.unwrap());
+
+        store.put("buckets", "client-1", b"10", None).await.unwrap();
+        let swap = |current: &'static [u8], value: &'static [u8]| {
+            store.compare_and_swap("buckets", "client-1", Some(current), value, None)
+        };
+        assert!(swap(b"10", b"9").await.unwrap());
+        assert!(!swap(b"10", b"9").await.unwrap());
+        assert_eq!(
+            store.get("buckets", "client-1").await.unwrap().as_deref(),
+            Some(&b"9"[..])
+        );
+
+        store.put("jobs", "b", b"2", None).await.unwrap();
+        store.put("jobs", "a", b"1", None).await.unwrap();
+        store.delete("jobs", "b").await.unwrap();
+        assert_eq!(
+            store.list("jobs").await.unwrap(),
+            [("a".to_string(), b"1".to_vec())]
+        );
+    }
+
+    // the state survives the store
+    let store = sqlite.open().await.unwrap();
+    assert!(!store.insert("idempotency", "k1", b"", None).await.unwrap());
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.to_string_lossy()));
+    }
+}
@@ -0,0 +1,40 @@
+//! Tasks spawned in the current span, keeping the correlation fields of the request
+
+use std::future::Future;
+
+use tokio::task::JoinHandle;
+use tracing::{Instrument, Span};
+
+/// Spawns `future` on the tokio runtime in the current span, so its events have the fields of
+/// the request spawning it (`tx`, `path`...), even once the request is over.
+pub fn spawn_instrumented<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(future.instrument(Span::current()))
+}
+
+/// Same as [`spawn_instrumented`] for blocking code, run with
+/// [`spawn_blocking`](tokio::task::spawn_blocking)
+pub fn spawn_blocking_instrumented<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(f))
+}
+
+#[cfg(all(test, feature = "testing"))]
+#[tokio::test]
+async fn keeps_the_current_span() {
+    let capture = crate::testing::TracingCapture::new();
+    let _guard = capture.set_default();
+    let span = tracing::info_span!("request", tx = "AYzbC2yXcNKhrA5Zr3NHfQ");
+    let task = span.in_scope(|| spawn_instrumented(async { tracing::info!("sending email") }));
+    task.await.unwrap();
+
+    let event = capture.events().remove(0);
+    assert_eq!(event.span_field("tx"), Some("AYzbC2yXcNKhrA5Zr3NHfQ"));
+}
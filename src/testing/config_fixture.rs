@@ -0,0 +1,151 @@
+use std::{
+    ffi::OsString,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+/// Serializes the tests touching process environment variables
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+static FIXTURE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary config files and environment variables for tests exercising `load_config`.
+///
+/// While a fixture is alive, it holds a process wide lock so tests using fixtures do not
+/// trample each other's environment variables. On drop, files are deleted and environment
+/// variables are restored to their previous values.
+///
+/// ```ignore
+/// let fixture = ConfigFixture::new()
+///     .file("config.yaml", "port: 8080")
+///     .env("PORT", "9090");
+/// let path = fixture.path("config.yaml");
+/// let config: Config = load_config(LoadConfigMode::FileOnly(path.to_str()), &SERVICE)?;
+/// ```
+///
+/// Only one fixture can be alive at a time: creating a second one in the same test
+/// deadlocks.
+pub struct ConfigFixture {
+    dir: PathBuf,
+    saved_env: Vec<(String, Option<OsString>)>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl ConfigFixture {
+    pub fn new() -> Self {
+        // a test panicking while holding the lock must not fail the others
+        let lock = ENV_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = std::env::temp_dir().join(format!(
+            "service-helpers-fixture-{}-{}",
+            std::process::id(),
+            FIXTURE_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).expect("Cannot create fixture directory");
+        Self {
+            dir,
+            saved_env: vec![],
+            _lock: lock,
+        }
+    }
+
+    /// Writes a file in the fixture directory. `name` may contain sub directories.
+    pub fn file(self, name: &str, contents: &str) -> Self {
+        let path = self.path(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("Cannot create fixture directory");
+        }
+        fs::write(&path, contents).expect("Cannot write fixture file");
+        self
+    }
+
+    /// Sets an environment variable until the fixture is dropped
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.save_env(key);
+        std::env::set_var(key, value);
+        self
+    }
+
+    /// Removes an environment variable until the fixture is dropped
+    pub fn remove_env(mut self, key: &str) -> Self {
+        self.save_env(key);
+        std::env::remove_var(key);
+        self
+    }
+
+    /// Absolute path of a file in the fixture directory
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// The fixture directory
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+
+    fn save_env(&mut self, key: &str) {
+        if !self.saved_env.iter().any(|(saved, _)| saved == key) {
+            self.saved_env
+                .push((key.to_string(), std::env::var_os(key)));
+        }
+    }
+}
+
+impl Default for ConfigFixture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for ConfigFixture {
+    fn drop(&mut self) {
+        for (key, value) in self.saved_env.drain(..) {
+            match value {
+                Some(value) => std::env::set_var(key, value),
+                None => std::env::remove_var(key),
+            }
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{
+        config::{load_config, LoadConfigMode},
+        ServiceDef,
+    };
+
+    const SERVICE: ServiceDef = ServiceDef::new("fixture-test", "0.0.0", "0000000");
+
+    #[derive(Deserialize)]
+    struct Config {
+        fixture_port: u16,
+    }
+
+    #[test]
+    fn loads_config_from_file_and_env() {
+        let fixture = ConfigFixture::new()
+            .file("config.yaml", "fixture_port: 8080")
+            .env("FIXTURE_PORT", "9090");
+        let path = fixture.path("config.yaml");
+
+        let config: Config =
+            load_config(LoadConfigMode::FileOnly(path.to_str()), &SERVICE).unwrap();
+        assert_eq!(config.fixture_port, 8080);
+        let config: Config = load_config(LoadConfigMode::EnvOnly, &SERVICE).unwrap();
+        assert_eq!(config.fixture_port, 9090);
+
+        drop(fixture);
+        assert!(std::env::var("FIXTURE_PORT").is_err());
+        assert!(!path.exists());
+    }
+}
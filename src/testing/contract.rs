@@ -0,0 +1,260 @@
+use std::fmt;
+
+use axum::{body::Body, extract::Request, http::HeaderName, response::Response, Router};
+use http::{header, Method, StatusCode};
+use tower::ServiceExt;
+
+use crate::problem::PROBLEM_JSON;
+
+/// Standard assertions every service of the platform should satisfy:
+/// - the health endpoint responds with a success status
+/// - metrics are exposed in the prometheus text format
+/// - unknown routes respond `404` with a `application/problem+json` body
+/// - wrong methods on known paths respond `405` with a problem body and an `Allow` header
+/// - the [configured](ContractSuite::security_headers) security headers are present
+/// - `OPTIONS` requests are handled
+///
+/// ```ignore
+/// #[tokio::test]
+/// async fn platform_contract() {
+///     ContractSuite::new().run(app()).await.assert_ok();
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ContractSuite {
+    health_path: String,
+    metrics_path: Option<String>,
+    unknown_path: String,
+    method_not_allowed: Option<(Method, String)>,
+    security_headers: Vec<HeaderName>,
+    options: bool,
+}
+
+impl ContractSuite {
+    pub fn new() -> Self {
+        Self {
+            health_path: "/health/live".to_string(),
+            metrics_path: Some("/metrics".to_string()),
+            unknown_path: "/__contract__/unknown-route".to_string(),
+            method_not_allowed: Some((Method::DELETE, "/health/live".to_string())),
+            security_headers: vec![],
+            options: true,
+        }
+    }
+
+    /// Path of the health endpoint (default: `/health/live`)
+    pub fn health_path(mut self, path: impl Into<String>) -> Self {
+        self.health_path = path.into();
+        self
+    }
+
+    /// Path of the metrics endpoint (default: `/metrics`), `None` to skip the check
+    pub fn metrics_path(mut self, path: Option<String>) -> Self {
+        self.metrics_path = path;
+        self
+    }
+
+    /// Request expected to be answered with `405` (default: `DELETE /health/live`), `None` to
+    /// skip the check
+    pub fn method_not_allowed(mut self, request: Option<(Method, String)>) -> Self {
+        self.method_not_allowed = request;
+        self
+    }
+
+    /// Headers expected on responses, like `X-Content-Type-Options` when the service sets it
+    /// (default: none)
+    pub fn security_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        self.security_headers = headers;
+        self
+    }
+
+    /// Check `OPTIONS` requests are handled (default: true)
+    pub fn options(mut self, options: bool) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Runs every check against the router and reports all failures
+    pub async fn run(&self, router: Router) -> ContractReport {
+        let mut report = ContractReport::default();
+
+        let health = call(&router, Method::GET, &self.health_path).await;
+        if !health.status().is_success() {
+            report.fail(
+                "health",
+                format!("GET {} responded {}", self.health_path, health.status()),
+            );
+        }
+        for name in &self.security_headers {
+            if !health.headers().contains_key(name) {
+                report.fail(
+                    "security_headers",
+                    format!("GET {} has no {} header", self.health_path, name),
+                );
+            }
+        }
+
+        if let Some(metrics_path) = &self.metrics_path {
+            let metrics = call(&router, Method::GET, metrics_path).await;
+            if metrics.status() != StatusCode::OK {
+                report.fail(
+                    "metrics",
+                    format!("GET {} responded {}", metrics_path, metrics.status()),
+                );
+            } else if !content_type(&metrics).starts_with("text/plain") {
+                report.fail(
+                    "metrics",
+                    format!(
+                        "GET {} content type is {:?}, expected text/plain",
+                        metrics_path,
+                        content_type(&metrics)
+                    ),
+                );
+            }
+        }
+
+        let not_found = call(&router, Method::GET, &self.unknown_path).await;
+        if not_found.status() != StatusCode::NOT_FOUND {
+            report.fail(
+                "not_found",
+                format!("GET {} responded {}", self.unknown_path, not_found.status()),
+            );
+        } else if content_type(&not_found) != PROBLEM_JSON {
+            report.fail(
+                "not_found",
+                format!(
+                    "404 content type is {:?}, expected {}",
+                    content_type(&not_found),
+                    PROBLEM_JSON
+                ),
+            );
+        }
+
+        if let Some((method, path)) = &self.method_not_allowed {
+            let resp = call(&router, method.clone(), path).await;
+            if resp.status() != StatusCode::METHOD_NOT_ALLOWED {
+                report.fail(
+                    "method_not_allowed",
+                    format!("{} {} responded {}", method, path, resp.status()),
+                );
+            } else {
+                if content_type(&resp) != PROBLEM_JSON {
+                    report.fail(
+                        "method_not_allowed",
+                        format!(
+                            "405 content type is {:?}, expected {}",
+                            content_type(&resp),
+                            PROBLEM_JSON
+                        ),
+                    );
+                }
+                if !resp.headers().contains_key(header::ALLOW) {
+                    report.fail("method_not_allowed", "405 has no Allow header".to_string());
+                }
+            }
+        }
+
+        if self.options {
+            let resp = call(&router, Method::OPTIONS, "/").await;
+            if !resp.status().is_success() {
+                report.fail("options", format!("OPTIONS / responded {}", resp.status()));
+            } else if !resp.headers().contains_key(header::ALLOW) {
+                report.fail("options", "OPTIONS / has no Allow header".to_string());
+            }
+        }
+
+        report
+    }
+}
+
+impl Default for ContractSuite {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs the default [`ContractSuite`] and panics if any check failed.
+pub async fn assert_contract(router: Router) {
+    ContractSuite::new().run(router).await.assert_ok();
+}
+
+/// Outcome of a [`ContractSuite`] run
+#[derive(Clone, Debug, Default)]
+pub struct ContractReport {
+    pub failures: Vec<ContractFailure>,
+}
+
+/// A failed check
+#[derive(Clone, Debug)]
+pub struct ContractFailure {
+    pub check: &'static str,
+    pub message: String,
+}
+
+impl ContractReport {
+    fn fail(&mut self, check: &'static str, message: String) {
+        self.failures.push(ContractFailure { check, message });
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Panics with every failure if any check failed
+    #[track_caller]
+    pub fn assert_ok(&self) {
+        if !self.is_ok() {
+            panic!("Service does not meet the platform contract:\n{self}");
+        }
+    }
+}
+
+impl fmt::Display for ContractReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for failure in &self.failures {
+            writeln!(f, "- [{}] {}", failure.check, failure.message)?;
+        }
+        Ok(())
+    }
+}
+
+async fn call(router: &Router, method: Method, path: &str) -> Response {
+    let req = Request::builder()
+        .method(method)
+        .uri(path)
+        .body(Body::empty())
+        .expect("valid request");
+    match router.clone().oneshot(req).await {
+        Ok(resp) => resp,
+        Err(infallible) => match infallible {},
+    }
+}
+
+fn content_type(resp: &Response) -> &str {
+    resp.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn checks_the_service_stack() {
+    use axum::{middleware::from_fn, routing::get};
+
+    let router = crate::axum::fallback_handlers(
+        Router::new()
+            .route("/", get(|| async { "orders" }))
+            .route("/health/live", get(|| async { "up" })),
+    )
+    .layer(from_fn(crate::axum::options_middleware));
+    let suite = ContractSuite::new().metrics_path(None);
+    suite.run(router.clone()).await.assert_ok();
+
+    let report = suite
+        .security_headers(vec![header::X_CONTENT_TYPE_OPTIONS])
+        .run(router)
+        .await;
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].check, "security_headers");
+}
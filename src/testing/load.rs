@@ -0,0 +1,158 @@
+use std::{
+    fmt,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use axum::{body::Body, extract::Request, Router};
+use tokio::time::MissedTickBehavior;
+use tower::ServiceExt;
+
+/// Sends requests at a constant rate and reports latency percentiles, for performance smoke
+/// tests of middlewares.
+///
+/// ```ignore
+/// #[tokio::test(flavor = "multi_thread")]
+/// async fn middlewares_are_fast() {
+///     let report = LoadTest::new(500, Duration::from_secs(5))
+///         .run_router(app(), || Request::get("/users/42").body(Body::empty()).unwrap())
+///         .await;
+///     println!("{report}");
+///     assert!(report.p99 < Duration::from_millis(20));
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct LoadTest {
+    rps: u32,
+    duration: Duration,
+}
+
+/// Outcome of a [`LoadTest`]
+#[derive(Clone, Debug, Default)]
+pub struct LoadReport {
+    pub requests: usize,
+    /// Calls that failed or responded with a 5xx status
+    pub errors: usize,
+    pub achieved_rps: f64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LoadTest {
+    /// `rps` requests per second during `duration`
+    pub fn new(rps: u32, duration: Duration) -> Self {
+        assert!(rps > 0, "rps must be positive");
+        Self { rps, duration }
+    }
+
+    /// Runs the load test against a router, without network
+    pub async fn run_router<F>(&self, router: Router, make_request: F) -> LoadReport
+    where
+        F: Fn() -> Request<Body>,
+    {
+        self.run(|| {
+            let call = router.clone().oneshot(make_request());
+            async move {
+                match call.await {
+                    Ok(resp) => !resp.status().is_server_error(),
+                    Err(infallible) => match infallible {},
+                }
+            }
+        })
+        .await
+    }
+
+    /// Runs the load test against an URL with `GET` requests
+    #[cfg(feature = "reqwest")]
+    pub async fn run_url(&self, client: &reqwest::Client, url: &str) -> LoadReport {
+        self.run(|| {
+            let call = client.get(url).send();
+            async move {
+                match call.await {
+                    Ok(resp) => !resp.status().is_server_error(),
+                    Err(_) => false,
+                }
+            }
+        })
+        .await
+    }
+
+    /// Runs the load test with a custom call, returning whether it succeeded
+    pub async fn run<F, Fut>(&self, call: F) -> LoadReport
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        let total = (self.rps as f64 * self.duration.as_secs_f64()).ceil() as usize;
+        let mut interval = tokio::time::interval(Duration::from_secs(1) / self.rps);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Burst);
+
+        let start = Instant::now();
+        let mut calls = Vec::with_capacity(total);
+        for _ in 0..total {
+            interval.tick().await;
+            let fut = call();
+            calls.push(tokio::spawn(async move {
+                let call_start = Instant::now();
+                let ok = fut.await;
+                (ok, call_start.elapsed())
+            }));
+        }
+
+        let mut latencies = Vec::with_capacity(total);
+        let mut errors = 0;
+        for call in calls {
+            match call.await {
+                Ok((ok, latency)) => {
+                    if !ok {
+                        errors += 1;
+                    }
+                    latencies.push(latency);
+                }
+                Err(_) => errors += 1,
+            }
+        }
+        let elapsed = start.elapsed();
+        latencies.sort();
+
+        LoadReport {
+            requests: total,
+            errors,
+            achieved_rps: total as f64 / elapsed.as_secs_f64(),
+            p50: percentile(&latencies, 0.50),
+            p90: percentile(&latencies, 0.90),
+            p99: percentile(&latencies, 0.99),
+            max: latencies.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requests ({} errors) at {:.1} rps: p50={:?} p90={:?} p99={:?} max={:?}",
+            self.requests, self.errors, self.achieved_rps, self.p50, self.p90, self.p99, self.max
+        )
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn nearest_rank_percentiles() {
+    let values: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+    assert_eq!(percentile(&values, 0.5), Duration::from_millis(50));
+    assert_eq!(percentile(&values, 0.99), Duration::from_millis(99));
+    assert_eq!(percentile(&[], 0.99), Duration::ZERO);
+}
@@ -0,0 +1,236 @@
+use std::{collections::BTreeMap, fmt};
+
+use prometheus::{proto::MetricType, Registry};
+
+/// Labels of a series, sorted by name
+pub type Labels = BTreeMap<String, String>;
+
+/// Values of the metrics of a registry at a point in time: metric name → labels → value.
+///
+/// Histograms and summaries are reported as two metrics, `<name>_count` and `<name>_sum`.
+/// The `Display` implementation prints one sorted `name{labels} value` line per series, to
+/// get readable diffs in assertion failures.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    metrics: BTreeMap<String, (MetricType, BTreeMap<Labels, f64>)>,
+}
+
+/// Gathers the metrics of `registry`
+pub fn metrics_snapshot(registry: &Registry) -> MetricsSnapshot {
+    let mut snapshot = MetricsSnapshot::default();
+    for family in registry.gather() {
+        let name = family.get_name();
+        let kind = family.get_field_type();
+        for metric in family.get_metric() {
+            let labels: Labels = metric
+                .get_label()
+                .iter()
+                .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+                .collect();
+            match kind {
+                MetricType::COUNTER => {
+                    snapshot.insert(name, kind, labels, metric.get_counter().get_value())
+                }
+                MetricType::GAUGE => {
+                    snapshot.insert(name, kind, labels, metric.get_gauge().get_value())
+                }
+                MetricType::UNTYPED => {
+                    snapshot.insert(name, kind, labels, metric.get_untyped().get_value())
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    snapshot.insert(
+                        &format!("{name}_count"),
+                        kind,
+                        labels.clone(),
+                        histogram.get_sample_count() as f64,
+                    );
+                    snapshot.insert(
+                        &format!("{name}_sum"),
+                        kind,
+                        labels,
+                        histogram.get_sample_sum(),
+                    );
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    snapshot.insert(
+                        &format!("{name}_count"),
+                        kind,
+                        labels.clone(),
+                        summary.get_sample_count() as f64,
+                    );
+                    snapshot.insert(
+                        &format!("{name}_sum"),
+                        kind,
+                        labels,
+                        summary.get_sample_sum(),
+                    );
+                }
+            }
+        }
+    }
+    snapshot
+}
+
+impl MetricsSnapshot {
+    fn insert(&mut self, name: &str, kind: MetricType, labels: Labels, value: f64) {
+        self.metrics
+            .entry(name.to_string())
+            .or_insert_with(|| (kind, BTreeMap::new()))
+            .1
+            .insert(labels, value);
+    }
+
+    /// Type of a metric, `None` if absent
+    pub fn metric_type(&self, name: &str) -> Option<MetricType> {
+        self.metrics.get(name).map(|(kind, _)| *kind)
+    }
+
+    /// Value of the series with exactly these labels
+    pub fn get(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        let labels: Labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        self.metrics.get(name)?.1.get(&labels).copied()
+    }
+
+    /// Sum of the series having (at least) these labels, `None` if no series match
+    pub fn sum(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        let (_, series) = self.metrics.get(name)?;
+        let matching: Vec<f64> = series
+            .iter()
+            .filter(|(series_labels, _)| {
+                labels
+                    .iter()
+                    .all(|(k, v)| series_labels.get(*k).map(String::as_str) == Some(*v))
+            })
+            .map(|(_, value)| *value)
+            .collect();
+        if matching.is_empty() {
+            None
+        } else {
+            Some(matching.iter().sum())
+        }
+    }
+
+    /// Only the series of one metric, for readable assertion messages
+    pub fn only(&self, name: &str) -> MetricsSnapshot {
+        MetricsSnapshot {
+            metrics: self
+                .metrics
+                .get_key_value(name)
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, (_, series)) in &self.metrics {
+            for (labels, value) in series {
+                let labels = labels
+                    .iter()
+                    .map(|(k, v)| format!("{k}={v:?}"))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                writeln!(f, "{name}{{{labels}}} {value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_metric_eq(
+    snapshot: &MetricsSnapshot,
+    kind: MetricType,
+    name: &str,
+    labels: &[(&str, &str)],
+    expected: f64,
+) {
+    match snapshot.metric_type(name) {
+        Some(actual) if actual == kind => {}
+        Some(actual) => panic!("{name} is a {actual:?}, expected a {kind:?}"),
+        None => panic!("{name} not found in snapshot:\n{snapshot}"),
+    }
+    let actual = snapshot.sum(name, labels);
+    if actual != Some(expected) {
+        panic!(
+            "{name}{labels:?}: expected {expected}, got {actual:?}\n{}",
+            snapshot.only(name)
+        );
+    }
+}
+
+/// Asserts the sum of the counter series having the given labels.
+///
+/// ```ignore
+/// let snapshot = metrics_snapshot(&registry);
+/// assert_counter_eq!(snapshot, "http_request_total", { "status" => "200" }, 1.0);
+/// ```
+#[macro_export]
+macro_rules! assert_counter_eq {
+    ($snapshot:expr, $name:expr, { $($key:expr => $value:expr),* $(,)? }, $expected:expr) => {
+        $crate::testing::assert_metric_eq(
+            &$snapshot,
+            $crate::prometheus::proto::MetricType::COUNTER,
+            $name,
+            &[$(($key, $value)),*],
+            $expected as f64,
+        )
+    };
+    ($snapshot:expr, $name:expr, $expected:expr) => {
+        $crate::assert_counter_eq!($snapshot, $name, {}, $expected)
+    };
+}
+
+/// Asserts the sum of the gauge series having the given labels.
+///
+/// ```ignore
+/// assert_gauge_eq!(snapshot, "inflight_http_request_total", 0);
+/// ```
+#[macro_export]
+macro_rules! assert_gauge_eq {
+    ($snapshot:expr, $name:expr, { $($key:expr => $value:expr),* $(,)? }, $expected:expr) => {
+        $crate::testing::assert_metric_eq(
+            &$snapshot,
+            $crate::prometheus::proto::MetricType::GAUGE,
+            $name,
+            &[$(($key, $value)),*],
+            $expected as f64,
+        )
+    };
+    ($snapshot:expr, $name:expr, $expected:expr) => {
+        $crate::assert_gauge_eq!($snapshot, $name, {}, $expected)
+    };
+}
+
+#[cfg(test)]
+#[test]
+fn snapshot_counters() {
+    let registry = Registry::new();
+    let counter = prometheus::IntCounterVec::new(
+        prometheus::Opts::new("jobs_total", "Jobs"),
+        &["name", "outcome"],
+    )
+    .unwrap();
+    registry.register(Box::new(counter.clone())).unwrap();
+    counter.with_label_values(&["import", "success"]).inc_by(2);
+    counter.with_label_values(&["import", "failure"]).inc();
+
+    let snapshot = metrics_snapshot(&registry);
+    crate::assert_counter_eq!(snapshot, "jobs_total", { "name" => "import" }, 3);
+    assert_eq!(
+        snapshot.get("jobs_total", &[("name", "import"), ("outcome", "failure")]),
+        Some(1.0)
+    );
+    assert_eq!(
+        snapshot.to_string(),
+        "jobs_total{name=\"import\",outcome=\"failure\"} 1\njobs_total{name=\"import\",outcome=\"success\"} 2\n"
+    );
+}
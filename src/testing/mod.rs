@@ -0,0 +1,19 @@
+//! Helpers to test services built with this crate.
+
+mod config_fixture;
+mod contract;
+mod load;
+#[cfg(feature = "metrics")]
+mod metrics_snapshot;
+#[cfg(feature = "tracing")]
+mod tracing_capture;
+mod upstream;
+
+pub use config_fixture::ConfigFixture;
+pub use contract::{assert_contract, ContractFailure, ContractReport, ContractSuite};
+pub use load::{LoadReport, LoadTest};
+#[cfg(feature = "metrics")]
+pub use metrics_snapshot::{assert_metric_eq, metrics_snapshot, Labels, MetricsSnapshot};
+#[cfg(feature = "tracing")]
+pub use tracing_capture::{CaptureLayer, CapturedEvent, CapturedSpan, TracingCapture};
+pub use upstream::{FakeResponse, FakeUpstream, RecordedCall};
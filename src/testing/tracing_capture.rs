@@ -0,0 +1,238 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    Layer, Registry,
+};
+
+/// Records spans and events emitted during a test, to assert on logging behavior.
+///
+/// ```ignore
+/// let capture = TracingCapture::new();
+/// let _guard = capture.set_default();
+/// // ... call the middleware or handler
+/// assert!(capture.has_event(|e| {
+///     e.target == "access_log" && e.field("http.response.status_code") == Some("500")
+/// }));
+/// ```
+///
+/// [`TracingCapture::set_default`] only captures on the current thread: use a
+/// `current_thread` tokio runtime (the default of `#[tokio::test]`), or add
+/// [`TracingCapture::layer`] to a global subscriber.
+#[derive(Clone, Default)]
+pub struct TracingCapture {
+    captured: Arc<Mutex<Captured>>,
+}
+
+#[derive(Default)]
+struct Captured {
+    spans: Vec<CapturedSpan>,
+    events: Vec<CapturedEvent>,
+}
+
+/// A span recorded by [`TracingCapture`], with the fields recorded at creation and later on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedSpan {
+    pub name: String,
+    pub target: String,
+    pub level: Level,
+    pub fields: BTreeMap<String, String>,
+}
+
+/// An event recorded by [`TracingCapture`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedEvent {
+    pub target: String,
+    pub level: Level,
+    pub fields: BTreeMap<String, String>,
+    /// Spans the event happened in, from the innermost to the root
+    pub spans: Vec<CapturedSpan>,
+}
+
+impl CapturedEvent {
+    /// Value of a field of the event, formatted as a string
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+
+    /// The formatted message of the event
+    pub fn message(&self) -> Option<&str> {
+        self.field("message")
+    }
+
+    /// Value of a field of the innermost enclosing span having it
+    pub fn span_field(&self, name: &str) -> Option<&str> {
+        self.spans
+            .iter()
+            .find_map(|span| span.fields.get(name))
+            .map(String::as_str)
+    }
+
+    /// Whether the event happened in a span with this name
+    pub fn in_span(&self, name: &str) -> bool {
+        self.spans.iter().any(|span| span.name == name)
+    }
+}
+
+impl TracingCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Layer recording into this capture
+    pub fn layer(&self) -> CaptureLayer {
+        CaptureLayer {
+            captured: self.captured.clone(),
+        }
+    }
+
+    /// Captures everything emitted on the current thread until the guard is dropped
+    pub fn set_default(&self) -> tracing::subscriber::DefaultGuard {
+        tracing::subscriber::set_default(Registry::default().with(self.layer()))
+    }
+
+    /// Every span created so far
+    pub fn spans(&self) -> Vec<CapturedSpan> {
+        self.captured.lock().unwrap().spans.clone()
+    }
+
+    /// Every event emitted so far
+    pub fn events(&self) -> Vec<CapturedEvent> {
+        self.captured.lock().unwrap().events.clone()
+    }
+
+    /// Events emitted with the given target
+    pub fn events_for_target(&self, target: &str) -> Vec<CapturedEvent> {
+        self.find_events(|e| e.target == target)
+    }
+
+    /// Events matching the predicate
+    pub fn find_events(&self, predicate: impl Fn(&CapturedEvent) -> bool) -> Vec<CapturedEvent> {
+        self.captured
+            .lock()
+            .unwrap()
+            .events
+            .iter()
+            .filter(|e| predicate(e))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether an event matching the predicate was emitted
+    pub fn has_event(&self, predicate: impl Fn(&CapturedEvent) -> bool) -> bool {
+        self.captured.lock().unwrap().events.iter().any(predicate)
+    }
+
+    /// Forgets everything captured so far
+    pub fn clear(&self) {
+        let mut captured = self.captured.lock().unwrap();
+        captured.spans.clear();
+        captured.events.clear();
+    }
+}
+
+/// Layer produced by [`TracingCapture::layer`]
+pub struct CaptureLayer {
+    captured: Arc<Mutex<Captured>>,
+}
+
+/// Fields of a span, kept in the span extensions
+struct SpanFields(BTreeMap<String, String>);
+
+impl<S> Layer<S> for CaptureLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut fields = FieldsVisitor::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields.0.clone()));
+        }
+        let metadata = attrs.metadata();
+        self.captured.lock().unwrap().spans.push(CapturedSpan {
+            name: metadata.name().to_string(),
+            target: metadata.target().to_string(),
+            level: *metadata.level(),
+            fields: fields.0,
+        });
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let mut fields = FieldsVisitor::default();
+        values.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            if let Some(SpanFields(existing)) = span.extensions_mut().get_mut::<SpanFields>() {
+                existing.extend(fields.0);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = FieldsVisitor::default();
+        event.record(&mut fields);
+        let spans = ctx
+            .event_scope(event)
+            .map(|scope| {
+                scope
+                    .map(|span| CapturedSpan {
+                        name: span.name().to_string(),
+                        target: span.metadata().target().to_string(),
+                        level: *span.metadata().level(),
+                        fields: span
+                            .extensions()
+                            .get::<SpanFields>()
+                            .map(|f| f.0.clone())
+                            .unwrap_or_default(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.captured.lock().unwrap().events.push(CapturedEvent {
+            target: event.metadata().target().to_string(),
+            level: *event.metadata().level(),
+            fields: fields.0,
+            spans,
+        });
+    }
+}
+
+#[derive(Default)]
+struct FieldsVisitor(BTreeMap<String, String>);
+
+impl Visit for FieldsVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn captures_events_with_span_fields() {
+    let capture = TracingCapture::new();
+    let _guard = capture.set_default();
+    tracing::info_span!("request", tx = "abc").in_scope(|| {
+        tracing::info!(target: "access_log", status = 500, "GET / {}", 500);
+    });
+
+    let events = capture.events_for_target("access_log");
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].field("status"), Some("500"));
+    assert_eq!(events[0].message(), Some("GET / 500"));
+    assert_eq!(events[0].span_field("tx"), Some("abc"));
+    assert!(events[0].in_span("request"));
+}
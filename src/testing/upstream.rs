@@ -0,0 +1,311 @@
+use std::{
+    collections::VecDeque,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    response::Response,
+    Router,
+};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use tokio::{net::TcpListener, sync::oneshot};
+
+/// Scriptable local HTTP server standing for an upstream service in tests.
+///
+/// Routes are programmed with a response (or a sequence of responses, the last one being
+/// repeated), which can be delayed or aborted to inject latency and errors. Every call is
+/// recorded. Unknown routes respond `404`.
+///
+/// ```ignore
+/// let upstream = FakeUpstream::start().await;
+/// upstream.on_sequence(
+///     Method::GET,
+///     "/users/42",
+///     vec![
+///         FakeResponse::new(StatusCode::SERVICE_UNAVAILABLE),
+///         FakeResponse::json(r#"{"id":42}"#).delay(Duration::from_millis(50)),
+///     ],
+/// );
+/// let client = MyClient::new(upstream.url("/"));
+/// // ...
+/// assert_eq!(upstream.calls_to(&Method::GET, "/users/42"), 2);
+/// ```
+pub struct FakeUpstream {
+    addr: SocketAddr,
+    state: Arc<UpstreamState>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Default)]
+struct UpstreamState {
+    routes: Mutex<Vec<FakeRoute>>,
+    calls: Mutex<Vec<RecordedCall>>,
+}
+
+struct FakeRoute {
+    method: Method,
+    path: String,
+    responses: VecDeque<FakeResponse>,
+}
+
+/// A request received by a [`FakeUpstream`]
+#[derive(Clone, Debug)]
+pub struct RecordedCall {
+    pub method: Method,
+    /// Path and query
+    pub uri: String,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl RecordedCall {
+    pub fn path(&self) -> &str {
+        self.uri.split('?').next().unwrap_or_default()
+    }
+}
+
+/// Response programmed on a [`FakeUpstream`] route
+#[derive(Clone, Debug)]
+pub struct FakeResponse {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: Bytes,
+    delay: Duration,
+    abort: bool,
+}
+
+impl FakeResponse {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: Bytes::new(),
+            delay: Duration::ZERO,
+            abort: false,
+        }
+    }
+
+    /// `200 OK` with a JSON body
+    pub fn json(body: impl Into<Bytes>) -> Self {
+        Self::new(StatusCode::OK)
+            .header(
+                http::header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )
+            .body(body)
+    }
+
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Waits before responding
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Aborts the connection instead of sending the body, so the client sees an IO error
+    pub fn abort(mut self) -> Self {
+        self.abort = true;
+        self
+    }
+
+    fn into_response(self) -> Response {
+        let body = if self.abort {
+            Body::from_stream(futures::stream::once(async {
+                Err::<Bytes, _>(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "aborted by FakeUpstream",
+                ))
+            }))
+        } else {
+            Body::from(self.body)
+        };
+        let mut resp = Response::new(body);
+        *resp.status_mut() = self.status;
+        resp.headers_mut().extend(self.headers);
+        resp
+    }
+}
+
+impl FakeUpstream {
+    /// Binds a random local port and starts serving. Requires a running tokio runtime.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Cannot bind FakeUpstream listener");
+        let addr = listener
+            .local_addr()
+            .expect("bound listener has an address");
+        let state = Arc::new(UpstreamState::default());
+        let router = Router::new().fallback(handle).with_state(state.clone());
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            axum::serve(listener, router)
+                .with_graceful_shutdown(async {
+                    let _ = stopped.await;
+                })
+                .await
+        });
+        Self {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Absolute URL of a path on this upstream
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Always respond to `method path` with `response`
+    pub fn on(&self, method: Method, path: &str, response: FakeResponse) -> &Self {
+        self.on_sequence(method, path, vec![response])
+    }
+
+    /// Respond to successive calls of `method path` with the given responses, the last one
+    /// being repeated. Replaces any response previously programmed for this route.
+    pub fn on_sequence(&self, method: Method, path: &str, responses: Vec<FakeResponse>) -> &Self {
+        assert!(!responses.is_empty(), "at least one response is required");
+        let mut routes = self.state.routes.lock().unwrap();
+        routes.retain(|r| !(r.method == method && r.path == path));
+        routes.push(FakeRoute {
+            method,
+            path: path.to_string(),
+            responses: responses.into(),
+        });
+        self
+    }
+
+    /// Every call received so far
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.state.calls.lock().unwrap().clone()
+    }
+
+    /// Number of calls received on `method path`
+    pub fn calls_to(&self, method: &Method, path: &str) -> usize {
+        self.state
+            .calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| c.method == method && c.path() == path)
+            .count()
+    }
+
+    /// Forgets programmed routes and recorded calls
+    pub fn reset(&self) {
+        self.state.routes.lock().unwrap().clear();
+        self.state.calls.lock().unwrap().clear();
+    }
+}
+
+impl Drop for FakeUpstream {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+async fn handle(State(state): State<Arc<UpstreamState>>, req: Request) -> Response {
+    let (parts, body) = req.into_parts();
+    let body = to_bytes(body, usize::MAX).await.unwrap_or_default();
+    let call = RecordedCall {
+        method: parts.method,
+        uri: parts
+            .uri
+            .path_and_query()
+            .map(|p| p.to_string())
+            .unwrap_or_default(),
+        headers: parts.headers,
+        body,
+    };
+
+    let response = {
+        let mut routes = state.routes.lock().unwrap();
+        routes
+            .iter_mut()
+            .find(|r| r.method == call.method && r.path == call.path())
+            .map(|route| {
+                if route.responses.len() > 1 {
+                    route.responses.pop_front().unwrap()
+                } else {
+                    route.responses[0].clone()
+                }
+            })
+    };
+    state.calls.lock().unwrap().push(call);
+
+    let response = response.unwrap_or_else(|| FakeResponse::new(StatusCode::NOT_FOUND));
+    if !response.delay.is_zero() {
+        tokio::time::sleep(response.delay).await;
+    }
+    response.into_response()
+}
+
+#[cfg(all(test, feature = "reqwest"))]
+#[tokio::test]
+async fn scripts_responses() {
+    let upstream = FakeUpstream::start().await;
+    upstream.on_sequence(
+        Method::POST,
+        "/users",
+        vec![
+            FakeResponse::new(StatusCode::SERVICE_UNAVAILABLE),
+            FakeResponse::json(r#"{"id":42}"#).delay(Duration::from_millis(20)),
+        ],
+    );
+    upstream.on(Method::GET, "/users/42", FakeResponse::json("{}").abort());
+    let client = reqwest::Client::new();
+    let post = || {
+        client
+            .post(upstream.url("/users?dry_run=1"))
+            .body("alice")
+            .send()
+    };
+
+    assert_eq!(
+        post().await.unwrap().status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    for _ in 0..2 {
+        let resp = post().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.text().await.unwrap(), r#"{"id":42}"#);
+    }
+    let aborted = match client.get(upstream.url("/users/42")).send().await {
+        Ok(resp) => resp.text().await.is_err(),
+        Err(_) => true,
+    };
+    assert!(aborted);
+    let resp = client.get(upstream.url("/orders")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    assert_eq!(upstream.calls_to(&Method::POST, "/users"), 3);
+    let calls = upstream.calls();
+    assert_eq!(calls.len(), 5);
+    assert_eq!(calls[0].uri, "/users?dry_run=1");
+    assert_eq!(calls[0].body, "alice");
+    upstream.reset();
+    assert!(upstream.calls().is_empty());
+    let resp = post().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,179 @@
+//! Time helpers: RFC3339 UTC timestamps, monotonic measurements and a mockable [`Clock`]
+//!
+//! Time dependent logic (token expiry, cache TTLs, schedulers) should take a `&dyn Clock`
+//! (or an `Arc<dyn Clock>`) instead of calling `SystemTime::now()` directly, so that tests
+//! can use a [`MockClock`].
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// Source of wall clock and monotonic time
+pub trait Clock: Send + Sync {
+    /// Current wall clock time
+    fn now(&self) -> SystemTime;
+
+    /// Current monotonic time, to be used to measure durations
+    fn monotonic(&self) -> Instant;
+}
+
+/// The real clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to. Both wall clock and monotonic time advance together.
+#[derive(Debug)]
+pub struct MockClock {
+    wall: SystemTime,
+    monotonic: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    /// Creates a clock frozen at `now`
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            wall: now,
+            monotonic: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Moves the clock forward
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(SystemTime::now())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        self.wall + self.elapsed()
+    }
+
+    fn monotonic(&self) -> Instant {
+        self.monotonic + self.elapsed()
+    }
+}
+
+/// Measures elapsed time using the monotonic time of a [`Clock`]
+pub struct Stopwatch<'a> {
+    clock: &'a dyn Clock,
+    start: Instant,
+}
+
+impl Stopwatch<'static> {
+    /// Starts a stopwatch on the [`SystemClock`]
+    pub fn start() -> Self {
+        Self::start_with(&SystemClock)
+    }
+}
+
+impl<'a> Stopwatch<'a> {
+    /// Starts a stopwatch on the given clock
+    pub fn start_with(clock: &'a dyn Clock) -> Self {
+        Self {
+            clock,
+            start: clock.monotonic(),
+        }
+    }
+
+    /// Time elapsed since the stopwatch was started
+    pub fn elapsed(&self) -> Duration {
+        self.clock.monotonic().saturating_duration_since(self.start)
+    }
+}
+
+/// Runs `f` and returns its result along with the time it took.
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let stopwatch = Stopwatch::start();
+    let ret = f();
+    (ret, stopwatch.elapsed())
+}
+
+/// Formats a timestamp as RFC3339 in UTC (eg. `2024-01-31T12:00:00.5Z`)
+pub fn format_rfc3339(time: SystemTime) -> String {
+    OffsetDateTime::from(time)
+        .format(&Rfc3339)
+        .expect("a SystemTime is always representable as RFC3339")
+}
+
+/// Parses a RFC3339 timestamp, whatever its offset.
+pub fn parse_rfc3339(value: &str) -> anyhow::Result<SystemTime> {
+    Ok(OffsetDateTime::parse(value, &Rfc3339)
+        .with_context(|| format!("Invalid RFC3339 timestamp {value}"))?
+        .into())
+}
+
+/// Serde helpers to (de)serialize `SystemTime` as RFC3339 UTC strings.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct Event {
+///     #[serde(with = "service_helpe_rs::time::rfc3339")]
+///     at: SystemTime,
+/// }
+/// ```
+pub mod rfc3339 {
+    use std::time::SystemTime;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::format_rfc3339(*time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        super::parse_rfc3339(&value).map_err(|e| D::Error::custom(format!("{e:#}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3339_is_utc_and_round_trips() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let formatted = format_rfc3339(time);
+        assert_eq!(formatted, "2023-11-14T22:13:20.5Z");
+        assert_eq!(parse_rfc3339(&formatted).unwrap(), time);
+        assert_eq!(parse_rfc3339("2023-11-14T23:13:20.5+01:00").unwrap(), time);
+    }
+
+    #[test]
+    fn mock_clock_advances() {
+        let clock = MockClock::new(SystemTime::UNIX_EPOCH);
+        let stopwatch = Stopwatch::start_with(&clock);
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(stopwatch.elapsed(), Duration::from_secs(30));
+        assert_eq!(
+            clock.now(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(30)
+        );
+    }
+}
@@ -1,52 +1,220 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+
 use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{span, Event, Subscriber};
 use tracing_gelf::Logger;
-use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::SubscriberBuilder, util::SubscriberInitExt, EnvFilter};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
 
-use crate::ServiceDef;
+use crate::{
+    logging::{LogFormat, LogLevels, LoggingBuilder, LoggingGuard, SamplingParams},
+    ServiceDef,
+};
 
+/// Graylog output. The logs are sent to Graylog and written on stdout at the same time, in the
+/// configured [`LogFormat`] (eg. pretty for `kubectl logs`).
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct GelfParams {
     pub tcp_address: String,
     pub env: String,
+    /// Additional fields of all the messages, like `datacenter: eu-west-1`. The `version`,
+    /// `service` and `env` fields cannot be overridden.
+    #[serde(default)]
+    pub additional_fields: BTreeMap<String, String>,
+    /// Format of the logs on stdout, see [`LogFormat::resolve`]
+    #[serde(default)]
+    pub log_format: Option<LogFormat>,
+    /// Levels by target, merged with `RUST_LOG`
+    #[serde(default)]
+    pub log_levels: LogLevels,
+    /// Sampling rates of the high-volume targets, for all the outputs
+    #[serde(default)]
+    pub sampling: SamplingParams,
+    /// Also sends the logs to syslog
+    #[cfg(feature = "syslog")]
+    #[serde(default)]
+    pub syslog: Option<crate::logging::SyslogParams>,
+    /// Also writes the logs to rotated files
+    #[cfg(feature = "file-log")]
+    #[serde(default)]
+    pub file: Option<crate::logging::FileLogParams>,
 }
 
-pub fn init<'a>(gelf: Option<GelfParams>, service: ServiceDef<'a>) -> anyhow::Result<()> {
-    let stdout = SubscriberBuilder::default()
-        // only enable colored output on real terminals
-        .with_ansi(atty::is(atty::Stream::Stdout))
-        .with_env_filter(EnvFilter::from_default_env())
-        // build but do not install the subscriber.
-        .finish();
-
-    match gelf {
-        Some(gelf) => {
-            println!(
-                "Configuring GELF logger env:{}, tcp:{}",
-                gelf.env, gelf.tcp_address
-            );
-            // launch tracing gelf
-            let mut conn_handle = Logger::builder()
-                .additional_field(
-                    "version",
-                    format!("{}-{}", service.version, service.git_hash),
-                )
-                .additional_field("service", service.pkg_name)
-                .additional_field("env", gelf.env)
-                .init_tcp_with_subscriber(gelf.tcp_address, stdout)?;
-            tokio::spawn(async move { conn_handle.connect().await });
-
-            // convert "classic" logs into tracing events
-            LogTracer::init()?;
+/// Longest wait of the queued events on shutdown
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Logger of the GELF layer, removed on shutdown to close the connection
+type SharedLogger = Arc<RwLock<Option<Logger>>>;
+
+/// Layer sending the events to Graylog until its [`GelfGuard`] is shut down
+pub(crate) struct GelfLayer {
+    logger: SharedLogger,
+}
+
+impl<S> Layer<S> for GelfLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let logger = self.logger.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(logger) = logger.as_ref() {
+            logger.on_new_span(attrs, id, ctx);
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let logger = self.logger.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(logger) = logger.as_ref() {
+            logger.on_record(id, values, ctx);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let logger = self.logger.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(logger) = logger.as_ref() {
+            logger.on_event(event, ctx);
         }
-        None => {
-            println!("Configuring stdout logger");
-            // only install tracing subscriber
-            stdout.init();
-            // no need to convert class logs with the LogTracer ; this is
-            // done automatically by init() method
+    }
+}
+
+/// Closes the connection to Graylog, see [`LoggingGuard::shutdown`]. When dropped, the events
+/// keep being sent.
+pub(crate) struct GelfGuard {
+    logger: SharedLogger,
+    connection: Option<JoinHandle<()>>,
+}
+
+impl GelfGuard {
+    /// Drops the logger, so the connection ends once the queued events are sent
+    fn close(&self) {
+        let mut logger = self.logger.write().unwrap_or_else(PoisonError::into_inner);
+        logger.take();
+    }
+
+    /// Closes the connection and waits for the queued events to be sent
+    pub(crate) async fn shutdown(mut self) {
+        self.close();
+        if let Some(connection) = self.connection.take() {
+            if tokio::time::timeout(FLUSH_TIMEOUT, connection)
+                .await
+                .is_err()
+            {
+                eprintln!("Cannot send the last logs to Graylog in {FLUSH_TIMEOUT:?}");
+            }
         }
     }
+}
+
+/// GELF layer of [`LoggingBuilder`], connected to Graylog in a background task
+pub(crate) fn gelf_layer(
+    gelf: &GelfParams,
+    service: &ServiceDef,
+) -> anyhow::Result<(GelfLayer, GelfGuard)> {
+    println!(
+        "Configuring GELF logger env:{}, tcp:{}",
+        gelf.env, gelf.tcp_address
+    );
+    let builder = gelf
+        .additional_fields
+        .iter()
+        .fold(Logger::builder(), |builder, (key, value)| {
+            builder.additional_field(key, value.clone())
+        });
+    let (logger, mut conn_handle) = builder
+        .additional_field(
+            "version",
+            format!("{}-{}", service.version, service.git_hash),
+        )
+        .additional_field("service", service.pkg_name)
+        .additional_field("env", gelf.env.clone())
+        .connect_tcp(gelf.tcp_address.clone())?;
+    // returns once the logger is dropped and the queued events are sent
+    let connection = tokio::spawn(async move {
+        let _ = conn_handle.connect().await;
+        crate::info::set_sink_state("gelf", "disconnected");
+    });
+    crate::info::register_sink("gelf", gelf.tcp_address.clone(), "configured");
+    let logger = Arc::new(RwLock::new(Some(logger)));
+    Ok((
+        GelfLayer {
+            logger: logger.clone(),
+        },
+        GelfGuard {
+            logger,
+            connection: Some(connection),
+        },
+    ))
+}
+
+/// Installs a subscriber logging on stdout, and also to Graylog if `gelf` is set, see
+/// [`LoggingBuilder`]. Without `gelf`, the format of the logs on stdout is the one of the
+/// `LOG_FORMAT` environment variable.
+///
+/// The `log` records are converted into tracing events. Call [`LoggingGuard::shutdown`] before
+/// exiting, so the last events reach Graylog.
+pub fn init<'a>(gelf: Option<GelfParams>, service: ServiceDef<'a>) -> anyhow::Result<LoggingGuard> {
+    let Some(gelf) = gelf else {
+        println!("Configuring stdout logger");
+        return LoggingBuilder::new(service).install();
+    };
+    let mut builder = LoggingBuilder::new(service)
+        .levels(gelf.log_levels.clone())
+        .sampling(gelf.sampling.clone());
+    builder.format = gelf.log_format;
+    #[cfg(feature = "syslog")]
+    {
+        builder.syslog = gelf.syslog.clone();
+    }
+    #[cfg(feature = "file-log")]
+    {
+        builder.file = gelf.file.clone();
+    }
+    builder.gelf(gelf).install()
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn sends_queued_events_on_shutdown() {
+    use std::io::Read;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let params = GelfParams {
+        tcp_address: listener.local_addr().unwrap().to_string(),
+        env: "staging".to_string(),
+        additional_fields: BTreeMap::new(),
+        log_format: None,
+        log_levels: LogLevels::default(),
+        sampling: SamplingParams::default(),
+        #[cfg(feature = "syslog")]
+        syslog: None,
+        #[cfg(feature = "file-log")]
+        file: None,
+    };
+    let received = std::thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        socket.read_to_end(&mut received).unwrap();
+        received
+    });
+    let service = ServiceDef::new("orders-api", "1.2.0", "abc123");
+    let (layer, guard) = gelf_layer(&params, &service).unwrap();
+    {
+        let _subscriber =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+        tracing::warn!("upstream slow");
+    }
+    guard.shutdown().await;
 
-    Ok(())
+    // the connection is closed once the events are sent
+    let received = received.join().unwrap();
+    let message = received.split(|byte| *byte == 0).next().unwrap();
+    let message: serde_json::Value = serde_json::from_slice(message).unwrap();
+    assert_eq!(message["short_message"], "upstream slow");
+    assert_eq!(message["_service"], "orders-api");
+    assert_eq!(message["_env"], "staging");
 }
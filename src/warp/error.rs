@@ -1,30 +1,67 @@
 use crate::errors::format_error;
+use crate::problem::{CodedError, ErrorCode, Problem, PROBLEM_JSON};
 use std::future::Future;
 
+#[cfg(feature = "metrics")]
+use crate::metrics::record_handled_error;
+#[cfg(not(feature = "metrics"))]
+fn record_handled_error(_code: &ErrorCode) {}
+
 /// Reject the request if a NotFound error is returned by the future. Otherwise, log the error
 /// and send  a 500 error.
+///
+/// Errors are responded as `application/problem+json` [`Problem`] bodies, with the code of a
+/// [`CodedError`] when one is returned.
+///
+/// With the `metrics` feature, errors are counted in `handled_errors_total{kind}`.
 pub async fn handle_errors<R: warp::Reply + 'static, F: Future<Output = anyhow::Result<R>>>(
     f: F,
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
-    match f.await {
-        Ok(resp) => Ok(Box::new(resp)),
-        Err(err) => match err.downcast::<NotFound>() {
-            Ok(_not_found) => Ok(Box::new(warp::http::StatusCode::NOT_FOUND)),
-            Err(err) => match err.downcast::<BadRequest>() {
-                Ok(bad_request) => Ok(Box::new(
-                    warp::http::Response::builder()
-                        .status(warp::http::StatusCode::BAD_REQUEST)
-                        .body(bad_request.to_string()),
-                )),
-                Err(err) => match err.downcast::<Forbidden>() {
-                    Ok(_forbidden) => Ok(Box::new(warp::http::StatusCode::FORBIDDEN)),
+    let err = match f.await {
+        Ok(resp) => return Ok(Box::new(resp)),
+        Err(err) => err,
+    };
+    let problem = match err.downcast::<NotFound>() {
+        Ok(_not_found) => Problem::new(ErrorCode::NotFound),
+        Err(err) => match err.downcast::<BadRequest>() {
+            Ok(bad_request) => Problem::new(ErrorCode::BadRequest).with_detail(bad_request.0),
+            Err(err) => match err.downcast::<Forbidden>() {
+                Ok(_forbidden) => Problem::new(ErrorCode::Forbidden),
+                Err(err) => match err.downcast::<CodedError>() {
+                    Ok(coded) if coded.code.status() < 500 => {
+                        Problem::new(coded.code).with_detail(coded.detail)
+                    }
+                    Ok(coded) => {
+                        let code = coded.code;
+                        log::error!("Unable to handle request: {}", format_error(coded));
+                        Problem::new(code)
+                    }
                     Err(err) => {
                         log::error!("Unable to handle request: {}", format_error(err));
-                        Ok(Box::new(warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+                        Problem::new(ErrorCode::Internal)
                     }
                 },
             },
         },
+    };
+    record_handled_error(&problem.code);
+    Ok(Box::new(problem))
+}
+
+impl warp::Reply for Problem {
+    fn into_response(self) -> warp::reply::Response {
+        let status = warp::http::StatusCode::from_u16(self.status)
+            .unwrap_or(warp::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let mut resp = warp::reply::with_status(
+            warp::reply::with_header(warp::reply::json(&self), "content-type", PROBLEM_JSON),
+            status,
+        )
+        .into_response();
+        for (name, value) in self.headers() {
+            resp.headers_mut()
+                .insert(name, warp::http::HeaderValue::from_str(&value).unwrap());
+        }
+        resp
     }
 }
 
@@ -1,68 +1,103 @@
 use std::sync::Arc;
 use warp::filters::log::{Info, Log};
 
-use crate::metrics::create_counter_with_labels;
+use crate::excluded_paths::ExcludedPaths;
+use crate::metrics::{
+    get_or_create_counter_with_labels, get_or_create_histogram,
+    get_or_create_histogram_with_labels, status_class, CardinalityLimiter,
+    DEFAULT_DURATION_BUCKETS,
+};
+
+/// Options of the [`requests_metrics_with_options`] filter
+#[derive(Clone, Debug)]
+pub struct RequestsMetricsOptions {
+    /// Also report counters and durations labelled by path
+    pub report_by_path: bool,
+    /// Paths for which no metrics are recorded. Defaults to everything under `/metrics`
+    /// and `/health`.
+    pub excluded_paths: ExcludedPaths,
+    /// Maximum number of distinct paths reported when `report_by_path` is set. Further
+    /// paths are reported as `__other__`.
+    pub max_paths: usize,
+}
+
+impl Default for RequestsMetricsOptions {
+    fn default() -> Self {
+        Self {
+            report_by_path: false,
+            excluded_paths: ExcludedPaths::none().prefix("/metrics").prefix("/health"),
+            max_paths: 200,
+        }
+    }
+}
 
 pub fn requests_metrics(report_by_path: bool) -> Log<impl Fn(Info) + Clone> {
-    let total = Arc::new(create_counter_with_labels(
+    requests_metrics_with_options(RequestsMetricsOptions {
+        report_by_path,
+        ..Default::default()
+    })
+}
+
+pub fn requests_metrics_with_options(
+    options: RequestsMetricsOptions,
+) -> Log<impl Fn(Info) + Clone> {
+    let RequestsMetricsOptions {
+        report_by_path,
+        excluded_paths,
+        max_paths,
+    } = options;
+    let paths = Arc::new(CardinalityLimiter::new(max_paths));
+
+    let total = Arc::new(get_or_create_counter_with_labels(
         "http_request_total",
         "HTTP requests handled",
-        &["status"],
+        &["status", "status_class"],
     ));
 
     let by_path = if report_by_path {
-        Some(Arc::new(create_counter_with_labels(
+        Some(Arc::new(get_or_create_counter_with_labels(
             "http_request_by_path_total",
             "HTTP requests handled",
-            &["path", "status"],
+            &["path", "status", "status_class"],
         )))
     } else {
         None
     };
 
-    let request_duration = prometheus::Histogram::with_opts(
-        prometheus::HistogramOpts::new("http_request_duration_seconds", "HTTP requests duration")
-            .buckets(vec![
-                0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 7.5, 10.0,
-                25.0, 50.0, 100.0,
-            ]),
-    )
-    .unwrap();
-    prometheus::register(Box::new(request_duration.clone())).unwrap();
+    let request_duration = get_or_create_histogram(
+        "http_request_duration_seconds",
+        "HTTP requests duration",
+        DEFAULT_DURATION_BUCKETS.to_vec(),
+    );
 
     let request_duration_by_path = if report_by_path {
-        let request_duration_by_path = prometheus::HistogramVec::new(
-            prometheus::HistogramOpts::new(
-                "http_request_duration_by_path_seconds",
-                "HTTP requests duration",
-            )
-            .buckets(vec![
-                0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 7.5, 10.0,
-                25.0, 50.0, 100.0,
-            ]),
+        Some(get_or_create_histogram_with_labels(
+            "http_request_duration_by_path_seconds",
+            "HTTP requests duration",
             &["path"],
-        )
-        .unwrap();
-        prometheus::register(Box::new(request_duration_by_path.clone())).unwrap();
-        Some(request_duration_by_path)
+            DEFAULT_DURATION_BUCKETS.to_vec(),
+        ))
     } else {
         None
     };
 
     warp::log::custom(move |info| {
-        if info.path().starts_with("/metrics") || info.path().starts_with("/health") {
+        if excluded_paths.is_excluded(info.path()) {
             return;
         }
+        let status = info.status().as_u16();
+        let class = status_class(status);
         total
             .clone()
-            .get_metric_with_label_values(&[&format!("{}", info.status().as_u16())])
+            .get_metric_with_label_values(&[&format!("{}", status), class])
             .unwrap()
             .inc();
         if let Some(by_path) = by_path.clone() {
             by_path
                 .get_metric_with_label_values(&[
-                    info.path(),
-                    &format!("{}", info.status().as_u16()),
+                    paths.label(info.path()),
+                    &format!("{}", status),
+                    class,
                 ])
                 .unwrap()
                 .inc();
@@ -72,7 +107,7 @@ pub fn requests_metrics(report_by_path: bool) -> Log<impl Fn(Info) + Clone> {
 
         if let Some(request_duration_by_path) = request_duration_by_path.clone() {
             request_duration_by_path
-                .get_metric_with_label_values(&[info.path()])
+                .get_metric_with_label_values(&[paths.label(info.path())])
                 .unwrap()
                 .observe(info.elapsed().as_secs_f64());
         }
@@ -83,4 +118,6 @@ pub fn requests_metrics(report_by_path: bool) -> Log<impl Fn(Info) + Clone> {
 #[test]
 fn test() {
     requests_metrics(true);
+    // can be built twice
+    requests_metrics(true);
 }